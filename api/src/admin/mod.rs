@@ -0,0 +1,154 @@
+use axum::{
+    Json, Router,
+    extract::State,
+    http::HeaderMap,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::auth::admin_auth;
+use crate::error::{ProxyError, create_error_response};
+use crate::proxy::circuit::CircuitBreakers;
+use crate::proxy::reload::LiveConfig;
+use crate::proxy::route::{RequestMeta, resolve_route};
+use crate::proxy::slo::{SloMonitor, SloStatus};
+use crate::request_id::request_id;
+
+#[derive(Clone)]
+struct AdminState {
+    config: Arc<LiveConfig>,
+    slo: Arc<SloMonitor>,
+}
+
+#[derive(Clone)]
+struct HealthState {
+    config: Arc<LiveConfig>,
+    slo: Arc<SloMonitor>,
+    circuits: Arc<CircuitBreakers>,
+    tls: TlsStatus,
+}
+
+/// How the listener accepts connections, reported by `/health/detailed`
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TlsStatus {
+    pub active: bool,
+    /// Whether clients must present a certificate
+    pub client_auth: bool,
+}
+
+/// Admin endpoints, gated by the admin token
+pub fn router(config: Arc<LiveConfig>, slo: Arc<SloMonitor>) -> Router {
+    Router::new()
+        .route("/admin/resolve", post(resolve))
+        .route("/admin/stats", get(stats))
+        .layer(middleware::from_fn(admin_auth))
+        .with_state(AdminState { config, slo })
+}
+
+/// `/health/detailed`, open to health checks
+pub fn health_router(
+    config: Arc<LiveConfig>,
+    slo: Arc<SloMonitor>,
+    circuits: Arc<CircuitBreakers>,
+    tls: TlsStatus,
+) -> Router {
+    Router::new()
+        .route("/health/detailed", get(health_detailed))
+        .with_state(HealthState { config, slo, circuits, tls })
+}
+
+/// Show how a request would be routed without sending it anywhere
+async fn resolve(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Json(meta): Json<RequestMeta>,
+) -> Response {
+    let config = state.config.config();
+    match resolve_route(&config, &meta) {
+        Some(plan) => Json(plan.redacted(&config.log_redact_headers)).into_response(),
+        None => {
+            let error = ProxyError::NotFound(format!("No endpoint for {} {}", meta.method, meta.path));
+            create_error_response(error, &request_id(&headers))
+        }
+    }
+}
+
+/// Per-endpoint SLO state, evaluated on demand
+async fn stats(State(state): State<AdminState>) -> Json<HashMap<String, SloStatus>> {
+    Json(state.slo.evaluate_current(&state.config.config()))
+}
+
+/// Overall health: `degraded` while any endpoint breaches its SLO or any
+/// upstream circuit is not closed. `config_version` counts the configuration
+/// reloads since startup. Endpoints removed by a reload do not count.
+async fn health_detailed(State(state): State<HealthState>) -> Json<Value> {
+    let endpoints = state.slo.evaluate_current(&state.config.config());
+    let breached = endpoints.values().any(|status| status.slo == "breached" && !status.removed);
+    let circuits = state.circuits.status();
+    let tripped = circuits.values().any(|status| status.state != "closed");
+    Json(json!({
+        "status": if breached || tripped { "degraded" } else { "ok" },
+        "slo": if breached { "breached" } else { "ok" },
+        "config_version": state.config.version(),
+        "tls": state.tls,
+        "endpoints": endpoints,
+        "components": {
+            "circuit_breakers": circuits,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+
+    use crate::test_support::{ADMIN_TOKEN, endpoint, init_admin_token, proxy_config, proxy_service, send};
+
+    fn admin_router() -> Router {
+        let service = proxy_service(proxy_config(vec![endpoint(json!({}))], json!({})));
+        let tls = TlsStatus { active: false, client_auth: false };
+        router(service.live_config(), service.slo()).merge(health_router(
+            service.live_config(),
+            service.slo(),
+            service.circuits(),
+            tls,
+        ))
+    }
+
+    fn request(method: &str, uri: &str, token: Option<&str>) -> Request<Body> {
+        let mut req = Request::builder().method(method).uri(uri).header("content-type", "application/json");
+        if let Some(token) = token {
+            req = req.header("authorization", format!("Bearer {token}"));
+        }
+        let body = if method == "POST" { r#"{"method":"POST","path":"/v1/test"}"# } else { "" };
+        req.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn admin_routes_require_the_admin_token() {
+        init_admin_token();
+        let router = admin_router();
+
+        for (method, uri) in [("GET", "/admin/stats"), ("POST", "/admin/resolve")] {
+            let (status, _, _) = send(&router, request(method, uri, None)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{method} {uri} without a token");
+            let (status, _, _) = send(&router, request(method, uri, Some("wrong"))).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{method} {uri} with a wrong token");
+            let (status, _, _) = send(&router, request(method, uri, Some(ADMIN_TOKEN))).await;
+            assert_eq!(status, StatusCode::OK, "{method} {uri} with the admin token");
+        }
+    }
+
+    #[tokio::test]
+    async fn detailed_health_is_not_gated() {
+        let (status, _, _) = send(&admin_router(), request("GET", "/health/detailed", None)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
@@ -0,0 +1,232 @@
+#[cfg(feature = "storage")]
+pub mod signing;
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, Method, StatusCode, Uri, header::AUTHORIZATION},
+    middleware::Next,
+    response::Response,
+};
+use std::{env, fmt, fs};
+use tracing::warn;
+
+use crate::error::{ProxyError, create_error_response};
+use crate::get_admin_token;
+use crate::proxy::config::{InboundAuthConfig, UserProfile, tokens_match};
+use crate::proxy::redact::REDACTED;
+use crate::request_id::request_id;
+
+/// Token a request authenticated with, added to the request extensions. Its
+/// `Display` and `Debug` show only the first characters.
+#[derive(Clone)]
+pub struct ClientKey(pub Arc<str>);
+
+impl fmt::Display for ClientKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.get(..4) {
+            Some(prefix) if self.0.len() > 8 => write!(f, "{prefix}{REDACTED}"),
+            _ => f.write_str(REDACTED),
+        }
+    }
+}
+
+impl fmt::Debug for ClientKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ClientKey({self})")
+    }
+}
+
+/// Identity of an authenticated client configured under `inbound_auth.clients`,
+/// added to the request extensions
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "storage"), allow(dead_code))]
+pub struct ClientIdentity(pub Arc<UserProfile>);
+
+/// Marks a request authenticated with one of `inbound_auth.admin_tokens`,
+/// added to the request extensions
+#[derive(Debug, Clone, Copy)]
+pub struct AdminAccess;
+
+/// Require a valid client token, taken from the `Authorization: Bearer` header
+/// or, when configured, from a query parameter. The query parameter is always
+/// stripped so it never reaches the upstream. `OPTIONS` requests pass
+/// without one: browsers send CORS preflights without credentials, and
+/// nothing is proxied for them.
+pub async fn require_client_token(
+    State(config): State<Arc<InboundAuthConfig>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if req.method() == Method::OPTIONS {
+        return next.run(req).await;
+    }
+
+    let mut accepted = bearer_token(req.headers()).filter(|token| config.accepts(token));
+
+    if let Some(param) = &config.query_param
+        && let Some(query) = req.uri().query()
+    {
+        let (remaining, token) = take_query_param(query, param);
+        if let Some(token) = token {
+            if accepted.is_none() && config.accepts(&token) {
+                accepted = Some(token);
+            }
+
+            match replace_query(req.uri(), remaining.as_deref()) {
+                Ok(uri) => *req.uri_mut() = uri,
+                Err(e) => {
+                    warn!("Failed to strip token query parameter: {}", e);
+                    let error = ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, "Invalid request URI".to_string());
+                    return create_error_response(error, &request_id(req.headers()));
+                }
+            }
+        }
+    }
+
+    let Some(token) = accepted else {
+        let error = ProxyError::Unauthorized("Missing or invalid access token".to_string());
+        return create_error_response(error, &request_id(req.headers()));
+    };
+
+    if let Some(client) = config.client(&token) {
+        req.extensions_mut().insert(ClientIdentity(Arc::new(client.user.clone())));
+    }
+    if config.is_admin(&token) {
+        req.extensions_mut().insert(AdminAccess);
+    }
+    req.extensions_mut().insert(ClientKey(token.into()));
+
+    next.run(req).await
+}
+
+/// Require `Authorization: Bearer <ADMIN_TOKEN>` on admin-only endpoints,
+/// answering `403` while no `ADMIN_TOKEN` is configured. Layered inside
+/// `require_client_token`, which accepts the admin token as well.
+#[cfg_attr(not(any(feature = "storage", feature = "admin", feature = "metrics")), allow(dead_code))]
+pub async fn admin_auth(mut req: Request, next: Next) -> Response {
+    if req.method() == Method::OPTIONS {
+        return next.run(req).await;
+    }
+
+    if let Err(error) = check_admin_token(get_admin_token(), req.headers()) {
+        return create_error_response(error, &request_id(req.headers()));
+    }
+
+    req.extensions_mut().insert(AdminAccess);
+    next.run(req).await
+}
+
+/// Whether the request carries `Authorization: Bearer <ADMIN_TOKEN>`, whether
+/// or not `inbound_auth` is configured
+pub fn has_admin_token(headers: &HeaderMap) -> bool {
+    check_admin_token(get_admin_token(), headers).is_ok()
+}
+
+fn check_admin_token(admin_token: Option<&str>, headers: &HeaderMap) -> Result<(), ProxyError> {
+    let Some(admin_token) = admin_token else {
+        return Err(ProxyError::Forbidden("Admin endpoints are disabled without ADMIN_TOKEN".to_string()));
+    };
+    if !bearer_token(headers).is_some_and(|token| tokens_match(&token, admin_token)) {
+        return Err(ProxyError::Unauthorized("Missing or invalid admin token".to_string()));
+    }
+    Ok(())
+}
+
+/// Client keys from `ALLOWED_CLIENT_KEYS` (comma-separated) and from the file
+/// named by `ALLOWED_CLIENT_KEYS_FILE` (one per line, `#` starting a comment),
+/// accepted on top of `inbound_auth.tokens`
+pub fn keys_from_env() -> Result<Vec<String>, String> {
+    let mut keys: Vec<String> = env::var("ALLOWED_CLIENT_KEYS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    if let Ok(path) = env::var("ALLOWED_CLIENT_KEYS_FILE") {
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read ALLOWED_CLIENT_KEYS_FILE {path}: {e}"))?;
+        keys.extend(
+            contents
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|key| !key.is_empty())
+                .map(str::to_string),
+        );
+    }
+    Ok(keys)
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string)
+}
+
+/// Split a query string into the decoded value of `name` and the remaining
+/// pairs, which are kept in their original encoding
+fn take_query_param(query: &str, name: &str) -> (Option<String>, Option<String>) {
+    let mut token = None;
+    let mut remaining = Vec::new();
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        match form_urlencoded::parse(pair.as_bytes()).next() {
+            Some((key, value)) if key == name => token = Some(value.into_owned()),
+            _ => remaining.push(pair),
+        }
+    }
+
+    let remaining = (!remaining.is_empty()).then(|| remaining.join("&"));
+    (remaining, token)
+}
+
+fn replace_query(uri: &Uri, query: Option<&str>) -> Result<Uri, axum::http::Error> {
+    let path_and_query = match query {
+        Some(query) => format!("{}?{}", uri.path(), query),
+        None => uri.path().to_string(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse()?);
+    Ok(Uri::from_parts(parts)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {token}")).unwrap());
+        headers
+    }
+
+    #[test]
+    fn admin_token_unset_is_forbidden() {
+        let result = check_admin_token(None, &bearer("anything"));
+        assert!(matches!(result, Err(ProxyError::Forbidden(_))));
+    }
+
+    #[test]
+    fn missing_or_wrong_admin_token_is_unauthorized() {
+        for headers in [HeaderMap::new(), bearer("wrong"), bearer("secret-token ")] {
+            let result = check_admin_token(Some("secret-token"), &headers);
+            assert!(matches!(result, Err(ProxyError::Unauthorized(_))));
+        }
+        assert!(check_admin_token(Some("secret-token"), &bearer("secret-token")).is_ok());
+    }
+
+    #[test]
+    fn admin_token_is_checked_without_inbound_auth() {
+        crate::test_support::init_admin_token();
+        assert!(has_admin_token(&bearer(crate::test_support::ADMIN_TOKEN)));
+        assert!(!has_admin_token(&bearer("client-key")));
+        assert!(!has_admin_token(&HeaderMap::new()));
+    }
+}
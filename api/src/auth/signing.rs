@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::error::{ProxyError, create_error_response};
+use crate::proxy::config::RequestSigningConfig;
+use crate::request_id::request_id;
+
+pub const TIMESTAMP_HEADER: &str = "x-amp-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-amp-signature";
+
+/// Checks `x-amp-signature`, the hex HMAC-SHA256 of `x-amp-timestamp`
+/// followed by the raw request body, and remembers the signatures seen within
+/// the clock-skew window so a captured request cannot be replayed
+pub struct RequestVerifier {
+    secret: Vec<u8>,
+    max_skew: u64,
+    /// Accepted signatures and their timestamps, until they expire
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl RequestVerifier {
+    pub fn new(config: &RequestSigningConfig) -> Result<Self, String> {
+        Ok(Self {
+            secret: config.secret().map_err(|e| format!("request_signing: {e}"))?,
+            max_skew: config.max_clock_skew_secs,
+            seen: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Why the request must be rejected, if it must
+    fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), &'static str> {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        let (Some(timestamp), Some(signature)) = (header(TIMESTAMP_HEADER), header(SIGNATURE_HEADER)) else {
+            return Err("Missing request signature");
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let sent: u64 = timestamp.parse().map_err(|_| "Invalid request timestamp")?;
+        if sent.abs_diff(now) > self.max_skew {
+            return Err("Request timestamp outside the allowed clock skew");
+        }
+
+        let expected = hmac_sha256(&self.secret, &[timestamp.as_bytes(), body]);
+        let matches = hex::decode(signature).is_ok_and(|signature| {
+            signature.len() == expected.len()
+                && signature.iter().zip(expected).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+        });
+        if !matches {
+            return Err("Invalid request signature");
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, sent| *sent + self.max_skew >= now);
+        if seen.insert(signature.to_ascii_lowercase(), sent).is_some() {
+            return Err("Replayed request");
+        }
+        Ok(())
+    }
+}
+
+/// Require a valid signature over the raw body, before any handler parses
+/// it; the handler then gets the same bytes
+pub async fn verify_signature(State(verifier): State<Arc<RequestVerifier>>, req: Request, next: Next) -> Response {
+    let request_id = request_id(req.headers());
+    let (parts, body) = req.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(_) => {
+            let error = ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, "Unable to read request body".to_string());
+            return create_error_response(error, &request_id);
+        }
+    };
+
+    if let Err(reason) = verifier.verify(&parts.headers, &body) {
+        warn!("Rejecting {} {}: {}", parts.method, parts.uri.path(), reason);
+        return create_error_response(ProxyError::Unauthorized(reason.to_string()), &request_id);
+    }
+
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+/// HMAC-SHA256 (RFC 2104) of the concatenated `message` parts
+fn hmac_sha256(key: &[u8], message: &[&[u8]]) -> [u8; 32] {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    for part in message {
+        inner.update(part);
+    }
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, middleware, routing::post};
+
+    use crate::test_support::send;
+
+    const SECRET: &[u8] = b"signing-secret";
+
+    fn verifier() -> RequestVerifier {
+        RequestVerifier { secret: SECRET.to_vec(), max_skew: 300, seen: Mutex::new(HashMap::new()) }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    fn signed(secret: &[u8], timestamp: u64, body: &[u8]) -> HeaderMap {
+        let timestamp = timestamp.to_string();
+        let signature = hex::encode(hmac_sha256(secret, &[timestamp.as_bytes(), body]));
+        let mut headers = HeaderMap::new();
+        headers.insert(TIMESTAMP_HEADER, timestamp.parse().unwrap());
+        headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        let mac = hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+        assert_eq!(hex::encode(mac), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn verifies_signed_requests() {
+        let body = br#"{"thread":"T-1"}"#;
+        let skewed = Err("Request timestamp outside the allowed clock skew");
+        let cases = [
+            // (case, headers, body sent, verdict)
+            ("valid", signed(SECRET, now(), body), &body[..], Ok(())),
+            ("within skew", signed(SECRET, now() - 299, body), body, Ok(())),
+            ("expired timestamp", signed(SECRET, now() - 301, body), body, skewed),
+            ("future timestamp", signed(SECRET, now() + 301, body), body, skewed),
+            ("tampered body", signed(SECRET, now(), body), br#"{"thread":"T-2"}"#, Err("Invalid request signature")),
+            ("wrong secret", signed(b"other-secret", now(), body), body, Err("Invalid request signature")),
+            ("unsigned", HeaderMap::new(), body, Err("Missing request signature")),
+        ];
+        for (name, headers, sent_body, expected) in cases {
+            assert_eq!(verifier().verify(&headers, sent_body), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_replayed_signatures() {
+        let verifier = verifier();
+        let headers = signed(SECRET, now(), b"{}");
+        assert_eq!(verifier.verify(&headers, b"{}"), Ok(()));
+        assert_eq!(verifier.verify(&headers, b"{}"), Err("Replayed request"));
+    }
+
+    #[tokio::test]
+    async fn middleware_passes_the_verified_body_on() {
+        let router = Router::new()
+            .route("/echo", post(|body: String| async move { body }))
+            .layer(middleware::from_fn_with_state(Arc::new(verifier()), verify_signature));
+        let request = |headers: HeaderMap, body: &'static str| {
+            let mut req = Request::post("/echo").body(Body::from(body)).unwrap();
+            *req.headers_mut() = headers;
+            req
+        };
+
+        let (status, _, body) = send(&router, request(signed(SECRET, now(), b"hello"), "hello")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "hello");
+
+        let (status, _, _) = send(&router, request(signed(SECRET, now(), b"hello"), "hullo")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
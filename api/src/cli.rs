@@ -0,0 +1,163 @@
+use std::fs;
+
+use serde_json::{Value, json};
+
+use crate::proxy::config::Conversion;
+use crate::proxy::convert::{self, ChatToAnthropicStream, StreamConverter};
+use crate::proxy::sse::{SseEvent, SseParser};
+
+const CONVERT_USAGE: &str = "usage: amp-server convert --from <format> --to <format> (--input <file.json> | --stream <file.sse>)
+
+Conversions:
+  --from completions --to chat-completions       request body (--input)
+  --from chat-completions --to completions       response body (--input) or event stream (--stream)
+  --from chat-completions --to anthropic-messages  request body (--input) or event stream (--stream)
+  --from anthropic-messages --to chat-completions  response body (--input) or event stream (--stream)";
+
+/// `amp-server convert`: run the proxy's conversions on a recorded payload,
+/// offline. Prints the converted payload, or a JSON error body, and returns
+/// the process exit code.
+pub fn convert(args: &[String]) -> i32 {
+    match run_convert(args) {
+        Ok(output) => {
+            print!("{output}");
+            0
+        }
+        Err(ConvertError::Usage(message)) => {
+            eprintln!("{message}\n\n{CONVERT_USAGE}");
+            2
+        }
+        Err(ConvertError::Failed(message)) => {
+            println!("{}", json!({ "error": { "type": "conversion_error", "message": message } }));
+            1
+        }
+    }
+}
+
+enum ConvertError {
+    /// Bad command line
+    Usage(String),
+    /// The payload could not be read or converted
+    Failed(String),
+}
+
+impl From<String> for ConvertError {
+    fn from(message: String) -> Self {
+        ConvertError::Failed(message)
+    }
+}
+
+struct ConvertArgs {
+    from: String,
+    to: String,
+    input: Option<String>,
+    stream: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<ConvertArgs, ConvertError> {
+    let mut parsed = ConvertArgs { from: String::new(), to: String::new(), input: None, stream: None };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| ConvertError::Usage(format!("{flag} needs a value")));
+        match flag.as_str() {
+            "--from" => parsed.from = value()?,
+            "--to" => parsed.to = value()?,
+            "--input" => parsed.input = Some(value()?),
+            "--stream" => parsed.stream = Some(value()?),
+            _ => return Err(ConvertError::Usage(format!("unknown argument {flag}"))),
+        }
+    }
+    Ok(parsed)
+}
+
+fn run_convert(args: &[String]) -> Result<String, ConvertError> {
+    let args = parse_args(args)?;
+    match (args.from.as_str(), args.to.as_str(), args.input, args.stream) {
+        ("completions", "chat-completions", Some(input), None) => {
+            let converted = convert::completions_to_chat_request(&read_json(&input)?)?;
+            Ok(format!("{converted:#}\n"))
+        }
+        ("chat-completions", "completions", Some(input), None) => {
+            let converted = convert::chat_to_completions_response(&read_json(&input)?);
+            Ok(format!("{converted:#}\n"))
+        }
+        ("chat-completions", "completions", None, Some(stream)) => {
+            Ok(convert_stream(&stream, StreamConverter::new(Conversion::LegacyCompletions))?)
+        }
+        ("chat-completions", "anthropic-messages", Some(input), None) => {
+            let converted = convert::chat_to_anthropic_request(&read_json(&input)?)?;
+            Ok(format!("{converted:#}\n"))
+        }
+        ("chat-completions", "anthropic-messages", None, Some(stream)) => Ok(convert_stream_to_anthropic(&stream)?),
+        ("anthropic-messages", "chat-completions", Some(input), None) => {
+            let converted = convert::anthropic_to_chat_response(&read_json(&input)?);
+            Ok(format!("{converted:#}\n"))
+        }
+        ("anthropic-messages", "chat-completions", None, Some(stream)) => {
+            Ok(convert_stream(&stream, StreamConverter::new(Conversion::OpenaiToAnthropic))?)
+        }
+        (_, _, Some(_), Some(_)) => Err(ConvertError::Usage("--input and --stream are exclusive".to_string())),
+        (from, to, _, _) => Err(ConvertError::Usage(format!("unsupported conversion from {from:?} to {to:?}"))),
+    }
+}
+
+fn read_json(path: &str) -> Result<Value, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    serde_json::from_str(&text).map_err(|e| format!("{path} is not valid JSON: {e}"))
+}
+
+fn read_stream(path: &str) -> Result<Vec<SseEvent>, String> {
+    let transcript = fs::read(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    let mut parser = SseParser::default();
+    let mut events = parser.feed(&transcript);
+    events.extend(parser.finish());
+    Ok(events)
+}
+
+/// Convert a recorded upstream event stream event by event, the way
+/// converted endpoints do. Provider error events end the stream.
+fn convert_stream(path: &str, mut converter: StreamConverter) -> Result<String, String> {
+    let mut output = String::new();
+    for event in read_stream(path)? {
+        if let Some(error) = event.provider_error() {
+            output.push_str(&format!("event: error\ndata: {}\n\n", error.body("")));
+            break;
+        }
+        if event.data == "[DONE]" {
+            output.push_str("data: [DONE]\n\n");
+            continue;
+        }
+        let chunk: Value = serde_json::from_str(&event.data)
+            .map_err(|e| format!("stream event is not valid JSON ({e}): {}", event.data))?;
+        for data in converter.convert(&chunk) {
+            output.push_str(&format!("data: {data}\n\n"));
+        }
+    }
+    Ok(output)
+}
+
+/// Convert a recorded chat completions event stream into Anthropic Messages
+/// events, closing the message at `[DONE]` or the end of the recording
+fn convert_stream_to_anthropic(path: &str) -> Result<String, String> {
+    let mut converter = ChatToAnthropicStream::default();
+    let mut events = Vec::new();
+    for event in read_stream(path)? {
+        if let Some(error) = event.provider_error() {
+            events.push(json!({ "type": "error", "error": { "type": error.error_type(), "message": error.message() } }));
+            break;
+        }
+        if event.data == "[DONE]" {
+            events.extend(converter.finish());
+            continue;
+        }
+        let chunk: Value = serde_json::from_str(&event.data)
+            .map_err(|e| format!("stream event is not valid JSON ({e}): {}", event.data))?;
+        events.extend(converter.convert(&chunk));
+    }
+    events.extend(converter.finish());
+
+    Ok(events
+        .iter()
+        .map(|event| format!("event: {}\ndata: {event}\n\n", event["type"].as_str().unwrap_or_default()))
+        .collect())
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use tracing::warn;
+
+use crate::error::{ProxyError, create_error_response};
+use crate::request_id::request_id;
+
+/// Answer 504 when a handler has not produced a response within `deadline`,
+/// covering conversion and buffering paths that have no upstream timeout of
+/// their own. Streaming bodies are not limited once their headers are sent.
+pub async fn enforce_deadline(State(deadline): State<Duration>, req: Request, next: Next) -> Response {
+    let request_id = request_id(req.headers());
+    let path = req.uri().path().to_string();
+
+    match tokio::time::timeout(deadline, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!("Request to {} did not complete within {}s", path, deadline.as_secs());
+            let error = ProxyError::TimeoutError(format!("Request did not complete within {}s", deadline.as_secs()));
+            create_error_response(error, &request_id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::StatusCode, middleware, routing::get};
+    use serde_json::Value;
+
+    use crate::test_support::send;
+
+    fn router(deadline: Duration) -> Router {
+        Router::new()
+            .route("/slow", get(|| async {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                "late"
+            }))
+            .route("/fast", get(|| async { "on time" }))
+            .route("/stream", get(|| async {
+                let chunks = async_stream::stream! {
+                    for chunk in ["a", "b"] {
+                        tokio::time::sleep(Duration::from_millis(150)).await;
+                        yield Ok::<_, std::convert::Infallible>(chunk);
+                    }
+                };
+                Body::from_stream(chunks)
+            }))
+            .layer(middleware::from_fn_with_state(deadline, enforce_deadline))
+    }
+
+    fn get_request(uri: &str) -> Request {
+        Request::get(uri).header("x-request-id", "req-1").body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn slow_handlers_are_answered_504() {
+        let router = router(Duration::from_millis(200));
+
+        let (status, _, body) = send(&router, get_request("/slow")).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"]["type"], "timeout_error");
+
+        let (status, _, body) = send(&router, get_request("/fast")).await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"on time"[..]));
+
+        // A body streaming past the deadline is not cut once its headers are out
+        let (status, _, body) = send(&router, get_request("/stream")).await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"ab"[..]));
+    }
+}
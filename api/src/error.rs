@@ -0,0 +1,113 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde_json::{Value, json};
+use std::fmt;
+
+/// Errors reported to clients as structured JSON
+#[derive(Debug)]
+pub enum ProxyError {
+    /// The client's request cannot be forwarded as sent (`400`, `413`, `431`)
+    InvalidRequest(StatusCode, String),
+    /// The request's estimated input does not fit the model's context window
+    ContextLengthExceeded { estimated_tokens: usize, context_window: usize, message: String },
+    /// The caller sent no valid access token
+    Unauthorized(String),
+    /// The caller is not allowed to make this request
+    Forbidden(String),
+    /// No endpoint serves the request
+    NotFound(String),
+    /// The proxy failed on its own, independently of the upstream
+    Internal(String),
+    /// The upstream did not answer, or stopped sending, within the endpoint timeout
+    TimeoutError(String),
+    /// The upstream answered with an error status and a body that is not JSON
+    UpstreamError(StatusCode, String),
+    /// The caller exceeded the endpoint's rate limit
+    RateLimited(String),
+    /// The endpoint has `max_concurrent` requests in flight
+    Overloaded(String),
+    /// The upstream reported an error inside an already started stream; the
+    /// provider's error type is kept so clients can tell retriable ones
+    StreamError { error_type: String, message: String },
+}
+
+impl ProxyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProxyError::InvalidRequest(status, _) => *status,
+            ProxyError::ContextLengthExceeded { .. } => StatusCode::BAD_REQUEST,
+            ProxyError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ProxyError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ProxyError::NotFound(_) => StatusCode::NOT_FOUND,
+            ProxyError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ProxyError::TimeoutError(_) => StatusCode::GATEWAY_TIMEOUT,
+            ProxyError::UpstreamError(status, _) => *status,
+            ProxyError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ProxyError::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyError::StreamError { .. } => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    pub fn error_type(&self) -> &str {
+        match self {
+            ProxyError::InvalidRequest(..) | ProxyError::ContextLengthExceeded { .. } => "invalid_request_error",
+            ProxyError::Unauthorized(_) => "authentication_error",
+            ProxyError::Forbidden(_) => "permission_error",
+            ProxyError::NotFound(_) => "not_found_error",
+            ProxyError::Internal(_) => "api_error",
+            ProxyError::TimeoutError(_) => "timeout_error",
+            ProxyError::UpstreamError(..) => "upstream_error",
+            ProxyError::RateLimited(_) => "rate_limit_error",
+            ProxyError::Overloaded(_) => "overloaded_error",
+            ProxyError::StreamError { error_type, .. } => error_type,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ProxyError::InvalidRequest(_, message)
+            | ProxyError::Unauthorized(message)
+            | ProxyError::Forbidden(message)
+            | ProxyError::NotFound(message)
+            | ProxyError::Internal(message)
+            | ProxyError::TimeoutError(message)
+            | ProxyError::UpstreamError(_, message)
+            | ProxyError::RateLimited(message)
+            | ProxyError::Overloaded(message)
+            | ProxyError::StreamError { message, .. }
+            | ProxyError::ContextLengthExceeded { message, .. } => message,
+        }
+    }
+
+    /// Error body, shared by JSON responses and SSE error events
+    pub fn body(&self, request_id: &str) -> Value {
+        let mut body = json!({
+            "error": {
+                "type": self.error_type(),
+                "message": self.message(),
+                "request_id": request_id,
+            }
+        });
+        // Same code and param as OpenAI, so clients handle it the same way
+        if let ProxyError::ContextLengthExceeded { estimated_tokens, context_window, .. } = self {
+            body["error"]["code"] = json!("context_length_exceeded");
+            body["error"]["param"] = json!("messages");
+            body["error"]["estimated_tokens"] = json!(estimated_tokens);
+            body["error"]["context_window"] = json!(context_window);
+        }
+        body
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.error_type(), self.message())
+    }
+}
+
+pub fn create_error_response(error: ProxyError, request_id: &str) -> Response {
+    (error.status(), Json(error.body(request_id))).into_response()
+}
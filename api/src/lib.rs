@@ -1,25 +1,70 @@
+#[cfg(feature = "storage")]
 mod user;
+#[cfg(feature = "telemetry-sink")]
 mod telemetry;
 mod proxy;
+mod auth;
+mod cli;
+mod metrics;
+#[cfg(feature = "admin")]
+mod admin;
+mod error;
+mod deadline;
+mod request_id;
+mod shutdown;
+#[cfg(feature = "tls")]
+mod tls;
+mod usage;
+#[cfg(test)]
+mod test_support;
 
 use anyhow::Result;
-use axum::Router;
+use axum::{Router, middleware};
 use std::env;
-use std::sync::OnceLock;
+use std::future::{Future, IntoFuture};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use tokio::signal;
 use tower::ServiceBuilder;
+use tower_http::request_id::{PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
-use tracing::{error, info};
+use tracing::{error, info, info_span, warn};
 use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 
+use crate::metrics::ProxyMetrics;
+use crate::proxy::config::InboundAuthConfig;
 use crate::proxy::{ProxyConfig, ProxyService};
+#[cfg(unix)]
+use crate::proxy::reload::LiveConfig;
+use crate::proxy::reload::refresh_periodically;
+use crate::request_id::{MakeRequestUlid, REQUEST_ID_HEADER};
 
 static AMP_API_KEY: OnceLock<String> = OnceLock::new();
 
+/// Key of the Mistral endpoints, unset when `MISTRAL_API_KEY` is empty
+static MISTRAL_API_KEY: OnceLock<Option<String>> = OnceLock::new();
+
+/// Token of the admin-only endpoints, unset when `ADMIN_TOKEN` is empty
+static ADMIN_TOKEN: OnceLock<Option<String>> = OnceLock::new();
+
+/// Configuration file read when `PROXY_CONFIG` is unset or cannot be loaded
+const DEFAULT_CONFIG_FILE: &str = "proxy_config.yaml";
+
 pub fn get_amp_api_key() -> &'static str {
     AMP_API_KEY.get().expect("AMP_API_KEY not initialized")
 }
 
+pub fn get_mistral_api_key() -> Option<&'static str> {
+    MISTRAL_API_KEY.get().expect("MISTRAL_API_KEY not initialized").as_deref()
+}
+
+pub fn get_admin_token() -> Option<&'static str> {
+    ADMIN_TOKEN.get().expect("ADMIN_TOKEN not initialized").as_deref()
+}
+
 #[tokio::main]
 async fn start() -> Result<()> {
     // Initialize tracing
@@ -35,35 +80,341 @@ async fn start() -> Result<()> {
     let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
     let amp_api_key = env::var("AMP_API_KEY").unwrap_or_else(|_| "sk-wxzIs8AEsu7RCSZbnSqdH4efdUyEXh61LgmlP4MdzRGo9bGt".to_string());
     AMP_API_KEY.set(amp_api_key).expect("AMP_API_KEY already initialized");
+    let mistral_api_key = env::var("MISTRAL_API_KEY").ok().filter(|key| !key.is_empty());
+    MISTRAL_API_KEY.set(mistral_api_key).expect("MISTRAL_API_KEY already initialized");
+    let admin_token = env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+    ADMIN_TOKEN.set(admin_token).expect("ADMIN_TOKEN already initialized");
     let server_url = format!("{host}:{port}");
     
-    // Load proxy configuration
-    let proxy_config = ProxyConfig::load_from_file("proxy_config.yaml")
+    // Load proxy configuration: PROXY_CONFIG (file or URL), local YAML file,
+    // then environment, then defaults
+    let proxy_config = Some(config_source())
+        .filter(|source| source != DEFAULT_CONFIG_FILE)
+        .and_then(|source| {
+            ProxyConfig::load_from_file(&source)
+                .inspect(|_| info!("Loaded proxy configuration from {}", source))
+                .inspect_err(|e| warn!("Could not load proxy configuration from {}: {}", source, e))
+                .ok()
+        })
+        .map_or_else(|| ProxyConfig::load_from_file(DEFAULT_CONFIG_FILE), Ok)
+        .or_else(|file_err| {
+            ProxyConfig::load_from_env().inspect(|_| {
+                info!("Loaded proxy configuration from environment ({})", file_err);
+            })
+        })
         .unwrap_or_else(|e| {
             info!("Using default proxy configuration ({})", e);
-            ProxyConfig::default()
+            ProxyConfig::default_from_env()
         });
     
+    let mut inbound_auth = proxy_config.inbound_auth.clone();
+    let client_keys = auth::keys_from_env().map_err(anyhow::Error::msg)?;
+    if !client_keys.is_empty() {
+        info!("Accepting {} client keys from the environment", client_keys.len());
+        let auth_config = inbound_auth.get_or_insert_with(|| InboundAuthConfig {
+            tokens: Vec::new(),
+            clients: Vec::new(),
+            admin_tokens: Vec::new(),
+            query_param: None,
+        });
+        auth_config.tokens.extend(client_keys);
+        auth_config.validate().map_err(|e| anyhow::anyhow!("inbound_auth: {e}"))?;
+    }
+    // The admin token gets past client authentication on the gated endpoints
+    if let Some(admin_token) = get_admin_token()
+        && let Some(auth_config) = &mut inbound_auth
+    {
+        auth_config.admin_tokens.push(admin_token.to_string());
+    }
+    let inbound_auth = inbound_auth.map(Arc::new);
+    let request_deadline = proxy_config.request_deadline();
+    let cors = proxy_config.cors.clone();
+    #[cfg(feature = "storage")]
+    let request_signing = proxy_config.request_signing.clone();
+    #[cfg(feature = "storage")]
+    let default_user = proxy_config.user.clone();
+
+    // Certificates are read before binding so a bad one stops startup
+    #[cfg(feature = "tls")]
+    let tls = match tls::TlsSettings::from_env().map_err(anyhow::Error::msg)? {
+        Some(settings) => {
+            let config = settings.server_config().map_err(anyhow::Error::msg)?;
+            Some((settings, axum_server::tls_rustls::RustlsConfig::from_config(config)))
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "tls"))]
+    if env::var_os("TLS_CERT_PATH").is_some() || env::var_os("TLS_KEY_PATH").is_some() {
+        anyhow::bail!("TLS_CERT_PATH and TLS_KEY_PATH need a build with the `tls` feature");
+    }
+    #[cfg(not(feature = "storage"))]
+    if env::var_os("THREAD_STORE_PATH").is_some() || env::var_os("AMP_USER_PROFILE_PATH").is_some() {
+        anyhow::bail!("THREAD_STORE_PATH and AMP_USER_PROFILE_PATH need a build with the `storage` feature");
+    }
+    #[cfg(not(feature = "telemetry-sink"))]
+    if env::var_os("TELEMETRY_DIR").is_some() || env::var_os("TELEMETRY_RETENTION_DAYS").is_some() {
+        anyhow::bail!("TELEMETRY_DIR and TELEMETRY_RETENTION_DAYS need a build with the `telemetry-sink` feature");
+    }
+
     // Create proxy service
-    let proxy_service = ProxyService::new(proxy_config);
+    let metrics = Arc::new(ProxyMetrics::new());
+    let proxy_service = ProxyService::new(proxy_config, metrics.clone()).map_err(anyhow::Error::msg)?;
+    proxy_service.slo().spawn();
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(proxy_service.live_config()));
+    if let Some(interval) = config_refresh_interval() {
+        info!("Refreshing proxy configuration every {}s", interval.as_secs());
+        tokio::spawn(refresh_periodically(proxy_service.live_config(), config_source(), interval));
+    }
+
+    let mut proxy_router = proxy_service.create_router();
+    #[cfg(feature = "admin")]
+    {
+        proxy_router = proxy_router.merge(admin::router(proxy_service.live_config(), proxy_service.slo()));
+    }
+    if let Some(auth_config) = &inbound_auth {
+        info!("Inbound client authentication enabled for proxy, user, telemetry, usage, metrics and admin endpoints");
+        proxy_router = proxy_router.layer(middleware::from_fn_with_state(
+            auth_config.clone(),
+            auth::require_client_token,
+        ));
+    }
     
-    // Initialize router
-    let app = Router::new()
-        .merge(user::router())
-        .merge(telemetry::router())
-        .merge(proxy_service.create_router())
-        .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()));
+    // Initialize router; proxy routes carry their own (possibly overridden) CORS policy
+    let mut app = Router::new();
+
+    // Detailed health stays open to health checks
+    #[cfg(feature = "admin")]
+    {
+        app = app.merge(admin::health_router(
+            proxy_service.live_config(),
+            proxy_service.slo(),
+            proxy_service.circuits(),
+            admin::TlsStatus {
+                #[cfg(feature = "tls")]
+                active: tls.is_some(),
+                #[cfg(not(feature = "tls"))]
+                active: false,
+                #[cfg(feature = "tls")]
+                client_auth: tls.as_ref().is_some_and(|(settings, _)| settings.client_auth()),
+                #[cfg(not(feature = "tls"))]
+                client_auth: false,
+            },
+        ));
+    }
+
+    // User endpoints answer clients with their own identity
+    #[cfg(feature = "storage")]
+    {
+        let mut user_router = user::router(&default_user).map_err(anyhow::Error::msg)?;
+        if let Some(signing) = &request_signing {
+            info!("Request signatures required on user and thread endpoints");
+            let verifier = Arc::new(auth::signing::RequestVerifier::new(signing).map_err(anyhow::Error::msg)?);
+            user_router = user_router.layer(middleware::from_fn_with_state(verifier, auth::signing::verify_signature));
+        }
+        if let Some(auth_config) = &inbound_auth {
+            user_router = user_router.layer(middleware::from_fn_with_state(
+                auth_config.clone(),
+                auth::require_client_token,
+            ));
+        }
+        app = app.merge(user_router);
+    }
+    #[cfg(feature = "telemetry-sink")]
+    let telemetry_sink = {
+        let (mut telemetry_router, sink) = telemetry::router(metrics.clone()).map_err(anyhow::Error::msg)?;
+        if let Some(auth_config) = &inbound_auth {
+            telemetry_router = telemetry_router.layer(middleware::from_fn_with_state(
+                auth_config.clone(),
+                auth::require_client_token,
+            ));
+        }
+        app = app.merge(telemetry_router);
+        sink
+    };
+    {
+        let mut usage_router = usage::router(proxy_service.usage(), proxy_service.live_config());
+        if let Some(auth_config) = &inbound_auth {
+            usage_router = usage_router.layer(middleware::from_fn_with_state(
+                auth_config.clone(),
+                auth::require_client_token,
+            ));
+        }
+        app = app.merge(usage_router);
+    }
+    #[cfg(feature = "metrics")]
+    {
+        let mut metrics_router = metrics::router(metrics);
+        if let Some(auth_config) = &inbound_auth {
+            metrics_router = metrics_router.layer(middleware::from_fn_with_state(
+                auth_config.clone(),
+                auth::require_client_token,
+            ));
+        }
+        app = app.merge(metrics_router);
+    }
+    if let Some(cors) = &cors {
+        app = app.layer(cors.layer());
+    }
+
+    let app = app
+        .merge(proxy_router)
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::new(REQUEST_ID_HEADER, MakeRequestUlid))
+                .layer(TraceLayer::new_for_http().make_span_with(|request: &axum::extract::Request| {
+                    let request_id = request
+                        .headers()
+                        .get(REQUEST_ID_HEADER)
+                        .and_then(|id| id.to_str().ok())
+                        .unwrap_or_default();
+                    info_span!(
+                        "request",
+                        method = %request.method(),
+                        // The query may carry a client token and is never recorded
+                        path = %request.uri().path(),
+                        request_id = %request_id,
+                    )
+                }))
+                .layer(PropagateRequestIdLayer::new(REQUEST_ID_HEADER))
+                .layer(middleware::from_fn_with_state(request_deadline, deadline::enforce_deadline)),
+        );
 
     // Start server
     let listener = tokio::net::TcpListener::bind(&server_url).await?;
-    info!("Listening on {}", server_url);
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    let shutdown = proxy_service.shutdown();
+    let usage = proxy_service.usage();
+    let drain = shutdown_drain_timeout();
+    let (draining, drain_started) = tokio::sync::oneshot::channel();
+    let signalled = async move {
+        shutdown_signal().await;
+        let _ = draining.send(());
+    };
+    let mut server = serve(
+        listener,
+        app,
+        signalled,
+        &server_url,
+        #[cfg(feature = "tls")]
+        tls,
+    )?;
+
+    // New connections stop at the signal; in-flight requests get the drain
+    // window, after which streams still running are cut
+    tokio::select! {
+        result = &mut server => result?,
+        Ok(()) = drain_started => {
+            let in_flight = shutdown.begin_drain();
+            info!("Draining {} in-flight requests for up to {}s", in_flight, drain.as_secs());
+            tokio::select! {
+                result = &mut server => result?,
+                () = tokio::time::sleep(drain) => {
+                    warn!("Drain window over, cutting the remaining streams");
+                    shutdown.cut();
+                    if let Ok(result) = tokio::time::timeout(SHUTDOWN_CUT_GRACE, &mut server).await {
+                        result?;
+                    }
+                }
+            }
+            let (drained, aborted) = shutdown.summary();
+            info!("Shutdown complete: {} requests drained, {} aborted", drained, aborted);
+        }
+    }
+    usage.persist();
+    #[cfg(feature = "telemetry-sink")]
+    telemetry_sink.flush().await;
 
     Ok(())
 }
 
+type ServerFuture = Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+
+/// Serve `app` on `listener`, over TLS when configured, until `signalled`
+/// resolves and the open connections have closed
+fn serve(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    signalled: impl Future<Output = ()> + Send + 'static,
+    server_url: &str,
+    #[cfg(feature = "tls")] tls: Option<(tls::TlsSettings, axum_server::tls_rustls::RustlsConfig)>,
+) -> Result<ServerFuture> {
+    let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    #[cfg(feature = "tls")]
+    if let Some((settings, config)) = tls {
+        let client_auth = if settings.client_auth() { ", client certificates required" } else { "" };
+        info!("Listening on {} (TLS{})", server_url, client_auth);
+        #[cfg(unix)]
+        tokio::spawn(tls::reload_on_hangup(settings, config.clone()));
+
+        let handle = axum_server::Handle::new();
+        let stopping = handle.clone();
+        tokio::spawn(async move {
+            signalled.await;
+            stopping.graceful_shutdown(None);
+        });
+        let server = axum_server::from_tcp_rustls(listener.into_std()?, config)
+            .handle(handle)
+            .serve(make_service);
+        return Ok(Box::pin(server));
+    }
+
+    info!("Listening on {}", server_url);
+    Ok(Box::pin(axum::serve(listener, make_service).with_graceful_shutdown(signalled).into_future()))
+}
+
+/// Time for cut streams to send their final event before the server stops
+const SHUTDOWN_CUT_GRACE: Duration = Duration::from_secs(1);
+
+/// `SHUTDOWN_DRAIN_SECONDS`: how long in-flight requests may run after a
+/// termination signal (default: 30)
+fn shutdown_drain_timeout() -> Duration {
+    let Ok(value) = env::var("SHUTDOWN_DRAIN_SECONDS") else {
+        return Duration::from_secs(30);
+    };
+    value.parse().map(Duration::from_secs).unwrap_or_else(|_| {
+        warn!("Ignoring SHUTDOWN_DRAIN_SECONDS={}: expected a number of seconds", value);
+        Duration::from_secs(30)
+    })
+}
+
+/// Where the proxy configuration is read from, at startup and on reload
+fn config_source() -> String {
+    env::var("PROXY_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_string())
+}
+
+/// Reload the proxy configuration on every SIGHUP. Requests started before
+/// the reload finish with the old configuration.
+#[cfg(unix)]
+async fn reload_on_hangup(config: Arc<LiveConfig>) {
+    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+        .expect("failed to install SIGHUP handler");
+
+    while hangup.recv().await.is_some() {
+        let source = config_source();
+        info!("Received SIGHUP, reloading proxy configuration from {}", source);
+        let config = config.clone();
+        let reloaded = tokio::task::spawn_blocking(move || config.reload_from(&source)).await;
+        match reloaded {
+            Ok(Ok(version)) => info!("Proxy configuration reloaded (version {})", version),
+            Ok(Err(e)) => error!("Keeping the current proxy configuration: {}", e),
+            Err(e) => error!("Proxy configuration reload failed: {}", e),
+        }
+    }
+}
+
+/// `CONFIG_REFRESH_SECS`, when set to a positive number of seconds
+fn config_refresh_interval() -> Option<Duration> {
+    let value = env::var("CONFIG_REFRESH_SECS").ok()?;
+    match value.parse::<u64>() {
+        Ok(secs) if secs > 0 => Some(Duration::from_secs(secs)),
+        _ => {
+            warn!("Ignoring CONFIG_REFRESH_SECS={}: expected a positive number of seconds", value);
+            None
+        }
+    }
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -91,6 +442,11 @@ async fn shutdown_signal() {
 }
 
 pub fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "convert") {
+        std::process::exit(cli::convert(&args[1..]));
+    }
+
     let result = start();
     if let Err(err) = result {
         error!("Error: {err}");
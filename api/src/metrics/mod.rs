@@ -0,0 +1,11 @@
+// Without the `metrics` feature the recording calls compile to nothing and
+// `/metrics` is not served
+#[cfg(feature = "metrics")]
+mod registry;
+#[cfg(feature = "metrics")]
+pub use registry::{InFlightGuard, ProxyMetrics, router};
+
+#[cfg(not(feature = "metrics"))]
+mod noop;
+#[cfg(not(feature = "metrics"))]
+pub use noop::{InFlightGuard, ProxyMetrics};
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use axum::http::StatusCode;
+
+use crate::proxy::timing::UpstreamPhases;
+
+/// Stand-in for the Prometheus metrics when the `metrics` feature is off
+#[derive(Default)]
+pub struct ProxyMetrics;
+
+impl ProxyMetrics {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn record_request(&self, _path: &str, _status: StatusCode) {}
+
+    pub fn observe_request_duration(&self, _path: &str, _elapsed: Duration) {}
+
+    pub fn record_upstream_error(&self, _path: &str, _status: Option<StatusCode>) {}
+
+    pub fn record_stream_error(&self, _path: &str, _error_type: &str) {}
+
+    pub fn record_conversion_failure(&self, _path: &str, _conversion: &str, _reason: &str) {}
+
+    pub fn record_cache_result(&self, _path: &str, _result: &str) {}
+
+    #[cfg_attr(not(feature = "telemetry-sink"), allow(dead_code))]
+    pub fn record_dropped_telemetry(&self, _count: u64) {}
+
+    pub fn record_shadow_request(&self, _path: &str, _status: Option<StatusCode>) {}
+
+    pub fn record_shadow_tokens(&self, _path: &str, _input: u64, _output: u64) {}
+
+    pub fn observe_upstream_latency(&self, _path: &str, _elapsed: Duration) {}
+
+    pub fn observe_upstream_phases(&self, _host: &str, _phases: &UpstreamPhases) {}
+
+    pub fn observe_timing_header(&self, _path: &str, _header: &str, _seconds: f64) {}
+
+    pub fn observe_time_to_first_byte(&self, _path: &str, _elapsed: Duration) {}
+
+    pub fn track_in_flight(&self, _path: &str) -> InFlightGuard {
+        InFlightGuard
+    }
+}
+
+pub struct InFlightGuard;
@@ -0,0 +1,345 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use tracing::error;
+
+use crate::auth::admin_auth;
+use crate::error::{ProxyError, create_error_response};
+use crate::proxy::timing::UpstreamPhases;
+use crate::request_id::request_id;
+
+/// Latency buckets in seconds, from fast completions to long generations
+const LATENCY_BUCKETS: &[f64] = &[
+    0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+];
+
+/// Proxy traffic metrics. Labels use the configured endpoint path rather than
+/// the raw request URI to keep cardinality bounded.
+pub struct ProxyMetrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    upstream_errors: IntCounterVec,
+    stream_errors: IntCounterVec,
+    conversion_failures: IntCounterVec,
+    response_cache: IntCounterVec,
+    upstream_latency: HistogramVec,
+    upstream_phases: HistogramVec,
+    upstream_timing_headers: HistogramVec,
+    time_to_first_byte: HistogramVec,
+    in_flight: IntGaugeVec,
+    telemetry_dropped: IntCounter,
+    shadow_requests: IntCounterVec,
+    shadow_tokens: IntCounterVec,
+}
+
+impl ProxyMetrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let requests = IntCounterVec::new(
+            Opts::new("amp_proxy_requests_total", "Proxied requests by endpoint and response status"),
+            &["path", "status"],
+        )
+        .expect("valid metric definition");
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "amp_proxy_request_duration_seconds",
+                "Time until the proxy response (headers, for streams) is ready",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["path"],
+        )
+        .expect("valid metric definition");
+        let upstream_errors = IntCounterVec::new(
+            Opts::new(
+                "amp_proxy_upstream_errors_total",
+                "Failed upstream exchanges by endpoint and upstream status (`transport` when no response arrived)",
+            ),
+            &["path", "status"],
+        )
+        .expect("valid metric definition");
+        let stream_errors = IntCounterVec::new(
+            Opts::new(
+                "amp_proxy_stream_errors_total",
+                "Streams ended by an error event from upstream, by endpoint and provider error type",
+            ),
+            &["path", "error_type"],
+        )
+        .expect("valid metric definition");
+        let conversion_failures = IntCounterVec::new(
+            Opts::new(
+                "amp_proxy_conversion_failures_total",
+                "Requests, responses and stream chunks that could not be converted, by endpoint, conversion and reason",
+            ),
+            &["path", "conversion", "reason"],
+        )
+        .expect("valid metric definition");
+        let response_cache = IntCounterVec::new(
+            Opts::new(
+                "amp_proxy_response_cache_total",
+                "Response cache hits, misses and evictions of expired entries, by endpoint",
+            ),
+            &["path", "result"],
+        )
+        .expect("valid metric definition");
+        let upstream_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "amp_proxy_upstream_latency_seconds",
+                "Time until upstream response headers arrive",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["path"],
+        )
+        .expect("valid metric definition");
+        let upstream_phases = HistogramVec::new(
+            HistogramOpts::new(
+                "amp_proxy_upstream_phase_seconds",
+                "Upstream DNS lookup, connect (TCP and TLS) and time to response headers, by upstream host; \
+                 requests over reused connections have no dns or connect sample",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["host", "phase"],
+        )
+        .expect("valid metric definition");
+        let upstream_timing_headers = HistogramVec::new(
+            HistogramOpts::new(
+                "amp_proxy_upstream_timing_header_seconds",
+                "Durations reported by the upstream in the configured `upstream_timing_headers`, by endpoint and header",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["path", "header"],
+        )
+        .expect("valid metric definition");
+        let time_to_first_byte = HistogramVec::new(
+            HistogramOpts::new(
+                "amp_proxy_time_to_first_byte_seconds",
+                "Time until the first streamed body chunk arrives from upstream",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["path"],
+        )
+        .expect("valid metric definition");
+        let in_flight = IntGaugeVec::new(
+            Opts::new("amp_proxy_in_flight_requests", "Proxied requests currently in flight"),
+            &["path"],
+        )
+        .expect("valid metric definition");
+        let telemetry_dropped = IntCounter::new(
+            "amp_telemetry_dropped_events_total",
+            "Received telemetry events that could not be stored",
+        )
+        .expect("valid metric definition");
+        let shadow_requests = IntCounterVec::new(
+            Opts::new(
+                "amp_shadow_requests_total",
+                "Requests mirrored to shadow upstreams, by endpoint and shadow status (`transport` when no response arrived)",
+            ),
+            &["path", "status"],
+        )
+        .expect("valid metric definition");
+        let shadow_tokens = IntCounterVec::new(
+            Opts::new(
+                "amp_shadow_tokens_total",
+                "Tokens reported by shadow upstreams, by endpoint and kind (`input` or `output`)",
+            ),
+            &["path", "kind"],
+        )
+        .expect("valid metric definition");
+
+        registry.register(Box::new(requests.clone())).expect("metric registered once");
+        registry.register(Box::new(request_duration.clone())).expect("metric registered once");
+        registry.register(Box::new(upstream_errors.clone())).expect("metric registered once");
+        registry.register(Box::new(stream_errors.clone())).expect("metric registered once");
+        registry.register(Box::new(conversion_failures.clone())).expect("metric registered once");
+        registry.register(Box::new(response_cache.clone())).expect("metric registered once");
+        registry.register(Box::new(upstream_latency.clone())).expect("metric registered once");
+        registry.register(Box::new(upstream_phases.clone())).expect("metric registered once");
+        registry.register(Box::new(upstream_timing_headers.clone())).expect("metric registered once");
+        registry.register(Box::new(time_to_first_byte.clone())).expect("metric registered once");
+        registry.register(Box::new(in_flight.clone())).expect("metric registered once");
+        registry.register(Box::new(telemetry_dropped.clone())).expect("metric registered once");
+        registry.register(Box::new(shadow_requests.clone())).expect("metric registered once");
+        registry.register(Box::new(shadow_tokens.clone())).expect("metric registered once");
+
+        Self {
+            registry,
+            requests,
+            request_duration,
+            upstream_errors,
+            stream_errors,
+            conversion_failures,
+            response_cache,
+            upstream_latency,
+            upstream_phases,
+            upstream_timing_headers,
+            time_to_first_byte,
+            in_flight,
+            telemetry_dropped,
+            shadow_requests,
+            shadow_tokens,
+        }
+    }
+
+    pub fn record_request(&self, path: &str, status: StatusCode) {
+        self.requests.with_label_values(&[path, status.as_str()]).inc();
+    }
+
+    pub fn observe_request_duration(&self, path: &str, elapsed: Duration) {
+        self.request_duration.with_label_values(&[path]).observe(elapsed.as_secs_f64());
+    }
+
+    /// Count an upstream error status, or a transport failure when `status` is `None`
+    pub fn record_upstream_error(&self, path: &str, status: Option<StatusCode>) {
+        let status = status.as_ref().map_or("transport", StatusCode::as_str);
+        self.upstream_errors.with_label_values(&[path, status]).inc();
+    }
+
+    /// Count a stream ended by an upstream error event
+    pub fn record_stream_error(&self, path: &str, error_type: &str) {
+        self.stream_errors.with_label_values(&[path, error_type]).inc();
+    }
+
+    /// Count a payload a conversion could not handle
+    pub fn record_conversion_failure(&self, path: &str, conversion: &str, reason: &str) {
+        self.conversion_failures.with_label_values(&[path, conversion, reason]).inc();
+    }
+
+    /// Count a response cache `hit`, `miss` or `eviction`
+    pub fn record_cache_result(&self, path: &str, result: &str) {
+        self.response_cache.with_label_values(&[path, result]).inc();
+    }
+
+    /// Count telemetry events that could not be stored
+    #[cfg_attr(not(feature = "telemetry-sink"), allow(dead_code))]
+    pub fn record_dropped_telemetry(&self, count: u64) {
+        self.telemetry_dropped.inc_by(count);
+    }
+
+    /// Count a shadow response status, or a transport failure when `status` is `None`
+    pub fn record_shadow_request(&self, path: &str, status: Option<StatusCode>) {
+        let status = status.as_ref().map_or("transport", StatusCode::as_str);
+        self.shadow_requests.with_label_values(&[path, status]).inc();
+    }
+
+    /// Count the tokens a shadow upstream reported, apart from client traffic
+    pub fn record_shadow_tokens(&self, path: &str, input: u64, output: u64) {
+        self.shadow_tokens.with_label_values(&[path, "input"]).inc_by(input);
+        self.shadow_tokens.with_label_values(&[path, "output"]).inc_by(output);
+    }
+
+    pub fn observe_upstream_latency(&self, path: &str, elapsed: Duration) {
+        self.upstream_latency.with_label_values(&[path]).observe(elapsed.as_secs_f64());
+    }
+
+    /// Record the phases of an upstream exchange; only set with `upstream_phase_metrics`
+    pub fn observe_upstream_phases(&self, host: &str, phases: &UpstreamPhases) {
+        let samples = [("dns", phases.dns), ("connect", phases.connect), ("headers", phases.headers)];
+        for (phase, elapsed) in samples {
+            if let Some(elapsed) = elapsed {
+                self.upstream_phases.with_label_values(&[host, phase]).observe(elapsed.as_secs_f64());
+            }
+        }
+    }
+
+    /// Record a duration the upstream reported in a response header
+    pub fn observe_timing_header(&self, path: &str, header: &str, seconds: f64) {
+        self.upstream_timing_headers.with_label_values(&[path, header]).observe(seconds);
+    }
+
+    pub fn observe_time_to_first_byte(&self, path: &str, elapsed: Duration) {
+        self.time_to_first_byte.with_label_values(&[path]).observe(elapsed.as_secs_f64());
+    }
+
+    /// Count a request as in flight until the returned guard is dropped
+    pub fn track_in_flight(&self, path: &str) -> InFlightGuard {
+        let gauge = self.in_flight.with_label_values(&[path]);
+        gauge.inc();
+        InFlightGuard { gauge }
+    }
+
+    fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+impl Default for ProxyMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decrements the in-flight gauge when dropped; streaming handlers move it
+/// into the response body so streams count until they finish
+pub struct InFlightGuard {
+    gauge: IntGauge,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+pub fn router(metrics: Arc<ProxyMetrics>) -> Router {
+    Router::new()
+        .route("/metrics", get(export_metrics))
+        .layer(middleware::from_fn(admin_auth))
+        .with_state(metrics)
+}
+
+async fn export_metrics(State(metrics): State<Arc<ProxyMetrics>>, headers: HeaderMap) -> Response {
+    match metrics.render() {
+        Ok(body) => ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response(),
+        Err(e) => {
+            error!("Failed to encode metrics: {}", e);
+            let error = ProxyError::Internal("Failed to encode metrics".to_string());
+            create_error_response(error, &request_id(&headers))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+
+    use crate::test_support::{ADMIN_TOKEN, init_admin_token, send};
+
+    fn get_metrics(token: Option<&str>) -> Request<Body> {
+        let mut req = Request::get("/metrics");
+        if let Some(token) = token {
+            req = req.header("authorization", format!("Bearer {token}"));
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn metrics_require_the_admin_token() {
+        init_admin_token();
+        let router = router(Arc::new(ProxyMetrics::new()));
+
+        let (status, _, _) = send(&router, get_metrics(None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _, _) = send(&router, get_metrics(Some("wrong"))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, headers, _) = send(&router, get_metrics(Some(ADMIN_TOKEN))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[CONTENT_TYPE], prometheus::TEXT_FORMAT);
+    }
+}
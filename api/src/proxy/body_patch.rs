@@ -0,0 +1,122 @@
+use axum::body::Bytes;
+use axum::http::{HeaderValue, StatusCode};
+use serde_json::{Map, Value};
+
+use crate::error::ProxyError;
+
+/// Header carrying a JSON merge patch (RFC 7386) for the request body, only
+/// honoured for admin tokens and never forwarded
+pub const BODY_PATCH_HEADER: &str = "x-proxy-body-patch";
+
+/// `body` with the merge patch in `patch` applied. Both must be JSON, and the
+/// body an object, so a patch never replaces the body wholesale.
+pub fn apply(patch: &HeaderValue, body: &[u8]) -> Result<Bytes, ProxyError> {
+    let invalid = |message: String| ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, message);
+
+    let patch = patch
+        .to_str()
+        .ok()
+        .and_then(|patch| serde_json::from_str::<Value>(patch).ok())
+        .filter(Value::is_object)
+        .ok_or_else(|| invalid(format!("{BODY_PATCH_HEADER} must be a JSON object")))?;
+    let mut body = serde_json::from_slice::<Value>(body)
+        .ok()
+        .filter(Value::is_object)
+        .ok_or_else(|| invalid(format!("{BODY_PATCH_HEADER} needs a JSON object request body")))?;
+
+    merge_patch(&mut body, &patch);
+    serde_json::to_vec(&body)
+        .map(Bytes::from)
+        .map_err(|e| ProxyError::Internal(format!("Failed to encode patched body: {e}")))
+}
+
+/// Apply `patch` to `target` as a JSON merge patch: objects merge key by key,
+/// `null` removes a key, and any other value replaces the target
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merge_patch_follows_rfc_7386() {
+        // The examples of RFC 7386, appendix A
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+            (json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+            (json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+        ];
+        for (target, patch, expected) in cases {
+            let mut patched = target.clone();
+            merge_patch(&mut patched, &patch);
+            assert_eq!(patched, expected, "{target} patched with {patch}");
+        }
+    }
+
+    #[test]
+    fn patches_request_bodies() {
+        let body = json!({
+            "model": "gpt-4o",
+            "temperature": 0.7,
+            "messages": [{"role": "user", "content": "hi"}],
+            "metadata": {"user": "u1", "trace": "t1"},
+        });
+        let patch = HeaderValue::from_static(
+            r#"{"temperature": null, "messages": [], "metadata": {"trace": "t2", "tag": "x"}}"#,
+        );
+        let patched: Value = serde_json::from_slice(&apply(&patch, body.to_string().as_bytes()).unwrap()).unwrap();
+        assert_eq!(
+            patched,
+            json!({"model": "gpt-4o", "messages": [], "metadata": {"user": "u1", "trace": "t2", "tag": "x"}})
+        );
+    }
+
+    #[test]
+    fn rejects_patches_that_are_not_objects() {
+        let cases = [
+            (r#"["temperature"]"#, r#"{"model": "a"}"#),
+            ("not json", r#"{"model": "a"}"#),
+            ("null", r#"{"model": "a"}"#),
+            (r#"{"model": "b"}"#, r#"["a"]"#),
+            (r#"{"model": "b"}"#, "model=a"),
+        ];
+        for (patch, body) in cases {
+            let error = apply(&HeaderValue::from_static(patch), body.as_bytes()).unwrap_err();
+            assert!(matches!(error, ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, _)), "{patch} on {body}");
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use tracing::info;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Rolling XXH3 checksum and chunk count of one side of a stream
+#[derive(Default)]
+struct Side {
+    hasher: Xxh3,
+    chunks: u64,
+    bytes: u64,
+}
+
+impl Side {
+    fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        self.chunks += 1;
+        self.bytes += chunk.len() as u64;
+    }
+
+    fn digest(&self) -> String {
+        format!("{:016x}", self.hasher.digest())
+    }
+}
+
+/// Diagnostic checksums of the bytes received from the upstream and the bytes
+/// sent to the client for one streaming request, logged when the stream ends
+#[derive(Default)]
+pub struct StreamChecksum {
+    input: Side,
+    output: Side,
+}
+
+impl StreamChecksum {
+    pub fn input(&mut self, chunk: &[u8]) {
+        self.input.update(chunk);
+    }
+
+    pub fn output(&mut self, chunk: &[u8]) {
+        self.output.update(chunk);
+    }
+
+    /// Log both checksums; they should match for passthrough streams
+    pub fn log_passthrough(&self, path: &str) {
+        info!(
+            path = %path,
+            input_xxh3 = %self.input.digest(),
+            output_xxh3 = %self.output.digest(),
+            matches = self.input.digest() == self.output.digest(),
+            input_chunks = self.input.chunks,
+            output_chunks = self.output.chunks,
+            input_bytes = self.input.bytes,
+            output_bytes = self.output.bytes,
+            "Stream checksums"
+        );
+    }
+
+    /// Log the input checksum of a stream the proxy re-encodes, with the
+    /// number of events it was re-chunked into
+    pub fn log_reencoded(&self, path: &str, output_events: u64) {
+        info!(
+            path = %path,
+            input_xxh3 = %self.input.digest(),
+            input_chunks = self.input.chunks,
+            input_bytes = self.input.bytes,
+            output_events,
+            note = "output is re-encoded, only the input checksum is meaningful",
+            "Stream checksums"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_depend_on_the_bytes_not_the_chunking() {
+        let mut checksum = StreamChecksum::default();
+        for chunk in [&b"data: 1\n\n"[..], b"data: ", b"2\n\n"] {
+            checksum.input(chunk);
+        }
+        checksum.output(b"data: 1\n\ndata: 2\n\n");
+        assert_eq!(checksum.input.digest(), checksum.output.digest());
+        assert_eq!((checksum.input.chunks, checksum.output.chunks), (3, 1));
+        assert_eq!((checksum.input.bytes, checksum.output.bytes), (18, 18));
+
+        checksum.output(b": keep-alive\n\n");
+        assert_ne!(checksum.input.digest(), checksum.output.digest());
+        assert_eq!(checksum.output.digest().len(), 16);
+    }
+}
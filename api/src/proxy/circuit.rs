@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "admin")]
+use serde::Serialize;
+use tracing::{info, warn};
+
+use super::config::{CircuitBreakerConfig, ProxyConfig};
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed,
+    /// Failing fast until `until`
+    Open { until: Instant },
+    /// Letting probes through one at a time, `probe_started` while one is
+    /// out; the circuit closes after `success_threshold` successful probes
+    HalfOpen { probe_started: Option<Instant> },
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: State,
+    consecutive_failures: u32,
+    /// Successful probes since the circuit went half-open
+    probe_successes: u32,
+}
+
+/// State of one circuit, as reported by `/health/detailed`
+#[cfg(feature = "admin")]
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStatus {
+    /// `closed`, `open` or `half_open`
+    pub state: &'static str,
+    pub consecutive_failures: u32,
+    /// Seconds until an open circuit lets a probe through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+}
+
+/// Circuit breakers by key: the upstream host (`host:port`) for the global
+/// breaker, shared by all endpoints forwarding to it, or the endpoint path
+/// for endpoints with their own (see `endpoint_target_key`). Transport errors, timeouts and `5xx`
+/// responses count as failures; requests failed fast count as nothing.
+#[derive(Default)]
+pub struct CircuitBreakers {
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreakers {
+    /// Whether a request on circuit `key` may go out, or the time left before
+    /// its open circuit lets a probe through. Once the cooldown has passed one
+    /// probe request goes out and the others keep failing fast until it
+    /// finishes, or until another cooldown passes without an answer.
+    pub fn allow(&self, key: &str, config: &CircuitBreakerConfig) -> Result<(), Duration> {
+        let mut circuits = self.circuits.lock().unwrap();
+        let Some(circuit) = circuits.get_mut(key) else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(config.cooldown_secs);
+        match circuit.state {
+            State::Closed => Ok(()),
+            State::Open { until } if now < until => Err(until - now),
+            State::HalfOpen { probe_started: Some(started) } if now.duration_since(started) < cooldown => {
+                Err(cooldown - now.duration_since(started))
+            }
+            State::Open { .. } => {
+                info!("Circuit for {} is half-open, sending a probe request", key);
+                circuit.state = State::HalfOpen { probe_started: Some(now) };
+                circuit.probe_successes = 0;
+                Ok(())
+            }
+            State::HalfOpen { .. } => {
+                circuit.state = State::HalfOpen { probe_started: Some(now) };
+                Ok(())
+            }
+        }
+    }
+
+    /// Count a success, closing a half-open circuit once `success_threshold`
+    /// probes in a row have succeeded
+    pub fn record_success(&self, key: &str, config: &CircuitBreakerConfig) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let Some(circuit) = circuits.get_mut(key) else {
+            return;
+        };
+        match circuit.state {
+            State::Closed => circuit.consecutive_failures = 0,
+            // A request sent before the circuit opened says nothing about now
+            State::Open { .. } => {}
+            State::HalfOpen { .. } => {
+                circuit.probe_successes += 1;
+                if circuit.probe_successes < config.success_threshold {
+                    circuit.state = State::HalfOpen { probe_started: None };
+                } else {
+                    info!("Circuit for {} closed", key);
+                    circuit.state = State::Closed;
+                    circuit.consecutive_failures = 0;
+                }
+            }
+        }
+    }
+
+    /// Count a failure, opening the circuit at `failure_threshold`
+    /// consecutive failures or when a probe fails
+    pub fn record_failure(&self, key: &str, config: &CircuitBreakerConfig) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(key.to_string()).or_insert(Circuit {
+            state: State::Closed,
+            consecutive_failures: 0,
+            probe_successes: 0,
+        });
+        circuit.consecutive_failures += 1;
+
+        let trip = match circuit.state {
+            State::Closed => circuit.consecutive_failures >= config.failure_threshold,
+            State::HalfOpen { .. } => true,
+            State::Open { .. } => false,
+        };
+        if trip {
+            warn!(
+                "Circuit for {} opened after {} consecutive failures, failing fast for {}s",
+                key, circuit.consecutive_failures, config.cooldown_secs
+            );
+            circuit.state = State::Open {
+                until: Instant::now() + Duration::from_secs(config.cooldown_secs),
+            };
+        }
+    }
+
+    /// Carry circuits over a configuration reload, forgetting those whose
+    /// breaker settings changed: endpoint circuits when the endpoint's own
+    /// `circuit_breaker` changed or went away, host circuits when the global
+    /// one did. Unchanged circuits stay open.
+    pub fn reload(&self, old: &ProxyConfig, new: &ProxyConfig) {
+        let mut circuits = self.circuits.lock().unwrap();
+        circuits.retain(|key, _| {
+            let unchanged = breaker_settings(old, key) == breaker_settings(new, key);
+            if !unchanged {
+                info!("Circuit for {} reset, its circuit breaker settings changed", key);
+            }
+            unchanged
+        });
+    }
+
+    /// State of every circuit that has failed since startup, by key
+    #[cfg(feature = "admin")]
+    pub fn status(&self) -> HashMap<String, CircuitStatus> {
+        let now = Instant::now();
+        let circuits = self.circuits.lock().unwrap();
+        circuits
+            .iter()
+            .map(|(key, circuit)| {
+                let (state, retry_after) = match circuit.state {
+                    State::Closed => ("closed", None),
+                    State::Open { until } => ("open", Some(until.saturating_duration_since(now).as_secs())),
+                    State::HalfOpen { .. } => ("half_open", None),
+                };
+                let status = CircuitStatus {
+                    state,
+                    consecutive_failures: circuit.consecutive_failures,
+                    retry_after_secs: retry_after,
+                };
+                (key.clone(), status)
+            })
+            .collect()
+    }
+}
+
+/// Breaker settings of the circuit `key` in `config`: an endpoint path's own,
+/// or the global ones for upstream hosts
+fn breaker_settings<'a>(config: &'a ProxyConfig, key: &str) -> Option<&'a CircuitBreakerConfig> {
+    if key.starts_with('/') {
+        let path = key.split_once(' ').map_or(key, |(path, _)| path);
+        config
+            .endpoints
+            .iter()
+            .find(|endpoint| endpoint.path == path)
+            .and_then(|endpoint| endpoint.circuit_breaker.as_ref())
+    } else {
+        config.circuit_breaker.as_ref()
+    }
+}
+
+/// Circuit key of a target of an endpoint with its own breaker: the endpoint
+/// path, followed by the configured URL of the target for endpoints with
+/// `targets` so a failing primary does not close off its fallbacks
+pub fn endpoint_target_key(path: &str, target_url: Option<&str>) -> String {
+    match target_url {
+        Some(url) => format!("{path} {url}"),
+        None => path.to_string(),
+    }
+}
+
+/// Circuit key of an upstream URL: its host and port
+pub fn upstream_host(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    Some(format!("{}:{}", url.host_str()?, url.port_or_known_default()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{endpoint, proxy_config};
+
+    const KEY: &str = "/v1/test";
+
+    fn breaker(failure_threshold: u32, success_threshold: u32) -> CircuitBreakerConfig {
+        CircuitBreakerConfig { failure_threshold, success_threshold, cooldown_secs: 60 }
+    }
+
+    fn state(circuits: &CircuitBreakers, key: &str) -> &'static str {
+        match circuits.circuits.lock().unwrap().get(key).map(|circuit| circuit.state) {
+            None | Some(State::Closed) => "closed",
+            Some(State::Open { .. }) => "open",
+            Some(State::HalfOpen { .. }) => "half_open",
+        }
+    }
+
+    /// Let the cooldown of an open circuit pass
+    fn cool_down(circuits: &CircuitBreakers, key: &str) {
+        if let Some(circuit) = circuits.circuits.lock().unwrap().get_mut(key)
+            && let State::Open { until } = &mut circuit.state
+        {
+            *until = Instant::now();
+        }
+    }
+
+    #[test]
+    fn failures_open_the_circuit_and_a_probe_closes_it() {
+        let circuits = CircuitBreakers::default();
+        let config = breaker(3, 1);
+
+        // Successes reset the count of consecutive failures
+        for _ in 0..2 {
+            circuits.record_failure(KEY, &config);
+        }
+        circuits.record_success(KEY, &config);
+        for _ in 0..2 {
+            circuits.record_failure(KEY, &config);
+        }
+        assert_eq!(state(&circuits, KEY), "closed");
+        assert!(circuits.allow(KEY, &config).is_ok());
+
+        circuits.record_failure(KEY, &config);
+        assert_eq!(state(&circuits, KEY), "open");
+        let retry_after = circuits.allow(KEY, &config).unwrap_err();
+        assert!(retry_after > Duration::from_secs(59) && retry_after <= Duration::from_secs(60));
+
+        // Once the cooldown passed one probe goes out at a time
+        cool_down(&circuits, KEY);
+        assert!(circuits.allow(KEY, &config).is_ok());
+        assert_eq!(state(&circuits, KEY), "half_open");
+        assert!(circuits.allow(KEY, &config).is_err());
+
+        circuits.record_success(KEY, &config);
+        assert_eq!(state(&circuits, KEY), "closed");
+        assert!(circuits.allow(KEY, &config).is_ok());
+    }
+
+    #[test]
+    fn a_failed_probe_opens_the_circuit_again() {
+        let circuits = CircuitBreakers::default();
+        let config = breaker(2, 1);
+        circuits.record_failure(KEY, &config);
+        circuits.record_failure(KEY, &config);
+        cool_down(&circuits, KEY);
+        assert!(circuits.allow(KEY, &config).is_ok());
+
+        circuits.record_failure(KEY, &config);
+        assert_eq!(state(&circuits, KEY), "open");
+        assert!(circuits.allow(KEY, &config).is_err());
+
+        // Requests sent before the circuit opened count as nothing
+        circuits.record_success(KEY, &config);
+        assert_eq!(state(&circuits, KEY), "open");
+    }
+
+    #[test]
+    fn half_open_circuits_close_after_success_threshold_probes() {
+        let circuits = CircuitBreakers::default();
+        let config = breaker(1, 3);
+        circuits.record_failure(KEY, &config);
+        cool_down(&circuits, KEY);
+
+        for probe in 1..=3 {
+            assert!(circuits.allow(KEY, &config).is_ok(), "probe {probe}");
+            assert_eq!(state(&circuits, KEY), "half_open", "probe {probe}");
+            circuits.record_success(KEY, &config);
+        }
+        assert_eq!(state(&circuits, KEY), "closed");
+
+        // A failure between probes starts the count over
+        circuits.record_failure(KEY, &config);
+        cool_down(&circuits, KEY);
+        assert!(circuits.allow(KEY, &config).is_ok());
+        circuits.record_success(KEY, &config);
+        assert!(circuits.allow(KEY, &config).is_ok());
+        circuits.record_failure(KEY, &config);
+        cool_down(&circuits, KEY);
+        for _ in 0..2 {
+            assert!(circuits.allow(KEY, &config).is_ok());
+            circuits.record_success(KEY, &config);
+        }
+        assert_eq!(state(&circuits, KEY), "half_open");
+    }
+
+    #[test]
+    fn reloads_keep_circuits_whose_settings_are_unchanged() {
+        let config = |global: u32, a: u32, b: u32| {
+            let endpoints = vec![
+                endpoint(json!({ "path": "/a", "circuit_breaker": { "failure_threshold": a } })),
+                endpoint(json!({ "path": "/b", "circuit_breaker": { "failure_threshold": b } })),
+                endpoint(json!({ "path": "/c" })),
+            ];
+            proxy_config(endpoints, json!({ "circuit_breaker": { "failure_threshold": global } }))
+        };
+        let keys = ["upstream.test:443", "/a", "/a https://a.test/", "/b"];
+        let open = |circuits: &CircuitBreakers| {
+            for key in keys {
+                circuits.record_failure(key, &breaker(1, 1));
+            }
+        };
+        let open_keys = |circuits: &CircuitBreakers| keys.into_iter().filter(|key| state(circuits, key) == "open").collect::<Vec<_>>();
+
+        let circuits = CircuitBreakers::default();
+        open(&circuits);
+        circuits.reload(&config(1, 1, 1), &config(1, 1, 2));
+        assert_eq!(open_keys(&circuits), ["upstream.test:443", "/a", "/a https://a.test/"]);
+        circuits.reload(&config(1, 1, 2), &config(2, 1, 2));
+        assert_eq!(open_keys(&circuits), ["/a", "/a https://a.test/"]);
+
+        // Endpoints that lose their breaker, or go away, lose their circuits
+        let circuits = CircuitBreakers::default();
+        open(&circuits);
+        let mut without_a = config(1, 1, 1);
+        without_a.endpoints.retain(|endpoint| endpoint.path != "/a");
+        circuits.reload(&config(1, 1, 1), &without_a);
+        assert_eq!(open_keys(&circuits), ["upstream.test:443", "/b"]);
+    }
+}
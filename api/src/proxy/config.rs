@@ -1,20 +1,715 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::time::Duration;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+use super::interpolate::interpolate;
+use super::path_template::{placeholders, PathTemplate};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyConfig {
     pub endpoints: Vec<EndpointConfig>,
+    /// Model prefix rules that pick the upstream from the request body's `model`
+    #[serde(default)]
+    pub model_routes: Vec<ModelRoute>,
+    /// Client authentication required on proxy, admin, user, telemetry and usage endpoints
+    #[serde(default)]
+    pub inbound_auth: Option<InboundAuthConfig>,
+    /// HMAC signatures required on the user and thread endpoints; disabled when unset
+    #[serde(default)]
+    pub request_signing: Option<RequestSigningConfig>,
+    /// Global CORS policy
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    /// Rate limit of endpoints without their own `rate_limit`
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Maximum size of upstream response bodies that are buffered in memory
+    /// (JSON, HTML and non-streaming responses); unlimited when unset
+    #[serde(default)]
+    pub max_response_bytes: Option<usize>,
+    /// Headers whose values are replaced with `***` in logs
+    #[serde(default = "default_log_redact_headers")]
+    pub log_redact_headers: Vec<String>,
+    /// JSON fields whose values are replaced with `***` in logged bodies
+    #[serde(default = "default_log_redact_fields")]
+    pub log_redact_fields: Vec<String>,
+    /// Request and response body logging
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Maximum length of a single forwarded request header value
+    #[serde(default = "default_max_header_value_bytes")]
+    pub max_header_value_bytes: usize,
+    /// What to do with forwarded header values over `max_header_value_bytes`
+    #[serde(default)]
+    pub oversized_header_action: OversizedHeaderAction,
+    /// Upstream timeout in seconds for endpoints without their own `timeout`
+    #[serde(default = "default_global_timeout")]
+    pub global_timeout: u64,
+    /// Seconds a response is replayed for repeated POSTs carrying the same
+    /// `Idempotency-Key`; 0 disables the cache
+    #[serde(default = "default_idempotency_ttl")]
+    pub idempotency_ttl: u64,
+    /// Request bodies larger than this are streamed upstream on endpoints with
+    /// `stream_request_body`
+    #[serde(default = "default_stream_request_body_min_bytes")]
+    pub stream_request_body_min_bytes: usize,
+    /// Seconds between SLO evaluations
+    #[serde(default = "default_slo_eval_interval")]
+    pub slo_eval_interval: u64,
+    /// Minimum seconds between repeated warnings for an ongoing SLO breach
+    #[serde(default = "default_slo_alert_interval")]
+    pub slo_alert_interval: u64,
+    /// Fail fast for upstream hosts that keep failing; disabled when unset
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Export DNS, connect and response header timings per upstream host
+    #[serde(default)]
+    pub upstream_phase_metrics: bool,
+    /// Numeric upstream response headers reporting durations in seconds,
+    /// such as `fireworks-prefill-duration`, exported as histograms
+    #[serde(default)]
+    pub upstream_timing_headers: Vec<String>,
+    /// Context-window checks of converted requests
+    #[serde(default)]
+    pub context_windows: ContextWindowConfig,
+    /// Route requests whose path differs from an endpoint path only by case
+    /// or a missing version segment; disabled when unset
+    #[serde(default)]
+    pub path_normalization: Option<PathNormalizationConfig>,
+    /// Record requests refused before reaching the upstream; disabled when unset
+    #[serde(default)]
+    pub dead_letter: Option<DeadLetterConfig>,
+    /// Token usage accounting of proxied responses; disabled when unset
+    #[serde(default)]
+    pub usage: Option<UsageConfig>,
+    /// Profile of callers without a client identity of their own
+    #[serde(default)]
+    pub user: DefaultUserConfig,
+    /// Deprecated and ignored: it turned `accept-encoding` conflicts into load
+    /// errors, and upstream responses are now always decompressed
+    #[serde(default)]
+    pub strict_config: Option<bool>,
+    /// Deprecated: renamed to `logging.log_request_body` and
+    /// `logging.log_response_body`, which it sets when loaded
+    #[serde(default)]
+    pub log_bodies: Option<bool>,
+    /// Deprecated: renamed to `logging.max_logged_body_bytes`, which it sets
+    /// when loaded
+    #[serde(default)]
+    pub max_body_log_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PathNormalizationConfig {
+    /// Compare literal path segments regardless of case
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Let paths without the version segment (`v1`, `v1beta`, ...) of an
+    /// endpoint path reach the endpoint
+    #[serde(default)]
+    pub optional_version: bool,
+}
+
+/// JSON lines file receiving requests that could not be forwarded, with
+/// their headers and body sanitized like logged ones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterConfig {
+    pub path: String,
+    /// Request body bytes kept per entry
+    #[serde(default = "default_dead_letter_body_bytes")]
+    pub max_body_bytes: usize,
+    /// Size at which the file is moved to `<path>.1`, replacing the previous one
+    #[serde(default = "default_dead_letter_file_bytes")]
+    pub max_file_bytes: u64,
+}
+
+impl DeadLetterConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.path.is_empty() {
+            return Err("path must not be empty".to_string());
+        }
+        if self.max_file_bytes == 0 {
+            return Err("max_file_bytes must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_dead_letter_body_bytes() -> usize {
+    16 * 1024
+}
+
+fn default_dead_letter_file_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+/// Daily token totals by endpoint and model, priced when queried
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageConfig {
+    /// JSON file keeping the totals across restarts; read at startup only.
+    /// Totals are kept in memory when unset
+    #[serde(default)]
+    pub store_path: Option<String>,
+    /// Prices by model; a trailing `*` matches by prefix
+    #[serde(default)]
+    pub prices: HashMap<String, ModelPrice>,
+}
+
+/// Model prices in US dollars per million tokens
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+    /// Price of prompt tokens served from the provider's cache; the input
+    /// price when unset
+    #[serde(default)]
+    pub cached_input_per_million: Option<f64>,
+}
+
+impl UsageConfig {
+    /// Price of `model`: its own entry, else the longest matching prefix
+    pub fn price(&self, model: &str) -> Option<&ModelPrice> {
+        self.prices.get(model).or_else(|| {
+            self.prices
+                .iter()
+                .filter_map(|(pattern, price)| Some((pattern.strip_suffix('*')?, price)))
+                .filter(|(prefix, _)| model.starts_with(prefix))
+                .max_by_key(|(prefix, _)| prefix.len())
+                .map(|(_, price)| price)
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (model, price) in &self.prices {
+            let prices = [Some(price.input_per_million), Some(price.output_per_million), price.cached_input_per_million];
+            if prices.into_iter().flatten().any(|price| !price.is_finite() || price < 0.0) {
+                return Err(format!("prices of {model} must not be negative"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Estimated input sizes of converted requests checked against the context
+/// window of their model, before anything is sent upstream
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContextWindowConfig {
+    #[serde(default)]
+    pub enforce: ContextWindowEnforcement,
+    /// Context windows in tokens by model prefix, taking precedence over the
+    /// built-in table
+    #[serde(default)]
+    pub models: HashMap<String, usize>,
+}
+
+impl ContextWindowConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.models.iter().find(|(_, window)| **window == 0) {
+            Some((model, _)) => Err(format!("context window of {model} must be positive")),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextWindowEnforcement {
+    Off,
+    /// Log oversized requests and forward them anyway
+    #[default]
+    Warn,
+    /// Answer oversized requests with `400 context_length_exceeded`
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures that open a circuit
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Consecutive successful probes that close a half-open circuit
+    #[serde(default = "default_success_threshold")]
+    pub success_threshold: u32,
+    /// Seconds an open circuit fails fast before letting a probe through
+    #[serde(default = "default_cooldown_secs", alias = "open_duration_secs")]
+    pub cooldown_secs: u64,
+}
+
+impl CircuitBreakerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.failure_threshold == 0 || self.success_threshold == 0 || self.cooldown_secs == 0 {
+            return Err("failure_threshold, success_threshold and cooldown_secs must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Attempts in total, the first one included, shared by send and
+    /// response body read failures
+    #[serde(default = "default_retry_attempts")]
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled before each further one
+    #[serde(default = "default_retry_backoff_ms")]
+    pub backoff_ms: u64,
+    /// The upstream request is safe to repeat although its method is not `GET`
+    #[serde(default)]
+    pub idempotent: bool,
+}
+
+impl RetryConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("max_attempts must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Secondary upstream receiving a sampled copy of an endpoint's traffic for
+/// evaluation; its responses are recorded, never returned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShadowConfig {
+    pub target_url: String,
+    /// Credential of the shadow upstream; without one the shadow gets the
+    /// primary request's headers minus the endpoint's own `auth` credential
+    #[serde(default)]
+    pub auth: Option<UpstreamAuthConfig>,
+    /// Share of requests mirrored, between 0 and 1
+    #[serde(default = "default_shadow_sample_rate")]
+    pub sample_rate: f64,
+    /// Most shadow requests started per second; sampled requests above it
+    /// are not mirrored. Unlimited when unset
+    #[serde(default)]
+    pub max_qps: Option<f64>,
+    /// JSON lines file receiving one record per shadow exchange, keyed by the
+    /// primary request id; shadow exchanges are only logged when unset
+    #[serde(default)]
+    pub record_path: Option<String>,
+    /// Record the shadow response text, not only its SHA-256
+    #[serde(default)]
+    pub record_body: bool,
+}
+
+impl ShadowConfig {
+    /// Shadow settings of a bare `shadow_target`: every request, no limit
+    fn target(target_url: &str) -> Self {
+        Self {
+            target_url: target_url.to_string(),
+            auth: None,
+            sample_rate: default_shadow_sample_rate(),
+            max_qps: None,
+            record_path: None,
+            record_body: false,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        reqwest::Url::parse(&self.target_url).map_err(|e| format!("invalid target_url: {e}"))?;
+        if !(0.0..=1.0).contains(&self.sample_rate) {
+            return Err("sample_rate must be between 0 and 1".to_string());
+        }
+        if self.max_qps.is_some_and(|qps| qps <= 0.0) {
+            return Err("max_qps must be positive".to_string());
+        }
+        if matches!(self.auth, Some(UpstreamAuthConfig::Passthrough)) {
+            return Err("auth must be bearer or header; leave it unset to send the primary request's headers".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_shadow_sample_rate() -> f64 {
+    1.0
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    100
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_success_threshold() -> u32 {
+    1
+}
+
+fn default_cooldown_secs() -> u64 {
+    30
+}
+
+fn default_stream_request_body_min_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_slo_eval_interval() -> u64 {
+    10
+}
+
+fn default_slo_alert_interval() -> u64 {
+    300
+}
+
+fn default_idempotency_ttl() -> u64 {
+    300
+}
+
+fn default_global_timeout() -> u64 {
+    300
+}
+
+fn default_max_header_value_bytes() -> usize {
+    8192
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizedHeaderAction {
+    /// Fail the request with 431
+    #[default]
+    Reject,
+    /// Forward the first `max_header_value_bytes` bytes
+    Truncate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Log request bodies
+    #[serde(default = "default_true")]
+    pub log_request_body: bool,
+    /// Log buffered response bodies
+    #[serde(default = "default_true")]
+    pub log_response_body: bool,
+    /// Bytes of each body written to the log, the rest is truncated
+    #[serde(default = "default_max_logged_body_bytes")]
+    pub max_logged_body_bytes: usize,
+    /// Level bodies are logged at
+    #[serde(default)]
+    pub level: BodyLogLevel,
+    /// JSON pointers (`/messages/0/content`, `*` matching any key or index)
+    /// of fields logged as `***`, on top of `log_redact_fields`
+    #[serde(default)]
+    pub redact_fields: Vec<String>,
+    /// Response bodies over this many bytes are only logged one time in
+    /// `large_response_sample_rate`; every body is logged when unset
+    #[serde(default)]
+    pub large_response_bytes: Option<usize>,
+    #[serde(default = "default_large_response_sample_rate")]
+    pub large_response_sample_rate: u64,
+}
+
+impl LoggingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.large_response_sample_rate == 0 {
+            return Err("large_response_sample_rate must be positive".to_string());
+        }
+        validate_pointers(&self.redact_fields)
+    }
+}
+
+fn default_large_response_sample_rate() -> u64 {
+    10
+}
+
+fn validate_pointers(pointers: &[String]) -> Result<(), String> {
+    match pointers.iter().find(|pointer| !pointer.starts_with('/')) {
+        Some(pointer) => Err(format!("redact_fields entry {pointer:?} is not a JSON pointer (it must start with /)")),
+        None => Ok(()),
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            log_request_body: true,
+            log_response_body: true,
+            max_logged_body_bytes: default_max_logged_body_bytes(),
+            level: BodyLogLevel::default(),
+            redact_fields: Vec::new(),
+            large_response_bytes: None,
+            large_response_sample_rate: default_large_response_sample_rate(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyLogLevel {
+    /// Bodies only show up with `RUST_LOG=debug`
+    #[default]
+    Debug,
+    Info,
+}
+
+/// Endpoint overrides of the global `logging` section
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EndpointLogging {
+    #[serde(default)]
+    pub log_request_body: Option<bool>,
+    #[serde(default)]
+    pub log_response_body: Option<bool>,
+    #[serde(default)]
+    pub max_logged_body_bytes: Option<usize>,
+    /// Added to the global `redact_fields`
+    #[serde(default)]
+    pub redact_fields: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_logged_body_bytes() -> usize {
+    4096
+}
+
+fn default_log_redact_fields() -> Vec<String> {
+    ["api_key", "apikey", "authorization", "access_token", "refresh_token", "client_secret", "password", "secret"]
+        .iter()
+        .map(|f| f.to_string())
+        .collect()
+}
+
+fn default_log_redact_headers() -> Vec<String> {
+    ["authorization", "proxy-authorization", "x-api-key", "cookie", "set-cookie"]
+        .iter()
+        .map(|h| h.to_string())
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Allowed origins, `*` allows any
+    #[serde(default = "default_cors_wildcard")]
+    pub allowed_origins: Vec<String>,
+    /// Allowed methods, `*` allows any
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Allowed request headers, `*` allows any
+    #[serde(default = "default_cors_wildcard")]
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache preflight results
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+    /// Whether credentials (cookies, authorization) are allowed
+    #[serde(default)]
+    pub allow_credentials: bool,
+}
+
+fn default_cors_wildcard() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "DELETE", "OPTIONS"]
+        .iter()
+        .map(|m| m.to_string())
+        .collect()
+}
+
+impl CorsConfig {
+    /// Reject policies that browsers (and tower-http) refuse to honor
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allow_credentials {
+            for (field, values) in [
+                ("allowed_origins", &self.allowed_origins),
+                ("allowed_methods", &self.allowed_methods),
+                ("allowed_headers", &self.allowed_headers),
+            ] {
+                if values.iter().any(|v| v == "*") {
+                    return Err(format!("allow_credentials cannot be combined with a wildcard in {field}"));
+                }
+            }
+        }
+
+        for origin in self.allowed_origins.iter().filter(|o| *o != "*") {
+            HeaderValue::from_str(origin).map_err(|_| format!("invalid CORS origin: {origin}"))?;
+        }
+        for method in self.allowed_methods.iter().filter(|m| *m != "*") {
+            Method::from_bytes(method.to_uppercase().as_bytes())
+                .map_err(|_| format!("invalid CORS method: {method}"))?;
+        }
+        for header in self.allowed_headers.iter().filter(|h| *h != "*") {
+            HeaderName::from_bytes(header.as_bytes()).map_err(|_| format!("invalid CORS header: {header}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Shared secret clients sign requests with, read from the environment
+/// variable `secret_env` or the file `secret_file`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestSigningConfig {
+    #[serde(default)]
+    pub secret_env: Option<String>,
+    #[serde(default)]
+    pub secret_file: Option<String>,
+    /// Largest accepted difference in seconds between a request's
+    /// timestamp and the server clock
+    #[serde(default = "default_max_clock_skew_secs")]
+    pub max_clock_skew_secs: u64,
+}
+
+impl RequestSigningConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.secret_env.is_some() == self.secret_file.is_some() {
+            return Err("exactly one of secret_env and secret_file must be set".to_string());
+        }
+        if self.max_clock_skew_secs == 0 {
+            return Err("max_clock_skew_secs must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// The secret, without the trailing newline of a secret file
+    #[cfg_attr(not(feature = "storage"), allow(dead_code))]
+    pub fn secret(&self) -> Result<Vec<u8>, String> {
+        let secret = match (&self.secret_env, &self.secret_file) {
+            (Some(env), _) => std::env::var(env).map_err(|_| format!("environment variable {env} is not set"))?,
+            (None, Some(file)) => std::fs::read_to_string(file)
+                .map_err(|e| format!("could not read {file}: {e}"))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+            (None, None) => return Err("no secret configured".to_string()),
+        };
+        if secret.is_empty() {
+            return Err("the secret is empty".to_string());
+        }
+        Ok(secret.into_bytes())
+    }
+}
+
+fn default_max_clock_skew_secs() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InboundAuthConfig {
+    /// Accepted client tokens, served as the default user
+    #[serde(default)]
+    pub tokens: Vec<String>,
+    /// Clients with a user identity of their own; their tokens are accepted too
+    #[serde(default)]
+    pub clients: Vec<ClientConfig>,
+    /// Operator tokens, accepted like `tokens` and also allowed admin-only
+    /// request features such as the `x-proxy-body-patch` header
+    #[serde(default)]
+    pub admin_tokens: Vec<String>,
+    /// Query parameter that may carry the token for clients that cannot set
+    /// headers (e.g. browser EventSource), stripped before forwarding
+    #[serde(default)]
+    pub query_param: Option<String>,
+}
+
+impl InboundAuthConfig {
+    /// Whether `token` is accepted, compared against every configured token
+    /// in constant time
+    pub fn accepts(&self, token: &str) -> bool {
+        let listed = self.tokens.iter().fold(false, |found, t| tokens_match(t, token) | found);
+        listed | self.is_admin(token) | self.client(token).is_some()
+    }
+
+    /// Whether `token` is one of the `admin_tokens`, compared in constant time
+    pub fn is_admin(&self, token: &str) -> bool {
+        self.admin_tokens.iter().fold(false, |found, t| tokens_match(t, token) | found)
+    }
+
+    /// Reject client tokens or user ids that are configured twice
+    pub fn validate(&self) -> Result<(), String> {
+        for (index, client) in self.clients.iter().enumerate() {
+            let earlier = &self.clients[..index];
+            if self.tokens.contains(&client.token) || earlier.iter().any(|c| c.token == client.token) {
+                return Err(format!("token of client {} is configured twice", client.user.id));
+            }
+            if earlier.iter().any(|c| c.user.id == client.user.id) {
+                return Err(format!("user id {} is configured twice", client.user.id));
+            }
+        }
+        Ok(())
+    }
+
+    /// The client a token belongs to, if it has its own identity
+    pub fn client(&self, token: &str) -> Option<&ClientConfig> {
+        self.clients
+            .iter()
+            .fold(None, |found, client| if tokens_match(&client.token, token) { Some(client) } else { found })
+    }
+}
+
+/// Compare tokens in time independent of their contents: both are hashed
+/// first, so neither the length nor the first differing byte shows
+pub fn tokens_match(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.as_slice().ct_eq(b.as_slice()).into()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientConfig {
+    pub token: String,
+    /// Identity returned to this client by the user endpoints
+    pub user: UserProfile,
+}
+
+/// Profile answered to callers without a client identity; `AMP_USER_NAME`,
+/// `AMP_USER_EMAIL` and `AMP_USER_DISPLAY_NAME` take precedence
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DefaultUserConfig {
+    /// Derived from the configured username when unset, or generated once at
+    /// startup when neither is set
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub first_name: Option<String>,
+    #[serde(default)]
+    pub last_name: Option<String>,
+    /// Further profile fields, such as the plan type and its limits
+    #[serde(default)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfile {
+    /// Stable user id; thread state is kept per id
+    pub id: String,
+    pub username: String,
+    pub email: String,
+    #[serde(default)]
+    pub first_name: Option<String>,
+    #[serde(default)]
+    pub last_name: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EndpointConfig {
-    /// Local route path
+    /// Local route path, may contain `{param}` placeholders and a trailing `{*rest}`
     pub path: String,
-    /// Target forwarding URL
+    /// Target forwarding URL, may use the placeholders captured from `path`.
+    /// Empty when `targets` lists the upstreams instead
+    #[serde(default)]
     pub target_url: String,
+    /// Upstreams tried in turn, failing over on transport errors and `5xx`
+    /// responses; replaces `target_url`
+    #[serde(default)]
+    pub targets: Option<Vec<UpstreamTarget>>,
     /// HTTP method (GET, POST, PUT, DELETE, etc.)
     pub method: String,
-    /// Response type (json, sse, stream, html)
+    /// Response type (json, sse, stream, html, auto)
     pub response_type: ResponseType,
     /// Custom request headers
     pub custom_headers: HashMap<String, String>,
@@ -24,6 +719,252 @@ pub struct EndpointConfig {
     pub forward_response_headers: Vec<String>,
     /// Whether this endpoint is enabled
     pub enabled: bool,
+    /// Proxy mode (proxy, observe)
+    #[serde(default)]
+    pub mode: EndpointMode,
+    /// CORS policy overriding the global one
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    /// Request/response conversion applied around the upstream call
+    #[serde(default, alias = "convert")]
+    pub conversion: Option<Conversion>,
+    /// Secondary upstream that receives a copy of each request for comparison;
+    /// its responses are logged, never returned. Shorthand for a `shadow`
+    /// section with only `target_url`
+    #[serde(default)]
+    pub shadow_target: Option<String>,
+    /// Sampled mirroring of requests to a secondary upstream, with recording
+    #[serde(default)]
+    pub shadow: Option<ShadowConfig>,
+    /// Token bucket rate limit for this endpoint, overriding the global one
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Upstream timeout in seconds: the whole exchange for JSON and HTML
+    /// responses, connect through the first body chunk for streams
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// Longest a stream may run after its first chunk, in seconds; streams
+    /// are not cut off when unset
+    #[serde(default)]
+    pub max_stream_secs: Option<u64>,
+    /// Models this endpoint may serve, matched against the request body's
+    /// `model`; a trailing `*` matches by prefix. Any model when unset
+    #[serde(default)]
+    pub allowed_models: Option<Vec<String>>,
+    /// Body logging settings overriding the global `logging` section
+    #[serde(default)]
+    pub logging: Option<EndpointLogging>,
+    /// Service level objectives tracked for this endpoint
+    #[serde(default)]
+    pub slo: Option<SloConfig>,
+    /// Stream chunked or large request bodies upstream instead of buffering
+    /// them; not applied when the endpoint needs to read the body
+    #[serde(default)]
+    pub stream_request_body: bool,
+    /// Log XXH3 checksums and chunk counts of streamed bytes received from
+    /// the upstream and sent to the client, to locate stream corruption
+    #[serde(default)]
+    pub stream_checksums: bool,
+    /// Credential added to upstream requests; the client's own when unset
+    #[serde(default)]
+    pub auth: Option<UpstreamAuthConfig>,
+    /// Most requests forwarded to this endpoint at the same time, streams
+    /// included; unlimited when unset
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    /// What happens to requests arriving while `max_concurrent` are in flight
+    #[serde(default)]
+    pub on_full: OnFull,
+    /// Circuit breaker of this endpoint alone, instead of the global one
+    /// shared by its upstream host
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Parse `multipart/form-data` request bodies and rebuild them for the
+    /// upstream part by part, keeping file names and content types
+    #[serde(default)]
+    pub forward_as_multipart: bool,
+    /// Serve repeated `GET` requests from a cache of successful responses for
+    /// this many seconds; disabled when unset
+    #[serde(default)]
+    pub cache_ttl_secs: Option<u64>,
+    /// Request headers the upstream insists on, such as `anthropic-version`;
+    /// requests without one of them are rejected with 400 instead of forwarded
+    #[serde(default)]
+    pub require_headers: Vec<String>,
+    /// Reissue requests whose upstream connection failed, while sending or
+    /// while reading the buffered response; disabled when unset
+    #[serde(default)]
+    pub retry: Option<RetryConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFull {
+    /// Wait for a free slot for up to `global_timeout`, then fail with 503
+    #[default]
+    Queue,
+    /// Fail with 503 right away
+    Reject,
+}
+
+/// One of the upstreams of an endpoint with `targets`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpstreamTarget {
+    /// May use the placeholders captured from the endpoint path
+    pub url: String,
+    /// Relative share of requests sent here first; with any weight set the
+    /// order is drawn per request, otherwise targets are tried as listed
+    #[serde(default)]
+    pub weight: Option<u32>,
+    /// Credential of this upstream, replacing the endpoint's `auth`
+    #[serde(default)]
+    pub auth: Option<UpstreamAuthConfig>,
+}
+
+/// Upstream credential of an endpoint, read from the environment at startup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum UpstreamAuthConfig {
+    /// `Authorization: Bearer <value of env>`
+    Bearer { env: String },
+    /// The value of `env` in header `name`
+    Header { name: String, env: String },
+    /// Whatever the client forwarded
+    Passthrough,
+}
+
+impl UpstreamAuthConfig {
+    /// Header the credential is sent in
+    pub fn header_name(&self) -> Option<&str> {
+        match self {
+            UpstreamAuthConfig::Bearer { .. } => Some("authorization"),
+            UpstreamAuthConfig::Header { name, .. } => Some(name),
+            UpstreamAuthConfig::Passthrough => None,
+        }
+    }
+
+    pub fn env(&self) -> Option<&str> {
+        match self {
+            UpstreamAuthConfig::Bearer { env } | UpstreamAuthConfig::Header { env, .. } => Some(env),
+            UpstreamAuthConfig::Passthrough => None,
+        }
+    }
+}
+
+/// Service level objectives checked over a rolling window
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SloConfig {
+    /// p95 request latency target in milliseconds
+    #[serde(default)]
+    pub latency_p95_ms: Option<u64>,
+    /// Minimum streaming throughput, counted as streamed events per second
+    #[serde(default)]
+    pub min_tokens_per_second: Option<f64>,
+    /// Highest tolerated share of 5xx responses, between 0 and 1
+    #[serde(default)]
+    pub max_error_rate: Option<f64>,
+    /// Rolling window length in seconds
+    #[serde(default = "default_slo_window")]
+    pub window_secs: u64,
+}
+
+fn default_slo_window() -> u64 {
+    300
+}
+
+impl SloConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window_secs == 0 {
+            return Err("window_secs must be positive".to_string());
+        }
+        if self.max_error_rate.is_some_and(|rate| !(0.0..=1.0).contains(&rate)) {
+            return Err("max_error_rate must be between 0 and 1".to_string());
+        }
+        if self.min_tokens_per_second.is_some_and(|rate| rate <= 0.0) {
+            return Err("min_tokens_per_second must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Sustained request rate; set this or `requests_per_minute`
+    #[serde(default)]
+    pub requests_per_second: Option<f64>,
+    #[serde(default)]
+    pub requests_per_minute: Option<f64>,
+    /// Requests allowed in a burst above the sustained rate
+    #[serde(alias = "burst")]
+    pub burst_size: u32,
+    /// Which requests share a bucket
+    #[serde(default)]
+    pub key: RateLimitKey,
+}
+
+impl RateLimitConfig {
+    /// Sustained rate in requests per second
+    pub fn rate(&self) -> f64 {
+        self.requests_per_second
+            .or(self.requests_per_minute.map(|rpm| rpm / 60.0))
+            .unwrap_or_default()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.requests_per_second, self.requests_per_minute) {
+            (Some(_), Some(_)) => return Err("set requests_per_second or requests_per_minute, not both".to_string()),
+            (None, None) => return Err("requests_per_second or requests_per_minute is required".to_string()),
+            _ => {}
+        }
+        if self.rate() <= 0.0 || self.burst_size == 0 {
+            return Err("the request rate and burst_size must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitKey {
+    /// One bucket shared by all clients of the endpoint
+    #[default]
+    Endpoint,
+    /// One bucket per client IP address
+    Ip,
+    /// One bucket per `Authorization` header value, keyed by its hash
+    Authorization,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelRoute {
+    /// Prefix matched against the request body's `model` field
+    pub model_prefix: String,
+    /// Target forwarding URL for matching models
+    pub target_url: String,
+    /// Provider serving the matched models
+    pub provider: Provider,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    OpenAI,
+    Anthropic,
+    Google,
+}
+
+impl Provider {
+    /// Conversion of a Chat Completions request routed to this provider from
+    /// an endpoint with `conversion`. Legacy completions are already turned
+    /// into chat requests and cannot be chained with another conversion, and
+    /// Google has no conversion of its own, so both keep the endpoint's.
+    pub fn conversion(self, conversion: Option<Conversion>) -> Option<Conversion> {
+        match (self, conversion) {
+            (_, Some(Conversion::LegacyCompletions)) | (Provider::Google, _) => conversion,
+            (Provider::Anthropic, _) => Some(Conversion::OpenaiToAnthropic),
+            (Provider::OpenAI, _) => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,8 +974,46 @@ pub enum ResponseType {
     Sse,
     Stream,
     Html,
+    /// Pick one of the above from the upstream `content-type`
+    Auto,
+    /// Upgrade the client connection and relay frames both ways to a `ws://`
+    /// or `wss://` target
+    WebSocket,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Conversion {
+    /// Serve legacy `/v1/completions` requests from a chat completions upstream
+    LegacyCompletions,
+    /// Serve chat completions requests from an Anthropic Messages upstream
+    OpenaiToAnthropic,
 }
 
+impl Conversion {
+    /// Name of the conversion in configuration and metrics
+    pub fn name(self) -> &'static str {
+        match self {
+            Conversion::LegacyCompletions => "legacy_completions",
+            Conversion::OpenaiToAnthropic => "openai_to_anthropic",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndpointMode {
+    /// Regular proxying with response handling based on `response_type`
+    #[default]
+    Proxy,
+    /// Bit-identical passthrough: bodies are streamed untouched both ways and
+    /// only hashed and logged on the side
+    Observe,
+}
+
+/// Path prefix of the built-in Mistral endpoints
+pub(super) const MISTRAL_PATH_PREFIX: &str = "/api/provider/mistral/";
+
 impl Default for ProxyConfig {
     fn default() -> Self {
         Self {
@@ -43,6 +1022,7 @@ impl Default for ProxyConfig {
                 EndpointConfig {
                     path: "/api/provider/openai/v1/chat/completions".to_string(),
                     target_url: "https://api-key.info/v1/chat/completions".to_string(),
+                    targets: None,
                     method: "POST".to_string(),
                     response_type: ResponseType::Stream,
                     custom_headers: HashMap::new(),
@@ -58,11 +1038,33 @@ impl Default for ProxyConfig {
                         "cache-control".to_string(),
                     ],
                     enabled: true,
+                    mode: EndpointMode::Proxy,
+                    cors: None,
+                    conversion: None,
+                    shadow_target: None,
+                    shadow: None,
+                    rate_limit: None,
+                    timeout: None,
+                    max_stream_secs: None,
+                    allowed_models: None,
+                    logging: None,
+                    slo: None,
+                    stream_request_body: false,
+                    stream_checksums: false,
+                    auth: None,
+                    max_concurrent: None,
+                    on_full: OnFull::default(),
+                    circuit_breaker: None,
+                    forward_as_multipart: false,
+                    cache_ttl_secs: None,
+                    require_headers: Vec::new(),
+                    retry: None,
                 },
                 // Anthropic compatible endpoint
                 EndpointConfig {
                     path: "/api/provider/anthropic/v1/messages".to_string(),
                     target_url: "https://api-key.info/v1/messages".to_string(),
+                    targets: None,
                     method: "POST".to_string(),
                     response_type: ResponseType::Stream,
                     custom_headers: HashMap::new(),
@@ -79,11 +1081,33 @@ impl Default for ProxyConfig {
                         "cache-control".to_string(),
                     ],
                     enabled: true,
+                    mode: EndpointMode::Proxy,
+                    cors: None,
+                    conversion: None,
+                    shadow_target: None,
+                    shadow: None,
+                    rate_limit: None,
+                    timeout: None,
+                    max_stream_secs: None,
+                    allowed_models: None,
+                    logging: None,
+                    slo: None,
+                    stream_request_body: false,
+                    stream_checksums: false,
+                    auth: None,
+                    max_concurrent: None,
+                    on_full: OnFull::default(),
+                    circuit_breaker: None,
+                    forward_as_multipart: false,
+                    cache_ttl_secs: None,
+                    require_headers: Vec::new(),
+                    retry: None,
                 },
                 // LLM proxy endpoint
                 EndpointConfig {
                     path: "/api/tab/llm-proxy".to_string(),
                     target_url: "https://ampcode.com/api/tab/llm-proxy".to_string(),
+                    targets: None,
                     method: "POST".to_string(),
                     response_type: ResponseType::Sse,
                     custom_headers: HashMap::new(),
@@ -113,22 +1137,657 @@ impl Default for ProxyConfig {
                         "fireworks-tokenizer-queue-duration".to_string(),
                     ],
                     enabled: true,
+                    mode: EndpointMode::Proxy,
+                    cors: None,
+                    conversion: None,
+                    shadow_target: None,
+                    shadow: None,
+                    rate_limit: None,
+                    timeout: None,
+                    max_stream_secs: None,
+                    allowed_models: None,
+                    logging: None,
+                    slo: None,
+                    stream_request_body: false,
+                    stream_checksums: false,
+                    auth: Some(UpstreamAuthConfig::Bearer { env: "AMP_API_KEY".to_string() }),
+                    max_concurrent: None,
+                    on_full: OnFull::default(),
+                    circuit_breaker: None,
+                    forward_as_multipart: false,
+                    cache_ttl_secs: None,
+                    require_headers: Vec::new(),
+                    retry: None,
+                },
+                // Mistral chat, OpenAI compatible, served when MISTRAL_API_KEY is set
+                EndpointConfig {
+                    path: "/api/provider/mistral/v1/chat/completions".to_string(),
+                    target_url: "https://api.mistral.ai/v1/chat/completions".to_string(),
+                    targets: None,
+                    method: "POST".to_string(),
+                    response_type: ResponseType::Stream,
+                    custom_headers: HashMap::new(),
+                    forward_request_headers: vec![
+                        "content-type".to_string(),
+                        "user-agent".to_string(),
+                        "accept".to_string(),
+                        "accept-encoding".to_string(),
+                    ],
+                    forward_response_headers: vec![
+                        "content-type".to_string(),
+                        "cache-control".to_string(),
+                    ],
+                    // Needs MISTRAL_API_KEY, see `with_mistral_endpoints`
+                    enabled: false,
+                    mode: EndpointMode::Proxy,
+                    cors: None,
+                    conversion: None,
+                    shadow_target: None,
+                    shadow: None,
+                    rate_limit: None,
+                    timeout: None,
+                    max_stream_secs: None,
+                    allowed_models: None,
+                    logging: None,
+                    slo: None,
+                    stream_request_body: false,
+                    stream_checksums: false,
+                    auth: Some(UpstreamAuthConfig::Bearer { env: "MISTRAL_API_KEY".to_string() }),
+                    max_concurrent: None,
+                    on_full: OnFull::default(),
+                    circuit_breaker: None,
+                    forward_as_multipart: false,
+                    cache_ttl_secs: None,
+                    require_headers: Vec::new(),
+                    retry: None,
+                },
+                // Mistral embeddings, served when MISTRAL_API_KEY is set
+                EndpointConfig {
+                    path: "/api/provider/mistral/v1/embeddings".to_string(),
+                    target_url: "https://api.mistral.ai/v1/embeddings".to_string(),
+                    targets: None,
+                    method: "POST".to_string(),
+                    response_type: ResponseType::Json,
+                    custom_headers: HashMap::new(),
+                    forward_request_headers: vec![
+                        "content-type".to_string(),
+                        "user-agent".to_string(),
+                        "accept".to_string(),
+                        "accept-encoding".to_string(),
+                    ],
+                    forward_response_headers: vec![
+                        "content-type".to_string(),
+                        "cache-control".to_string(),
+                    ],
+                    // Needs MISTRAL_API_KEY, see `with_mistral_endpoints`
+                    enabled: false,
+                    mode: EndpointMode::Proxy,
+                    cors: None,
+                    conversion: None,
+                    shadow_target: None,
+                    shadow: None,
+                    rate_limit: None,
+                    timeout: None,
+                    max_stream_secs: None,
+                    allowed_models: None,
+                    logging: None,
+                    slo: None,
+                    stream_request_body: false,
+                    stream_checksums: false,
+                    auth: Some(UpstreamAuthConfig::Bearer { env: "MISTRAL_API_KEY".to_string() }),
+                    max_concurrent: None,
+                    on_full: OnFull::default(),
+                    circuit_breaker: None,
+                    forward_as_multipart: false,
+                    cache_ttl_secs: None,
+                    require_headers: Vec::new(),
+                    retry: None,
                 },
             ],
+            model_routes: Vec::new(),
+            inbound_auth: None,
+            request_signing: None,
+            cors: None,
+            rate_limit: None,
+            max_response_bytes: None,
+            log_redact_headers: default_log_redact_headers(),
+            log_redact_fields: default_log_redact_fields(),
+            logging: LoggingConfig::default(),
+            strict_config: None,
+            log_bodies: None,
+            max_body_log_bytes: None,
+            max_header_value_bytes: default_max_header_value_bytes(),
+            oversized_header_action: OversizedHeaderAction::default(),
+            global_timeout: default_global_timeout(),
+            idempotency_ttl: default_idempotency_ttl(),
+            stream_request_body_min_bytes: default_stream_request_body_min_bytes(),
+            slo_eval_interval: default_slo_eval_interval(),
+            slo_alert_interval: default_slo_alert_interval(),
+            circuit_breaker: None,
+            upstream_phase_metrics: false,
+            upstream_timing_headers: Vec::new(),
+            context_windows: ContextWindowConfig::default(),
+            path_normalization: None,
+            dead_letter: None,
+            usage: None,
+            user: DefaultUserConfig::default(),
+        }
+    }
+}
+
+impl EndpointConfig {
+    /// Shadow settings, from the `shadow` section or a bare `shadow_target`
+    pub fn shadow(&self) -> Option<Cow<'_, ShadowConfig>> {
+        match (&self.shadow, &self.shadow_target) {
+            (Some(shadow), _) => Some(Cow::Borrowed(shadow)),
+            (None, Some(target_url)) => Some(Cow::Owned(ShadowConfig::target(target_url))),
+            (None, None) => None,
+        }
+    }
+
+    /// The upstreams of the endpoint: its `targets`, or `target_url` alone
+    pub fn upstream_targets(&self) -> Cow<'_, [UpstreamTarget]> {
+        match &self.targets {
+            Some(targets) => Cow::Borrowed(targets),
+            None => Cow::Owned(vec![UpstreamTarget { url: self.target_url.clone(), weight: None, auth: None }]),
+        }
+    }
+
+    /// Check the endpoint for settings that cannot work at runtime
+    pub fn validate(&self) -> Result<(), String> {
+        let template = PathTemplate::parse(&self.path).map_err(|e| format!("path: {e}"))?;
+        let path_params = template.params();
+        for name in placeholders(&self.target_url) {
+            if !path_params.contains(&name.as_str()) {
+                return Err(format!("target_url placeholder {{{name}}} does not appear in path"));
+            }
+        }
+
+        match (&self.targets, self.target_url.is_empty()) {
+            (None, true) => return Err("target_url or targets is required".to_string()),
+            (Some(_), false) => return Err("set target_url or targets, not both".to_string()),
+            (Some(targets), true) => {
+                if targets.is_empty() {
+                    return Err("targets must not be empty".to_string());
+                }
+                if matches!(self.response_type, ResponseType::WebSocket) || self.mode == EndpointMode::Observe {
+                    return Err("targets are not supported on websocket or observe endpoints".to_string());
+                }
+                for (index, target) in targets.iter().enumerate() {
+                    if targets[..index].iter().any(|earlier| earlier.url == target.url) {
+                        return Err(format!("duplicate target url {}", target.url));
+                    }
+                    if target.weight == Some(0) {
+                        return Err(format!("weight of target {} must be positive", target.url));
+                    }
+                    for name in placeholders(&target.url) {
+                        if !path_params.contains(&name.as_str()) {
+                            return Err(format!("target {} placeholder {{{name}}} does not appear in path", target.url));
+                        }
+                    }
+                }
+            }
+            (None, false) => {}
+        }
+
+        if let Some(cors) = &self.cors {
+            cors.validate().map_err(|e| format!("cors: {e}"))?;
+        }
+
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.validate().map_err(|e| format!("rate_limit: {e}"))?;
+        }
+
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            circuit_breaker.validate().map_err(|e| format!("circuit_breaker: {e}"))?;
+        }
+
+        if let Some(logging) = &self.logging {
+            validate_pointers(&logging.redact_fields).map_err(|e| format!("logging: {e}"))?;
+        }
+
+        if self.timeout == Some(0) {
+            return Err("timeout must be positive".to_string());
+        }
+
+        if self.max_stream_secs == Some(0) {
+            return Err("max_stream_secs must be positive".to_string());
+        }
+
+        if self.max_concurrent == Some(0) {
+            return Err("max_concurrent must be positive".to_string());
+        }
+
+        if matches!(self.response_type, ResponseType::WebSocket) {
+            if !self.target_url.starts_with("ws://") && !self.target_url.starts_with("wss://") {
+                return Err("websocket endpoints need a ws:// or wss:// target_url".to_string());
+            }
+            if !self.method.eq_ignore_ascii_case("GET") {
+                return Err("websocket endpoints must use method GET".to_string());
+            }
+            if self.conversion.is_some() || self.mode == EndpointMode::Observe {
+                return Err("websocket endpoints support neither conversion nor observe mode".to_string());
+            }
+        }
+
+        if self.forward_as_multipart {
+            if self.method.eq_ignore_ascii_case("GET") || self.method.eq_ignore_ascii_case("DELETE") {
+                return Err(format!("forward_as_multipart needs a request body, not method {}", self.method));
+            }
+            if self.conversion.is_some() {
+                return Err("forward_as_multipart cannot be combined with conversion".to_string());
+            }
+        }
+
+        for header in &self.require_headers {
+            HeaderName::from_bytes(header.as_bytes()).map_err(|_| format!("invalid required header: {header}"))?;
+        }
+
+        if let Some(ttl) = self.cache_ttl_secs {
+            if ttl == 0 {
+                return Err("cache_ttl_secs must be positive".to_string());
+            }
+            if !self.method.eq_ignore_ascii_case("GET")
+                || !matches!(self.response_type, ResponseType::Json | ResponseType::Html)
+                || self.conversion.is_some()
+            {
+                return Err("cache_ttl_secs needs a GET endpoint with response_type json or html and no conversion".to_string());
+            }
+        }
+
+        if let Some(retry) = &self.retry {
+            retry.validate().map_err(|e| format!("retry: {e}"))?;
+            if !self.method.eq_ignore_ascii_case("GET") && !retry.idempotent {
+                return Err(format!("retry needs a GET endpoint, or idempotent: true for method {}", self.method));
+            }
+            if !matches!(self.response_type, ResponseType::Json | ResponseType::Html)
+                || self.conversion.is_some()
+                || self.mode == EndpointMode::Observe
+            {
+                return Err("retry needs response_type json or html, no conversion and proxy mode".to_string());
+            }
         }
+
+        if self.shadow_target.is_some() && self.shadow.is_some() {
+            return Err("set shadow_target or shadow, not both".to_string());
+        }
+        if let Some(shadow) = self.shadow() {
+            shadow.validate().map_err(|e| format!("shadow: {e}"))?;
+        }
+
+        if let Some(slo) = &self.slo {
+            slo.validate().map_err(|e| format!("slo: {e}"))?;
+        }
+
+        let target_auths = self.targets.iter().flatten().filter_map(|target| target.auth.as_ref());
+        for auth in self.auth.iter().chain(target_auths) {
+            if let Some(header) = auth.header_name()
+                && self.custom_headers.keys().any(|name| name.eq_ignore_ascii_case(header))
+            {
+                return Err(format!("auth conflicts with the custom {header} header"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The first of `require_headers` missing from `headers`
+    pub fn missing_required_header(&self, headers: &HeaderMap) -> Option<&str> {
+        self.require_headers
+            .iter()
+            .find(|header| !headers.contains_key(header.as_str()))
+            .map(String::as_str)
     }
+
+    pub fn allows_model(&self, model: Option<&str>) -> bool {
+        let Some(allowed) = &self.allowed_models else {
+            return true;
+        };
+        let Some(model) = model else {
+            return false;
+        };
+        allowed.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => model.starts_with(prefix),
+            None => model == pattern,
+        })
+    }
+
+}
+
+/// How long the startup fetch of a remote configuration may take
+const CONFIG_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fetch a remote configuration document. The blocking client runs on its own
+/// thread so this also works when called from inside the async runtime.
+fn fetch_config(url: &str) -> Result<String, String> {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                reqwest::blocking::Client::builder()
+                    .timeout(CONFIG_FETCH_TIMEOUT)
+                    .build()
+                    .and_then(|client| client.get(url).send())
+                    .and_then(|response| response.error_for_status())
+                    .and_then(|response| response.text())
+                    .map_err(|e| format!("failed to fetch {url}: {e}"))
+            })
+            .join()
+            .map_err(|_| format!("failed to fetch {url}: fetch thread panicked"))?
+    })
 }
 
 impl ProxyConfig {
-    /// Load configuration from YAML file
+    /// Load configuration from a YAML file, or from an `http(s)://` URL for
+    /// centrally managed configuration. `${VAR}` references are expanded
+    /// before parsing, see `interpolate`.
     pub fn load_from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = std::fs::read_to_string(path)?;
-        let config: ProxyConfig = serde_yaml::from_str(&content)?;
+        let content = if path.starts_with("http://") || path.starts_with("https://") {
+            fetch_config(path)?
+        } else {
+            std::fs::read_to_string(path)?
+        };
+        let content = interpolate(&content, &|name| std::env::var(name).ok())?;
+        let config = serde_yaml::from_str::<ProxyConfig>(&content)?.migrate_deprecated_keys();
+        config.validate()?;
         Ok(config)
     }
 
+    /// Move renamed settings to their new place and warn about the ones that
+    /// no longer do anything
+    fn migrate_deprecated_keys(mut self) -> Self {
+        if let Some(log_bodies) = self.log_bodies.take() {
+            warn!("log_bodies is deprecated, use logging.log_request_body and logging.log_response_body");
+            self.logging.log_request_body = log_bodies;
+            self.logging.log_response_body = log_bodies;
+        }
+        if let Some(max_body_log_bytes) = self.max_body_log_bytes.take() {
+            warn!("max_body_log_bytes is deprecated, use logging.max_logged_body_bytes");
+            self.logging.max_logged_body_bytes = max_body_log_bytes;
+        }
+        if self.strict_config.is_some() {
+            warn!(
+                "strict_config is deprecated and ignored: accept-encoding is no longer forwarded \
+                 and upstream responses are decompressed, so there are no conflicts left to check"
+            );
+        }
+        self
+    }
+
+    /// Check the configuration for settings that cannot work at runtime
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(cors) = &self.cors {
+            cors.validate().map_err(|e| format!("cors: {e}"))?;
+        }
+
+        if let Some(inbound_auth) = &self.inbound_auth {
+            inbound_auth.validate().map_err(|e| format!("inbound_auth: {e}"))?;
+        }
+
+        if let Some(request_signing) = &self.request_signing {
+            request_signing.validate().map_err(|e| format!("request_signing: {e}"))?;
+        }
+
+        // Settings of subsystems left out of this build would be ignored
+        #[cfg(not(feature = "storage"))]
+        {
+            if self.request_signing.is_some() {
+                return Err("request_signing needs a build with the `storage` feature".to_string());
+            }
+            if self.user != DefaultUserConfig::default() {
+                return Err("user needs a build with the `storage` feature".to_string());
+            }
+        }
+
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.validate().map_err(|e| format!("rate_limit: {e}"))?;
+        }
+
+        if let Some(name) = self.upstream_timing_headers.iter().find(|name| HeaderName::from_bytes(name.as_bytes()).is_err()) {
+            return Err(format!("upstream_timing_headers: invalid header name {name}"));
+        }
+
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            circuit_breaker.validate().map_err(|e| format!("circuit_breaker: {e}"))?;
+        }
+
+        self.logging.validate().map_err(|e| format!("logging: {e}"))?;
+        self.context_windows.validate().map_err(|e| format!("context_windows: {e}"))?;
+
+        if let Some(dead_letter) = &self.dead_letter {
+            dead_letter.validate().map_err(|e| format!("dead_letter: {e}"))?;
+        }
+
+        if let Some(usage) = &self.usage {
+            usage.validate().map_err(|e| format!("usage: {e}"))?;
+        }
+
+        if self.global_timeout == 0 {
+            return Err("global_timeout must be positive".to_string());
+        }
+
+        if self.slo_eval_interval == 0 {
+            return Err("slo_eval_interval must be positive".to_string());
+        }
+
+        for endpoint in &self.endpoints {
+            endpoint.validate().map_err(|e| format!("endpoint {}: {e}", endpoint.path))?;
+        }
+
+        Ok(())
+    }
+
+    /// Upstream timeout for an endpoint, falling back to `global_timeout`. It
+    /// bounds connect, response headers and the first body chunk, never the
+    /// streaming that follows, which only `max_stream_secs` limits.
+    pub fn get_timeout(&self, endpoint: &EndpointConfig) -> Duration {
+        Duration::from_secs(endpoint.timeout.unwrap_or(self.global_timeout))
+    }
+
+    /// Rate limit of an endpoint: its own, or the global default
+    pub fn rate_limit<'a>(&'a self, endpoint: &'a EndpointConfig) -> Option<&'a RateLimitConfig> {
+        endpoint.rate_limit.as_ref().or(self.rate_limit.as_ref())
+    }
+
+    /// Body logging settings for an endpoint, its overrides applied on top
+    /// of the global `logging` section
+    pub fn body_logging(&self, endpoint: &EndpointConfig) -> LoggingConfig {
+        let mut logging = self.logging.clone();
+        if let Some(overrides) = &endpoint.logging {
+            if let Some(log_request_body) = overrides.log_request_body {
+                logging.log_request_body = log_request_body;
+            }
+            if let Some(log_response_body) = overrides.log_response_body {
+                logging.log_response_body = log_response_body;
+            }
+            if let Some(max_logged_body_bytes) = overrides.max_logged_body_bytes {
+                logging.max_logged_body_bytes = max_logged_body_bytes;
+            }
+            logging.redact_fields.extend(overrides.redact_fields.iter().cloned());
+        }
+        logging
+    }
+
+    /// Deadline for any handler to produce a response: `global_timeout`, or
+    /// the longest endpoint `timeout` if that is longer, so no endpoint is cut
+    /// off before its own timeout fires
+    pub fn request_deadline(&self) -> Duration {
+        let longest = self
+            .enabled_endpoints()
+            .into_iter()
+            .filter_map(|endpoint| endpoint.timeout)
+            .fold(self.global_timeout, u64::max);
+        Duration::from_secs(longest)
+    }
+
     /// Get enabled endpoint configurations
     pub fn enabled_endpoints(&self) -> Vec<&EndpointConfig> {
         self.endpoints.iter().filter(|e| e.enabled).collect()
     }
-}
\ No newline at end of file
+
+    /// The enabled endpoint registered for `method` and `path`
+    pub fn find_endpoint(&self, method: &str, path: &str) -> Option<&EndpointConfig> {
+        self.endpoints
+            .iter()
+            .find(|e| e.enabled && e.path == path && e.method.eq_ignore_ascii_case(method))
+    }
+
+    /// Enable the built-in Mistral endpoints, which are off by default since
+    /// they cannot start without `MISTRAL_API_KEY`
+    pub fn with_mistral_endpoints(mut self) -> Self {
+        for endpoint in &mut self.endpoints {
+            if endpoint.path.starts_with(MISTRAL_PATH_PREFIX) {
+                endpoint.enabled = true;
+            }
+        }
+        self
+    }
+
+    /// Find the model route for a model name, the longest matching prefix wins
+    pub fn match_model_route(&self, model: &str) -> Option<&ModelRoute> {
+        self.model_routes
+            .iter()
+            .filter(|route| model.starts_with(&route.model_prefix))
+            .max_by_key(|route| route.model_prefix.len())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mistral_enabled(config: &ProxyConfig) -> Vec<bool> {
+        config
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.path.starts_with(MISTRAL_PATH_PREFIX))
+            .map(|endpoint| endpoint.enabled)
+            .collect()
+    }
+
+    #[test]
+    fn mistral_endpoints_are_enabled_explicitly() {
+        assert_eq!(mistral_enabled(&ProxyConfig::default()), [false, false]);
+        assert_eq!(mistral_enabled(&ProxyConfig::default().with_mistral_endpoints()), [true, true]);
+        ProxyConfig::default().validate().unwrap();
+    }
+
+    #[test]
+    fn strict_config_is_still_accepted() {
+        let config: ProxyConfig = serde_yaml::from_str("endpoints: []\nstrict_config: true\n").unwrap();
+        assert_eq!(config.strict_config, Some(true));
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn renamed_body_logging_keys_are_migrated() {
+        let yaml = "endpoints: []\nlog_bodies: false\nmax_body_log_bytes: 100\n";
+        let config = serde_yaml::from_str::<ProxyConfig>(yaml).unwrap().migrate_deprecated_keys();
+        assert!(!config.logging.log_request_body);
+        assert!(!config.logging.log_response_body);
+        assert_eq!(config.logging.max_logged_body_bytes, 100);
+        assert_eq!((config.log_bodies, config.max_body_log_bytes), (None, None));
+    }
+
+    #[test]
+    fn allowed_models_match_exactly_or_by_prefix() {
+        let mut endpoint = crate::test_support::endpoint(serde_json::json!({}));
+        assert!(endpoint.allows_model(None));
+        assert!(endpoint.allows_model(Some("anything")));
+
+        endpoint.allowed_models = Some(vec!["gpt-4o".to_string(), "claude-*".to_string()]);
+        for (model, allowed) in [
+            (Some("gpt-4o"), true),
+            (Some("gpt-4o-mini"), false),
+            (Some("claude-sonnet-4"), true),
+            (Some("claude"), false),
+            (None, false),
+        ] {
+            assert_eq!(endpoint.allows_model(model), allowed, "{model:?}");
+        }
+    }
+
+    #[test]
+    fn the_request_deadline_covers_the_longest_endpoint_timeout() {
+        use crate::test_support::{endpoint, proxy_config};
+        use serde_json::json;
+
+        let config = proxy_config(vec![endpoint(json!({ "timeout": 30 }))], json!({ "global_timeout": 60 }));
+        assert_eq!(config.request_deadline(), Duration::from_secs(60));
+
+        let endpoints = vec![endpoint(json!({ "timeout": 300 })), endpoint(json!({ "timeout": 900, "enabled": false }))];
+        let config = proxy_config(endpoints, json!({ "global_timeout": 60 }));
+        assert_eq!(config.request_deadline(), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn multipart_endpoints_need_a_request_body() {
+        use crate::test_support::endpoint;
+        use serde_json::json;
+
+        for (method, valid) in [("POST", true), ("PUT", true), ("GET", false), ("DELETE", false)] {
+            let endpoint = endpoint(json!({ "method": method, "forward_as_multipart": true }));
+            assert_eq!(endpoint.validate().is_ok(), valid, "{method}");
+        }
+        let converted = endpoint(json!({ "forward_as_multipart": true, "conversion": "legacy_completions" }));
+        assert!(converted.validate().unwrap_err().contains("conversion"));
+    }
+
+    #[tokio::test]
+    async fn configuration_is_loaded_from_a_url() {
+        use axum::{Router, http::StatusCode, routing::get};
+
+        const REMOTE: &str = "endpoints:
+  - path: /v1/remote
+    target_url: https://api.example.com/v1/chat/completions
+    method: POST
+    response_type: json
+    custom_headers: {}
+    forward_request_headers: []
+    forward_response_headers: []
+    enabled: true
+global_timeout: 42
+";
+        let server = crate::test_support::spawn_upstream(
+            Router::new()
+                .route("/config.yaml", get(|| async { REMOTE }))
+                .route("/invalid.yaml", get(|| async { "endpoints: []\nglobal_timeout: 0\n" }))
+                .route("/missing.yaml", get(|| async { StatusCode::NOT_FOUND })),
+        )
+        .await;
+        // The fetch blocks, so it runs off the runtime serving the config
+        let load = |name: &str| {
+            let url = format!("{server}/{name}");
+            tokio::task::spawn_blocking(move || ProxyConfig::load_from_file(&url).map_err(|e| e.to_string()))
+        };
+
+        let config = load("config.yaml").await.unwrap().unwrap();
+        assert_eq!(config.global_timeout, 42);
+        assert_eq!(config.endpoints[0].path, "/v1/remote");
+
+        let error = load("invalid.yaml").await.unwrap().unwrap_err();
+        assert!(error.contains("global_timeout must be positive"), "{error}");
+        let error = load("missing.yaml").await.unwrap().unwrap_err();
+        assert!(error.contains("404"), "{error}");
+    }
+
+    const SIGNED_USER_YAML: &str = "endpoints: []\nrequest_signing:\n  secret_env: SIGNING_SECRET\nuser:\n  id: alice\n";
+
+    #[cfg(feature = "storage")]
+    #[test]
+    fn storage_settings_are_accepted_with_storage() {
+        let config = serde_yaml::from_str::<ProxyConfig>(SIGNED_USER_YAML).unwrap();
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[cfg(not(feature = "storage"))]
+    #[test]
+    fn storage_settings_are_rejected_without_storage() {
+        let mut config = serde_yaml::from_str::<ProxyConfig>(SIGNED_USER_YAML).unwrap();
+        assert!(config.validate().unwrap_err().contains("request_signing needs"));
+
+        config.request_signing = None;
+        assert!(config.validate().unwrap_err().contains("user needs"));
+
+        config.user = DefaultUserConfig::default();
+        assert_eq!(config.validate(), Ok(()));
+    }
+}
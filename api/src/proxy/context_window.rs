@@ -0,0 +1,90 @@
+use serde_json::Value;
+
+use super::config::ContextWindowConfig;
+
+/// Context windows in tokens of common models, by model prefix
+const KNOWN_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+    ("claude-", 200_000),
+    ("gemini-1.5-pro", 2_097_152),
+    ("gemini-1.5-flash", 1_048_576),
+    ("gemini-2", 1_048_576),
+];
+
+/// Context window of `model`: the longest configured prefix matching it,
+/// then the longest built-in one
+pub fn context_window(config: &ContextWindowConfig, model: &str) -> Option<usize> {
+    let configured = config.models.iter().map(|(prefix, window)| (prefix.as_str(), *window));
+    longest_match(configured, model).or_else(|| longest_match(KNOWN_WINDOWS.iter().copied(), model))
+}
+
+fn longest_match<'a>(windows: impl Iterator<Item = (&'a str, usize)>, model: &str) -> Option<usize> {
+    windows
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, window)| window)
+}
+
+/// Rough input size of a chat request in tokens: a quarter of the characters
+/// of its message text and of an Anthropic `system` prompt
+pub fn estimate_tokens(request: &Value) -> usize {
+    let system = request.get("system").and_then(Value::as_str).map_or(0, |system| system.chars().count());
+    let messages: usize = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|message| match message.get("content") {
+            Some(Value::String(text)) => text.chars().count(),
+            Some(Value::Array(parts)) => parts
+                .iter()
+                .filter_map(|part| part.get("text")?.as_str())
+                .map(|text| text.chars().count())
+                .sum(),
+            _ => 0,
+        })
+        .sum();
+    (system + messages).div_ceil(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn configured_windows_take_precedence_by_longest_prefix() {
+        let config = ContextWindowConfig {
+            models: [("gpt-4o".to_string(), 64_000), ("gpt-4o-mini".to_string(), 32_000)].into(),
+            ..Default::default()
+        };
+        assert_eq!(context_window(&config, "gpt-4o-2024-08-06"), Some(64_000));
+        assert_eq!(context_window(&config, "gpt-4o-mini"), Some(32_000));
+        assert_eq!(context_window(&config, "gpt-4-0613"), Some(8_192));
+        assert_eq!(context_window(&config, "gpt-4.1-mini"), Some(1_047_576));
+        assert_eq!(context_window(&config, "claude-sonnet-4"), Some(200_000));
+        assert_eq!(context_window(&config, "llama-3"), None);
+    }
+
+    #[test]
+    fn estimates_a_quarter_token_per_character() {
+        let request = json!({
+            "system": "abcd",
+            "messages": [
+                { "role": "user", "content": "a".repeat(10) },
+                { "role": "user", "content": [{ "type": "text", "text": "éééé" }, { "type": "image_url" }] },
+                { "role": "assistant", "tool_calls": [] },
+            ],
+        });
+        assert_eq!(estimate_tokens(&request), 5);
+        assert_eq!(estimate_tokens(&json!({})), 0);
+    }
+}
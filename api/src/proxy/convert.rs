@@ -0,0 +1,871 @@
+use serde_json::{Map, Value, json};
+
+use super::config::Conversion;
+
+/// Parameters copied verbatim from a legacy completions request to chat
+const PASSTHROUGH_PARAMS: &[&str] = &[
+    "model",
+    "max_tokens",
+    "stop",
+    "temperature",
+    "top_p",
+    "n",
+    "stream",
+    "presence_penalty",
+    "frequency_penalty",
+    "seed",
+    "user",
+];
+
+/// Convert a legacy `/v1/completions` request into a chat completions request.
+/// The prompt becomes a single user message; array prompts are joined with
+/// newlines. `echo` and `logprobs` cannot be emulated on top of chat and are
+/// rejected.
+pub fn completions_to_chat_request(body: &Value) -> Result<Value, String> {
+    let request = body.as_object().ok_or("request body must be a JSON object")?;
+
+    if request.get("echo").and_then(Value::as_bool).unwrap_or(false) {
+        return Err("echo is not supported when completions are served by a chat model".to_string());
+    }
+    if request.get("logprobs").is_some_and(|v| !v.is_null()) {
+        return Err("logprobs is not supported when completions are served by a chat model".to_string());
+    }
+
+    let prompt = match request.get("prompt") {
+        Some(Value::String(prompt)) => prompt.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .map(|part| part.as_str().ok_or("prompt array must contain only strings"))
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n"),
+        Some(_) => return Err("prompt must be a string or an array of strings".to_string()),
+        None => String::new(),
+    };
+
+    let mut chat = Map::new();
+    for param in PASSTHROUGH_PARAMS {
+        if let Some(value) = request.get(*param) {
+            chat.insert(param.to_string(), value.clone());
+        }
+    }
+    chat.insert("messages".to_string(), json!([{ "role": "user", "content": prompt }]));
+
+    Ok(Value::Object(chat))
+}
+
+/// Convert a non-streaming chat completions response into the legacy shape
+pub fn chat_to_completions_response(chat: &Value) -> Value {
+    let choices: Vec<Value> = chat
+        .get("choices")
+        .and_then(Value::as_array)
+        .map(|choices| {
+            choices
+                .iter()
+                .map(|choice| {
+                    json!({
+                        "text": choice.pointer("/message/content").and_then(Value::as_str).unwrap_or_default(),
+                        "index": choice.get("index").cloned().unwrap_or(json!(0)),
+                        "logprobs": null,
+                        "finish_reason": choice.get("finish_reason").cloned().unwrap_or(Value::Null),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut response = legacy_envelope(chat, choices);
+    if let Some(usage) = chat.get("usage") {
+        response["usage"] = usage.clone();
+    }
+    response
+}
+
+/// Convert one streamed `chat.completion.chunk` into a legacy completion chunk
+pub fn chat_chunk_to_completions_chunk(chunk: &Value) -> Value {
+    let choices: Vec<Value> = chunk
+        .get("choices")
+        .and_then(Value::as_array)
+        .map(|choices| {
+            choices
+                .iter()
+                .map(|choice| {
+                    json!({
+                        "text": choice.pointer("/delta/content").and_then(Value::as_str).unwrap_or_default(),
+                        "index": choice.get("index").cloned().unwrap_or(json!(0)),
+                        "logprobs": null,
+                        "finish_reason": choice.get("finish_reason").cloned().unwrap_or(Value::Null),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut converted = legacy_envelope(chunk, choices);
+    if let Some(usage) = chunk.get("usage").filter(|u| !u.is_null()) {
+        converted["usage"] = usage.clone();
+    }
+    converted
+}
+
+fn legacy_envelope(source: &Value, choices: Vec<Value>) -> Value {
+    json!({
+        "id": source.get("id").cloned().unwrap_or(Value::Null),
+        "object": "text_completion",
+        "created": source.get("created").cloned().unwrap_or(Value::Null),
+        "model": source.get("model").cloned().unwrap_or(Value::Null),
+        "choices": choices,
+    })
+}
+
+/// `max_tokens` sent to Anthropic, which requires it, when the chat request
+/// sets no limit
+const DEFAULT_ANTHROPIC_MAX_TOKENS: u64 = 4096;
+
+/// Convert a chat completions request into an Anthropic Messages request.
+/// System and developer messages become the `system` prompt, tool calls and
+/// tool results become `tool_use` and `tool_result` blocks, and consecutive
+/// messages of the same role are merged, as Anthropic requires alternating
+/// roles. `n` above 1 and `logprobs` have no Anthropic equivalent and are
+/// rejected.
+pub fn chat_to_anthropic_request(body: &Value) -> Result<Value, String> {
+    let request = body.as_object().ok_or("request body must be a JSON object")?;
+
+    if request.get("n").and_then(Value::as_u64).is_some_and(|n| n > 1) {
+        return Err("n greater than 1 is not supported by Anthropic models".to_string());
+    }
+    if request.get("logprobs").and_then(Value::as_bool).unwrap_or(false) {
+        return Err("logprobs is not supported by Anthropic models".to_string());
+    }
+
+    let mut system = Vec::new();
+    let mut messages: Vec<Value> = Vec::new();
+    for message in request.get("messages").and_then(Value::as_array).ok_or("messages must be an array")? {
+        let role = message.get("role").and_then(Value::as_str).ok_or("every message needs a role")?;
+        let (role, blocks) = match role {
+            "system" | "developer" => {
+                system.push(content_text(message.get("content")));
+                continue;
+            }
+            "user" => ("user", content_blocks(message.get("content"))?),
+            "assistant" => {
+                let mut blocks = content_blocks(message.get("content"))?;
+                for call in message.get("tool_calls").and_then(Value::as_array).into_iter().flatten() {
+                    let arguments = call.pointer("/function/arguments").and_then(Value::as_str).unwrap_or("{}");
+                    let input: Value = serde_json::from_str(arguments)
+                        .map_err(|e| format!("tool call arguments must be a JSON object: {e}"))?;
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": call.get("id").cloned().unwrap_or(Value::Null),
+                        "name": call.pointer("/function/name").cloned().unwrap_or(Value::Null),
+                        "input": input,
+                    }));
+                }
+                ("assistant", blocks)
+            }
+            "tool" => (
+                "user",
+                vec![json!({
+                    "type": "tool_result",
+                    "tool_use_id": message.get("tool_call_id").cloned().unwrap_or(Value::Null),
+                    "content": content_text(message.get("content")),
+                })],
+            ),
+            other => return Err(format!("unsupported message role {other}")),
+        };
+
+        match messages.last_mut() {
+            Some(last) if last["role"] == role => {
+                if let Some(content) = last["content"].as_array_mut() {
+                    content.extend(blocks);
+                }
+            }
+            _ => messages.push(json!({ "role": role, "content": blocks })),
+        }
+    }
+
+    let mut anthropic = Map::new();
+    for param in ["model", "temperature", "top_p", "stream"] {
+        if let Some(value) = request.get(param) {
+            anthropic.insert(param.to_string(), value.clone());
+        }
+    }
+    let max_tokens = request
+        .get("max_completion_tokens")
+        .or_else(|| request.get("max_tokens"))
+        .and_then(Value::as_u64)
+        .unwrap_or(DEFAULT_ANTHROPIC_MAX_TOKENS);
+    anthropic.insert("max_tokens".to_string(), json!(max_tokens));
+    anthropic.insert("messages".to_string(), Value::Array(messages));
+    if !system.is_empty() {
+        anthropic.insert("system".to_string(), json!(system.join("\n\n")));
+    }
+    match request.get("stop") {
+        Some(Value::String(stop)) => {
+            anthropic.insert("stop_sequences".to_string(), json!([stop]));
+        }
+        Some(Value::Array(stops)) => {
+            anthropic.insert("stop_sequences".to_string(), Value::Array(stops.clone()));
+        }
+        _ => {}
+    }
+    if let Some(user) = request.get("user").and_then(Value::as_str) {
+        anthropic.insert("metadata".to_string(), json!({ "user_id": user }));
+    }
+    if let Some(tools) = request.get("tools").and_then(Value::as_array) {
+        let tools: Vec<Value> = tools
+            .iter()
+            .filter_map(|tool| tool.get("function"))
+            .map(|function| {
+                json!({
+                    "name": function.get("name").cloned().unwrap_or(Value::Null),
+                    "description": function.get("description").cloned().unwrap_or(json!("")),
+                    "input_schema": function.get("parameters").cloned().unwrap_or(json!({ "type": "object" })),
+                })
+            })
+            .collect();
+        anthropic.insert("tools".to_string(), Value::Array(tools));
+    }
+    let tool_choice = match request.get("tool_choice") {
+        Some(Value::String(choice)) if choice == "auto" => Some(json!({ "type": "auto" })),
+        Some(Value::String(choice)) if choice == "required" => Some(json!({ "type": "any" })),
+        Some(Value::String(choice)) if choice == "none" => Some(json!({ "type": "none" })),
+        Some(choice @ Value::Object(_)) => choice
+            .pointer("/function/name")
+            .map(|name| json!({ "type": "tool", "name": name })),
+        _ => None,
+    };
+    if let Some(tool_choice) = tool_choice {
+        anthropic.insert("tool_choice".to_string(), tool_choice);
+    }
+
+    Ok(Value::Object(anthropic))
+}
+
+/// Text of a chat message content, its text parts joined for part arrays
+fn content_text(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| part.get("text")?.as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Anthropic content blocks of a chat message content. Images given as data
+/// URLs are sent inline, others by URL.
+fn content_blocks(content: Option<&Value>) -> Result<Vec<Value>, String> {
+    let parts = match content {
+        None | Some(Value::Null) => return Ok(Vec::new()),
+        Some(Value::String(text)) if text.is_empty() => return Ok(Vec::new()),
+        Some(Value::String(text)) => return Ok(vec![json!({ "type": "text", "text": text })]),
+        Some(Value::Array(parts)) => parts,
+        Some(_) => return Err("message content must be a string or an array of parts".to_string()),
+    };
+
+    parts
+        .iter()
+        .map(|part| match part.get("type").and_then(Value::as_str) {
+            Some("text") => Ok(json!({ "type": "text", "text": part.get("text").cloned().unwrap_or(json!("")) })),
+            Some("image_url") => {
+                let url = part
+                    .pointer("/image_url/url")
+                    .and_then(Value::as_str)
+                    .ok_or("image_url parts need a url")?;
+                let source = match url.strip_prefix("data:").and_then(|data| data.split_once(";base64,")) {
+                    Some((media_type, data)) => json!({ "type": "base64", "media_type": media_type, "data": data }),
+                    None => json!({ "type": "url", "url": url }),
+                };
+                Ok(json!({ "type": "image", "source": source }))
+            }
+            other => Err(format!("unsupported content part type {}", other.unwrap_or("(none)"))),
+        })
+        .collect()
+}
+
+/// Chat completions `finish_reason` of an Anthropic `stop_reason`
+fn finish_reason(stop_reason: &Value) -> Value {
+    match stop_reason.as_str() {
+        Some("end_turn" | "stop_sequence" | "pause_turn") => json!("stop"),
+        Some("max_tokens") => json!("length"),
+        Some("tool_use") => json!("tool_calls"),
+        Some("refusal") => json!("content_filter"),
+        Some(other) => json!(other),
+        None => Value::Null,
+    }
+}
+
+/// Anthropic `stop_reason` of a chat completions `finish_reason`
+fn stop_reason(finish_reason: &Value) -> Value {
+    match finish_reason.as_str() {
+        Some("stop") => json!("end_turn"),
+        Some("length") => json!("max_tokens"),
+        Some("tool_calls" | "function_call") => json!("tool_use"),
+        Some("content_filter") => json!("refusal"),
+        Some(other) => json!(other),
+        None => Value::Null,
+    }
+}
+
+fn chat_usage(input_tokens: u64, output_tokens: u64) -> Value {
+    json!({
+        "prompt_tokens": input_tokens,
+        "completion_tokens": output_tokens,
+        "total_tokens": input_tokens + output_tokens,
+    })
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Convert a non-streaming Anthropic Messages response into a chat
+/// completion: text blocks are joined into the message content and
+/// `tool_use` blocks become tool calls
+pub fn anthropic_to_chat_response(message: &Value) -> Value {
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+    for block in message.get("content").and_then(Value::as_array).into_iter().flatten() {
+        match block.get("type").and_then(Value::as_str) {
+            Some("text") => text.push_str(block.get("text").and_then(Value::as_str).unwrap_or_default()),
+            Some("tool_use") => tool_calls.push(json!({
+                "id": block.get("id").cloned().unwrap_or(Value::Null),
+                "type": "function",
+                "function": {
+                    "name": block.get("name").cloned().unwrap_or(Value::Null),
+                    "arguments": block.get("input").map(Value::to_string).unwrap_or_else(|| "{}".to_string()),
+                },
+            })),
+            _ => {}
+        }
+    }
+
+    let mut chat_message = json!({
+        "role": "assistant",
+        "content": if text.is_empty() && !tool_calls.is_empty() { Value::Null } else { json!(text) },
+    });
+    if !tool_calls.is_empty() {
+        chat_message["tool_calls"] = Value::Array(tool_calls);
+    }
+    let token_count = |name| message.pointer(&format!("/usage/{name}")).and_then(Value::as_u64).unwrap_or(0);
+
+    json!({
+        "id": message.get("id").cloned().unwrap_or(Value::Null),
+        "object": "chat.completion",
+        "created": unix_now(),
+        "model": message.get("model").cloned().unwrap_or(Value::Null),
+        "choices": [{
+            "index": 0,
+            "message": chat_message,
+            "finish_reason": finish_reason(message.get("stop_reason").unwrap_or(&Value::Null)),
+        }],
+        "usage": chat_usage(token_count("input_tokens"), token_count("output_tokens")),
+    })
+}
+
+/// Per-stream state of a streamed response conversion
+pub enum StreamConverter {
+    LegacyCompletions,
+    OpenaiToAnthropic(AnthropicToChatStream),
+}
+
+impl StreamConverter {
+    pub fn new(conversion: Conversion) -> Self {
+        match conversion {
+            Conversion::LegacyCompletions => StreamConverter::LegacyCompletions,
+            Conversion::OpenaiToAnthropic => StreamConverter::OpenaiToAnthropic(AnthropicToChatStream::default()),
+        }
+    }
+
+    /// Data of the events to send for one upstream event, `[DONE]` included
+    /// when the upstream event ends the stream
+    pub fn convert(&mut self, chunk: &Value) -> Vec<String> {
+        match self {
+            StreamConverter::LegacyCompletions => vec![chat_chunk_to_completions_chunk(chunk).to_string()],
+            StreamConverter::OpenaiToAnthropic(stream) => stream.convert(chunk),
+        }
+    }
+}
+
+/// Turns Anthropic Messages stream events into `chat.completion.chunk`s,
+/// carrying the message id and model of `message_start` into every chunk
+/// and numbering `tool_use` blocks as tool calls
+#[derive(Default)]
+pub struct AnthropicToChatStream {
+    id: Value,
+    model: Value,
+    created: u64,
+    input_tokens: u64,
+    /// Tool call index of each open `tool_use` content block, by block index
+    tool_calls: Vec<(u64, usize)>,
+}
+
+impl AnthropicToChatStream {
+    pub fn convert(&mut self, event: &Value) -> Vec<String> {
+        let index = event.get("index").and_then(Value::as_u64).unwrap_or(0);
+        match event.get("type").and_then(Value::as_str) {
+            Some("message_start") => {
+                let message = event.get("message").unwrap_or(&Value::Null);
+                self.id = message.get("id").cloned().unwrap_or(Value::Null);
+                self.model = message.get("model").cloned().unwrap_or(Value::Null);
+                self.created = unix_now();
+                self.input_tokens = message.pointer("/usage/input_tokens").and_then(Value::as_u64).unwrap_or(0);
+                vec![self.chunk(json!({ "role": "assistant", "content": "" }), Value::Null).to_string()]
+            }
+            Some("content_block_start") => {
+                let block = event.get("content_block").unwrap_or(&Value::Null);
+                match block.get("type").and_then(Value::as_str) {
+                    Some("tool_use") => {
+                        let call = self.tool_calls.len();
+                        self.tool_calls.push((index, call));
+                        let delta = json!({ "tool_calls": [{
+                            "index": call,
+                            "id": block.get("id").cloned().unwrap_or(Value::Null),
+                            "type": "function",
+                            "function": { "name": block.get("name").cloned().unwrap_or(Value::Null), "arguments": "" },
+                        }] });
+                        vec![self.chunk(delta, Value::Null).to_string()]
+                    }
+                    Some("text") => match block.get("text").and_then(Value::as_str) {
+                        Some(text) if !text.is_empty() => vec![self.chunk(json!({ "content": text }), Value::Null).to_string()],
+                        _ => Vec::new(),
+                    },
+                    _ => Vec::new(),
+                }
+            }
+            Some("content_block_delta") => {
+                let delta = event.get("delta").unwrap_or(&Value::Null);
+                match delta.get("type").and_then(Value::as_str) {
+                    Some("text_delta") => {
+                        let text = delta.get("text").cloned().unwrap_or(json!(""));
+                        vec![self.chunk(json!({ "content": text }), Value::Null).to_string()]
+                    }
+                    Some("input_json_delta") => {
+                        let Some(&(_, call)) = self.tool_calls.iter().find(|(block, _)| *block == index) else {
+                            return Vec::new();
+                        };
+                        let arguments = delta.get("partial_json").cloned().unwrap_or(json!(""));
+                        let delta = json!({ "tool_calls": [{ "index": call, "function": { "arguments": arguments } }] });
+                        vec![self.chunk(delta, Value::Null).to_string()]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            Some("message_delta") => {
+                let reason = finish_reason(event.pointer("/delta/stop_reason").unwrap_or(&Value::Null));
+                let mut chunk = self.chunk(json!({}), reason);
+                if let Some(output_tokens) = event.pointer("/usage/output_tokens").and_then(Value::as_u64) {
+                    chunk["usage"] = chat_usage(self.input_tokens, output_tokens);
+                }
+                vec![chunk.to_string()]
+            }
+            Some("message_stop") => vec!["[DONE]".to_string()],
+            _ => Vec::new(),
+        }
+    }
+
+    fn chunk(&self, delta: Value, finish_reason: Value) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        })
+    }
+}
+
+/// Turns `chat.completion.chunk`s into Anthropic Messages stream events,
+/// the reverse of `AnthropicToChatStream`. Only the first choice is kept.
+#[derive(Default)]
+pub struct ChatToAnthropicStream {
+    started: bool,
+    /// Index and type of the open content block
+    open_block: Option<(u64, &'static str)>,
+    next_block: u64,
+    stop_reason: Value,
+    /// Usage of the final chunk, when the client asked for it
+    input_tokens: u64,
+    output_tokens: u64,
+    finished: bool,
+}
+
+impl ChatToAnthropicStream {
+    /// Events for one chunk, each named after its `type`
+    pub fn convert(&mut self, chunk: &Value) -> Vec<Value> {
+        let mut events = Vec::new();
+        if !self.started {
+            self.started = true;
+            let input_tokens = chunk.pointer("/usage/prompt_tokens").and_then(Value::as_u64).unwrap_or(0);
+            events.push(json!({
+                "type": "message_start",
+                "message": {
+                    "id": chunk.get("id").cloned().unwrap_or(Value::Null),
+                    "type": "message",
+                    "role": "assistant",
+                    "model": chunk.get("model").cloned().unwrap_or(Value::Null),
+                    "content": [],
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": { "input_tokens": input_tokens, "output_tokens": 0 },
+                },
+            }));
+        }
+        if let Some(usage) = chunk.get("usage").filter(|usage| !usage.is_null()) {
+            self.input_tokens = usage.get("prompt_tokens").and_then(Value::as_u64).unwrap_or(self.input_tokens);
+            self.output_tokens = usage.get("completion_tokens").and_then(Value::as_u64).unwrap_or(self.output_tokens);
+        }
+
+        let Some(choice) = chunk.pointer("/choices/0") else {
+            return events;
+        };
+        if let Some(text) = choice.pointer("/delta/content").and_then(Value::as_str).filter(|text| !text.is_empty()) {
+            let index = match self.open_block {
+                Some((index, "text")) => index,
+                _ => self.start_block(&mut events, json!({ "type": "text", "text": "" }), "text"),
+            };
+            events.push(json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": { "type": "text_delta", "text": text },
+            }));
+        }
+        for call in choice.pointer("/delta/tool_calls").and_then(Value::as_array).into_iter().flatten() {
+            let index = match (call.get("id"), self.open_block) {
+                (Some(id), _) if !id.is_null() => {
+                    let block = json!({
+                        "type": "tool_use",
+                        "id": id,
+                        "name": call.pointer("/function/name").cloned().unwrap_or(Value::Null),
+                        "input": {},
+                    });
+                    self.start_block(&mut events, block, "tool_use")
+                }
+                (_, Some((index, "tool_use"))) => index,
+                _ => continue,
+            };
+            if let Some(arguments) = call.pointer("/function/arguments").and_then(Value::as_str).filter(|a| !a.is_empty()) {
+                events.push(json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": { "type": "input_json_delta", "partial_json": arguments },
+                }));
+            }
+        }
+        if let Some(reason) = choice.get("finish_reason").filter(|reason| !reason.is_null()) {
+            self.stop_reason = stop_reason(reason);
+        }
+        events
+    }
+
+    /// Events closing the message, at `[DONE]` or the end of the stream;
+    /// nothing when the message was already closed
+    pub fn finish(&mut self) -> Vec<Value> {
+        if self.finished || !self.started {
+            return Vec::new();
+        }
+        self.finished = true;
+        let mut events = Vec::new();
+        if let Some((index, _)) = self.open_block.take() {
+            events.push(json!({ "type": "content_block_stop", "index": index }));
+        }
+        events.push(json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": self.stop_reason, "stop_sequence": null },
+            "usage": { "input_tokens": self.input_tokens, "output_tokens": self.output_tokens },
+        }));
+        events.push(json!({ "type": "message_stop" }));
+        events
+    }
+
+    /// Close the open block and open `block`, returning its index
+    fn start_block(&mut self, events: &mut Vec<Value>, block: Value, kind: &'static str) -> u64 {
+        if let Some((index, _)) = self.open_block.take() {
+            events.push(json!({ "type": "content_block_stop", "index": index }));
+        }
+        let index = self.next_block;
+        self.next_block += 1;
+        self.open_block = Some((index, kind));
+        events.push(json!({ "type": "content_block_start", "index": index, "content_block": block }));
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chunks of an `AnthropicToChatStream`, parsed, without `created`
+    fn chat_chunks(stream: &mut AnthropicToChatStream, events: &[Value]) -> Vec<Value> {
+        events
+            .iter()
+            .flat_map(|event| stream.convert(event))
+            .map(|data| match serde_json::from_str::<Value>(&data) {
+                Ok(mut chunk) => {
+                    chunk.as_object_mut().unwrap().remove("created");
+                    chunk
+                }
+                Err(_) => json!(data),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chat_requests_become_anthropic_requests() {
+        let chat = json!({
+            "model": "claude-sonnet-4",
+            "temperature": 0.2,
+            "max_completion_tokens": 256,
+            "stop": "END",
+            "user": "u1",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "developer", "content": [{ "type": "text", "text": "Use tools." }] },
+                { "role": "user", "content": "Weather in Paris?" },
+                { "role": "user", "content": [
+                    { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } },
+                    { "type": "image_url", "image_url": { "url": "https://img.test/a.png" } },
+                ] },
+                { "role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "weather", "arguments": "{\"city\":\"Paris\"}" },
+                }] },
+                { "role": "tool", "tool_call_id": "call_1", "content": "Sunny" },
+            ],
+            "tools": [{ "type": "function", "function": {
+                "name": "weather",
+                "parameters": { "type": "object", "properties": { "city": { "type": "string" } } },
+            } }],
+            "tool_choice": "required",
+        });
+        let expected = json!({
+            "model": "claude-sonnet-4",
+            "temperature": 0.2,
+            "max_tokens": 256,
+            "system": "Be brief.\n\nUse tools.",
+            "stop_sequences": ["END"],
+            "metadata": { "user_id": "u1" },
+            "messages": [
+                { "role": "user", "content": [
+                    { "type": "text", "text": "Weather in Paris?" },
+                    { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "AAAA" } },
+                    { "type": "image", "source": { "type": "url", "url": "https://img.test/a.png" } },
+                ] },
+                { "role": "assistant", "content": [
+                    { "type": "tool_use", "id": "call_1", "name": "weather", "input": { "city": "Paris" } },
+                ] },
+                { "role": "user", "content": [{ "type": "tool_result", "tool_use_id": "call_1", "content": "Sunny" }] },
+            ],
+            "tools": [{
+                "name": "weather",
+                "description": "",
+                "input_schema": { "type": "object", "properties": { "city": { "type": "string" } } },
+            }],
+            "tool_choice": { "type": "any" },
+        });
+        assert_eq!(chat_to_anthropic_request(&chat).unwrap(), expected);
+
+        let minimal = chat_to_anthropic_request(&json!({ "model": "m", "messages": [{ "role": "user", "content": "hi" }] }));
+        assert_eq!(
+            minimal.unwrap(),
+            json!({
+                "model": "m",
+                "max_tokens": DEFAULT_ANTHROPIC_MAX_TOKENS,
+                "messages": [{ "role": "user", "content": [{ "type": "text", "text": "hi" }] }],
+            })
+        );
+    }
+
+    #[test]
+    fn unsupported_chat_requests_are_refused() {
+        let message = json!([{ "role": "user", "content": "hi" }]);
+        let cases = [
+            json!({ "messages": message, "n": 2 }),
+            json!({ "messages": message, "logprobs": true }),
+            json!({ "messages": "hi" }),
+            json!({ "messages": [{ "content": "hi" }] }),
+            json!({ "messages": [{ "role": "function", "content": "hi" }] }),
+            json!({ "messages": [{ "role": "user", "content": [{ "type": "input_audio" }] }] }),
+            json!({ "messages": [{ "role": "assistant", "tool_calls": [{ "function": { "arguments": "not json" } }] }] }),
+            json!([]),
+        ];
+        for case in cases {
+            assert!(chat_to_anthropic_request(&case).is_err(), "{case}");
+        }
+    }
+
+    #[test]
+    fn anthropic_responses_become_chat_completions() {
+        let message = json!({
+            "id": "msg_1",
+            "model": "claude-sonnet-4",
+            "content": [
+                { "type": "text", "text": "Checking. " },
+                { "type": "thinking", "thinking": "..." },
+                { "type": "tool_use", "id": "toolu_1", "name": "weather", "input": { "city": "Paris" } },
+            ],
+            "stop_reason": "tool_use",
+            "usage": { "input_tokens": 12, "output_tokens": 5 },
+        });
+        let mut chat = anthropic_to_chat_response(&message);
+        assert!(chat.as_object_mut().unwrap().remove("created").is_some());
+        assert_eq!(
+            chat,
+            json!({
+                "id": "msg_1",
+                "object": "chat.completion",
+                "model": "claude-sonnet-4",
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": "Checking. ",
+                        "tool_calls": [{
+                            "id": "toolu_1",
+                            "type": "function",
+                            "function": { "name": "weather", "arguments": "{\"city\":\"Paris\"}" },
+                        }],
+                    },
+                    "finish_reason": "tool_calls",
+                }],
+                "usage": { "prompt_tokens": 12, "completion_tokens": 5, "total_tokens": 17 },
+            })
+        );
+
+        // Tool calls alone leave no content
+        let tool_only = json!({ "content": [{ "type": "tool_use", "id": "t", "name": "n", "input": {} }] });
+        assert_eq!(anthropic_to_chat_response(&tool_only)["choices"][0]["message"]["content"], Value::Null);
+
+        for (stop, finish) in [
+            ("end_turn", "stop"),
+            ("stop_sequence", "stop"),
+            ("pause_turn", "stop"),
+            ("max_tokens", "length"),
+            ("refusal", "content_filter"),
+            ("other", "other"),
+        ] {
+            let chat = anthropic_to_chat_response(&json!({ "content": [], "stop_reason": stop }));
+            assert_eq!(chat["choices"][0]["finish_reason"], finish, "{stop}");
+        }
+    }
+
+    #[test]
+    fn anthropic_streams_become_chat_chunks() {
+        let events = [
+            json!({ "type": "message_start", "message": { "id": "msg_1", "model": "claude", "usage": { "input_tokens": 10 } } }),
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "text", "text": "" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "Hi" } }),
+            json!({ "type": "content_block_stop", "index": 0 }),
+            json!({ "type": "ping" }),
+            json!({ "type": "content_block_start", "index": 1, "content_block": { "type": "tool_use", "id": "toolu_1", "name": "weather" } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "input_json_delta", "partial_json": "{\"city\":" } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "input_json_delta", "partial_json": "\"Paris\"}" } }),
+            json!({ "type": "content_block_start", "index": 2, "content_block": { "type": "tool_use", "id": "toolu_2", "name": "time" } }),
+            json!({ "type": "content_block_delta", "index": 2, "delta": { "type": "input_json_delta", "partial_json": "{}" } }),
+            json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" }, "usage": { "output_tokens": 7 } }),
+            json!({ "type": "message_stop" }),
+        ];
+        let chunk = |delta: Value, finish_reason: Value| {
+            json!({
+                "id": "msg_1",
+                "object": "chat.completion.chunk",
+                "model": "claude",
+                "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+            })
+        };
+        let call = |index: usize, arguments: &str| json!({ "tool_calls": [{ "index": index, "function": { "arguments": arguments } }] });
+        let mut last = chunk(json!({}), json!("tool_calls"));
+        last["usage"] = json!({ "prompt_tokens": 10, "completion_tokens": 7, "total_tokens": 17 });
+
+        let expected = vec![
+            chunk(json!({ "role": "assistant", "content": "" }), Value::Null),
+            chunk(json!({ "content": "Hi" }), Value::Null),
+            chunk(
+                json!({ "tool_calls": [{ "index": 0, "id": "toolu_1", "type": "function", "function": { "name": "weather", "arguments": "" } }] }),
+                Value::Null,
+            ),
+            chunk(call(0, "{\"city\":"), Value::Null),
+            chunk(call(0, "\"Paris\"}"), Value::Null),
+            chunk(
+                json!({ "tool_calls": [{ "index": 1, "id": "toolu_2", "type": "function", "function": { "name": "time", "arguments": "" } }] }),
+                Value::Null,
+            ),
+            chunk(call(1, "{}"), Value::Null),
+            last,
+            json!("[DONE]"),
+        ];
+        assert_eq!(chat_chunks(&mut AnthropicToChatStream::default(), &events), expected);
+
+        let mut stream = AnthropicToChatStream::default();
+        let stops = [("end_turn", "stop"), ("max_tokens", "length"), ("refusal", "content_filter")];
+        for (stop, finish) in stops {
+            let chunks = chat_chunks(&mut stream, &[json!({ "type": "message_delta", "delta": { "stop_reason": stop } })]);
+            assert_eq!(chunks[0]["choices"][0]["finish_reason"], finish, "{stop}");
+            assert!(chunks[0].get("usage").is_none());
+        }
+    }
+
+    #[test]
+    fn chat_chunks_become_anthropic_events() {
+        let chunk = |delta: Value, finish_reason: Value| {
+            json!({ "id": "chatcmpl-1", "model": "gpt-4o", "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }] })
+        };
+        let chunks = [
+            chunk(json!({ "role": "assistant", "content": "" }), Value::Null),
+            chunk(json!({ "content": "Hi" }), Value::Null),
+            chunk(json!({ "content": " there" }), Value::Null),
+            chunk(
+                json!({ "tool_calls": [{ "index": 0, "id": "call_1", "function": { "name": "weather", "arguments": "" } }] }),
+                Value::Null,
+            ),
+            chunk(json!({ "tool_calls": [{ "index": 0, "function": { "arguments": "{\"city\":\"Paris\"}" } }] }), Value::Null),
+            chunk(json!({}), json!("tool_calls")),
+            json!({ "id": "chatcmpl-1", "choices": [], "usage": { "prompt_tokens": 9, "completion_tokens": 4 } }),
+        ];
+        let mut stream = ChatToAnthropicStream::default();
+        let mut events: Vec<Value> = chunks.iter().flat_map(|chunk| stream.convert(chunk)).collect();
+        events.extend(stream.finish());
+
+        let expected = vec![
+            json!({ "type": "message_start", "message": {
+                "id": "chatcmpl-1",
+                "type": "message",
+                "role": "assistant",
+                "model": "gpt-4o",
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": { "input_tokens": 0, "output_tokens": 0 },
+            } }),
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "text", "text": "" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "Hi" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": " there" } }),
+            json!({ "type": "content_block_stop", "index": 0 }),
+            json!({ "type": "content_block_start", "index": 1, "content_block": { "type": "tool_use", "id": "call_1", "name": "weather", "input": {} } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "input_json_delta", "partial_json": "{\"city\":\"Paris\"}" } }),
+            json!({ "type": "content_block_stop", "index": 1 }),
+            json!({
+                "type": "message_delta",
+                "delta": { "stop_reason": "tool_use", "stop_sequence": null },
+                "usage": { "input_tokens": 9, "output_tokens": 4 },
+            }),
+            json!({ "type": "message_stop" }),
+        ];
+        assert_eq!(events, expected);
+        assert!(stream.finish().is_empty(), "the message is closed once");
+
+        for (finish, stop) in [("stop", "end_turn"), ("length", "max_tokens"), ("content_filter", "refusal")] {
+            let mut stream = ChatToAnthropicStream::default();
+            stream.convert(&chunk(json!({ "content": "x" }), json!(finish)));
+            let events = stream.finish();
+            assert_eq!(events[events.len() - 2]["delta"]["stop_reason"], stop, "{finish}");
+        }
+        assert!(ChatToAnthropicStream::default().finish().is_empty(), "nothing to close before the first chunk");
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode, header::ALLOW};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use super::config::CorsConfig;
+
+impl CorsConfig {
+    /// Build the CORS layer for this policy. Entries that fail to parse are
+    /// skipped; `ProxyConfig::validate` reports them at load time.
+    pub fn layer(&self) -> CorsLayer {
+        let origins = if self.allowed_origins.iter().any(|o| o == "*") {
+            AllowOrigin::any()
+        } else {
+            AllowOrigin::list(
+                self.allowed_origins
+                    .iter()
+                    .filter_map(|o| HeaderValue::from_str(o).ok()),
+            )
+        };
+
+        let methods = if self.allowed_methods.iter().any(|m| m == "*") {
+            AllowMethods::any()
+        } else {
+            AllowMethods::list(
+                self.allowed_methods
+                    .iter()
+                    .filter_map(|m| Method::from_bytes(m.to_uppercase().as_bytes()).ok()),
+            )
+        };
+
+        let headers = if self.allowed_headers.iter().any(|h| h == "*") {
+            AllowHeaders::any()
+        } else {
+            AllowHeaders::list(
+                self.allowed_headers
+                    .iter()
+                    .filter_map(|h| HeaderName::from_bytes(h.as_bytes()).ok()),
+            )
+        };
+
+        let mut layer = CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .allow_credentials(self.allow_credentials);
+
+        if let Some(max_age) = self.max_age_secs {
+            layer = layer.max_age(Duration::from_secs(max_age));
+        }
+
+        layer
+    }
+}
+
+/// Handler answering `OPTIONS` on a proxy route with `204` and the route's
+/// methods in `Allow`. Preflights get their `Access-Control-Allow-*` headers
+/// from the route's CORS layer, which answers them before this handler runs.
+pub fn answer_options(methods: &[&str]) -> impl Fn() -> std::future::Ready<Response> + Clone + Send + 'static {
+    let allow = methods.iter().copied().chain(["OPTIONS"]).collect::<Vec<_>>().join(", ");
+    move || std::future::ready((StatusCode::NO_CONTENT, [(ALLOW, allow.clone())]).into_response())
+}
+
+/// Answer preflights with `204` rather than the CORS layer's empty `200`
+pub async fn preflight_no_content(req: Request, next: Next) -> Response {
+    let options = req.method() == Method::OPTIONS;
+    let mut response = next.run(req).await;
+    if options && response.status() == StatusCode::OK {
+        *response.status_mut() = StatusCode::NO_CONTENT;
+    }
+    response
+}
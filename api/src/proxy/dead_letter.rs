@@ -0,0 +1,84 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use axum::http::request::Parts;
+use serde_json::{Map, Value, json};
+use tracing::warn;
+
+use crate::error::ProxyError;
+use super::config::{DeadLetterConfig, EndpointConfig, ProxyConfig};
+use super::redact::{sanitize_body, sanitize_headers};
+
+/// Appends requests that could not be forwarded to the `dead_letter` file,
+/// one JSON object per line
+#[derive(Default)]
+pub struct DeadLetterLog {
+    /// Keeps appends and rotation from interleaving
+    write: Arc<Mutex<()>>,
+}
+
+impl DeadLetterLog {
+    /// Record a request refused with `error` before it reached the upstream.
+    /// Headers and body are sanitized like logged ones and the body cut to
+    /// `max_body_bytes`; the file is written in the background.
+    pub fn record(
+        &self,
+        config: &ProxyConfig,
+        endpoint: &EndpointConfig,
+        request: &Parts,
+        body: &[u8],
+        error: &ProxyError,
+        request_id: &str,
+    ) {
+        let Some(settings) = config.dead_letter.clone() else {
+            return;
+        };
+
+        let logging = config.body_logging(endpoint);
+        let body = sanitize_body(body, &config.log_redact_fields, &logging.redact_fields);
+        let limit = body.len().min(settings.max_body_bytes);
+        let headers: Map<String, Value> = sanitize_headers(&request.headers, &config.log_redact_headers)
+            .iter()
+            .map(|(name, value)| (name.to_string(), Value::String(String::from_utf8_lossy(value.as_bytes()).into_owned())))
+            .collect();
+        let entry = json!({
+            "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "request_id": request_id,
+            "endpoint": endpoint.path,
+            "method": request.method.as_str(),
+            "uri": request.uri.to_string(),
+            "status": error.status().as_u16(),
+            "error": { "type": error.error_type(), "message": error.message() },
+            "headers": headers,
+            "body": String::from_utf8_lossy(&body[..limit]),
+            "body_bytes": body.len(),
+            "body_truncated": limit < body.len(),
+        });
+
+        let write = self.write.clone();
+        tokio::task::spawn_blocking(move || {
+            let _write = write.lock().unwrap();
+            if let Err(e) = append(&settings, &entry) {
+                warn!("Could not write dead letter to {}: {}", settings.path, e);
+            }
+        });
+    }
+}
+
+/// Append `entry` as a line, first moving a file that would outgrow
+/// `max_file_bytes` to `<path>.1`
+fn append(settings: &DeadLetterConfig, entry: &Value) -> io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    let path = Path::new(&settings.path);
+    if let Ok(metadata) = fs::metadata(path)
+        && metadata.len() > 0
+        && metadata.len() + line.len() as u64 > settings.max_file_bytes
+    {
+        fs::rename(path, format!("{}.1", settings.path))?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(&line)
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::config::{EndpointConfig, EndpointMode, OnFull, ProxyConfig, ResponseType};
+
+/// Endpoint indices scanned for `PROXY_ENDPOINT_<N>_*` variables
+const MAX_ENV_ENDPOINTS: usize = 64;
+
+#[derive(Debug)]
+pub enum EnvError {
+    /// `PROXY_ENDPOINT_0_PATH` is not set
+    NoEndpoints,
+    /// A required variable is missing
+    Missing(String),
+    /// A variable is set to a value that cannot be parsed
+    Invalid { var: String, value: String, reason: String },
+    /// The assembled configuration failed validation
+    Validation(String),
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvError::NoEndpoints => write!(f, "PROXY_ENDPOINT_0_PATH is not set"),
+            EnvError::Missing(var) => write!(f, "{var} is not set"),
+            EnvError::Invalid { var, value, reason } => write!(f, "{var}={value}: {reason}"),
+            EnvError::Validation(message) => write!(f, "invalid configuration: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for EnvError {}
+
+impl From<String> for EnvError {
+    fn from(message: String) -> Self {
+        EnvError::Validation(message)
+    }
+}
+
+impl ProxyConfig {
+    /// Build the configuration from `PROXY_ENDPOINT_<N>_*` environment
+    /// variables, for deployments without a mounted YAML file. Endpoints are
+    /// read from `N = 0` up to the first index without a `_PATH`; settings
+    /// other than the endpoints keep their defaults.
+    pub fn load_from_env() -> Result<Self, EnvError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Built-in defaults, for deployments configured neither by a file nor
+    /// by `PROXY_ENDPOINT_<N>_*`; the Mistral endpoints are enabled when
+    /// `MISTRAL_API_KEY` is set
+    pub fn default_from_env() -> Self {
+        Self::default_from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn default_from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        match var("MISTRAL_API_KEY").filter(|key| !key.is_empty()) {
+            Some(_) => ProxyConfig::default().with_mistral_endpoints(),
+            None => ProxyConfig::default(),
+        }
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, EnvError> {
+        let mut endpoints = Vec::new();
+        for index in 0..MAX_ENV_ENDPOINTS {
+            let prefix = format!("PROXY_ENDPOINT_{index}_");
+            let Some(path) = var(&format!("{prefix}PATH")) else {
+                break;
+            };
+            endpoints.push(endpoint_from_vars(&prefix, path, &var)?);
+        }
+        if endpoints.is_empty() {
+            return Err(EnvError::NoEndpoints);
+        }
+
+        let config = ProxyConfig {
+            endpoints,
+            ..ProxyConfig::default()
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// One endpoint from the variables under `prefix`:
+///
+/// - `TARGET_URL` (required)
+/// - `METHOD` (default `POST`)
+/// - `RESPONSE_TYPE` (default `stream`)
+/// - `FORWARD_REQUEST_HEADERS`, `FORWARD_RESPONSE_HEADERS`: comma-separated names
+/// - `CUSTOM_HEADERS`: comma-separated `name=value` pairs
+/// - `ENABLED` (default `true`)
+/// - `TIMEOUT`: seconds
+fn endpoint_from_vars(
+    prefix: &str,
+    path: String,
+    var: &impl Fn(&str) -> Option<String>,
+) -> Result<EndpointConfig, EnvError> {
+    let name = |field: &str| format!("{prefix}{field}");
+    let invalid = |field: &str, value: &str, reason: &str| EnvError::Invalid {
+        var: name(field),
+        value: value.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let target_url = var(&name("TARGET_URL")).ok_or_else(|| EnvError::Missing(name("TARGET_URL")))?;
+    let method = var(&name("METHOD")).map_or_else(|| "POST".to_string(), |m| m.to_uppercase());
+
+    let response_type = match var(&name("RESPONSE_TYPE")) {
+        None => ResponseType::Stream,
+        Some(value) => match value.to_lowercase().as_str() {
+            "json" => ResponseType::Json,
+            "sse" => ResponseType::Sse,
+            "stream" => ResponseType::Stream,
+            "html" => ResponseType::Html,
+            "auto" => ResponseType::Auto,
+            "websocket" => ResponseType::WebSocket,
+            _ => return Err(invalid("RESPONSE_TYPE", &value, "expected json, sse, stream, html, auto or websocket")),
+        },
+    };
+
+    let mut custom_headers = HashMap::new();
+    if let Some(value) = var(&name("CUSTOM_HEADERS")) {
+        for pair in split_list(&value) {
+            let (header, header_value) = pair
+                .split_once('=')
+                .ok_or_else(|| invalid("CUSTOM_HEADERS", &value, "expected name=value pairs"))?;
+            custom_headers.insert(header.trim().to_string(), header_value.trim().to_string());
+        }
+    }
+
+    let enabled = match var(&name("ENABLED")) {
+        None => true,
+        Some(value) => value
+            .parse()
+            .map_err(|_| invalid("ENABLED", &value, "expected true or false"))?,
+    };
+
+    let timeout = match var(&name("TIMEOUT")) {
+        None => None,
+        Some(value) => Some(
+            value
+                .parse()
+                .map_err(|_| invalid("TIMEOUT", &value, "expected a number of seconds"))?,
+        ),
+    };
+
+    Ok(EndpointConfig {
+        path,
+        target_url,
+        targets: None,
+        method,
+        response_type,
+        custom_headers,
+        forward_request_headers: var(&name("FORWARD_REQUEST_HEADERS")).map_or_else(Vec::new, |v| split_list(&v)),
+        forward_response_headers: var(&name("FORWARD_RESPONSE_HEADERS")).map_or_else(Vec::new, |v| split_list(&v)),
+        enabled,
+        mode: EndpointMode::Proxy,
+        cors: None,
+        conversion: None,
+        shadow_target: None,
+        shadow: None,
+        rate_limit: None,
+        timeout,
+        max_stream_secs: None,
+        allowed_models: None,
+        logging: None,
+        slo: None,
+        stream_request_body: false,
+        stream_checksums: false,
+        auth: None,
+        max_concurrent: None,
+        on_full: OnFull::default(),
+        circuit_breaker: None,
+        forward_as_multipart: false,
+        cache_ttl_secs: None,
+        require_headers: Vec::new(),
+        retry: None,
+    })
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::config::MISTRAL_PATH_PREFIX;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn endpoints_are_read_until_the_first_missing_path() {
+        let config = ProxyConfig::from_vars(vars(&[
+            ("PROXY_ENDPOINT_0_PATH", "/v1/chat/completions"),
+            ("PROXY_ENDPOINT_0_TARGET_URL", "https://api.openai.com/v1/chat/completions"),
+            ("PROXY_ENDPOINT_0_METHOD", "post"),
+            ("PROXY_ENDPOINT_0_RESPONSE_TYPE", "SSE"),
+            ("PROXY_ENDPOINT_0_FORWARD_REQUEST_HEADERS", "authorization, content-type,"),
+            ("PROXY_ENDPOINT_0_CUSTOM_HEADERS", "x-team = core, x-env=prod"),
+            ("PROXY_ENDPOINT_0_TIMEOUT", "30"),
+            ("PROXY_ENDPOINT_1_PATH", "/v1/models"),
+            ("PROXY_ENDPOINT_1_TARGET_URL", "https://api.openai.com/v1/models"),
+            ("PROXY_ENDPOINT_1_ENABLED", "false"),
+            // Not read: index 2 has no path
+            ("PROXY_ENDPOINT_3_PATH", "/v1/embeddings"),
+        ]))
+        .unwrap();
+
+        assert_eq!(config.endpoints.len(), 2);
+        let chat = &config.endpoints[0];
+        assert_eq!(chat.target_url, "https://api.openai.com/v1/chat/completions");
+        assert_eq!(chat.method, "POST");
+        assert!(matches!(chat.response_type, ResponseType::Sse));
+        assert_eq!(chat.forward_request_headers, ["authorization", "content-type"]);
+        assert!(chat.forward_response_headers.is_empty());
+        assert_eq!(
+            chat.custom_headers,
+            HashMap::from([("x-team".to_string(), "core".to_string()), ("x-env".to_string(), "prod".to_string())])
+        );
+        assert_eq!(chat.timeout, Some(30));
+        assert!(chat.enabled);
+
+        // Defaults
+        let models = &config.endpoints[1];
+        assert_eq!(models.method, "POST");
+        assert!(matches!(models.response_type, ResponseType::Stream));
+        assert!(models.custom_headers.is_empty());
+        assert_eq!(models.timeout, None);
+        assert!(!models.enabled);
+        assert_eq!(config.idempotency_ttl, ProxyConfig::default().idempotency_ttl);
+    }
+
+    #[test]
+    fn invalid_values_name_their_variable() {
+        let endpoint = [("PROXY_ENDPOINT_0_PATH", "/v1/test"), ("PROXY_ENDPOINT_0_TARGET_URL", "https://upstream.test/")];
+        let cases = [
+            ("PROXY_ENDPOINT_0_TIMEOUT", "ten"),
+            ("PROXY_ENDPOINT_0_TIMEOUT", "-1"),
+            ("PROXY_ENDPOINT_0_ENABLED", "yes"),
+            ("PROXY_ENDPOINT_0_RESPONSE_TYPE", "xml"),
+            ("PROXY_ENDPOINT_0_CUSTOM_HEADERS", "x-team"),
+        ];
+        for (name, value) in cases {
+            let mut pairs = endpoint.to_vec();
+            pairs.push((name, value));
+            match ProxyConfig::from_vars(vars(&pairs)) {
+                Err(EnvError::Invalid { var, value: actual, .. }) => {
+                    assert_eq!((var.as_str(), actual.as_str()), (name, value));
+                }
+                other => panic!("{name}={value}: {other:?}"),
+            }
+        }
+
+        assert!(matches!(ProxyConfig::from_vars(vars(&[])), Err(EnvError::NoEndpoints)));
+        assert!(matches!(
+            ProxyConfig::from_vars(vars(&[("PROXY_ENDPOINT_0_PATH", "/v1/test")])),
+            Err(EnvError::Missing(var)) if var == "PROXY_ENDPOINT_0_TARGET_URL"
+        ));
+        assert!(matches!(
+            ProxyConfig::from_vars(vars(&[
+                ("PROXY_ENDPOINT_0_PATH", "/v1/test"),
+                ("PROXY_ENDPOINT_0_TARGET_URL", "https://upstream.test/{model}"),
+            ])),
+            Err(EnvError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn mistral_endpoints_follow_the_api_key() {
+        let mistral_enabled = |config: &ProxyConfig| {
+            let enabled: Vec<bool> = config
+                .endpoints
+                .iter()
+                .filter(|endpoint| endpoint.path.starts_with(MISTRAL_PATH_PREFIX))
+                .map(|endpoint| endpoint.enabled)
+                .collect();
+            assert!(!enabled.is_empty());
+            enabled.into_iter().all(|enabled| enabled)
+        };
+        assert!(!mistral_enabled(&ProxyConfig::default_from_vars(vars(&[]))));
+        assert!(!mistral_enabled(&ProxyConfig::default_from_vars(vars(&[("MISTRAL_API_KEY", "")]))));
+        assert!(mistral_enabled(&ProxyConfig::default_from_vars(vars(&[("MISTRAL_API_KEY", "key")]))));
+    }
+}
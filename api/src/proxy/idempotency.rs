@@ -0,0 +1,127 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Marks responses served from the idempotency cache
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Response stored for an idempotency key
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+}
+
+impl CachedResponse {
+    pub fn new(status: StatusCode, headers: HeaderMap, body: Bytes) -> Self {
+        Self {
+            status,
+            headers,
+            body,
+            stored_at: Instant::now(),
+        }
+    }
+
+    pub fn is_fresh(&self, ttl: Duration) -> bool {
+        self.stored_at.elapsed() < ttl
+    }
+
+    pub fn to_response(&self) -> Response {
+        self.to_response_marked(REPLAYED_HEADER, "true")
+    }
+
+    /// The stored response, with a `name: value` header telling where it came from
+    pub fn to_response_marked(&self, name: &'static str, value: &'static str) -> Response {
+        let mut response = Response::new(Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response.headers_mut().insert(name, HeaderValue::from_static(value));
+        response
+    }
+}
+
+/// Response stored for an idempotency key, with the SHA-256 digest of the
+/// request body it answered
+#[derive(Debug, Clone)]
+pub struct IdempotentResponse {
+    pub body_digest: String,
+    pub response: CachedResponse,
+}
+
+/// One slot per key; the async lock is held while the first request is
+/// forwarded, so concurrent duplicates wait for its response instead of
+/// sending their own
+pub type IdempotencySlot = Arc<tokio::sync::Mutex<Option<IdempotentResponse>>>;
+
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    ttl: Duration,
+    slots: Mutex<HashMap<String, IdempotencySlot>>,
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Get or create the slot for a key, dropping expired and unused slots
+    pub fn slot(&self, key: &str) -> IdempotencySlot {
+        let mut slots = self.slots.lock().unwrap();
+        slots.retain(|_, slot| {
+            // Slots someone else holds are in use, even when still empty
+            Arc::strong_count(slot) > 1
+                || slot
+                    .try_lock()
+                    .is_ok_and(|cached| cached.as_ref().is_some_and(|c| c.response.is_fresh(self.ttl)))
+        });
+        slots.entry(key.to_string()).or_default().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored(body: &'static str) -> Option<IdempotentResponse> {
+        Some(IdempotentResponse {
+            body_digest: body.to_string(),
+            response: CachedResponse::new(StatusCode::OK, HeaderMap::new(), Bytes::from_static(body.as_bytes())),
+        })
+    }
+
+    #[tokio::test]
+    async fn expired_and_unused_slots_are_dropped() {
+        let cache = IdempotencyCache::new(Duration::from_millis(50));
+        *cache.slot("stored").lock().await = stored("a");
+        let _held = cache.slot("held");
+        let _ = cache.slot("empty");
+
+        let slots = |cache: &IdempotencyCache| {
+            let mut keys: Vec<_> = cache.slots.lock().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        cache.slot("other");
+        assert_eq!(slots(&cache), ["held", "other", "stored"]);
+        assert!(cache.slot("stored").lock().await.is_some());
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(cache.slot("stored").lock().await.is_none(), "expired responses are forgotten");
+        assert_eq!(slots(&cache), ["held", "stored"]);
+    }
+}
@@ -0,0 +1,202 @@
+/// Expand environment references in a configuration document before it is
+/// parsed, so one file can serve several deployments:
+///
+/// - `${VAR}`: value of `VAR`, an error when it is unset
+/// - `${VAR:-default}`: `default` when `VAR` is unset or empty; the default
+///   may itself contain references
+/// - `$${text}`: the literal `${text}`
+///
+/// Values that YAML would read as more than one plain scalar, such as ones
+/// holding `: `, ` #`, quotes or a newline, are written as a double-quoted
+/// scalar when the reference is the whole value, and rejected when it is
+/// part of a longer one. Full-line comments are left untouched.
+pub fn interpolate(text: &str, var: &impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        if line.trim_start().starts_with('#') {
+            out.push_str(line);
+        } else {
+            out.push_str(&expand(line, var, true)?);
+        }
+    }
+    Ok(out)
+}
+
+/// Expand the references in `text`; `quote` escapes their values for a
+/// configuration line, defaults are expanded as they are written
+fn expand(text: &str, var: &impl Fn(&str) -> Option<String>, quote: bool) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = closing_brace(after).ok_or_else(|| format!("unterminated ${{ in {:?}", text.trim()))?;
+            let reference = &after[..end];
+            let value = resolve(reference, var)?;
+            rest = &after[end + 1..];
+            if quote {
+                out.push_str(&scalar(reference, &value, starts_scalar(&out), ends_scalar(rest))?);
+            } else {
+                out.push_str(&value);
+            }
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Whether a reference after `before` on its line begins a YAML value
+fn starts_scalar(before: &str) -> bool {
+    let key = before.trim_end();
+    key.is_empty() || (before.len() > key.len() && (key.ends_with(':') || key.ends_with('-')))
+}
+
+/// Whether a reference followed by `after` on its line ends a YAML value
+fn ends_scalar(after: &str) -> bool {
+    let tail = after.trim_start();
+    tail.is_empty() || (tail.len() < after.len() && tail.starts_with('#'))
+}
+
+/// `value` as it can be written in place of `${reference}`: unchanged when
+/// YAML reads it back as the same plain scalar, double-quoted when it stands
+/// for a whole value
+fn scalar(reference: &str, value: &str, starts: bool, ends: bool) -> Result<String, String> {
+    let special = value.contains(|c: char| c.is_control() || "\"'\\,[]{}".contains(c))
+        || value.contains(": ")
+        || value.contains(" #")
+        || (starts && value.starts_with(|c: char| c.is_whitespace() || "-?:#&*!|>%@`".contains(c)))
+        || (ends && (value.ends_with(char::is_whitespace) || value.ends_with(':')));
+    if !special {
+        Ok(value.to_string())
+    } else if starts && ends {
+        Ok(serde_json::Value::from(value).to_string())
+    } else {
+        Err(format!(
+            "the value of ${{{reference}}} needs quoting, which is only done when it is a whole value"
+        ))
+    }
+}
+
+/// Position of the `}` closing a reference whose body starts `text`,
+/// skipping over nested references
+fn closing_brace(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                depth += 1;
+                i += 1;
+            }
+            b'}' if depth == 0 => return Some(i),
+            b'}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Value of one `VAR` or `VAR:-default` reference
+fn resolve(reference: &str, var: &impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let (name, default) = match reference.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (reference, None),
+    };
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("invalid environment variable name {name:?} in ${{{reference}}}"));
+    }
+
+    match (var(name), default) {
+        (Some(value), None) => Ok(value),
+        (Some(value), Some(_)) if !value.is_empty() => Ok(value),
+        (_, Some(default)) => expand(default, var, false),
+        (None, None) => Err(format!(
+            "environment variable {name} is not set (use ${{{name}:-default}} to make it optional)"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some("api.example.com".to_string()),
+            "PORT" => Some("8443".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expands_references() {
+        let cases = [
+            ("url: https://${HOST}:${PORT}/v1", "url: https://api.example.com:8443/v1"),
+            ("plain: $HOST and $ alone", "plain: $HOST and $ alone"),
+            ("port: ${PORT:-80}", "port: 8443"),
+            ("port: ${MISSING:-80}", "port: 80"),
+            ("port: ${EMPTY:-80}", "port: 80"),
+            ("port: ${MISSING:-}", "port: "),
+            ("url: ${MISSING:-https://${HOST}:${PORT:-1}}", "url: https://api.example.com:8443"),
+            ("url: ${MISSING:-${ALSO_MISSING:-${HOST}}}", "url: api.example.com"),
+            ("literal: $${HOST}", "literal: ${HOST}"),
+            ("literal: $${MISSING:-x} ${HOST}", "literal: ${MISSING:-x} api.example.com"),
+            ("key: ${HOST}\n# ${MISSING}\n  # ${MISSING}\n", "key: api.example.com\n# ${MISSING}\n  # ${MISSING}\n"),
+            ("key: value # ${HOST}", "key: value # api.example.com"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(interpolate(text, &env).as_deref(), Ok(expected), "{text}");
+        }
+    }
+
+    #[test]
+    fn quotes_values_that_are_not_plain_scalars() {
+        let env = |name: &str| match name {
+            "NOTE" => Some("a: b # c".to_string()),
+            "LINES" => Some("one\ntwo: \"2\"".to_string()),
+            "ALIAS" => Some("*anchor".to_string()),
+            "PORT" => Some("8443".to_string()),
+            _ => None,
+        };
+        let text = "note: ${NOTE}\nlines: ${LINES} # comment\nitems:\n  - ${ALIAS}\nport: ${PORT}\n";
+        let yaml = interpolate(text, &env).unwrap();
+        let value: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(value["note"], "a: b # c");
+        assert_eq!(value["lines"], "one\ntwo: \"2\"");
+        assert_eq!(value["items"][0], "*anchor");
+        assert_eq!(value["port"], 8443);
+        assert_eq!(value.as_mapping().unwrap().len(), 4);
+
+        let error = interpolate("url: https://${NOTE}/v1", &env).unwrap_err();
+        assert!(error.contains("${NOTE} needs quoting"), "{error}");
+        assert!(interpolate("key: \"${LINES}\"", &env).is_err());
+    }
+
+    #[test]
+    fn rejects_unusable_references() {
+        let cases = [
+            ("key: ${MISSING}", "environment variable MISSING is not set"),
+            ("key: ${MISSING:-${ALSO_MISSING}}", "environment variable ALSO_MISSING is not set"),
+            ("key: ${HOST", "unterminated ${"),
+            ("key: ${1ST}", "invalid environment variable name \"1ST\""),
+            ("key: ${}", "invalid environment variable name \"\""),
+            ("key: ${HOST-NAME}", "invalid environment variable name \"HOST-NAME\""),
+        ];
+        for (text, expected) in cases {
+            let error = interpolate(text, &env).unwrap_err();
+            assert!(error.contains(expected), "{text}: {error}");
+        }
+    }
+}
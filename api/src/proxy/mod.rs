@@ -1,5 +1,28 @@
+pub mod body_patch;
+pub mod checksum;
+pub mod circuit;
 pub mod config;
+pub mod context_window;
+pub mod convert;
+pub mod cors;
+pub mod dead_letter;
+pub mod env;
+pub mod idempotency;
+pub mod interpolate;
+pub mod normalize;
+pub mod path_template;
+pub mod rate_limit;
+pub mod redact;
+pub mod reload;
+pub mod response_cache;
+pub mod route;
 pub mod service;
+pub mod shadow;
+pub mod slo;
+pub mod sse;
+pub mod timing;
+pub mod upstream_auth;
+pub mod websocket;
 
 pub use config::ProxyConfig;
 pub use service::ProxyService;
\ No newline at end of file
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    extract::Request,
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+use super::config::{EndpointConfig, PathNormalizationConfig};
+use super::path_template::PathTemplate;
+
+/// Maps request paths that no route matched onto endpoint paths, per the
+/// `path_normalization` settings
+pub struct PathNormalizer {
+    settings: PathNormalizationConfig,
+    templates: Vec<(String, PathTemplate)>,
+}
+
+impl PathNormalizer {
+    pub fn new(settings: PathNormalizationConfig, endpoints: &[&EndpointConfig]) -> Self {
+        let templates = endpoints
+            .iter()
+            .filter_map(|endpoint| Some((endpoint.path.clone(), PathTemplate::parse(&endpoint.path).ok()?)))
+            .collect();
+        Self { settings, templates }
+    }
+
+    /// The endpoint spelling of `path`, when it normalizes to exactly one
+    /// endpoint path. Paths matching several endpoints are left alone rather
+    /// than guessed.
+    pub fn normalize(&self, path: &str) -> Option<String> {
+        let mut matches: Vec<(&str, String)> = self
+            .templates
+            .iter()
+            .filter_map(|(endpoint, template)| {
+                let normalized = template.normalize(path, self.settings.case_insensitive, self.settings.optional_version)?;
+                Some((endpoint.as_str(), normalized))
+            })
+            .collect();
+        // Endpoints sharing a path under different methods are one match
+        matches.sort_by(|a, b| a.1.cmp(&b.1));
+        matches.dedup_by(|a, b| a.1 == b.1);
+
+        match matches.as_slice() {
+            [(_, normalized)] => Some(normalized.clone()),
+            [] => None,
+            _ => {
+                let endpoints: Vec<&str> = matches.iter().map(|(endpoint, _)| *endpoint).collect();
+                warn!("Path {} is ambiguous after normalization, matching {:?}", path, endpoints);
+                None
+            }
+        }
+    }
+}
+
+/// Fallback of the proxy routes: route the request again under its
+/// normalized path, or answer `404`
+pub async fn route_normalized(normalizer: Arc<PathNormalizer>, routes: Router, mut req: Request) -> Response {
+    let Some(path) = normalizer.normalize(req.uri().path()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let uri = match req.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+    let Ok(uri) = uri.parse::<Uri>() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    debug!("Normalized request path {} to {}", req.uri().path(), uri.path());
+    *req.uri_mut() = uri;
+    match routes.oneshot(req).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, body::Body, routing::post};
+    use serde_json::json;
+
+    use crate::test_support::{endpoint, proxy_config, proxy_service, send, spawn_upstream};
+
+    fn normalizer(case_insensitive: bool, optional_version: bool) -> PathNormalizer {
+        let endpoints: Vec<EndpointConfig> = [
+            "/v1/chat/completions",
+            "/api/provider/google/v1beta/models/{model}:streamGenerateContent",
+            "/api/v1/messages",
+            "/api/v2/messages",
+            "/files/{*rest}",
+        ]
+        .into_iter()
+        .map(|path| endpoint(json!({ "path": path })))
+        .collect();
+        let settings = PathNormalizationConfig { case_insensitive, optional_version };
+        PathNormalizer::new(settings, &endpoints.iter().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn normalizes_to_exactly_one_endpoint_path() {
+        let cases = [
+            // (case_insensitive, optional_version, request path, normalized)
+            (true, true, "/v1/chat/completions", Some("/v1/chat/completions")),
+            (true, false, "/V1/Chat/Completions", Some("/v1/chat/completions")),
+            (false, false, "/V1/Chat/Completions", None),
+            (false, true, "/chat/completions", Some("/v1/chat/completions")),
+            (false, false, "/chat/completions", None),
+            (true, true, "/CHAT/completions", Some("/v1/chat/completions")),
+            (
+                true,
+                false,
+                "/API/Provider/Google/V1BETA/Models/Gemini-2.5-Pro:streamGenerateContent",
+                Some("/api/provider/google/v1beta/models/Gemini-2.5-Pro:streamGenerateContent"),
+            ),
+            (
+                false,
+                true,
+                "/api/provider/google/models/gemini-2.5-pro:streamGenerateContent",
+                Some("/api/provider/google/v1beta/models/gemini-2.5-pro:streamGenerateContent"),
+            ),
+            (true, false, "/api/provider/google/v1beta/models/gemini-2.5-pro:countTokens", None),
+            (true, true, "/Api/V2/Messages", Some("/api/v2/messages")),
+            // Without its version the path fits both message endpoints
+            (true, true, "/api/messages", None),
+            (true, false, "/FILES/a/B/c.txt", Some("/files/a/B/c.txt")),
+            (true, true, "/v1/chat/completions/extra", None),
+            (true, true, "/unknown", None),
+        ];
+        for (case_insensitive, optional_version, path, expected) in cases {
+            let normalized = normalizer(case_insensitive, optional_version).normalize(path);
+            assert_eq!(
+                normalized.as_deref(),
+                expected,
+                "{path} (case_insensitive: {case_insensitive}, optional_version: {optional_version})"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unmatched_paths_fall_back_to_their_normalized_route() {
+        let upstream = spawn_upstream(Router::new().route("/{*path}", post(|uri: Uri| async move { Json(json!({ "uri": uri.to_string() })) }))).await;
+        let endpoints = ["/api/v1/messages", "/api/v2/messages", "/v1/chat/completions"]
+            .into_iter()
+            .map(|path| endpoint(json!({ "path": path, "target_url": format!("{upstream}{path}") })))
+            .collect();
+        let settings = json!({ "path_normalization": { "case_insensitive": true, "optional_version": true } });
+        let router = proxy_service(proxy_config(endpoints, settings)).create_router();
+
+        let cases = [
+            // (request path, upstream request URI)
+            ("/v1/chat/completions", Some("/v1/chat/completions")),
+            ("/Chat/Completions?x=1", Some("/v1/chat/completions?x=1")),
+            ("/API/V2/messages", Some("/api/v2/messages")),
+            ("/api/messages", None),
+            ("/v1/embeddings", None),
+        ];
+        for (path, expected) in cases {
+            let req = Request::post(path).header("content-type", "application/json").body(Body::from("{}")).unwrap();
+            let (status, _, body) = send(&router, req).await;
+            match expected {
+                Some(uri) => {
+                    assert_eq!(status, StatusCode::OK, "{path}");
+                    assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["uri"], uri, "{path}");
+                }
+                None => assert_eq!(status, StatusCode::NOT_FOUND, "{path}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+
+/// Endpoint path with `{name}` placeholders, which may share a segment with
+/// literals (`{model}:{op}`), and an optional trailing `{*rest}` catch-all.
+/// Axum only allows one whole-segment parameter, so mixed segments are
+/// registered as a single parameter and matched here.
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Parts(Vec<Part>),
+    CatchAll(String),
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Param(String),
+}
+
+impl PathTemplate {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let raw_segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        let mut segments = Vec::with_capacity(raw_segments.len());
+
+        for (index, raw) in raw_segments.iter().enumerate() {
+            if let Some(name) = raw.strip_prefix("{*").and_then(|r| r.strip_suffix('}')) {
+                if index != raw_segments.len() - 1 {
+                    return Err(format!("catch-all {{*{name}}} must be the last segment"));
+                }
+                segments.push(Segment::CatchAll(name.to_string()));
+                continue;
+            }
+            segments.push(Segment::Parts(parse_segment(raw)?));
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Names of all placeholders in the path
+    pub fn params(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::CatchAll(name) => names.push(name.as_str()),
+                Segment::Parts(parts) => names.extend(parts.iter().filter_map(|part| match part {
+                    Part::Param(name) => Some(name.as_str()),
+                    Part::Literal(_) => None,
+                })),
+            }
+        }
+        names
+    }
+
+    /// Route pattern to register with axum
+    pub fn route_path(&self) -> String {
+        let segments: Vec<String> = self
+            .segments
+            .iter()
+            .enumerate()
+            .map(|(index, segment)| match segment {
+                Segment::CatchAll(name) => format!("{{*{name}}}"),
+                Segment::Parts(parts) => match parts.as_slice() {
+                    [Part::Param(name)] => format!("{{{name}}}"),
+                    parts if parts.iter().all(|p| matches!(p, Part::Literal(_))) => parts
+                        .iter()
+                        .map(|p| match p {
+                            Part::Literal(literal) => literal.as_str(),
+                            Part::Param(_) => unreachable!(),
+                        })
+                        .collect(),
+                    _ => format!("{{__segment{index}}}"),
+                },
+            })
+            .collect();
+        format!("/{}", segments.join("/"))
+    }
+
+    /// Extract placeholder values from a request path
+    pub fn match_path(&self, path: &str) -> Option<HashMap<String, String>> {
+        let mut actual = path.trim_start_matches('/').split('/');
+        let mut params = HashMap::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::CatchAll(name) => {
+                    params.insert(name.clone(), actual.by_ref().collect::<Vec<_>>().join("/"));
+                }
+                Segment::Parts(parts) => match_segment(parts, actual.next()?, &mut params)?,
+            }
+        }
+
+        actual.next().is_none().then_some(params)
+    }
+
+    /// `path` rewritten to this template's spelling when it matches with
+    /// literal segments compared regardless of case (`case_insensitive`) or
+    /// with the template's version segment left out (`optional_version`).
+    /// Placeholder values are kept as sent.
+    pub fn normalize(&self, path: &str, case_insensitive: bool, optional_version: bool) -> Option<String> {
+        let actual: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        let version = self.segments.iter().position(|segment| match segment {
+            Segment::Parts(parts) => matches!(parts.as_slice(), [Part::Literal(literal)] if is_version(literal)),
+            Segment::CatchAll(_) => false,
+        });
+
+        self.normalize_segments(&actual, case_insensitive, None).or_else(|| {
+            let version = version.filter(|_| optional_version)?;
+            self.normalize_segments(&actual, case_insensitive, Some(version))
+        })
+    }
+
+    fn normalize_segments(&self, actual: &[&str], case_insensitive: bool, skip: Option<usize>) -> Option<String> {
+        let mut actual = actual.iter();
+        let mut normalized = Vec::with_capacity(self.segments.len());
+        let mut params = HashMap::new();
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::CatchAll(_) => normalized.extend(actual.by_ref().copied()),
+                Segment::Parts(parts) => match parts.as_slice() {
+                    [Part::Literal(literal)] => {
+                        if skip != Some(index) {
+                            let sent = actual.next()?;
+                            let same = if case_insensitive { sent.eq_ignore_ascii_case(literal) } else { sent == literal };
+                            if !same {
+                                return None;
+                            }
+                        }
+                        normalized.push(literal.as_str());
+                    }
+                    parts => {
+                        let sent = actual.next()?;
+                        match_segment(parts, sent, &mut params)?;
+                        normalized.push(sent);
+                    }
+                },
+            }
+        }
+
+        actual.next().is_none().then(|| format!("/{}", normalized.join("/")))
+    }
+}
+
+/// Version segments such as `v1`, `v2` or `v1beta`
+fn is_version(literal: &str) -> bool {
+    literal
+        .strip_prefix('v')
+        .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()) && rest.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+fn parse_segment(raw: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = raw;
+
+    while !rest.is_empty() {
+        match rest.find('{') {
+            Some(0) => {
+                let end = rest.find('}').ok_or_else(|| format!("unclosed placeholder in segment {raw}"))?;
+                let name = &rest[1..end];
+                if name.is_empty() || name.starts_with('*') {
+                    return Err(format!("invalid placeholder in segment {raw}"));
+                }
+                if matches!(parts.last(), Some(Part::Param(_))) {
+                    return Err(format!("placeholders must be separated by a literal in segment {raw}"));
+                }
+                parts.push(Part::Param(name.to_string()));
+                rest = &rest[end + 1..];
+            }
+            Some(start) => {
+                parts.push(Part::Literal(rest[..start].to_string()));
+                rest = &rest[start..];
+            }
+            None => {
+                parts.push(Part::Literal(rest.to_string()));
+                rest = "";
+            }
+        }
+    }
+
+    Ok(parts)
+}
+
+fn match_segment(parts: &[Part], actual: &str, params: &mut HashMap<String, String>) -> Option<()> {
+    let mut rest = actual;
+
+    for (index, part) in parts.iter().enumerate() {
+        match part {
+            Part::Literal(literal) => rest = rest.strip_prefix(literal.as_str())?,
+            Part::Param(name) => {
+                let value = match parts.get(index + 1) {
+                    Some(Part::Literal(next)) => {
+                        // The last parameter before the final literal is greedy
+                        let more_literals = parts[index + 2..].iter().any(|p| matches!(p, Part::Literal(_)));
+                        let end = if more_literals { rest.find(next.as_str())? } else { rest.rfind(next.as_str())? };
+                        &rest[..end]
+                    }
+                    _ => rest,
+                };
+                if value.is_empty() {
+                    return None;
+                }
+                params.insert(name.clone(), value.to_string());
+                rest = &rest[value.len()..];
+            }
+        }
+    }
+
+    rest.is_empty().then_some(())
+}
+
+/// Names of `{name}` (or `{*name}`) placeholders in a target URL
+pub fn placeholders(template: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else { break };
+        names.push(rest[start + 1..start + len].trim_start_matches('*').to_string());
+        rest = &rest[start + len + 1..];
+    }
+    names
+}
+
+/// Replace `{name}` (or `{*name}`) placeholders in a target URL; `None` when
+/// a value holds a dot segment, which would move the URL out of the
+/// template's path once resolved
+pub fn substitute(template: &str, params: &HashMap<String, String>) -> Option<String> {
+    if params.values().any(|value| has_dot_segment(value)) {
+        return None;
+    }
+    let mut url = template.to_string();
+    for (name, value) in params {
+        url = url.replace(&format!("{{{name}}}"), value).replace(&format!("{{*{name}}}"), value);
+    }
+    Some(url)
+}
+
+/// Whether a value has a `.` or `..` segment, also spelled with `%2e`. URLs
+/// with a special scheme such as `http` treat `\` as a separator too.
+fn has_dot_segment(value: &str) -> bool {
+    value.split(['/', '\\']).any(|segment| {
+        let decoded = segment.to_ascii_lowercase().replace("%2e", ".");
+        decoded == "." || decoded == ".."
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn matches_placeholders_and_literals() {
+        let cases = [
+            // (template, request path, captured values)
+            ("/v1/chat/completions", "/v1/chat/completions", Some(params(&[]))),
+            ("/v1/chat/completions", "/v1/chat", None),
+            ("/v1/chat/completions", "/v1/chat/completions/x", None),
+            ("/v1/models/{model}", "/v1/models/gpt-4o", Some(params(&[("model", "gpt-4o")]))),
+            ("/v1/models/{model}", "/v1/models/", None),
+            (
+                "/v1beta/models/{model}:{action}",
+                "/v1beta/models/gemini-2.5-pro:streamGenerateContent",
+                Some(params(&[("model", "gemini-2.5-pro"), ("action", "streamGenerateContent")])),
+            ),
+            // The last placeholder before the final literal is greedy
+            ("/models/{model}:{action}", "/models/a:b:c", Some(params(&[("model", "a:b"), ("action", "c")]))),
+            ("/v1beta/models/{model}:{action}", "/v1beta/models/gemini-2.5-pro", None),
+            ("/files/{*rest}", "/files/a/b.txt", Some(params(&[("rest", "a/b.txt")]))),
+            ("/files/{*rest}", "/files/", Some(params(&[("rest", "")]))),
+        ];
+        for (template, path, expected) in cases {
+            let template = PathTemplate::parse(template).unwrap();
+            assert_eq!(template.match_path(path), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn registers_mixed_segments_as_one_parameter() {
+        let template = PathTemplate::parse("/v1beta/models/{model}:{action}").unwrap();
+        assert_eq!(template.route_path(), "/v1beta/models/{__segment2}");
+        assert_eq!(template.params(), ["model", "action"]);
+        assert_eq!(PathTemplate::parse("/files/{*rest}").unwrap().route_path(), "/files/{*rest}");
+
+        for invalid in ["/files/{*rest}/x", "/a/{}", "/a/{model", "/a/{x}{y}"] {
+            assert!(PathTemplate::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn normalizes_case_and_missing_versions() {
+        let template = PathTemplate::parse("/v1/chat/{id}/messages").unwrap();
+        let cases = [
+            // (request path, case insensitive, optional version, normalized)
+            ("/v1/chat/AbC/messages", false, false, Some("/v1/chat/AbC/messages")),
+            ("/V1/Chat/AbC/MESSAGES", false, false, None),
+            ("/V1/Chat/AbC/MESSAGES", true, false, Some("/v1/chat/AbC/messages")),
+            ("/chat/AbC/messages", false, false, None),
+            ("/chat/AbC/messages", false, true, Some("/v1/chat/AbC/messages")),
+            ("/Chat/AbC/messages", true, true, Some("/v1/chat/AbC/messages")),
+            ("/v2/chat/AbC/messages", true, true, None),
+        ];
+        for (path, case_insensitive, optional_version, expected) in cases {
+            assert_eq!(
+                template.normalize(path, case_insensitive, optional_version).as_deref(),
+                expected,
+                "{path} {case_insensitive} {optional_version}"
+            );
+        }
+    }
+
+    #[test]
+    fn substitutes_values_without_dot_segments() {
+        let template = "https://files.test/{bucket}/{rest}";
+        assert_eq!(
+            substitute(template, &params(&[("bucket", "b"), ("rest", "a/.hidden/x..y")])).as_deref(),
+            Some("https://files.test/b/a/.hidden/x..y")
+        );
+        for rest in ["..", ".", "a/../../admin", "a/./b", "%2e%2e/admin", "%2E%2e", ".%2E", "%2e", "a\\..\\b"] {
+            assert_eq!(substitute(template, &params(&[("bucket", "b"), ("rest", rest)])), None, "{rest}");
+        }
+        assert_eq!(placeholders(template), ["bucket", "rest"]);
+        assert_eq!(placeholders("https://files.test/{*rest}"), ["rest"]);
+    }
+}
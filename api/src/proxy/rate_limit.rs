@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::config::RateLimitConfig;
+
+/// How often idle buckets are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Classic token bucket: holds up to `capacity` tokens and refills at `rate`
+/// tokens per second; each request consumes one token
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let capacity = f64::from(burst.max(1));
+        Self {
+            capacity,
+            tokens: capacity,
+            rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Add the tokens accumulated over `elapsed`, capped at capacity
+    pub fn refill(&mut self, elapsed: Duration) {
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+    }
+
+    /// Take one token if available
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        self.refill(now.duration_since(self.last_refill));
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the bucket was built for these limits
+    pub fn has_limits(&self, rate: f64, burst: u32) -> bool {
+        self.rate == rate && self.capacity == f64::from(burst.max(1))
+    }
+
+    /// Whether the bucket has refilled completely by `now`, making it no
+    /// different from a new one
+    fn is_idle(&self, now: Instant) -> bool {
+        self.tokens + now.duration_since(self.last_refill).as_secs_f64() * self.rate >= self.capacity
+    }
+
+    /// Time until the next token becomes available
+    pub fn retry_after(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+    }
+}
+
+struct LimiterState {
+    buckets: HashMap<(String, String), TokenBucket>,
+    last_sweep: Instant,
+}
+
+/// Token buckets by endpoint and client, created on first use and dropped
+/// once they have refilled. Buckets follow configuration reloads: a bucket
+/// whose limits changed starts over with the new ones.
+pub struct RateLimiter {
+    state: Mutex<LimiterState>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(LimiterState {
+                buckets: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// Consume a token from the bucket of `client` on `endpoint`, returning
+    /// the wait time when none is left
+    pub fn check(&self, endpoint: &str, client: String, limit: &RateLimitConfig) -> Option<Duration> {
+        let (rate, burst) = (limit.rate(), limit.burst_size);
+        let mut state = self.state.lock().unwrap();
+
+        let now = Instant::now();
+        if now.duration_since(state.last_sweep) >= SWEEP_INTERVAL {
+            state.buckets.retain(|_, bucket| !bucket.is_idle(now));
+            state.last_sweep = now;
+        }
+
+        let bucket = state
+            .buckets
+            .entry((endpoint.to_string(), client))
+            .or_insert_with(|| TokenBucket::new(rate, burst));
+        if !bucket.has_limits(rate, burst) {
+            *bucket = TokenBucket::new(rate, burst);
+        }
+        (!bucket.try_acquire()).then(|| bucket.retry_after())
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(requests_per_second: f64, burst_size: u32) -> RateLimitConfig {
+        RateLimitConfig { requests_per_second: Some(requests_per_second), requests_per_minute: None, burst_size, key: Default::default() }
+    }
+
+    #[test]
+    fn a_full_bucket_allows_one_burst() {
+        let mut bucket = TokenBucket::new(1.0, 3);
+        assert!((0..3).all(|_| bucket.try_acquire()));
+        assert!(!bucket.try_acquire());
+
+        let wait = bucket.retry_after();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1), "{wait:?}");
+    }
+
+    #[test]
+    fn refills_at_the_rate_up_to_the_burst() {
+        let mut bucket = TokenBucket::new(10.0, 5);
+        while bucket.try_acquire() {}
+
+        bucket.refill(Duration::from_millis(250));
+        assert_eq!(bucket.retry_after(), Duration::ZERO);
+        assert!(bucket.try_acquire() && bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+
+        bucket.refill(Duration::from_secs(60));
+        assert!((0..5).all(|_| bucket.try_acquire()));
+        assert!(!bucket.try_acquire());
+    }
+
+    #[test]
+    fn buckets_are_per_endpoint_and_client() {
+        let limiter = RateLimiter::new();
+        let limit = limit(0.5, 1);
+        assert_eq!(limiter.check("/v1/a", "alice".to_string(), &limit), None);
+        assert!(limiter.check("/v1/a", "alice".to_string(), &limit).is_some());
+
+        assert_eq!(limiter.check("/v1/a", "bob".to_string(), &limit), None);
+        assert_eq!(limiter.check("/v1/b", "alice".to_string(), &limit), None);
+    }
+
+    #[test]
+    fn changed_limits_start_a_new_bucket() {
+        let limiter = RateLimiter::new();
+        assert_eq!(limiter.check("/v1/a", "alice".to_string(), &limit(0.5, 1)), None);
+        assert!(limiter.check("/v1/a", "alice".to_string(), &limit(0.5, 1)).is_some());
+
+        assert_eq!(limiter.check("/v1/a", "alice".to_string(), &limit(0.5, 2)), None);
+        assert_eq!(limiter.check("/v1/a", "alice".to_string(), &limit(0.5, 2)), None);
+        assert!(limiter.check("/v1/a", "alice".to_string(), &limit(0.5, 2)).is_some());
+    }
+}
@@ -0,0 +1,187 @@
+use axum::http::{HeaderMap, HeaderValue};
+use serde_json::Value;
+use std::borrow::Cow;
+
+pub const REDACTED: &str = "***";
+
+/// Copy of `headers` with the values of sensitive headers replaced by `***`,
+/// for logging only; the forwarded request keeps the original values
+pub fn sanitize_headers(headers: &HeaderMap, redact: &[String]) -> HeaderMap {
+    let mut sanitized = headers.clone();
+    for (name, value) in sanitized.iter_mut() {
+        if redact.iter().any(|r| name.as_str().eq_ignore_ascii_case(r)) {
+            *value = HeaderValue::from_static(REDACTED);
+        }
+    }
+    sanitized
+}
+
+/// JSON body with the values of sensitive fields, named in `redact` at any
+/// depth or located by the JSON `pointers`, replaced by `***`; bodies that
+/// are not JSON are returned as-is
+pub fn sanitize_body<'a>(body: &'a [u8], redact: &[String], pointers: &[String]) -> Cow<'a, [u8]> {
+    let Ok(mut json) = serde_json::from_slice::<Value>(body) else {
+        return Cow::Borrowed(body);
+    };
+    let mut redacted = redact_fields(&mut json, redact);
+    for pointer in pointers {
+        let tokens: Vec<String> = pointer.split('/').skip(1).map(unescape_token).collect();
+        redacted |= redact_pointer(&mut json, &tokens);
+    }
+    if !redacted {
+        return Cow::Borrowed(body);
+    }
+    serde_json::to_vec(&json).map_or(Cow::Borrowed(body), Cow::Owned)
+}
+
+/// Reference token of a JSON pointer with `~1` and `~0` decoded
+fn unescape_token(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+/// Redact the values `tokens` lead to, `*` standing for every key or index.
+/// Returns whether anything was redacted.
+fn redact_pointer(value: &mut Value, tokens: &[String]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        *value = Value::String(REDACTED.to_string());
+        return true;
+    };
+    match value {
+        Value::Object(map) if token == "*" => map
+            .values_mut()
+            .fold(false, |redacted, field| redact_pointer(field, rest) | redacted),
+        Value::Object(map) => map.get_mut(token).is_some_and(|field| redact_pointer(field, rest)),
+        Value::Array(items) if token == "*" => items
+            .iter_mut()
+            .fold(false, |redacted, item| redact_pointer(item, rest) | redacted),
+        Value::Array(items) => token
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| items.get_mut(index))
+            .is_some_and(|item| redact_pointer(item, rest)),
+        _ => false,
+    }
+}
+
+/// Returns whether anything was redacted
+fn redact_fields(value: &mut Value, redact: &[String]) -> bool {
+    match value {
+        Value::Object(map) => {
+            let mut redacted = false;
+            for (key, field) in map.iter_mut() {
+                if redact.iter().any(|r| key.eq_ignore_ascii_case(r)) {
+                    *field = Value::String(REDACTED.to_string());
+                    redacted = true;
+                } else {
+                    redacted |= redact_fields(field, redact);
+                }
+            }
+            redacted
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .fold(false, |redacted, item| redact_fields(item, redact) | redacted),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{endpoint, proxy_config};
+
+    #[test]
+    fn sensitive_headers_are_logged_as_stars() {
+        let defaults = proxy_config(Vec::new(), json!({}));
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer sk-secret-token"));
+        headers.insert("X-Api-Key", HeaderValue::from_static("sk-ant-secret-key"));
+        headers.append("cookie", HeaderValue::from_static("session=secret-session"));
+        headers.append("cookie", HeaderValue::from_static("theme=secret-theme"));
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+
+        let sanitized = sanitize_headers(&headers, &defaults.log_redact_headers);
+        let logged = format!("{sanitized:?}");
+        for secret in ["sk-secret-token", "sk-ant-secret-key", "secret-session", "secret-theme"] {
+            assert!(!logged.contains(secret), "{logged}");
+        }
+        assert!(logged.contains(r#""authorization": "***""#), "{logged}");
+        assert_eq!(sanitized["x-api-key"], REDACTED);
+        assert_eq!(sanitized.get_all("cookie").iter().count(), 2);
+        assert_eq!(sanitized["content-type"], "application/json");
+
+        // The forwarded headers keep their values
+        assert_eq!(headers["authorization"], "Bearer sk-secret-token");
+        // Only the configured names are redacted
+        let sanitized = sanitize_headers(&headers, &["X-API-KEY".to_string()]);
+        assert_eq!(sanitized["x-api-key"], REDACTED);
+        assert_eq!(sanitized["authorization"], "Bearer sk-secret-token");
+    }
+
+    #[test]
+    fn key_bearing_body_fields_are_logged_as_stars() {
+        let defaults = proxy_config(Vec::new(), json!({}));
+        let body = json!({
+            "model": "gpt-4o",
+            "api_key": "sk-secret-1",
+            "auth": { "Authorization": "Bearer secret-2", "scheme": "bearer" },
+            "tools": [{ "name": "fetch", "config": { "client_secret": "secret-3", "password": { "nested": "secret-4" } } }],
+            "messages": [{ "role": "user", "content": "my password is public" }],
+        });
+        let raw = body.to_string();
+
+        let sanitized = sanitize_body(raw.as_bytes(), &defaults.log_redact_fields, &[]);
+        let logged = String::from_utf8_lossy(&sanitized);
+        for secret in ["sk-secret-1", "secret-2", "secret-3", "secret-4"] {
+            assert!(!logged.contains(secret), "{logged}");
+        }
+        let expected = json!({
+            "model": "gpt-4o",
+            "api_key": "***",
+            "auth": { "Authorization": "***", "scheme": "bearer" },
+            "tools": [{ "name": "fetch", "config": { "client_secret": "***", "password": "***" } }],
+            "messages": [{ "role": "user", "content": "my password is public" }],
+        });
+        assert_eq!(serde_json::from_slice::<Value>(&sanitized).unwrap(), expected);
+
+        // Bodies without sensitive fields, or that are not JSON, are logged as sent
+        let clean = br#"{ "model" : "gpt-4o" }"#;
+        assert!(matches!(sanitize_body(clean, &defaults.log_redact_fields, &[]), Cow::Borrowed(b) if b == clean));
+        let text = b"api_key=sk-not-json";
+        assert!(matches!(sanitize_body(text, &defaults.log_redact_fields, &[]), Cow::Borrowed(b) if b == text));
+    }
+
+    #[test]
+    fn pointed_fields_are_logged_as_stars() {
+        let config = proxy_config(
+            vec![endpoint(json!({ "logging": { "redact_fields": ["/metadata/a~1b"] } }))],
+            json!({ "logging": { "redact_fields": ["/messages/*/content", "/items/1", "/missing/field"] } }),
+        );
+        let logging = config.body_logging(&config.endpoints[0]);
+        let body = json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "private 1" }, { "role": "assistant", "content": "private 2" }],
+            "items": ["kept", "private 3", "kept"],
+            "metadata": { "a/b": "private 4", "a": { "b": "kept" } },
+            "authorization": "private 5",
+        });
+
+        let sanitized = sanitize_body(body.to_string().as_bytes(), &config.log_redact_fields, &logging.redact_fields).into_owned();
+        let expected = json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "***" }, { "role": "assistant", "content": "***" }],
+            "items": ["kept", "***", "kept"],
+            "metadata": { "a/b": "***", "a": { "b": "kept" } },
+            "authorization": "***",
+        });
+        assert_eq!(serde_json::from_slice::<Value>(&sanitized).unwrap(), expected);
+        assert!(!String::from_utf8_lossy(&sanitized).contains("private"));
+
+        // Endpoints without overrides only use the global pointers
+        assert_eq!(config.body_logging(&endpoint(json!({}))).redact_fields.len(), 3);
+        let invalid = proxy_config(Vec::new(), json!({ "logging": { "redact_fields": ["messages/0"] } }));
+        assert!(invalid.logging.validate().is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, error, info, warn};
+
+use super::circuit::CircuitBreakers;
+use super::config::ProxyConfig;
+use super::upstream_auth::{Credential, resolve_credentials, resolve_shadow_credentials, resolve_target_credentials};
+
+/// The configuration in effect and the upstream credentials resolved from it
+#[derive(Clone)]
+pub struct ConfigSnapshot {
+    pub config: Arc<ProxyConfig>,
+    pub credentials: Arc<HashMap<String, Credential>>,
+    /// Credentials of the endpoints' shadow upstreams, by endpoint path
+    pub shadow_credentials: Arc<HashMap<String, Credential>>,
+    /// Credentials of the `targets` of endpoints, by endpoint path
+    pub target_credentials: Arc<HashMap<String, Vec<Option<Credential>>>>,
+}
+
+/// Configuration shared by the proxy handlers, replaced as a whole on reload.
+/// Requests take a snapshot when they start, so in-flight requests finish
+/// with the configuration they started with. Metrics, SLO windows and rate
+/// limit buckets live outside the configuration and carry over.
+pub struct LiveConfig {
+    current: RwLock<ConfigSnapshot>,
+    /// Successful reloads since startup
+    version: AtomicU64,
+    /// Circuit state, reset per circuit when its breaker settings change
+    circuits: Arc<CircuitBreakers>,
+}
+
+impl LiveConfig {
+    /// Fails when an endpoint's upstream credential cannot be read from the environment
+    pub fn new(config: ProxyConfig, circuits: Arc<CircuitBreakers>) -> Result<Self, String> {
+        let credentials = resolve_credentials(&config)?;
+        let shadow_credentials = resolve_shadow_credentials(&config)?;
+        let target_credentials = resolve_target_credentials(&config)?;
+        Ok(Self {
+            current: RwLock::new(ConfigSnapshot {
+                config: Arc::new(config),
+                credentials: Arc::new(credentials),
+                shadow_credentials: Arc::new(shadow_credentials),
+                target_credentials: Arc::new(target_credentials),
+            }),
+            version: AtomicU64::new(0),
+            circuits,
+        })
+    }
+
+    pub fn snapshot(&self) -> ConfigSnapshot {
+        self.current.read().expect("config lock poisoned").clone()
+    }
+
+    pub fn config(&self) -> Arc<ProxyConfig> {
+        self.snapshot().config
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Re-read and validate the configuration from `source` (a file path or
+    /// URL) and swap it in. Blocks while reading; an invalid configuration
+    /// leaves the current one in place.
+    pub fn reload_from(&self, source: &str) -> Result<u64, String> {
+        let config = ProxyConfig::load_from_file(source).map_err(|e| e.to_string())?;
+        self.replace(config)
+    }
+
+    /// Like `reload_from`, but leaves the configuration and its version alone
+    /// when the source is unchanged; `None` in that case
+    pub fn refresh_from(&self, source: &str) -> Result<Option<u64>, String> {
+        let config = ProxyConfig::load_from_file(source).map_err(|e| e.to_string())?;
+        if serde_json::to_value(&config).ok() == serde_json::to_value(&*self.config()).ok() {
+            return Ok(None);
+        }
+        self.replace(config).map(Some)
+    }
+
+    /// Swap in an already validated configuration and return the new version.
+    /// Nothing changes when its credentials cannot be resolved.
+    pub fn replace(&self, config: ProxyConfig) -> Result<u64, String> {
+        let credentials = resolve_credentials(&config)?;
+        let shadow_credentials = resolve_shadow_credentials(&config)?;
+        let target_credentials = resolve_target_credentials(&config)?;
+        let mut current = self.current.write().expect("config lock poisoned");
+        warn_route_changes(&current.config, &config);
+        self.circuits.reload(&current.config, &config);
+        *current = ConfigSnapshot {
+            config: Arc::new(config),
+            credentials: Arc::new(credentials),
+            shadow_credentials: Arc::new(shadow_credentials),
+            target_credentials: Arc::new(target_credentials),
+        };
+        Ok(self.version.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+}
+
+/// Re-read `source` every `interval` and swap it in when it changed, so
+/// central configuration changes propagate without signals
+pub async fn refresh_periodically(config: Arc<LiveConfig>, source: String, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes immediately, right after startup loaded the config
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let live = config.clone();
+        let source = source.clone();
+        match tokio::task::spawn_blocking(move || live.refresh_from(&source)).await {
+            Ok(Ok(Some(version))) => info!("Proxy configuration refreshed (version {})", version),
+            Ok(Ok(None)) => debug!("Proxy configuration unchanged"),
+            Ok(Err(e)) => warn!("Keeping the current proxy configuration: {}", e),
+            Err(e) => error!("Proxy configuration refresh failed: {}", e),
+        }
+    }
+}
+
+/// Routes are registered once at startup: endpoints added by a reload are not
+/// served until a restart, and removed ones answer 404
+fn warn_route_changes(old: &ProxyConfig, new: &ProxyConfig) {
+    let routes = |config: &ProxyConfig| -> HashSet<(String, String)> {
+        config
+            .enabled_endpoints()
+            .into_iter()
+            .map(|endpoint| (endpoint.method.to_uppercase(), endpoint.path.clone()))
+            .collect()
+    };
+    let (old, new) = (routes(old), routes(new));
+    for (method, path) in new.difference(&old) {
+        warn!("Endpoint {} {} was added by the reload and needs a restart to be served", method, path);
+    }
+    for (method, path) in old.difference(&new) {
+        warn!("Endpoint {} {} was removed by the reload and now answers 404", method, path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::proxy::config::CircuitBreakerConfig;
+    use crate::test_support::{endpoint, proxy_config, temp_dir};
+
+    fn yaml(target_url: &str) -> String {
+        format!(
+            "endpoints:
+  - path: /v1/test
+    target_url: {target_url}
+    method: POST
+    response_type: json
+    custom_headers:
+      x-team: core
+    forward_request_headers: []
+    forward_response_headers: []
+    enabled: true
+"
+        )
+    }
+
+    #[test]
+    fn reloads_swap_the_configuration_for_new_snapshots() {
+        let path = temp_dir("reload").join("proxy_config.yaml");
+        std::fs::write(&path, yaml("https://old.example.com/v1")).unwrap();
+        let source = path.to_str().unwrap();
+        let live = LiveConfig::new(ProxyConfig::load_from_file(source).unwrap(), Arc::default()).unwrap();
+        let in_flight = live.snapshot();
+
+        std::fs::write(&path, yaml("https://new.example.com/v1")).unwrap();
+        assert_eq!(live.reload_from(source), Ok(1));
+        assert_eq!(live.config().endpoints[0].target_url, "https://new.example.com/v1");
+        assert_eq!(in_flight.config.endpoints[0].target_url, "https://old.example.com/v1");
+
+        // An invalid file keeps the configuration and its version
+        std::fs::write(&path, "endpoints: []\nglobal_timeout: 0\n").unwrap();
+        assert!(live.reload_from(source).unwrap_err().contains("global_timeout"));
+        assert_eq!(live.config().endpoints[0].target_url, "https://new.example.com/v1");
+        assert!(live.reload_from(path.with_extension("missing").to_str().unwrap()).is_err());
+
+        std::fs::write(&path, yaml("https://newer.example.com/v1")).unwrap();
+        assert_eq!(live.reload_from(source), Ok(2));
+    }
+
+    #[tokio::test]
+    async fn refreshes_pick_up_changes_to_the_source() {
+        let path = temp_dir("refresh").join("proxy_config.yaml");
+        std::fs::write(&path, yaml("https://old.example.com/v1")).unwrap();
+        let source = path.to_str().unwrap().to_string();
+        let live = Arc::new(LiveConfig::new(ProxyConfig::load_from_file(&source).unwrap(), Arc::default()).unwrap());
+        assert_eq!(live.refresh_from(&source), Ok(None), "unchanged sources are not swapped in");
+
+        let refresh = tokio::spawn(refresh_periodically(live.clone(), source, Duration::from_millis(50)));
+        std::fs::write(&path, "endpoints: []\nglobal_timeout: 0\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(live.config().endpoints[0].target_url, "https://old.example.com/v1");
+
+        std::fs::write(&path, yaml("https://new.example.com/v1")).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while live.config().endpoints[0].target_url != "https://new.example.com/v1" {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("the refresh swaps in the changed configuration");
+        refresh.abort();
+    }
+
+    #[test]
+    fn reloads_editing_one_endpoint_keep_the_circuits_of_the_others() {
+        let config = |b_timeout: u64, b_threshold: u32| {
+            let endpoints = vec![
+                endpoint(json!({ "path": "/a", "circuit_breaker": { "failure_threshold": 1 } })),
+                endpoint(json!({
+                    "path": "/b",
+                    "timeout": b_timeout,
+                    "circuit_breaker": { "failure_threshold": b_threshold },
+                })),
+            ];
+            proxy_config(endpoints, json!({}))
+        };
+        let circuits: Arc<CircuitBreakers> = Arc::default();
+        let live = LiveConfig::new(config(30, 1), circuits.clone()).unwrap();
+        let breaker = CircuitBreakerConfig { failure_threshold: 1, success_threshold: 1, cooldown_secs: 60 };
+        for key in ["/a", "/b"] {
+            circuits.record_failure(key, &breaker);
+        }
+        let open = |key: &str| circuits.allow(key, &breaker).is_err();
+
+        // Settings other than the breaker leave the circuit alone
+        assert_eq!(live.replace(config(60, 1)), Ok(1));
+        assert!(open("/a") && open("/b"));
+
+        assert_eq!(live.replace(config(60, 2)), Ok(2));
+        assert!(open("/a"), "the unchanged endpoint keeps its open circuit");
+        assert!(!open("/b"), "a changed breaker starts closed");
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use axum::http::{HeaderMap, header::CACHE_CONTROL};
+
+use super::idempotency::CachedResponse;
+
+/// Marks responses of endpoints with `cache_ttl_secs` as served from the
+/// cache (`HIT`) or forwarded (`MISS`)
+pub const CACHE_HEADER: &str = "x-cache";
+
+/// Response extension set when the upstream answered `Cache-Control: no-store`,
+/// whether or not that header is forwarded to the client
+#[derive(Debug, Clone, Copy)]
+pub struct NoStore;
+
+/// Whether upstream response headers forbid storing the response
+pub fn forbids_storing(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"))
+}
+
+struct Entry {
+    path: String,
+    ttl: Duration,
+    response: CachedResponse,
+}
+
+/// Successful responses of `GET` endpoints with `cache_ttl_secs`, by key
+#[derive(Default)]
+pub struct ResponseCache {
+    entries: RwLock<HashMap<String, Entry>>,
+}
+
+impl ResponseCache {
+    /// The stored response for `key`, if still fresh
+    pub fn get(&self, key: &str) -> Option<CachedResponse> {
+        let entries = self.entries.read().unwrap();
+        let entry = entries.get(key)?;
+        entry.response.is_fresh(entry.ttl).then(|| entry.response.clone())
+    }
+
+    /// Store a response for `ttl`, dropping expired entries on the way.
+    /// Returns the endpoint paths of the dropped entries.
+    pub fn insert(&self, key: String, path: &str, ttl: Duration, response: CachedResponse) -> Vec<String> {
+        let mut entries = self.entries.write().unwrap();
+        let mut evicted = Vec::new();
+        entries.retain(|_, entry| {
+            let fresh = entry.response.is_fresh(entry.ttl);
+            if !fresh {
+                evicted.push(entry.path.clone());
+            }
+            fresh
+        });
+        entries.insert(key, Entry { path: path.to_string(), ttl, response });
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use axum::http::{HeaderValue, StatusCode};
+
+    fn response(body: &'static str) -> CachedResponse {
+        CachedResponse::new(StatusCode::OK, HeaderMap::new(), Bytes::from_static(body.as_bytes()))
+    }
+
+    #[test]
+    fn no_store_is_found_among_other_directives() {
+        let headers = |values: &[&'static str]| {
+            let mut headers = HeaderMap::new();
+            for value in values {
+                headers.append(CACHE_CONTROL, HeaderValue::from_static(value));
+            }
+            headers
+        };
+        assert!(forbids_storing(&headers(&["no-store"])));
+        assert!(forbids_storing(&headers(&["private, No-Store , max-age=0"])));
+        assert!(forbids_storing(&headers(&["max-age=60", "no-store"])));
+        assert!(!forbids_storing(&headers(&["no-cache, max-age=60"])));
+        assert!(!forbids_storing(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn entries_expire_after_their_ttl_and_are_evicted_on_insert() {
+        let cache = ResponseCache::default();
+        assert!(cache.insert("a".to_string(), "/v1/a", Duration::from_millis(50), response("a")).is_empty());
+        assert!(cache.insert("b".to_string(), "/v1/b", Duration::from_secs(60), response("b")).is_empty());
+        assert!(cache.get("a").is_some());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("missing").is_none());
+
+        let evicted = cache.insert("c".to_string(), "/v1/c", Duration::from_secs(60), response("c"));
+        assert_eq!(evicted, ["/v1/a"]);
+        assert_eq!(cache.entries.read().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,315 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::config::{
+    Conversion, EndpointConfig, EndpointMode, ModelRoute, ProxyConfig, ResponseType, UpstreamAuthConfig,
+};
+use super::path_template::{PathTemplate, substitute};
+#[cfg(feature = "admin")]
+use super::redact::REDACTED;
+
+/// The parts of an inbound request that decide where it is forwarded
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RequestMeta {
+    /// Only read when resolving a route from scratch, see `resolve_route`
+    #[cfg_attr(not(feature = "admin"), allow(dead_code))]
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub query: Option<String>,
+    /// `model` field of the request body
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// How a request is handled, decided without any network I/O
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutePlan {
+    /// Configured path of the matched endpoint
+    pub endpoint: String,
+    pub method: String,
+    pub mode: EndpointMode,
+    pub response_type: ResponseType,
+    pub conversion: Option<Conversion>,
+    /// URL of the first of `targets`
+    pub target_url: String,
+    /// Upstreams the request may go to, as configured; see `attempt_order`
+    pub targets: Vec<PlannedTarget>,
+    pub model_route: Option<ModelRoute>,
+    pub upstream_auth: UpstreamAuthConfig,
+    /// Request headers copied upstream
+    pub forwarded_headers: HashMap<String, String>,
+    /// Headers added from the endpoint configuration
+    pub custom_headers: HashMap<String, String>,
+}
+
+/// One upstream of a planned request
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedTarget {
+    pub url: String,
+    pub weight: Option<u32>,
+    pub upstream_auth: UpstreamAuthConfig,
+    /// Position in the endpoint's `targets`; unset for `target_url` and
+    /// model routes, which use the endpoint credential
+    #[serde(skip)]
+    pub index: Option<usize>,
+}
+
+impl RoutePlan {
+    /// Targets in the order they are tried: as listed, or drawn by weight
+    /// when any target has one, targets without a weight counting as 1
+    pub fn attempt_order(&self) -> Vec<&PlannedTarget> {
+        let mut remaining: Vec<&PlannedTarget> = self.targets.iter().collect();
+        if remaining.iter().all(|target| target.weight.is_none()) {
+            return remaining;
+        }
+        let weight = |target: &PlannedTarget| u64::from(target.weight.unwrap_or(1));
+        let mut order = Vec::with_capacity(remaining.len());
+        while !remaining.is_empty() {
+            let total: u64 = remaining.iter().map(|target| weight(target)).sum();
+            let mut draw = ulid::Ulid::new().random() as u64 % total;
+            let index = remaining
+                .iter()
+                .position(|target| match draw.checked_sub(weight(target)) {
+                    Some(rest) => {
+                        draw = rest;
+                        false
+                    }
+                    None => true,
+                })
+                .unwrap_or(0);
+            order.push(remaining.remove(index));
+        }
+        order
+    }
+
+    /// Mask the values of headers listed in `names`
+    #[cfg(feature = "admin")]
+    pub fn redacted(mut self, names: &[String]) -> Self {
+        for headers in [&mut self.forwarded_headers, &mut self.custom_headers] {
+            for (name, value) in headers.iter_mut() {
+                if names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+                    *value = REDACTED.to_string();
+                }
+            }
+        }
+        self
+    }
+}
+
+/// Find the enabled endpoint serving a request and plan its forwarding
+#[cfg(feature = "admin")]
+pub fn resolve_route(config: &ProxyConfig, meta: &RequestMeta) -> Option<RoutePlan> {
+    config
+        .enabled_endpoints()
+        .into_iter()
+        .filter(|endpoint| endpoint.method.eq_ignore_ascii_case(&meta.method))
+        .find_map(|endpoint| plan_route(config, endpoint, meta))
+}
+
+/// Plan the forwarding of a request already matched to `endpoint`; `None`
+/// when the request path does not fit the endpoint's path template or fills
+/// a placeholder with a dot segment
+pub fn plan_route(config: &ProxyConfig, endpoint: &EndpointConfig, meta: &RequestMeta) -> Option<RoutePlan> {
+    let params = PathTemplate::parse(&endpoint.path).ok()?.match_path(&meta.path)?;
+    let observe = endpoint.mode == EndpointMode::Observe;
+
+    // Observe mode streams the body untouched, so it never looks at the model
+    let model_route = match (&meta.model, observe) {
+        (Some(model), false) => config.match_model_route(model).cloned(),
+        _ => None,
+    };
+
+    let upstream_auth = match &endpoint.auth {
+        Some(auth) if !observe => auth.clone(),
+        _ => UpstreamAuthConfig::Passthrough,
+    };
+    let query = meta.query.as_deref();
+    let targets: Vec<PlannedTarget> = match &model_route {
+        Some(route) => vec![PlannedTarget {
+            url: with_query(route.target_url.clone(), query),
+            weight: None,
+            upstream_auth: upstream_auth.clone(),
+            index: None,
+        }],
+        None => endpoint
+            .upstream_targets()
+            .iter()
+            .enumerate()
+            .map(|(index, target)| {
+                Some(PlannedTarget {
+                    url: with_query(substitute(&target.url, &params)?, query),
+                    weight: target.weight,
+                    upstream_auth: match &target.auth {
+                        Some(auth) if !observe => auth.clone(),
+                        _ => upstream_auth.clone(),
+                    },
+                    index: endpoint.targets.is_some().then_some(index),
+                })
+            })
+            .collect::<Option<_>>()?,
+    };
+
+    let forwarded_headers = endpoint
+        .forward_request_headers
+        .iter()
+        .filter_map(|name| {
+            meta.headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| (name.clone(), value.clone()))
+        })
+        .collect();
+
+    Some(RoutePlan {
+        endpoint: endpoint.path.clone(),
+        method: endpoint.method.to_uppercase(),
+        mode: endpoint.mode.clone(),
+        response_type: endpoint.response_type.clone(),
+        conversion: match (&model_route, observe) {
+            (_, true) => None,
+            (Some(route), false) => route.provider.conversion(endpoint.conversion),
+            (None, false) => endpoint.conversion,
+        },
+        target_url: targets.first()?.url.clone(),
+        targets,
+        model_route,
+        upstream_auth,
+        forwarded_headers,
+        custom_headers: if observe { HashMap::new() } else { endpoint.custom_headers.clone() },
+    })
+}
+
+/// Append the incoming query string to an upstream URL
+fn with_query(mut url: String, query: Option<&str>) -> String {
+    if let Some(query) = query.filter(|q| !q.is_empty()) {
+        url.push(if url.contains('?') { '&' } else { '?' });
+        url.push_str(query);
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    use crate::proxy::config::Provider;
+
+    use crate::test_support::{endpoint, proxy_config};
+
+    fn meta(path: &str, model: Option<&str>) -> RequestMeta {
+        RequestMeta {
+            method: "POST".to_string(),
+            path: path.to_string(),
+            model: model.map(str::to_string),
+            ..RequestMeta::default()
+        }
+    }
+
+    fn routed_config(conversion: Option<&str>) -> (ProxyConfig, EndpointConfig) {
+        let endpoint = endpoint(json!({
+            "path": "/v1/chat/completions",
+            "target_url": "https://upstream.test/v1/chat/completions",
+            "conversion": conversion,
+        }));
+        let config = proxy_config(vec![endpoint.clone()], json!({
+            "model_routes": [
+                { "model_prefix": "claude-", "target_url": "https://anthropic.test/v1/messages", "provider": "anthropic" },
+                { "model_prefix": "gpt-", "target_url": "https://openai.test/v1/chat/completions", "provider": "openai" },
+                { "model_prefix": "gemini-", "target_url": "https://google.test/v1/chat/completions", "provider": "google" },
+            ],
+        }));
+        (config, endpoint)
+    }
+
+    #[test]
+    fn plans_the_upstream_url() {
+        let endpoints = [
+            ("/v1/chat/completions", "https://upstream.test/v1/chat/completions"),
+            (
+                "/api/provider/google/v1beta/models/{model}:{action}",
+                "https://google.test/v1beta/models/{model}:{action}",
+            ),
+            ("/files/{*rest}", "https://files.test/{rest}"),
+        ];
+        let (config, _) = routed_config(None);
+        let cases = [
+            // (endpoint, request path, query, model, planned target)
+            (0, "/v1/chat/completions", None, None, Some("https://upstream.test/v1/chat/completions")),
+            (0, "/v1/chat/completions", Some("a=1"), None, Some("https://upstream.test/v1/chat/completions?a=1")),
+            (0, "/v1/chat/completions", None, Some("llama-3"), Some("https://upstream.test/v1/chat/completions")),
+            (0, "/v1/chat/completions", None, Some("gpt-4o"), Some("https://openai.test/v1/chat/completions")),
+            (0, "/v1/chat/completions", Some("a=1"), Some("claude-sonnet-4"), Some("https://anthropic.test/v1/messages?a=1")),
+            (0, "/v1/chat", None, None, None),
+            (0, "/v1/chat/completions/extra", None, None, None),
+            (
+                1,
+                "/api/provider/google/v1beta/models/gemini-2.5-pro:streamGenerateContent",
+                Some("alt=sse"),
+                None,
+                Some("https://google.test/v1beta/models/gemini-2.5-pro:streamGenerateContent?alt=sse"),
+            ),
+            (1, "/api/provider/google/v1beta/models/gemini-2.5-pro", None, None, None),
+            (2, "/files/a/b.txt", None, None, Some("https://files.test/a/b.txt")),
+            (2, "/files/a/../../admin", None, None, None),
+            (2, "/files/%2E%2e/admin", None, None, None),
+        ];
+        for (index, path, query, model, expected) in cases {
+            let (endpoint_path, target_url) = endpoints[index];
+            let endpoint = endpoint(json!({ "path": endpoint_path, "target_url": target_url }));
+            let meta = RequestMeta { query: query.map(str::to_string), ..meta(path, model) };
+            let plan = plan_route(&config, &endpoint, &meta);
+            assert_eq!(plan.as_ref().map(|plan| plan.target_url.as_str()), expected, "{path} {query:?} {model:?}");
+        }
+    }
+
+    #[test]
+    fn longest_model_prefix_wins() {
+        let (mut config, endpoint) = routed_config(None);
+        config.model_routes.push(ModelRoute {
+            model_prefix: "claude-3".to_string(),
+            target_url: "https://legacy.test/v1/messages".to_string(),
+            provider: Provider::Anthropic,
+        });
+        let plan = |model| plan_route(&config, &endpoint, &meta("/v1/chat/completions", Some(model))).unwrap();
+        assert_eq!(plan("claude-3-opus").target_url, "https://legacy.test/v1/messages");
+        assert_eq!(plan("claude-sonnet-4").target_url, "https://anthropic.test/v1/messages");
+    }
+
+    #[test]
+    fn model_route_provider_picks_the_conversion() {
+        let anthropic = Some(Conversion::OpenaiToAnthropic);
+        let legacy = Some(Conversion::LegacyCompletions);
+        let cases = [
+            // (endpoint conversion, model, planned conversion)
+            (None, Some("claude-sonnet-4"), anthropic),
+            (None, Some("gpt-4o"), None),
+            (None, Some("gemini-2.5-pro"), None),
+            (None, Some("mistral-large"), None),
+            (None, None, None),
+            (Some("openai_to_anthropic"), Some("gpt-4o"), None),
+            (Some("openai_to_anthropic"), Some("claude-sonnet-4"), anthropic),
+            (Some("openai_to_anthropic"), Some("gemini-2.5-pro"), anthropic),
+            (Some("openai_to_anthropic"), Some("mistral-large"), anthropic),
+            (Some("legacy_completions"), Some("claude-sonnet-4"), legacy),
+            (Some("legacy_completions"), Some("gpt-4o"), legacy),
+        ];
+        for (conversion, model, expected) in cases {
+            let (config, endpoint) = routed_config(conversion);
+            let plan = plan_route(&config, &endpoint, &meta("/v1/chat/completions", model)).unwrap();
+            assert_eq!(plan.conversion, expected, "endpoint {conversion:?}, model {model:?}");
+        }
+    }
+
+    #[test]
+    fn observe_mode_never_converts() {
+        let (config, mut endpoint) = routed_config(Some("openai_to_anthropic"));
+        endpoint.mode = EndpointMode::Observe;
+        let plan = plan_route(&config, &endpoint, &meta("/v1/chat/completions", Some("claude-sonnet-4"))).unwrap();
+        assert_eq!(plan.conversion, None);
+        assert!(plan.model_route.is_none());
+    }
+}
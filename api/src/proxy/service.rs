@@ -2,171 +2,2002 @@ use async_stream::stream;
 use axum::{
     Json, Router,
     body::Body,
-    extract::Request,
-    http::{HeaderMap, HeaderName, StatusCode, Method},
+    extract::{ConnectInfo, FromRequestParts, Request, ws::WebSocketUpgrade},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Method, request::Parts, header::{ACCEPT, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER, TRANSFER_ENCODING}},
     response::{
         IntoResponse, Response,
         sse::{Event, Sse},
     },
+    middleware,
     routing::{get, post, put, delete},
 };
+use bytes::Bytes;
+use futures_util::Stream;
 use reqwest::Client;
+use reqwest::multipart::{Form, Part};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::convert::Infallible;
-use tracing::{error, info, warn};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tracing::{Instrument, Level, debug, error, info, info_span, warn};
 use serde_json::Value;
 
-use crate::get_amp_api_key;
-use super::config::{ProxyConfig, EndpointConfig, ResponseType};
+use crate::auth::{AdminAccess, ClientKey, has_admin_token};
+use crate::error::{ProxyError, create_error_response};
+use crate::metrics::{InFlightGuard, ProxyMetrics};
+use crate::request_id::{REQUEST_ID_HEADER, request_id};
+use crate::shutdown::{DrainGuard, Shutdown};
+use crate::usage::{UsageLedger, UsageRecorder};
+use super::config::{
+    BodyLogLevel, CircuitBreakerConfig, ContextWindowEnforcement, Conversion, ProxyConfig, EndpointConfig, EndpointMode, LoggingConfig,
+    OnFull, OversizedHeaderAction, RateLimitKey, ResponseType, RetryConfig,
+};
+use super::body_patch::{self, BODY_PATCH_HEADER};
+use super::checksum::StreamChecksum;
+use super::circuit::{CircuitBreakers, endpoint_target_key, upstream_host};
+use super::context_window::{context_window, estimate_tokens};
+use super::dead_letter::DeadLetterLog;
+use super::cors::{answer_options, preflight_no_content};
+use super::convert::{self, StreamConverter};
+use super::idempotency::{CachedResponse, IdempotencyCache, IdempotentResponse};
+use super::response_cache::{CACHE_HEADER, NoStore, ResponseCache, forbids_storing};
+use super::normalize::{PathNormalizer, route_normalized};
+use super::path_template::PathTemplate;
+use super::route::{PlannedTarget, RequestMeta, RoutePlan, plan_route};
+use super::rate_limit::RateLimiter;
+use super::redact::{sanitize_body, sanitize_headers};
+use super::shadow::{PrimaryOutcome, ShadowRequest, ShadowTraffic};
+use super::slo::{SloMonitor, SloTracker};
+use super::sse::{SseParser, provider_error};
+use super::timing::{TimedConnectLayer, TimedResolver, UpstreamPhases, record_phases};
+use super::reload::{ConfigSnapshot, LiveConfig};
+use super::upstream_auth::Credential;
+use super::websocket;
+
+/// Running SHA-256 and byte count of a body observed in passing
+#[derive(Default)]
+struct BodyDigest {
+    hasher: Sha256,
+    len: u64,
+}
+
+impl BodyDigest {
+    fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        self.len += chunk.len() as u64;
+    }
+
+    fn finish(self) -> (u64, String) {
+        (self.len, hex::encode(self.hasher.finalize()))
+    }
+}
+
+/// Why a stream stopped waiting for its next item
+enum Interrupted {
+    /// `wait` passed
+    TimedOut,
+    /// The server is shutting down and its drain window is over
+    ShuttingDown,
+}
+
+/// Next item of a stream, or `Err` once `wait` has passed or shutdown cuts
+/// the stream off; without a `wait` this waits as long as the stream does
+async fn next_within<S: Stream + Unpin>(
+    stream: &mut S,
+    wait: Option<Duration>,
+    drain: &DrainGuard,
+) -> Result<Option<S::Item>, Interrupted> {
+    let next = async {
+        let next = futures_util::StreamExt::next(stream);
+        match wait {
+            Some(wait) => tokio::time::timeout(wait, next).await.map_err(|_| Interrupted::TimedOut),
+            None => Ok(next.await),
+        }
+    };
+    tokio::select! {
+        next = next => next,
+        () = drain.cut_off() => Err(Interrupted::ShuttingDown),
+    }
+}
 
+/// SSE comment sent to streams cut by shutdown
+const SHUTDOWN_COMMENT: &str = "server shutting down";
+
+/// Response header naming the target that served an endpoint with `targets`
+const UPSTREAM_HEADER: &str = "x-amp-upstream";
+
+/// Framing of converted streams, negotiated from the client's `Accept`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamFormat {
+    Sse,
+    /// One JSON document per line, as `application/x-ndjson`
+    Ndjson,
+}
+
+impl StreamFormat {
+    /// NDJSON when the client prefers it over `text/event-stream` by `q`
+    /// value; SSE otherwise
+    fn negotiate(headers: &HeaderMap) -> Self {
+        let accept = headers.get(ACCEPT).and_then(|a| a.to_str().ok()).unwrap_or_default();
+        let (mut sse_q, mut ndjson_q) = (0.0f32, 0.0f32);
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or_default().trim();
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            match media_type {
+                "text/event-stream" => sse_q = sse_q.max(q),
+                "application/x-ndjson" | "application/ndjson" => ndjson_q = ndjson_q.max(q),
+                _ => {}
+            }
+        }
+        if ndjson_q > sse_q { StreamFormat::Ndjson } else { StreamFormat::Sse }
+    }
+}
+
+/// A converted stream item before framing
+enum ConvertedFrame {
+    Data(String),
+    Error(Value),
+    /// The stream is cut by shutdown; SSE clients get a comment
+    ShuttingDown,
+}
+
+/// Per-request bookkeeping, moved into streaming bodies so a request counts
+/// as in flight until its stream finishes
+struct RequestContext {
+    path: String,
+    /// Sent upstream and included in structured error bodies
+    request_id: String,
+    started: Instant,
+    metrics: Arc<ProxyMetrics>,
+    /// Upstream timeout covering connect, response headers and, for streams,
+    /// the first body chunk
+    timeout: Duration,
+    /// Cap on streaming time after the first chunk, see `max_stream_secs`
+    max_stream: Option<Duration>,
+    slo: Option<Arc<SloTracker>>,
+    /// Log stream checksums, see `EndpointConfig::stream_checksums`
+    stream_checksums: bool,
+    _in_flight: InFlightGuard,
+    /// Keeps shutdown waiting for the request, and cuts its stream once the
+    /// drain window is over
+    drain: DrainGuard,
+    /// Concurrency slot of endpoints with `max_concurrent`
+    _slot: Option<OwnedSemaphorePermit>,
+    /// Upstream phase timings, reported in the access log
+    phases: Arc<Mutex<UpstreamPhases>>,
+    /// Upstream requests reissued by `retry`, reported in the access log
+    retries: Arc<UpstreamRetries>,
+    /// Token usage of the response, taken by the handler that reads it;
+    /// only set with a `usage` section
+    usage: Option<UsageRecorder>,
+}
+
+/// Retries of an upstream request, by the phase whose failure caused them
+#[derive(Default)]
+struct UpstreamRetries {
+    send: AtomicU64,
+    body_read: AtomicU64,
+}
+
+impl UpstreamRetries {
+    /// Count for the access log, `None` when there were none
+    fn count(counter: &AtomicU64) -> Option<u64> {
+        Some(counter.load(Ordering::Relaxed)).filter(|&count| count > 0)
+    }
+}
+
+impl RequestContext {
+    fn observe_first_byte(&self) {
+        self.metrics.observe_time_to_first_byte(&self.path, self.started.elapsed());
+    }
+
+    /// Time left to wait for the next stream chunk: the rest of `timeout`
+    /// before the first chunk, then the rest of `max_stream` (if any)
+    fn stream_wait(&self, first_chunk_at: Option<Instant>) -> Option<Duration> {
+        match first_chunk_at {
+            None => Some(self.timeout.saturating_sub(self.started.elapsed())),
+            Some(at) => self.max_stream.map(|max| max.saturating_sub(at.elapsed())),
+        }
+    }
+
+    /// Error ending a stream that ran out of `stream_wait`
+    fn stream_timeout(&self, first_chunk_at: Option<Instant>) -> ProxyError {
+        let message = match (first_chunk_at, self.max_stream) {
+            (Some(_), Some(max)) => format!("Stream exceeded the {}s limit", max.as_secs()),
+            _ => format!("Upstream sent no data within {}s", self.timeout.as_secs()),
+        };
+        warn!("{}, closing", message);
+        ProxyError::TimeoutError(message)
+    }
+
+    /// Report a finished stream's throughput to the endpoint SLO, measured
+    /// from its first chunk
+    fn observe_stream_end(&self, events: u64, first_chunk_at: Option<Instant>) {
+        if let (Some(slo), Some(first_chunk_at)) = (&self.slo, first_chunk_at) {
+            slo.record_stream(events, first_chunk_at.elapsed());
+        }
+    }
+
+    /// Log and count an error the upstream reported mid-stream; the stream
+    /// ends with it
+    fn stream_failed(&self, error: ProxyError) -> ProxyError {
+        warn!(
+            path = %self.path,
+            outcome = "upstream_error",
+            error_type = error.error_type(),
+            "Upstream reported an error mid-stream, closing"
+        );
+        self.metrics.record_stream_error(&self.path, error.error_type());
+        error
+    }
+
+    /// Count a payload `conversion` could not handle, for `reason`
+    fn conversion_failed(&self, conversion: Conversion, reason: &str) {
+        self.metrics.record_conversion_failure(&self.path, conversion.name(), reason);
+    }
+}
+
+#[derive(Clone)]
 pub struct ProxyService {
-    config: ProxyConfig,
+    /// Configuration snapshot of the request being handled, taken from `live`
+    config: Arc<ProxyConfig>,
+    live: Arc<LiveConfig>,
+    client: Client,
+    /// Client without response decompression, for observe mode
+    passthrough_client: Client,
+    metrics: Arc<ProxyMetrics>,
+    rate_limiter: Arc<RateLimiter>,
+    circuits: Arc<CircuitBreakers>,
+    shutdown: Arc<Shutdown>,
+    /// `max_concurrent` slots by endpoint path
+    concurrency: Arc<HashMap<String, Arc<Semaphore>>>,
+    idempotency: Arc<IdempotencyCache>,
+    response_cache: Arc<ResponseCache>,
+    dead_letters: Arc<DeadLetterLog>,
+    slo: Arc<SloMonitor>,
+    /// Response bodies over `large_response_bytes` seen so far, for sampling
+    large_responses: Arc<AtomicU64>,
+    /// Upstream credentials by endpoint path, from the same snapshot as `config`
+    credentials: Arc<HashMap<String, Credential>>,
+    /// Shadow upstream credentials by endpoint path, from the same snapshot
+    shadow_credentials: Arc<HashMap<String, Credential>>,
+    /// Credentials of endpoint `targets` by endpoint path, from the same snapshot
+    target_credentials: Arc<HashMap<String, Vec<Option<Credential>>>>,
+    shadow: Arc<ShadowTraffic>,
+    usage: Arc<UsageLedger>,
 }
 
 impl ProxyService {
-    pub fn new(config: ProxyConfig) -> Self {
-        Self {
+    /// Fails when an endpoint's upstream credential cannot be read from the environment.
+    /// SLO trackers, concurrency limits, the idempotency TTL and CORS policies
+    /// are built here and keep their startup settings across reloads.
+    pub fn new(config: ProxyConfig, metrics: Arc<ProxyMetrics>) -> Result<Self, String> {
+        let circuits = Arc::new(CircuitBreakers::default());
+        let live = Arc::new(LiveConfig::new(config, circuits.clone())?);
+        let ConfigSnapshot { config, credentials, shadow_credentials, target_credentials } = live.snapshot();
+        let concurrency = config
+            .enabled_endpoints()
+            .into_iter()
+            .filter_map(|endpoint| Some((endpoint.path.clone(), Arc::new(Semaphore::new(endpoint.max_concurrent?)))))
+            .collect();
+        let idempotency = IdempotencyCache::new(Duration::from_secs(config.idempotency_ttl));
+        let slo = Arc::new(SloMonitor::new(&config));
+        let usage = Arc::new(UsageLedger::new(&config)?);
+        let client = Self::build_client(true);
+        let shadow = Arc::new(ShadowTraffic::new(client.clone(), metrics.clone()));
+
+        Ok(Self {
             config,
-        }
+            live,
+            client,
+            passthrough_client: Self::build_client(false),
+            metrics,
+            rate_limiter: Arc::new(RateLimiter::new()),
+            circuits,
+            shutdown: Arc::new(Shutdown::new()),
+            concurrency: Arc::new(concurrency),
+            idempotency: Arc::new(idempotency),
+            response_cache: Arc::new(ResponseCache::default()),
+            dead_letters: Arc::new(DeadLetterLog::default()),
+            slo,
+            large_responses: Arc::new(AtomicU64::new(0)),
+            credentials,
+            shadow_credentials,
+            target_credentials,
+            shadow,
+            usage,
+        })
+    }
+
+    /// Build the HTTP client shared by all endpoints. `reqwest::Client` keeps
+    /// its connection pool behind an `Arc`, so clones handed to the handlers
+    /// reuse the same pooled connections, HTTP/2 sessions and TLS sessions.
+    ///
+    /// - `pool_max_idle_per_host`: idle connections kept per upstream host
+    /// - `pool_idle_timeout`: how long an idle connection stays in the pool
+    /// - `tcp_keepalive`: TCP keepalive probes so idle connections to the
+    ///   upstream are not silently dropped by NATs and load balancers
+    /// - `connection_verbose`: logs connection reads/writes under the
+    ///   `reqwest::connect::verbose` target at TRACE level
+    /// - `gzip`/`brotli`/`deflate`/`zstd`: advertise and transparently decode
+    ///   compressed responses, so body handling always sees plaintext
+    fn build_client(decompress: bool) -> Client {
+        Client::builder()
+            .pool_max_idle_per_host(32)
+            .pool_idle_timeout(Duration::from_secs(90))
+            .tcp_keepalive(Duration::from_secs(60))
+            .connection_verbose(true)
+            .gzip(decompress)
+            .brotli(decompress)
+            .deflate(decompress)
+            .zstd(decompress)
+            .dns_resolver(Arc::new(TimedResolver))
+            .connector_layer(TimedConnectLayer)
+            .build()
+            .expect("failed to build HTTP client")
+    }
+
+    pub fn live_config(&self) -> Arc<LiveConfig> {
+        self.live.clone()
+    }
+
+    pub fn slo(&self) -> Arc<SloMonitor> {
+        self.slo.clone()
+    }
+
+    pub fn usage(&self) -> Arc<UsageLedger> {
+        self.usage.clone()
+    }
+
+    pub fn shutdown(&self) -> Arc<Shutdown> {
+        self.shutdown.clone()
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn circuits(&self) -> Arc<CircuitBreakers> {
+        self.circuits.clone()
     }
 
     pub fn create_router(&self) -> Router {
         let mut router = Router::new();
 
+        // Methods served on each route, answered to `OPTIONS` by the route's
+        // first endpoint
+        let mut route_methods: HashMap<String, Vec<&str>> = HashMap::new();
+        for endpoint in self.config.enabled_endpoints() {
+            let method = endpoint.method.to_uppercase();
+            if let Ok(template) = PathTemplate::parse(&endpoint.path)
+                && let Some(method) = ["GET", "POST", "PUT", "DELETE"].into_iter().find(|m| *m == method)
+            {
+                route_methods.entry(template.route_path()).or_default().push(method);
+            }
+        }
+
         for endpoint in self.config.enabled_endpoints() {
             let endpoint_clone = endpoint.clone();
-            let path = endpoint.path.clone();
+            let path = match PathTemplate::parse(&endpoint.path) {
+                Ok(template) => template.route_path(),
+                Err(e) => {
+                    warn!("Invalid path {}: {}", endpoint.path, e);
+                    continue;
+                }
+            };
+            let service = self.clone();
+
+            let mut method_router = match endpoint.method.to_uppercase().as_str() {
+                "GET" => get(move |req| service.handle_proxy_request(endpoint_clone, req)),
+                "POST" => post(move |req| service.handle_proxy_request(endpoint_clone, req)),
+                "PUT" => put(move |req| service.handle_proxy_request(endpoint_clone, req)),
+                "DELETE" => delete(move |req| service.handle_proxy_request(endpoint_clone, req)),
+                _ => {
+                    warn!("Unsupported HTTP method: {} for path: {}", endpoint.method, endpoint.path);
+                    continue;
+                }
+            };
+
+            if let Some(methods) = route_methods.remove(&path) {
+                method_router = method_router.options(answer_options(&methods));
+            }
+
+            // Endpoint CORS policy overrides the global one
+            if let Some(cors) = endpoint.cors.as_ref().or(self.config.cors.as_ref()) {
+                method_router = method_router
+                    .layer(cors.layer())
+                    .layer(middleware::from_fn(preflight_no_content));
+            }
+
+            router = router.route(&path, method_router);
+        }
+
+        if let Some(settings) = &self.config.path_normalization {
+            let normalizer = Arc::new(PathNormalizer::new(settings.clone(), &self.config.enabled_endpoints()));
+            let routes = router.clone();
+            router = router.fallback(move |req: Request| route_normalized(normalizer, routes, req));
+        }
+
+        router
+    }
+
+    /// `route` is the endpoint the route was registered for; the request is
+    /// handled with that endpoint's settings from the current configuration.
+    /// Every failure is answered with a structured JSON error.
+    async fn handle_proxy_request(mut self, route: EndpointConfig, req: Request) -> Response {
+        let request_id = request_id(req.headers());
+        let ConfigSnapshot { config, credentials, shadow_credentials, target_credentials } = self.live.snapshot();
+        self.config = config;
+        self.credentials = credentials;
+        self.shadow_credentials = shadow_credentials;
+        self.target_credentials = target_credentials;
+        let Some(config) = self.config.find_endpoint(&route.method, &route.path).cloned() else {
+            warn!("Endpoint {} {} is no longer configured", route.method, route.path);
+            let error = ProxyError::NotFound(format!("No endpoint for {} {}", route.method, route.path));
+            return create_error_response(error, &request_id);
+        };
+
+        let span = info_span!("proxy_request", request_id = %request_id, endpoint = %config.path);
+        self.handle_request_in_span(config, req, request_id).instrument(span).await
+    }
+
+    async fn handle_request_in_span(
+        self,
+        config: EndpointConfig,
+        req: Request,
+        request_id: String,
+    ) -> Response {
+        let received = Instant::now();
+        let client_key = req.extensions().get::<ClientKey>().cloned();
+        let rate_limited = self.check_rate_limit(&config, &req);
+        let (slot, overloaded) = match rate_limited {
+            Some(_) => (None, None),
+            None => match self.acquire_slot(&config).await {
+                Ok(slot) => (slot, None),
+                Err(error) => (None, Some(error)),
+            },
+        };
+
+        // Upstream timeouts start once the request holds its slot
+        let phases = Arc::new(Mutex::new(UpstreamPhases::default()));
+        let retries = Arc::new(UpstreamRetries::default());
+        let ctx = RequestContext {
+            path: config.path.clone(),
+            request_id: request_id.clone(),
+            started: Instant::now(),
+            metrics: self.metrics.clone(),
+            timeout: self.config.get_timeout(&config),
+            max_stream: config.max_stream_secs.map(Duration::from_secs),
+            slo: self.slo.tracker(&config.path),
+            stream_checksums: config.stream_checksums,
+            _in_flight: self.metrics.track_in_flight(&config.path),
+            drain: self.shutdown.track(),
+            _slot: slot,
+            phases: phases.clone(),
+            retries: retries.clone(),
+            usage: self.config.usage.as_ref().map(|_| self.usage.recorder(&config.path)),
+        };
+
+        let result = if let Some(retry_after) = rate_limited {
+            warn!("Rate limit exceeded for {}", config.path);
+            let mut response = create_error_response(
+                ProxyError::RateLimited(format!("Rate limit exceeded for {}", config.path)),
+                &request_id,
+            );
+            let retry_after = retry_after.as_secs_f64().ceil().max(1.0).to_string();
+            if let Ok(value) = HeaderValue::from_str(&retry_after) {
+                response.headers_mut().insert(RETRY_AFTER, value);
+            }
+            Ok(response)
+        } else if let Some(error) = overloaded {
+            Ok(create_error_response(error, &request_id))
+        } else if let Some(header) = config.missing_required_header(req.headers()) {
+            warn!("Rejecting request to {}: missing required header {}", config.path, header);
+            let error = ProxyError::InvalidRequest(
+                StatusCode::BAD_REQUEST,
+                format!("Missing required header {header}"),
+            );
+            Ok(create_error_response(error, &request_id))
+        } else if config.mode == EndpointMode::Observe {
+            self.handle_observe_request(&config, req, ctx).await
+        } else if matches!(config.response_type, ResponseType::WebSocket) {
+            self.handle_websocket_response(&config, req, ctx).await
+        } else if let Some(ttl) = config.cache_ttl_secs {
+            self.forward_cached(&config, Duration::from_secs(ttl), req, ctx).await
+        } else if let Some(key) = self.idempotency_key(&config, &req) {
+            self.forward_idempotent(&config, key, req, ctx).await
+        } else {
+            self.forward_request(&config, req, ctx).await
+        };
+
+        let response = result.unwrap_or_else(|error| create_error_response(error, &request_id));
+        let status = response.status();
+        self.metrics.record_request(&config.path, status);
+        self.metrics.observe_request_duration(&config.path, received.elapsed());
+        if let Some(slo) = self.slo.tracker(&config.path) {
+            slo.record_request(received.elapsed(), status.is_server_error());
+        }
+        let phases = *phases.lock().unwrap();
+        let client_key = client_key.as_ref().map(tracing::field::display);
+        let send_retries = UpstreamRetries::count(&retries.send);
+        let read_retries = UpstreamRetries::count(&retries.body_read);
+        if phases.headers.is_some() {
+            info!(
+                dns = %phases.display(phases.dns),
+                connect = %phases.display(phases.connect),
+                upstream_headers = %phases.display(phases.headers),
+                client_key,
+                send_retries,
+                read_retries,
+                "{} {} -> {}", config.method, config.path, status.as_u16()
+            );
+        } else {
+            info!(client_key, send_retries, read_retries, "{} {} -> {}", config.method, config.path, status.as_u16());
+        }
+
+        response
+    }
+
+    /// Log a request body if the endpoint's logging settings ask for it
+    fn log_request_body(&self, config: &EndpointConfig, body: &[u8]) {
+        let logging = self.config.body_logging(config);
+        if logging.log_request_body {
+            self.log_body(&logging, "Request", &config.path, body);
+        }
+    }
+
+    /// Log a buffered response body if the endpoint's logging settings ask for it,
+    /// sampling bodies over `large_response_bytes`
+    fn log_response_body(&self, label: &str, config: &EndpointConfig, body: &[u8]) {
+        let logging = self.config.body_logging(config);
+        if !logging.log_response_body {
+            return;
+        }
+        if let Some(large) = logging.large_response_bytes
+            && body.len() > large
+            && !self.sample_large_response(logging.large_response_sample_rate)
+        {
+            debug!("{} body for {}: {} bytes, not sampled", label, config.path, body.len());
+            return;
+        }
+        self.log_body(&logging, label, &config.path, body);
+    }
 
-            match endpoint.method.to_uppercase().as_str() {
-                "GET" => {
-                    router = router.route(&path, get(move |req| {
-                        Self::handle_proxy_request(endpoint_clone, req)
-                    }));
+    /// Whether this large response is the one in `rate` that gets logged;
+    /// the first one always is
+    fn sample_large_response(&self, rate: u64) -> bool {
+        self.large_responses.fetch_add(1, Ordering::Relaxed).is_multiple_of(rate)
+    }
+
+    /// Log a body at the configured level, with sensitive fields redacted and
+    /// truncated to `max_logged_body_bytes`. Bodies are only sanitized when
+    /// that level is actually enabled.
+    fn log_body(&self, logging: &LoggingConfig, label: &str, path: &str, body: &[u8]) {
+        let enabled = match logging.level {
+            BodyLogLevel::Debug => tracing::enabled!(Level::DEBUG),
+            BodyLogLevel::Info => tracing::enabled!(Level::INFO),
+        };
+        if !enabled {
+            return;
+        }
+
+        let body = sanitize_body(body, &self.config.log_redact_fields, &logging.redact_fields);
+
+        let limit = body.len().min(logging.max_logged_body_bytes);
+        let mut text = String::from_utf8_lossy(&body[..limit]).into_owned();
+        if limit < body.len() {
+            text.push_str(&format!("... [truncated {} bytes]", body.len() - limit));
+        }
+        match logging.level {
+            BodyLogLevel::Debug => debug!("{} body for {}: {}", label, path, text),
+            BodyLogLevel::Info => info!("{} body for {}: {}", label, path, text),
+        }
+    }
+
+    /// Apply `max_header_value_bytes` to a header value about to be forwarded
+    fn limit_header_value(&self, name: &str, value: &HeaderValue) -> Result<HeaderValue, ProxyError> {
+        let max = self.config.max_header_value_bytes;
+        if value.len() <= max {
+            return Ok(value.clone());
+        }
+
+        match self.config.oversized_header_action {
+            OversizedHeaderAction::Reject => {
+                warn!("Rejecting request: header {} is {} bytes (max {})", name, value.len(), max);
+                Err(ProxyError::InvalidRequest(
+                    StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                    format!("Header {name} exceeds {max} bytes"),
+                ))
+            }
+            OversizedHeaderAction::Truncate => {
+                warn!("Truncating header {} from {} to {} bytes", name, value.len(), max);
+                // A prefix of a valid header value is still valid
+                HeaderValue::from_bytes(&value.as_bytes()[..max])
+                    .map_err(|_| ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, format!("Invalid header value for {name}")))
+            }
+        }
+    }
+
+    /// Record the durations found in the configured `upstream_timing_headers`;
+    /// values that are not non-negative numbers are skipped
+    fn observe_timing_headers(&self, path: &str, headers: &HeaderMap) {
+        for name in &self.config.upstream_timing_headers {
+            let seconds = headers
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<f64>().ok())
+                .filter(|seconds| seconds.is_finite() && *seconds >= 0.0);
+            if let Some(seconds) = seconds {
+                self.metrics.observe_timing_header(path, &name.to_ascii_lowercase(), seconds);
+            }
+        }
+    }
+
+    /// Send an upstream request, giving up when no response arrives within
+    /// the request timeout, and record where the time went
+    async fn send_upstream(
+        &self,
+        req_builder: reqwest::RequestBuilder,
+        ctx: &RequestContext,
+    ) -> Result<reqwest::Response, ProxyError> {
+        let timeout = ctx.timeout;
+        let sent = record_phases(ctx.phases.clone(), tokio::time::timeout(timeout, req_builder.send())).await;
+        match sent {
+            Ok(Ok(response)) => {
+                let phases = {
+                    let mut phases = ctx.phases.lock().unwrap();
+                    phases.headers = Some(ctx.started.elapsed());
+                    *phases
+                };
+                if self.config.upstream_phase_metrics
+                    && let Some(host) = upstream_host(response.url().as_str())
+                {
+                    self.metrics.observe_upstream_phases(&host, &phases);
                 }
-                "POST" => {
-                    router = router.route(&path, post(move |req| {
-                        Self::handle_proxy_request(endpoint_clone, req)
-                    }));
+                Ok(response)
+            }
+            Ok(Err(e)) if e.is_timeout() => Err(Self::upstream_timeout(timeout)),
+            Ok(Err(e)) => {
+                error!("Failed to forward request: {}", e);
+                Err(ProxyError::UpstreamError(StatusCode::BAD_GATEWAY, format!("Forward failed: {e}")))
+            }
+            Err(_) => Err(Self::upstream_timeout(timeout)),
+        }
+    }
+
+    /// Send the request and buffer the response body, reissuing the whole
+    /// request when the upstream connection fails in either phase, within
+    /// `retry.max_attempts` and the request timeout. Nothing has reached the
+    /// client before the body is complete, so retries stay invisible to it.
+    /// Error statuses are returned as they are, unread. Each retried failure
+    /// counts on the target's `circuit`, and retries stop once it opens; the
+    /// returned outcome is left to the caller to record.
+    async fn send_with_retries(
+        &self,
+        retry: &RetryConfig,
+        req_builder: reqwest::RequestBuilder,
+        circuit: Option<(&CircuitBreakerConfig, &str)>,
+        ctx: &RequestContext,
+    ) -> Result<reqwest::Response, ProxyError> {
+        let mut backoff = Duration::from_millis(retry.backoff_ms);
+        let mut attempt = 1;
+        loop {
+            // Streamed request bodies cannot be sent twice
+            let Some(request) = req_builder.try_clone() else {
+                return self.send_upstream(req_builder, ctx).await;
+            };
+
+            let (error, phase, counter) = match self.send_upstream(request, ctx).await {
+                Ok(response) if !response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status();
+                    let version = response.version();
+                    let headers = response.headers().clone();
+                    match self.read_body_within(response, ctx.timeout).await {
+                        Ok(body) => {
+                            let mut buffered = axum::http::Response::new(body);
+                            *buffered.status_mut() = status;
+                            *buffered.version_mut() = version;
+                            *buffered.headers_mut() = headers;
+                            return Ok(reqwest::Response::from(buffered));
+                        }
+                        // `read_error` reports broken connections as internal
+                        // errors; timeouts and oversized bodies are final
+                        Err(error @ ProxyError::Internal(_)) => (error, "response body read", &ctx.retries.body_read),
+                        Err(error) => return Err(error),
+                    }
                 }
-                "PUT" => {
-                    router = router.route(&path, put(move |req| {
-                        Self::handle_proxy_request(endpoint_clone, req)
-                    }));
+                Err(error @ ProxyError::UpstreamError(..)) => (error, "send", &ctx.retries.send),
+                Err(error) => return Err(error),
+            };
+
+            if attempt >= retry.max_attempts || ctx.started.elapsed() + backoff >= ctx.timeout {
+                return Err(error);
+            }
+            if let Some((breaker, key)) = circuit {
+                self.circuits.record_failure(key, breaker);
+                if self.circuits.allow(key, breaker).is_err() {
+                    warn!("Circuit for {} opened, not retrying {}", key, ctx.path);
+                    return Err(error);
+                }
+            }
+            counter.fetch_add(1, Ordering::Relaxed);
+            attempt += 1;
+            warn!("Upstream {} failed, retrying {} (attempt {} of {})", phase, ctx.path, attempt, retry.max_attempts);
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    fn upstream_timeout(timeout: Duration) -> ProxyError {
+        warn!("Upstream did not respond within {}s", timeout.as_secs());
+        ProxyError::TimeoutError(format!("Upstream did not respond within {}s", timeout.as_secs()))
+    }
+
+    /// SSE event ending a stream that timed out or failed upstream
+    fn error_event(error: ProxyError, request_id: &str) -> Event {
+        Event::default().event("error").data(error.body(request_id).to_string())
+    }
+
+    /// Cache key for POSTs carrying an `Idempotency-Key`, scoped to the
+    /// endpoint and the caller: its client key when authenticated, else its
+    /// `Authorization`, so clients never get each other's responses
+    fn idempotency_key(&self, config: &EndpointConfig, req: &Request) -> Option<String> {
+        if self.config.idempotency_ttl == 0 || req.method() != Method::POST {
+            return None;
+        }
+        let key = req.headers().get("idempotency-key")?.to_str().ok()?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(key);
+        if let Some(ClientKey(client)) = req.extensions().get::<ClientKey>() {
+            hasher.update(b"\nclient:");
+            hasher.update(client.as_bytes());
+        } else if let Some(authorization) = req.headers().get(AUTHORIZATION) {
+            hasher.update(b"\nauthorization:");
+            hasher.update(authorization.as_bytes());
+        }
+        Some(format!("{} {}", config.path, hex::encode(hasher.finalize())))
+    }
+
+    /// Forward a request once per idempotency key and replay the stored
+    /// response to repeats within the TTL. Streams, server errors and local
+    /// failures are not stored, so those requests can be retried. The body is
+    /// buffered to compare its digest: reusing a key with a different body is
+    /// answered `422` rather than replayed.
+    async fn forward_idempotent(
+        &self,
+        config: &EndpointConfig,
+        key: String,
+        req: Request,
+        ctx: RequestContext,
+    ) -> Result<Response, ProxyError> {
+        let (parts, body) = req.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
+            error!("Failed to read request body: {}", e);
+            ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, "Unable to read request body".to_string())
+        })?;
+        let body_digest = hex::encode(Sha256::digest(&body));
+        let req = Request::from_parts(parts, Body::from(body));
+
+        let slot = self.idempotency.slot(&key);
+        let mut cached = slot.lock().await;
+
+        if let Some(entry) = cached.as_ref().filter(|c| c.response.is_fresh(self.idempotency.ttl())) {
+            if entry.body_digest != body_digest {
+                warn!("Idempotency key {} reused with a different request body", key);
+                return Err(ProxyError::InvalidRequest(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Idempotency-Key was already used with a different request body".to_string(),
+                ));
+            }
+            info!("Replaying cached response for idempotency key {}", key);
+            return Ok(entry.response.to_response());
+        }
+
+        let response = self.forward_request(config, req, ctx).await?;
+        let is_stream = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .is_some_and(|ct| ct.contains("text/event-stream") || ct.contains("application/stream"));
+        if is_stream || response.status().is_server_error() {
+            return Ok(response);
+        }
+
+        let (parts, body) = response.into_parts();
+        let body_bytes = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
+            error!("Failed to buffer response for idempotency cache: {}", e);
+            ProxyError::UpstreamError(StatusCode::BAD_GATEWAY, "Failed to read response".to_string())
+        })?;
+        *cached = Some(IdempotentResponse {
+            body_digest,
+            response: CachedResponse::new(parts.status, parts.headers.clone(), body_bytes.clone()),
+        });
+
+        Ok(Response::from_parts(parts, Body::from(body_bytes)))
+    }
+
+    /// Serve a `GET` from the response cache, or forward it and store a
+    /// successful response for `ttl` unless the upstream says `no-store`.
+    /// The key covers the path, query and the caller's `Authorization`, so
+    /// clients never get each other's responses.
+    async fn forward_cached(
+        &self,
+        config: &EndpointConfig,
+        ttl: Duration,
+        req: Request,
+        ctx: RequestContext,
+    ) -> Result<Response, ProxyError> {
+        let mut hasher = Sha256::new();
+        hasher.update(req.uri().path_and_query().map_or("", |pq| pq.as_str()));
+        if let Some(authorization) = req.headers().get(AUTHORIZATION) {
+            hasher.update(b"\n");
+            hasher.update(authorization.as_bytes());
+        }
+        let key = format!("{} {}", config.path, hex::encode(hasher.finalize()));
+
+        if let Some(cached) = self.response_cache.get(&key) {
+            debug!("Response cache hit for {}", config.path);
+            self.metrics.record_cache_result(&config.path, "hit");
+            return Ok(cached.to_response_marked(CACHE_HEADER, "HIT"));
+        }
+        self.metrics.record_cache_result(&config.path, "miss");
+
+        let response = self.forward_request(config, req, ctx).await?;
+        let no_store = response.extensions().get::<NoStore>().is_some();
+        let (mut parts, body) = response.into_parts();
+        parts.headers.insert(CACHE_HEADER, HeaderValue::from_static("MISS"));
+        if !parts.status.is_success() || no_store {
+            return Ok(Response::from_parts(parts, body));
+        }
+
+        let body_bytes = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
+            error!("Failed to buffer response for the response cache: {}", e);
+            ProxyError::UpstreamError(StatusCode::BAD_GATEWAY, "Failed to read response".to_string())
+        })?;
+        let mut headers = parts.headers.clone();
+        headers.remove(CACHE_HEADER);
+        let evicted = self.response_cache.insert(
+            key,
+            &config.path,
+            ttl,
+            CachedResponse::new(parts.status, headers, body_bytes.clone()),
+        );
+        for path in evicted {
+            self.metrics.record_cache_result(&path, "eviction");
+        }
+
+        Ok(Response::from_parts(parts, Body::from(body_bytes)))
+    }
+
+    /// Whether to stream the request body upstream: the endpoint opts in, does
+    /// not need the body for conversion or the model allowlist, and the body is
+    /// chunked or over `stream_request_body_min_bytes`. Model routes do not
+    /// apply to streamed bodies.
+    fn streams_request_body(&self, config: &EndpointConfig, headers: &HeaderMap) -> bool {
+        if !config.stream_request_body || config.conversion.is_some() || config.allowed_models.is_some() {
+            return false;
+        }
+
+        let chunked = headers
+            .get(TRANSFER_ENCODING)
+            .and_then(|te| te.to_str().ok())
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+        let large = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok()?.parse::<usize>().ok())
+            .is_some_and(|len| len > self.config.stream_request_body_min_bytes);
+        chunked || large
+    }
+
+    /// Read a `multipart/form-data` body into a form for the upstream, along
+    /// with its `model` field when there is one. Parts stay in order and keep
+    /// their file names and content types.
+    async fn read_multipart(headers: &HeaderMap, body: Body) -> Result<(Form, Option<String>), ProxyError> {
+        let invalid = |message: String| ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, message);
+        let content_type = headers.get(CONTENT_TYPE).and_then(|ct| ct.to_str().ok()).unwrap_or_default();
+        let boundary = multer::parse_boundary(content_type)
+            .map_err(|e| invalid(format!("Expected a multipart/form-data body: {e}")))?;
+
+        let mut fields = multer::Multipart::new(body.into_data_stream(), boundary);
+        let mut form = Form::new();
+        let mut model = None;
+        while let Some(field) = fields
+            .next_field()
+            .await
+            .map_err(|e| invalid(format!("Invalid multipart body: {e}")))?
+        {
+            let name = field.name().unwrap_or_default().to_string();
+            let file_name = field.file_name().map(str::to_string);
+            let mime = field.content_type().map(ToString::to_string);
+            let data = field
+                .bytes()
+                .await
+                .map_err(|e| invalid(format!("Invalid multipart field {name}: {e}")))?;
+            debug!("Multipart field {}: {} bytes, file name {:?}, type {:?}", name, data.len(), file_name, mime);
+
+            if name == "model" && file_name.is_none() {
+                model = std::str::from_utf8(&data).ok().map(str::to_string);
+            }
+            let mut part = Part::stream(data);
+            if let Some(file_name) = file_name {
+                part = part.file_name(file_name);
+            }
+            if let Some(mime) = mime {
+                part = part
+                    .mime_str(&mime)
+                    .map_err(|_| invalid(format!("Invalid content type for multipart field {name}")))?;
+            }
+            form = form.part(name, part);
+        }
+        Ok((form, model))
+    }
+
+    /// Take one of the endpoint's `max_concurrent` slots, waiting up to
+    /// `global_timeout` for one when the endpoint queues
+    async fn acquire_slot(&self, config: &EndpointConfig) -> Result<Option<OwnedSemaphorePermit>, ProxyError> {
+        let Some(semaphore) = self.concurrency.get(&config.path) else {
+            return Ok(None);
+        };
+        let full = || {
+            warn!("Concurrency limit reached for {}", config.path);
+            ProxyError::Overloaded(format!("Too many concurrent requests for {}", config.path))
+        };
+
+        match config.on_full {
+            OnFull::Reject => semaphore.clone().try_acquire_owned().map(Some).map_err(|_| full()),
+            OnFull::Queue => {
+                let wait = Duration::from_secs(self.config.global_timeout);
+                match tokio::time::timeout(wait, semaphore.clone().acquire_owned()).await {
+                    Ok(Ok(permit)) => Ok(Some(permit)),
+                    _ => Err(full()),
                 }
-                "DELETE" => {
-                    router = router.route(&path, delete(move |req| {
-                        Self::handle_proxy_request(endpoint_clone, req)
-                    }));
+            }
+        }
+    }
+
+    /// Consume a token from the caller's bucket, returning the wait time when
+    /// none is left
+    fn check_rate_limit(&self, config: &EndpointConfig, req: &Request) -> Option<Duration> {
+        let limit = self.config.rate_limit(config)?;
+        let client = match limit.key {
+            RateLimitKey::Endpoint => String::new(),
+            RateLimitKey::Ip => req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+                .unwrap_or_default(),
+            RateLimitKey::Authorization => req
+                .headers()
+                .get(AUTHORIZATION)
+                .map(|value| hex::encode(Sha256::digest(value.as_bytes())))
+                .unwrap_or_default(),
+        };
+        self.rate_limiter.check(&config.path, client, limit)
+    }
+
+    async fn forward_request(
+        &self,
+        config: &EndpointConfig,
+        req: Request,
+        ctx: RequestContext,
+    ) -> Result<Response, ProxyError> {
+        let (parts, body) = req.into_parts();
+
+        // Read request body, unless it is multipart or large enough to be streamed through
+        let mut streamed_body = None;
+        let mut multipart = None;
+        let body_bytes = if config.forward_as_multipart {
+            multipart = Some(Self::read_multipart(&parts.headers, body).await?);
+            Bytes::new()
+        } else if self.streams_request_body(config, &parts.headers) {
+            info!("Streaming request body for {}", config.path);
+            streamed_body = Some(body);
+            Bytes::new()
+        } else {
+            match axum::body::to_bytes(body, usize::MAX).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error!("Failed to read request body: {}", e);
+                    return Err(ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, "Unable to read request body".to_string()));
                 }
-                _ => {
-                    warn!("Unsupported HTTP method: {} for path: {}", endpoint.method, endpoint.path);
+            }
+        };
+
+        // Requests refused from here on go to the dead-letter log
+        let received = body_bytes.clone();
+        let refused = |error: ProxyError| {
+            self.dead_letters.record(&self.config, config, &parts, &received, &error, &ctx.request_id);
+            error
+        };
+
+        // Apply an operator's merge patch before the body is converted or routed on
+        let patched = parts.headers.get(BODY_PATCH_HEADER);
+        let body_bytes = match patched {
+            Some(_) if parts.extensions.get::<AdminAccess>().is_none() && !has_admin_token(&parts.headers) => {
+                warn!("Rejected {} without an admin token on {}", BODY_PATCH_HEADER, config.path);
+                return Err(ProxyError::Forbidden(format!("{BODY_PATCH_HEADER} requires an admin token")));
+            }
+            Some(_) if streamed_body.is_some() || multipart.is_some() => {
+                return Err(ProxyError::InvalidRequest(
+                    StatusCode::BAD_REQUEST,
+                    format!("{BODY_PATCH_HEADER} needs a buffered JSON request body"),
+                ));
+            }
+            Some(patch) => {
+                let body = body_patch::apply(patch, &body_bytes)?;
+                info!("Applied {} to the request body on {}", BODY_PATCH_HEADER, config.path);
+                body
+            }
+            None => body_bytes,
+        };
+
+        let model = match &multipart {
+            Some((_, model)) => model.clone(),
+            None => self.request_model(config, &body_bytes),
+        };
+        if !config.allows_model(model.as_deref()) {
+            warn!("Model {:?} is not allowed on {}", model, config.path);
+            return Err(refused(ProxyError::Forbidden(format!(
+                "Model {} is not allowed on this endpoint",
+                model.as_deref().unwrap_or("(none)")
+            ))));
+        }
+
+        // Pick the upstream from the model routes, falling back to the endpoint target
+        let meta = RequestMeta {
+            method: parts.method.to_string(),
+            path: parts.uri.path().to_string(),
+            query: parts.uri.query().map(str::to_string),
+            model,
+            headers: HashMap::new(),
+        };
+        let plan = plan_route(&self.config, config, &meta)
+            .ok_or_else(|| ProxyError::NotFound("Not Found".to_string()))?;
+        if let Some(route) = &plan.model_route {
+            info!("Model route matched: prefix={}, provider={:?}", route.model_prefix, route.provider);
+        }
+
+        // Convert the request body to the API shape of the planned upstream
+        let body_bytes = match plan.conversion {
+            Some(conversion) => self.convert_request_body(conversion, &body_bytes, &ctx).map_err(refused)?,
+            None => body_bytes,
+        };
+        let order = plan.attempt_order();
+        let primary = order[0];
+
+        info!("Forwarding request: {} -> {}", config.path, primary.url);
+        debug!("Headers: {:?}", sanitize_headers(&parts.headers, &self.config.log_redact_headers));
+        if streamed_body.is_none() && multipart.is_none() {
+            self.log_request_body(config, &body_bytes);
+        }
+
+        // Build request
+        let method = Method::from_bytes(config.method.as_bytes())
+            .map_err(|_| ProxyError::Internal("Invalid HTTP method".to_string()))?;
+
+        let mut req_builder = self.client.request(method, &primary.url);
+        req_builder = match (multipart, streamed_body) {
+            (Some((form, _)), _) => req_builder.multipart(form),
+            (None, Some(body)) => req_builder.body(reqwest::Body::wrap_stream(body.into_data_stream())),
+            (None, None) => req_builder.body(body_bytes),
+        };
+
+        // Buffered responses get a total timeout; streams only a first-byte
+        // timeout, since a total one would cut off long generations
+        if plan.conversion.is_none() && matches!(config.response_type, ResponseType::Json | ResponseType::Html) {
+            req_builder = req_builder.timeout(ctx.timeout);
+        }
+
+        // Targets may each have their own credential
+        let client_headers = Self::forwardable_headers(&parts);
+        let target_headers = |target: &PlannedTarget| {
+            let mut headers = self
+                .upstream_headers(config, self.target_credential(config, target), &client_headers, &ctx.request_id)
+                .map_err(refused)?;
+            if config.forward_as_multipart {
+                // The rebuilt form has its own boundary and length
+                headers.remove(CONTENT_TYPE);
+                headers.remove(CONTENT_LENGTH);
+            } else if patched.is_some() {
+                headers.remove(CONTENT_LENGTH);
+            }
+            Ok::<_, ProxyError>(headers)
+        };
+        req_builder = req_builder.headers(target_headers(primary)?);
+
+        // Mirror sampled requests to the shadow upstream once the primary responds
+        let shadow = config.shadow().and_then(|settings| {
+            let request = ShadowRequest {
+                request: req_builder.try_clone()?.build().ok()?,
+                path: config.path.clone(),
+                request_id: ctx.request_id.clone(),
+                primary_credential: self.target_credential(config, primary).map(|(name, _)| name.clone()),
+                credential: self.shadow_credentials.get(&config.path).cloned(),
+            };
+            self.shadow.spawn(&settings, request)
+        });
+
+        // Try the targets in turn until one answers without a transport error
+        // or 5xx status. Only response headers have arrived when a target is
+        // given up, so nothing has reached the client yet; streamed and
+        // multipart request bodies cannot be sent twice and only go to the
+        // first target.
+        let mut next_request = Some(req_builder);
+        let mut outcome = None;
+        let mut open_circuit = None;
+        for (position, target) in order.iter().enumerate() {
+            let Some(req_builder) = next_request.take() else { break };
+            if position + 1 < order.len() {
+                next_request = req_builder.try_clone();
+            }
+            let req_builder = match position {
+                0 => Ok(req_builder),
+                _ => target_headers(target).and_then(|headers| self.retarget(req_builder, target, headers)),
+            };
+
+            // Skip targets whose circuit, or that of the endpoint, is open
+            let circuit = match &config.circuit_breaker {
+                Some(breaker) => {
+                    let configured = target.index.and_then(|index| config.targets.as_ref()?.get(index));
+                    Some((breaker, endpoint_target_key(&config.path, configured.map(|target| target.url.as_str()))))
+                }
+                None => self.config.circuit_breaker.as_ref().zip(upstream_host(&target.url)),
+            };
+            if let Some((breaker, key)) = &circuit
+                && let Err(retry_after) = self.circuits.allow(key, breaker)
+            {
+                warn!("Circuit for {} is open, failing fast", key);
+                open_circuit = Some((key.clone(), retry_after));
+                continue;
+            }
+
+            // Send request
+            let response = match req_builder {
+                Ok(req_builder) => match &config.retry {
+                    Some(retry) => {
+                        let circuit = circuit.as_ref().map(|(breaker, key)| (*breaker, key.as_str()));
+                        self.send_with_retries(retry, req_builder, circuit, &ctx).await
+                    }
+                    None => self.send_upstream(req_builder, &ctx).await,
+                },
+                Err(error) => Err(error),
+            };
+            if let Some((breaker, key)) = &circuit {
+                match &response {
+                    Ok(response) if !response.status().is_server_error() => self.circuits.record_success(key, breaker),
+                    _ => self.circuits.record_failure(key, breaker),
+                }
+            }
+            let failed = match &response {
+                Ok(response) if !response.status().is_success() => {
+                    self.metrics.record_upstream_error(&config.path, Some(response.status()));
+                    response.status().is_server_error()
+                }
+                Ok(_) => false,
+                Err(error) => {
+                    self.metrics.record_upstream_error(&config.path, None);
+                    matches!(error, ProxyError::UpstreamError(..))
+                }
+            };
+            outcome = Some((*target, response));
+            if failed && next_request.is_some() {
+                warn!("Upstream {} failed for {}, failing over", target.url, config.path);
+                continue;
+            }
+            break;
+        }
+
+        let (served_by, response) = match outcome {
+            Some(outcome) => outcome,
+            None => {
+                let (key, retry_after) = open_circuit.expect("every target was skipped for an open circuit");
+                let error = ProxyError::UpstreamError(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    format!("Upstream {key} is failing, retry later"),
+                );
+                let mut response = create_error_response(error, &ctx.request_id);
+                let retry_after = retry_after.as_secs_f64().ceil().max(1.0).to_string();
+                if let Ok(value) = HeaderValue::from_str(&retry_after) {
+                    response.headers_mut().insert(RETRY_AFTER, value);
+                }
+                return Ok(response);
+            }
+        };
+        let response = response?;
+        self.metrics.observe_upstream_latency(&config.path, ctx.started.elapsed());
+        self.observe_timing_headers(&config.path, response.headers());
+
+        if let Some(shadow) = shadow {
+            let _ = shadow.send(PrimaryOutcome {
+                status: response.status().as_u16(),
+                content_type: response
+                    .headers()
+                    .get("content-type")
+                    .and_then(|ct| ct.to_str().ok())
+                    .map(str::to_string),
+                latency_ms: ctx.started.elapsed().as_millis() as u64,
+            });
+        }
+
+        // Name the target that answered when the endpoint has several
+        let served_by = config
+            .targets
+            .is_some()
+            .then(|| HeaderValue::from_str(served_by.url.split('?').next().unwrap_or_default()).ok())
+            .flatten();
+        let mut final_response = self.handle_upstream_response(response, config, &plan, &client_headers, ctx).await?;
+        if let Some(served_by) = served_by {
+            final_response.headers_mut().insert(UPSTREAM_HEADER, served_by);
+        }
+        Ok(final_response)
+    }
+
+    /// Credential sent to `target`: its own or the endpoint's, none for
+    /// passthrough auth
+    fn target_credential(&self, config: &EndpointConfig, target: &PlannedTarget) -> Option<&Credential> {
+        target.upstream_auth.header_name()?;
+        match target.index {
+            Some(index) => self.target_credentials.get(&config.path)?.get(index)?.as_ref(),
+            None => self.credentials.get(&config.path),
+        }
+    }
+
+    /// The request of `req_builder` sent to `target` instead, with `headers`
+    fn retarget(
+        &self,
+        req_builder: reqwest::RequestBuilder,
+        target: &PlannedTarget,
+        headers: HeaderMap,
+    ) -> Result<reqwest::RequestBuilder, ProxyError> {
+        let mut request = req_builder
+            .build()
+            .map_err(|e| ProxyError::UpstreamError(StatusCode::BAD_GATEWAY, format!("Forward failed: {e}")))?;
+        *request.url_mut() = reqwest::Url::parse(&target.url)
+            .map_err(|e| ProxyError::UpstreamError(StatusCode::BAD_GATEWAY, format!("Invalid target URL {}: {}", target.url, e)))?;
+        *request.headers_mut() = headers;
+        Ok(reqwest::RequestBuilder::from_parts(self.client.clone(), request))
+    }
+
+    /// Answer the client from a successful upstream response, or pass an
+    /// upstream error on
+    async fn handle_upstream_response(
+        &self,
+        response: reqwest::Response,
+        config: &EndpointConfig,
+        plan: &RoutePlan,
+        client_headers: &HeaderMap,
+        mut ctx: RequestContext,
+    ) -> Result<Response, ProxyError> {
+        if !response.status().is_success() {
+            error!("Upstream server returned error status: {}", response.status());
+            return self.handle_upstream_error(response, config, &ctx).await;
+        }
+
+        if let Some(conversion) = plan.conversion {
+            let format = StreamFormat::negotiate(client_headers);
+            return self.handle_converted_response(conversion, response, config, ctx, format).await;
+        }
+
+        // Handle based on response type
+        let response_type = match config.response_type {
+            ResponseType::Auto => Self::detect_response_type(&response),
+            ref response_type => response_type.clone(),
+        };
+
+        let no_store = forbids_storing(response.headers());
+        let mut final_response = match response_type {
+            ResponseType::Sse => Self::handle_sse_response(response, config, ctx).await,
+            ResponseType::Json => self.handle_json_response(response, config, ctx.timeout, ctx.usage.take()).await,
+            ResponseType::Html => self.handle_html_response(response, config, ctx.timeout).await,
+            ResponseType::Stream | ResponseType::Auto => self.handle_stream_response(response, config, ctx).await,
+            ResponseType::WebSocket => unreachable!("WebSocket endpoints are relayed by handle_websocket_response"),
+        }?;
+        if no_store {
+            final_response.extensions_mut().insert(NoStore);
+        }
+        Ok(final_response)
+    }
+
+    /// Upgrade the client connection and relay frames to and from the
+    /// upstream WebSocket. The upstream is connected first, so a failed
+    /// handshake is answered with an error instead of an upgraded socket.
+    async fn handle_websocket_response(
+        &self,
+        config: &EndpointConfig,
+        req: Request,
+        ctx: RequestContext,
+    ) -> Result<Response, ProxyError> {
+        let (mut parts, _body) = req.into_parts();
+        let upgrade = WebSocketUpgrade::from_request_parts(&mut parts, &())
+            .await
+            .map_err(|rejection| ProxyError::InvalidRequest(rejection.status(), rejection.body_text()))?;
+
+        let meta = RequestMeta {
+            method: parts.method.to_string(),
+            path: parts.uri.path().to_string(),
+            query: parts.uri.query().map(str::to_string),
+            model: None,
+            headers: HashMap::new(),
+        };
+        let plan = plan_route(&self.config, config, &meta)
+            .ok_or_else(|| ProxyError::NotFound("Not Found".to_string()))?;
+
+        let mut request = plan
+            .target_url
+            .as_str()
+            .into_client_request()
+            .map_err(|e| ProxyError::Internal(format!("Invalid upstream URL: {e}")))?;
+        request
+            .headers_mut()
+            .extend(self.upstream_headers(
+                config,
+                self.target_credential(config, &plan.targets[0]),
+                &Self::forwardable_headers(&parts),
+                &ctx.request_id,
+            )?);
+
+        info!("Opening WebSocket: {} -> {}", config.path, plan.target_url);
+        let (upstream, _) = match tokio::time::timeout(ctx.timeout, tokio_tungstenite::connect_async(request)).await {
+            Ok(Ok(connected)) => connected,
+            Ok(Err(e)) => {
+                self.metrics.record_upstream_error(&config.path, None);
+                error!("Upstream WebSocket handshake failed: {}", e);
+                return Err(ProxyError::UpstreamError(StatusCode::BAD_GATEWAY, format!("Upstream WebSocket handshake failed: {e}")));
+            }
+            Err(_) => {
+                self.metrics.record_upstream_error(&config.path, None);
+                return Err(Self::upstream_timeout(ctx.timeout));
+            }
+        };
+        self.metrics.observe_upstream_latency(&config.path, ctx.started.elapsed());
+
+        // The context moves into the relay, so the socket counts as in flight
+        // until it closes
+        Ok(upgrade.on_upgrade(move |socket| async move {
+            websocket::relay(socket, upstream, &ctx.path).await;
+        }))
+    }
+
+    /// Headers sent upstream: the forwarded client headers, the endpoint's
+    /// custom headers, the request ID and the upstream credential
+    fn upstream_headers(
+        &self,
+        config: &EndpointConfig,
+        credential: Option<&Credential>,
+        client_headers: &HeaderMap,
+        request_id: &str,
+    ) -> Result<HeaderMap, ProxyError> {
+        let mut headers = HeaderMap::new();
+
+        // The configured credential replaces whatever the client sent in its header
+
+        // Add forwarded request headers. The client negotiates its own
+        // `accept-encoding` so responses only use encodings it can decode.
+        for header_name in &config.forward_request_headers {
+            if header_name.eq_ignore_ascii_case("accept-encoding")
+                || header_name.eq_ignore_ascii_case(BODY_PATCH_HEADER)
+                || credential.is_some_and(|(name, _)| name.as_str().eq_ignore_ascii_case(header_name))
+            {
+                continue;
+            }
+            if let Some(header_value) = client_headers.get(header_name)
+                && let Ok(name) = HeaderName::from_bytes(header_name.as_bytes())
+            {
+                headers.append(name, self.limit_header_value(header_name, header_value)?);
+            }
+        }
+
+        // Add custom request headers
+        for (name, value) in &config.custom_headers {
+            let header = HeaderName::from_bytes(name.as_bytes())
+                .ok()
+                .zip(HeaderValue::from_str(value).ok())
+                .ok_or_else(|| ProxyError::Internal(format!("Invalid custom header {name}")))?;
+            headers.append(header.0, header.1);
+        }
+
+        if let Ok(value) = HeaderValue::from_str(request_id) {
+            headers.insert(REQUEST_ID_HEADER, value);
+        }
+
+        if let Some((name, value)) = credential {
+            headers.append(name.clone(), value.clone());
+        }
+
+        Ok(headers)
+    }
+
+    /// The client's headers as they may be forwarded: an admin credential
+    /// authorizes the request to the proxy, such as a body patch, and never
+    /// reaches an upstream
+    fn forwardable_headers(parts: &Parts) -> Cow<'_, HeaderMap> {
+        if parts.extensions.get::<AdminAccess>().is_none() && !has_admin_token(&parts.headers) {
+            return Cow::Borrowed(&parts.headers);
+        }
+        let mut headers = parts.headers.clone();
+        headers.remove(AUTHORIZATION);
+        Cow::Owned(headers)
+    }
+
+    /// Whether a response header may be copied onto a decoded response body.
+    /// The body is decompressed (and for JSON re-encoded), so the upstream
+    /// encoding and length no longer describe it.
+    fn forwards_response_header(name: &str) -> bool {
+        !name.eq_ignore_ascii_case("content-encoding") && !name.eq_ignore_ascii_case("content-length")
+    }
+
+    /// Response handling matching the upstream `content-type`
+    fn detect_response_type(response: &reqwest::Response) -> ResponseType {
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .unwrap_or_default();
+
+        if content_type.contains("text/event-stream") {
+            ResponseType::Sse
+        } else if content_type.contains("application/json") {
+            ResponseType::Json
+        } else if content_type.contains("text/html") {
+            ResponseType::Html
+        } else {
+            ResponseType::Stream
+        }
+    }
+
+    /// Pass an upstream error through with its original status. JSON bodies are
+    /// forwarded untouched; anything else is wrapped in a structured error.
+    async fn handle_upstream_error(
+        &self,
+        response: reqwest::Response,
+        config: &EndpointConfig,
+        ctx: &RequestContext,
+    ) -> Result<Response, ProxyError> {
+        let status = response.status();
+        let content_type = response.headers().get(CONTENT_TYPE).cloned();
+        let body_bytes = self.read_body_within(response, ctx.timeout).await?;
+        self.log_response_body("Error response", config, &body_bytes);
+
+        if serde_json::from_slice::<Value>(&body_bytes).is_ok() {
+            let content_type = content_type
+                .filter(|ct| ct.to_str().is_ok_and(|ct| ct.contains("json")))
+                .unwrap_or_else(|| HeaderValue::from_static("application/json"));
+            return Ok((status, [(CONTENT_TYPE, content_type)], body_bytes).into_response());
+        }
+
+        let message = String::from_utf8_lossy(&body_bytes).trim().to_string();
+        let message = if message.is_empty() {
+            format!("Upstream returned {status}")
+        } else {
+            message
+        };
+        Ok(create_error_response(ProxyError::UpstreamError(status, message), &ctx.request_id))
+    }
+
+    fn convert_request_body(
+        &self,
+        conversion: Conversion,
+        body: &[u8],
+        ctx: &RequestContext,
+    ) -> Result<Bytes, ProxyError> {
+        let request: Value = serde_json::from_slice(body).map_err(|e| {
+            ctx.conversion_failed(conversion, "invalid_request_json");
+            ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, format!("Invalid JSON request body: {e}"))
+        })?;
+
+        let converted = match conversion {
+            Conversion::LegacyCompletions => convert::completions_to_chat_request(&request),
+            Conversion::OpenaiToAnthropic => convert::chat_to_anthropic_request(&request),
+        }
+        .map_err(|e| {
+            ctx.conversion_failed(conversion, "unsupported_request");
+            ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, e)
+        })?;
+        self.check_context_window(&converted)?;
+
+        serde_json::to_vec(&converted)
+            .map(Bytes::from)
+            .map_err(|e| ProxyError::Internal(format!("Failed to encode converted request: {e}")))
+    }
+
+    /// Estimate the converted request's input and compare it with its model's
+    /// context window, per `context_windows.enforce`
+    fn check_context_window(&self, request: &Value) -> Result<(), ProxyError> {
+        let settings = &self.config.context_windows;
+        if settings.enforce == ContextWindowEnforcement::Off {
+            return Ok(());
+        }
+        let Some(model) = request.get("model").and_then(Value::as_str) else {
+            return Ok(());
+        };
+        let Some(window) = context_window(settings, model) else {
+            return Ok(());
+        };
+
+        let estimate = estimate_tokens(request);
+        if estimate <= window {
+            return Ok(());
+        }
+        warn!("Request for {} is about {} tokens, over its {} token context window", model, estimate, window);
+        match settings.enforce {
+            ContextWindowEnforcement::Reject => Err(ProxyError::ContextLengthExceeded {
+                estimated_tokens: estimate,
+                context_window: window,
+                message: format!(
+                    "This model's maximum context length is {window} tokens. However, your messages resulted in about {estimate} tokens."
+                ),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Convert an upstream response back into the client's API shape, streaming
+    /// event by event, as SSE or NDJSON, when the upstream streams
+    async fn handle_converted_response(
+        &self,
+        conversion: Conversion,
+        response: reqwest::Response,
+        config: &EndpointConfig,
+        mut ctx: RequestContext,
+        format: StreamFormat,
+    ) -> Result<Response, ProxyError> {
+        let status = response.status();
+        let mut response_headers = HeaderMap::new();
+
+        // Forward response headers; the body is re-encoded so its length and type change
+        for header_name in &config.forward_response_headers {
+            if !Self::forwards_response_header(header_name) || header_name.eq_ignore_ascii_case("content-type") {
+                continue;
+            }
+            if let Some(header_value) = response.headers().get(header_name)
+                && let Ok(name) = HeaderName::from_bytes(header_name.as_bytes())
+            {
+                response_headers.insert(name, header_value.clone());
+            }
+        }
+
+        let is_streaming = response
+            .headers()
+            .get("content-type")
+            .and_then(|ct| ct.to_str().ok())
+            .is_some_and(|ct| ct.contains("text/event-stream"));
+
+        if !is_streaming {
+            let body_bytes = self.read_body_within(response, ctx.timeout).await?;
+            self.log_response_body("Response", config, &body_bytes);
+            let upstream: Value = serde_json::from_slice(&body_bytes).map_err(|e| {
+                error!("Failed to parse upstream response for conversion: {}", e);
+                ctx.conversion_failed(conversion, "invalid_response_json");
+                ProxyError::UpstreamError(StatusCode::BAD_GATEWAY, "Failed to parse upstream response".to_string())
+            })?;
+            if let Some(usage) = &mut ctx.usage {
+                usage.tracker().observe(&upstream);
+            }
+            let expected = match conversion {
+                Conversion::LegacyCompletions => "choices",
+                Conversion::OpenaiToAnthropic => "content",
+            };
+            if !upstream.get(expected).is_some_and(Value::is_array) {
+                warn!("Upstream response to convert has no {} array", expected);
+                ctx.conversion_failed(conversion, "unexpected_shape");
+            }
+
+            let converted = match conversion {
+                Conversion::LegacyCompletions => convert::chat_to_completions_response(&upstream),
+                Conversion::OpenaiToAnthropic => convert::anthropic_to_chat_response(&upstream),
+            };
+
+            let mut json_response = Json(converted).into_response();
+            *json_response.status_mut() = status;
+            json_response.headers_mut().extend(response_headers);
+            return Ok(json_response);
+        }
+
+        let mut usage = ctx.usage.take();
+        let stream = stream! {
+            let mut bytes_stream = response.bytes_stream();
+            let mut buffer = Vec::new();
+            let mut first_chunk_at = None;
+            let mut events = 0u64;
+            let mut checksum = ctx.stream_checksums.then(StreamChecksum::default);
+            let mut converter = StreamConverter::new(conversion);
+
+            loop {
+                let chunk = match next_within(&mut bytes_stream, ctx.stream_wait(first_chunk_at), &ctx.drain).await {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break,
+                    Err(Interrupted::TimedOut) => {
+                        yield ConvertedFrame::Error(ctx.stream_timeout(first_chunk_at).body(&ctx.request_id));
+                        return;
+                    }
+                    Err(Interrupted::ShuttingDown) => {
+                        ctx.drain.record_cut();
+                        yield ConvertedFrame::ShuttingDown;
+                        yield ConvertedFrame::Data("[DONE]".to_string());
+                        return;
+                    }
+                };
+                match chunk {
+                    Ok(bytes) => {
+                        if first_chunk_at.is_none() {
+                            first_chunk_at = Some(Instant::now());
+                            ctx.observe_first_byte();
+                        }
+                        if let Some(checksum) = &mut checksum {
+                            checksum.input(&bytes);
+                        }
+                        if let Some(usage) = &mut usage {
+                            usage.tracker().observe_stream(&bytes);
+                        }
+                        buffer.extend_from_slice(&bytes);
+
+                        while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                            let line: Vec<u8> = buffer.drain(..=pos).collect();
+                            match Self::convert_stream_line(conversion, &mut converter, &line, &ctx) {
+                                Some(Ok(converted)) => {
+                                    for data in converted {
+                                        events += 1;
+                                        yield ConvertedFrame::Data(data);
+                                    }
+                                }
+                                Some(Err(error)) => {
+                                    yield ConvertedFrame::Error(ctx.stream_failed(error).body(&ctx.request_id));
+                                    return;
+                                }
+                                None => {}
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to read upstream stream for conversion: {}", e);
+                        break;
+                    }
                 }
             }
+
+            match Self::convert_stream_line(conversion, &mut converter, &buffer, &ctx) {
+                Some(Ok(converted)) => {
+                    for data in converted {
+                        events += 1;
+                        yield ConvertedFrame::Data(data);
+                    }
+                }
+                Some(Err(error)) => {
+                    yield ConvertedFrame::Error(ctx.stream_failed(error).body(&ctx.request_id));
+                    return;
+                }
+                None => {}
+            }
+            ctx.observe_stream_end(events, first_chunk_at);
+            if let Some(checksum) = &checksum {
+                checksum.log_reencoded(&ctx.path, events);
+            }
+        };
+
+        let mut final_response = match format {
+            StreamFormat::Sse => Sse::new(futures_util::StreamExt::map(stream, |frame| {
+                Ok::<Event, Infallible>(match frame {
+                    ConvertedFrame::Data(data) => Event::default().data(data),
+                    ConvertedFrame::Error(body) => Event::default().event("error").data(body.to_string()),
+                    ConvertedFrame::ShuttingDown => Event::default().comment(SHUTDOWN_COMMENT),
+                })
+            }))
+            .into_response(),
+            // NDJSON has no end-of-stream marker, so `[DONE]` is dropped
+            StreamFormat::Ndjson => {
+                let lines = futures_util::StreamExt::filter_map(stream, |frame| {
+                    let line = match frame {
+                        ConvertedFrame::Data(data) if data == "[DONE]" => None,
+                        ConvertedFrame::Data(data) => Some(format!("{data}\n")),
+                        ConvertedFrame::Error(body) => Some(format!("{body}\n")),
+                        ConvertedFrame::ShuttingDown => None,
+                    };
+                    futures_util::future::ready(line.map(Ok::<String, Infallible>))
+                });
+                ([(CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(lines)).into_response()
+            }
+        };
+        final_response.headers_mut().extend(response_headers);
+
+        Ok(final_response)
+    }
+
+    /// Convert a single upstream SSE line, returning the data of the events
+    /// to emit or the error the upstream reported in it
+    fn convert_stream_line(
+        conversion: Conversion,
+        converter: &mut StreamConverter,
+        line: &[u8],
+        ctx: &RequestContext,
+    ) -> Option<Result<Vec<String>, ProxyError>> {
+        let line = String::from_utf8_lossy(line);
+        let data = line.trim().strip_prefix("data:")?.trim();
+
+        if data == "[DONE]" {
+            return Some(Ok(vec![data.to_string()]));
+        }
+        if let Some(error) = provider_error(None, data) {
+            return Some(Err(error));
+        }
+
+        let chunk: Value = match serde_json::from_str(data) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                warn!("Skipping unparseable stream chunk: {}", e);
+                ctx.conversion_failed(conversion, "invalid_chunk");
+                return None;
+            }
+        };
+        let shaped = match conversion {
+            Conversion::LegacyCompletions => chunk.get("choices").is_some_and(Value::is_array),
+            Conversion::OpenaiToAnthropic => chunk.get("type").is_some_and(Value::is_string),
+        };
+        if !shaped {
+            warn!("Stream chunk to convert has an unexpected shape");
+            ctx.conversion_failed(conversion, "unexpected_shape");
+        }
+
+        Some(Ok(converter.convert(&chunk)))
+    }
+
+    /// Buffer an upstream response body, failing with 502 once it grows past
+    /// `max_response_bytes` or when it is compressed with an encoding the
+    /// client could not decode
+    async fn read_body_limited(&self, response: reqwest::Response) -> Result<Bytes, ProxyError> {
+        // Decoded bodies lose their `content-encoding`; one left over was not understood
+        if let Some(encoding) = response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .filter(|encoding| !encoding.trim().eq_ignore_ascii_case("identity"))
+        {
+            error!("Upstream response uses unsupported content-encoding {}", encoding);
+            return Err(ProxyError::UpstreamError(
+                StatusCode::BAD_GATEWAY,
+                format!("Upstream response uses unsupported content-encoding {encoding}"),
+            ));
+        }
+
+        let Some(limit) = self.config.max_response_bytes else {
+            return response.bytes().await.map_err(Self::read_error);
+        };
+
+        let too_large = || {
+            error!("Upstream response exceeds the {} byte limit", limit);
+            ProxyError::UpstreamError(StatusCode::BAD_GATEWAY, "Upstream response too large".to_string())
+        };
+
+        if response.content_length().is_some_and(|len| len > limit as u64) {
+            return Err(too_large());
+        }
+
+        let mut body = Vec::new();
+        let mut bytes_stream = response.bytes_stream();
+        while let Some(chunk) = futures_util::StreamExt::next(&mut bytes_stream).await {
+            let chunk = chunk.map_err(Self::read_error)?;
+            if body.len() + chunk.len() > limit {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(Bytes::from(body))
+    }
+
+    /// Read a buffered body, bounded by the endpoint timeout even when the
+    /// request itself has no total timeout
+    async fn read_body_within(
+        &self,
+        response: reqwest::Response,
+        timeout: Duration,
+    ) -> Result<Bytes, ProxyError> {
+        tokio::time::timeout(timeout, self.read_body_limited(response))
+            .await
+            .map_err(|_| ProxyError::TimeoutError("Upstream response timed out".to_string()))?
+    }
+
+    fn read_error(e: reqwest::Error) -> ProxyError {
+        if e.is_timeout() {
+            return ProxyError::TimeoutError("Upstream response timed out".to_string());
+        }
+        error!("Failed to read response body: {}", e);
+        ProxyError::Internal("Failed to read response".to_string())
+    }
+
+    /// The request body's `model` field, only looked at when model routes or
+    /// an allowlist need it
+    fn request_model(&self, config: &EndpointConfig, body: &[u8]) -> Option<String> {
+        if self.config.model_routes.is_empty() && config.allowed_models.is_none() {
+            return None;
         }
 
-        router
+        let body: Value = serde_json::from_slice(body).ok()?;
+        Some(body.get("model")?.as_str()?.to_string())
     }
 
-    async fn handle_proxy_request(
-        config: EndpointConfig,
+    /// Forward request and response bodies byte-for-byte, hashing both on the
+    /// way through. Only the configured forward header lists are applied; no
+    /// custom headers, auth injection or response re-encoding takes place.
+    async fn handle_observe_request(
+        &self,
+        config: &EndpointConfig,
         req: Request,
-    ) -> Result<Response, (StatusCode, String)> {
-        info!("Forwarding request: {} -> {}", config.path, config.target_url);
-
-        let client = Client::new();
+        mut ctx: RequestContext,
+    ) -> Result<Response, ProxyError> {
         let (parts, body) = req.into_parts();
-
-        // Read request body
-        let body_bytes = match axum::body::to_bytes(body, usize::MAX).await {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                error!("Failed to read request body: {}", e);
-                return Err((StatusCode::BAD_REQUEST, "Unable to read request body".to_string()));
-            }
+        let meta = RequestMeta {
+            method: parts.method.to_string(),
+            path: parts.uri.path().to_string(),
+            query: parts.uri.query().map(str::to_string),
+            ..Default::default()
         };
+        let target_url = plan_route(&self.config, config, &meta)
+            .ok_or_else(|| ProxyError::NotFound("Not Found".to_string()))?
+            .target_url;
+
+        info!("Observing request: {} -> {}", config.path, target_url);
 
-        // Build request
         let method = Method::from_bytes(config.method.as_bytes())
-            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Invalid HTTP method".to_string()))?;
+            .map_err(|_| ProxyError::Internal("Invalid HTTP method".to_string()))?;
+
+        // Stream the request body upstream while hashing it
+        let request_digest = Arc::new(Mutex::new(BodyDigest::default()));
+        let request_stream = {
+            let request_digest = request_digest.clone();
+            futures_util::StreamExt::map(body.into_data_stream(), move |chunk| {
+                if let Ok(bytes) = &chunk {
+                    request_digest.lock().unwrap().update(bytes);
+                }
+                chunk
+            })
+        };
 
-        let mut req_builder = client
-            .request(method, &config.target_url)
-            .body(body_bytes);
+        let mut req_builder = self.passthrough_client
+            .request(method, &target_url)
+            .body(reqwest::Body::wrap_stream(request_stream));
 
         // Add forwarded request headers
         for header_name in &config.forward_request_headers {
             if let Some(header_value) = parts.headers.get(header_name) {
-                req_builder = req_builder.header(header_name, header_value);
+                req_builder = req_builder.header(header_name, self.limit_header_value(header_name, header_value)?);
             }
         }
 
-        // Add custom request headers
-        for (name, value) in &config.custom_headers {
-            req_builder = req_builder.header(name, value);
+        let response = self.send_upstream(req_builder, &ctx)
+            .await
+            .inspect_err(|_| self.metrics.record_upstream_error(&config.path, None))?;
+        self.metrics.observe_upstream_latency(&config.path, ctx.started.elapsed());
+        self.observe_timing_headers(&config.path, response.headers());
+
+        // Upstream status is passed through as-is, errors included
+        let status = response.status();
+        if !status.is_success() {
+            self.metrics.record_upstream_error(&config.path, Some(status));
         }
+        let mut response_builder = Response::builder().status(status);
 
-        // Special handling: add auth header for LLM proxy
-        if config.path.contains("llm-proxy") {
-            req_builder = req_builder.header("authorization", format!("Bearer {}", get_amp_api_key()));
+        // Forward response headers
+        for header_name in &config.forward_response_headers {
+            if let Some(header_value) = response.headers().get(header_name) {
+                response_builder = response_builder.header(header_name, header_value);
+            }
         }
 
-        // Send request
-        let response = match req_builder.send().await {
-            Ok(resp) => resp,
-            Err(e) => {
-                error!("Failed to forward request: {}", e);
-                return Err((StatusCode::BAD_GATEWAY, format!("Forward failed: {e}")));
+        let mut usage = ctx.usage.take();
+        let stream = stream! {
+            let mut response_digest = BodyDigest::default();
+            let mut bytes_stream = response.bytes_stream();
+
+            while let Some(chunk) = futures_util::StreamExt::next(&mut bytes_stream).await {
+                match chunk {
+                    Ok(bytes) => {
+                        if response_digest.len == 0 {
+                            ctx.observe_first_byte();
+                        }
+                        response_digest.update(&bytes);
+                        if let Some(usage) = &mut usage {
+                            usage.tracker().observe_stream(&bytes);
+                        }
+                        yield Ok::<Bytes, std::io::Error>(bytes);
+                    }
+                    Err(e) => {
+                        error!("Failed to read observed response stream: {}", e);
+                        yield Err(std::io::Error::other(e));
+                        break;
+                    }
+                }
             }
-        };
 
-        if !response.status().is_success() {
-            error!("Upstream server returned error status: {}", response.status());
-            return Err((StatusCode::BAD_GATEWAY, "Upstream server error".to_string()));
-        }
+            let request_digest = std::mem::take(&mut *request_digest.lock().unwrap());
+            let (request_len, request_sha256) = request_digest.finish();
+            let (response_len, response_sha256) = response_digest.finish();
+            info!(
+                path = %ctx.path,
+                status = status.as_u16(),
+                request_bytes = request_len,
+                request_sha256 = %request_sha256,
+                response_bytes = response_len,
+                response_sha256 = %response_sha256,
+                elapsed_ms = ctx.started.elapsed().as_millis() as u64,
+                "Observed exchange"
+            );
+        };
 
-        // Handle based on response type
-        match config.response_type {
-            ResponseType::Sse => Self::handle_sse_response(response, &config).await,
-            ResponseType::Stream => Self::handle_stream_response(response, &config).await,
-            ResponseType::Json => Self::handle_json_response(response, &config).await,
-            ResponseType::Html => Self::handle_html_response(response, &config).await,
-        }
+        response_builder.body(Body::from_stream(stream))
+            .map_err(|e| {
+                error!("Failed to build observed response: {}", e);
+                ProxyError::Internal("Failed to build response".to_string())
+            })
     }
 
     async fn handle_sse_response(
         response: reqwest::Response,
         config: &EndpointConfig,
-    ) -> Result<Response, (StatusCode, String)> {
+        mut ctx: RequestContext,
+    ) -> Result<Response, ProxyError> {
         let mut response_headers = HeaderMap::new();
         
         // Forward response headers
         for header_name in &config.forward_response_headers {
-            if let Some(header_value) = response.headers().get(header_name) {
-                if let Ok(name) = HeaderName::from_bytes(header_name.as_bytes()) {
-                    response_headers.insert(name, header_value.clone());
-                }
+            if let Some(header_value) = response.headers().get(header_name)
+                && Self::forwards_response_header(header_name)
+                && let Ok(name) = HeaderName::from_bytes(header_name.as_bytes())
+            {
+                response_headers.insert(name, header_value.clone());
             }
         }
 
+        let mut usage = ctx.usage.take();
         let stream = stream! {
             let mut bytes_stream = response.bytes_stream();
-            let mut buffer = Vec::new();
+            let mut parser = SseParser::default();
+            let mut first_chunk_at = None;
+            let mut events = 0u64;
+            let mut checksum = ctx.stream_checksums.then(StreamChecksum::default);
 
-            while let Some(chunk) = futures_util::StreamExt::next(&mut bytes_stream).await {
+            loop {
+                let chunk = match next_within(&mut bytes_stream, ctx.stream_wait(first_chunk_at), &ctx.drain).await {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break,
+                    Err(Interrupted::TimedOut) => {
+                        yield Ok::<Event, Infallible>(Self::error_event(ctx.stream_timeout(first_chunk_at), &ctx.request_id));
+                        return;
+                    }
+                    Err(Interrupted::ShuttingDown) => {
+                        ctx.drain.record_cut();
+                        yield Ok::<Event, Infallible>(Event::default().comment(SHUTDOWN_COMMENT));
+                        return;
+                    }
+                };
                 match chunk {
                     Ok(bytes) => {
-                        buffer.extend_from_slice(&bytes);
-
-                        let text = String::from_utf8_lossy(&buffer);
-                        let lines_vec: Vec<&str> = text.lines().collect();
+                        if first_chunk_at.is_none() {
+                            first_chunk_at = Some(Instant::now());
+                            ctx.observe_first_byte();
+                        }
+                        if let Some(checksum) = &mut checksum {
+                            checksum.input(&bytes);
+                        }
+                        if let Some(usage) = &mut usage {
+                            usage.tracker().observe_stream(&bytes);
+                        }
 
-                        if lines_vec.len() > 1 {
-                            for line in &lines_vec[..lines_vec.len()-1] {
-                                if let Some(data) = Self::parse_sse_line(line) {
-                                    yield Ok::<Event, Infallible>(Event::default().data(data));
-                                }
+                        for event in parser.feed(&bytes) {
+                            if let Some(error) = event.provider_error() {
+                                yield Ok::<Event, Infallible>(Self::error_event(ctx.stream_failed(error), &ctx.request_id));
+                                return;
                             }
-
-                            buffer = lines_vec.last().unwrap().as_bytes().to_vec();
+                            events += 1;
+                            yield Ok::<Event, Infallible>(event.into_event());
                         }
                     }
                     Err(e) => {
@@ -176,13 +2007,17 @@ impl ProxyService {
                 }
             }
 
-            if !buffer.is_empty() {
-                let text = String::from_utf8_lossy(&buffer);
-                for line in text.lines() {
-                    if let Some(data) = Self::parse_sse_line(line) {
-                        yield Ok::<Event, Infallible>(Event::default().data(data));
-                    }
+            if let Some(event) = parser.finish() {
+                if let Some(error) = event.provider_error() {
+                    yield Ok::<Event, Infallible>(Self::error_event(ctx.stream_failed(error), &ctx.request_id));
+                    return;
                 }
+                events += 1;
+                yield Ok::<Event, Infallible>(event.into_event());
+            }
+            ctx.observe_stream_end(events, first_chunk_at);
+            if let Some(checksum) = &checksum {
+                checksum.log_reencoded(&ctx.path, events);
             }
         };
 
@@ -194,9 +2029,11 @@ impl ProxyService {
     }
 
     async fn handle_stream_response(
+        &self,
         response: reqwest::Response,
         config: &EndpointConfig,
-    ) -> Result<Response, (StatusCode, String)> {
+        mut ctx: RequestContext,
+    ) -> Result<Response, ProxyError> {
         let status = response.status();
         let headers = response.headers().clone();
 
@@ -206,7 +2043,10 @@ impl ProxyService {
         for header_name in &config.forward_response_headers {
             if let Some(header_value) = headers.get(header_name) {
                 let name_str = header_name.as_str();
-                if !name_str.starts_with("connection") && !name_str.starts_with("transfer-encoding") {
+                if !name_str.starts_with("connection")
+                    && !name_str.starts_with("transfer-encoding")
+                    && Self::forwards_response_header(name_str)
+                {
                     response_builder = response_builder.header(header_name, header_value);
                 }
             }
@@ -220,52 +2060,119 @@ impl ProxyService {
             .unwrap_or(false);
 
         if is_streaming {
-            let stream = futures_util::StreamExt::map(response.bytes_stream(), |result| {
-                result.map_err(std::io::Error::other)
-            });
+            let is_sse = headers
+                .get("content-type")
+                .and_then(|ct| ct.to_str().ok())
+                .is_some_and(|ct| ct.contains("text/event-stream"));
+            let mut usage = ctx.usage.take();
+            let stream = stream! {
+                let mut bytes_stream = response.bytes_stream();
+                let mut first_chunk_at = None;
+                let mut checksum = ctx.stream_checksums.then(StreamChecksum::default);
+
+                loop {
+                    match next_within(&mut bytes_stream, ctx.stream_wait(first_chunk_at), &ctx.drain).await {
+                        Ok(Some(result)) => {
+                            if first_chunk_at.is_none() {
+                                first_chunk_at = Some(Instant::now());
+                                ctx.observe_first_byte();
+                            }
+                            if let (Some(checksum), Ok(bytes)) = (&mut checksum, &result) {
+                                checksum.input(bytes);
+                                checksum.output(bytes);
+                            }
+                            if let (Some(usage), Ok(bytes)) = (&mut usage, &result) {
+                                usage.tracker().observe_stream(bytes);
+                            }
+                            yield result.map_err(std::io::Error::other);
+                        }
+                        Ok(None) => break,
+                        Err(Interrupted::ShuttingDown) => {
+                            ctx.drain.record_cut();
+                            if is_sse {
+                                let comment = Bytes::from(format!(": {SHUTDOWN_COMMENT}\n\n"));
+                                if let Some(checksum) = &mut checksum {
+                                    checksum.output(&comment);
+                                }
+                                yield Ok(comment);
+                            } else {
+                                yield Err(std::io::Error::new(std::io::ErrorKind::Interrupted, SHUTDOWN_COMMENT));
+                            }
+                            break;
+                        }
+                        // SSE clients get an error event, anything else an aborted body
+                        Err(Interrupted::TimedOut) if is_sse => {
+                            let error = ctx.stream_timeout(first_chunk_at);
+                            let event = Bytes::from(format!("event: error\ndata: {}\n\n", error.body(&ctx.request_id)));
+                            if let Some(checksum) = &mut checksum {
+                                checksum.output(&event);
+                            }
+                            yield Ok(event);
+                            break;
+                        }
+                        Err(Interrupted::TimedOut) => {
+                            let error = ctx.stream_timeout(first_chunk_at);
+                            yield Err(std::io::Error::new(std::io::ErrorKind::TimedOut, error.to_string()));
+                            break;
+                        }
+                    }
+                }
+
+                if let Some(checksum) = &checksum {
+                    checksum.log_passthrough(&ctx.path);
+                }
+            };
             let body = Body::from_stream(stream);
             
             response_builder.body(body)
                 .map_err(|e| {
                     error!("Failed to build streaming response: {}", e);
-                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to build streaming response".to_string())
+                    ProxyError::Internal("Failed to build streaming response".to_string())
                 })
         } else {
-            let body_bytes = response.bytes().await
-                .map_err(|e| {
-                    error!("Failed to read response body: {}", e);
-                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read response".to_string())
-                })?;
+            let body_bytes = self.read_body_within(response, ctx.timeout).await?;
+            if let Some(usage) = &mut ctx.usage {
+                usage.tracker().observe_body(&body_bytes);
+            }
 
             response_builder.body(Body::from(body_bytes))
                 .map_err(|e| {
                     error!("Failed to build response: {}", e);
-                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to build response".to_string())
+                    ProxyError::Internal("Failed to build response".to_string())
                 })
         }
     }
 
     async fn handle_json_response(
+        &self,
         response: reqwest::Response,
         config: &EndpointConfig,
-    ) -> Result<Response, (StatusCode, String)> {
+        timeout: Duration,
+        usage: Option<UsageRecorder>,
+    ) -> Result<Response, ProxyError> {
         let status = response.status();
         let mut response_headers = HeaderMap::new();
 
         // Forward response headers
         for header_name in &config.forward_response_headers {
-            if let Some(header_value) = response.headers().get(header_name) {
-                if let Ok(name) = HeaderName::from_bytes(header_name.as_bytes()) {
-                    response_headers.insert(name, header_value.clone());
-                }
+            if let Some(header_value) = response.headers().get(header_name)
+                && Self::forwards_response_header(header_name)
+                && let Ok(name) = HeaderName::from_bytes(header_name.as_bytes())
+            {
+                response_headers.insert(name, header_value.clone());
             }
         }
 
-        let json_data: Value = response.json().await
+        let body_bytes = self.read_body_within(response, timeout).await?;
+        self.log_response_body("Response", config, &body_bytes);
+        let json_data: Value = serde_json::from_slice(&body_bytes)
             .map_err(|e| {
                 error!("Failed to parse JSON response: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse response".to_string())
+                ProxyError::Internal("Failed to parse response".to_string())
             })?;
+        if let Some(mut usage) = usage {
+            usage.tracker().observe(&json_data);
+        }
 
         let mut json_response = Json(json_data).into_response();
         *json_response.status_mut() = status;
@@ -275,26 +2182,27 @@ impl ProxyService {
     }
 
     async fn handle_html_response(
+        &self,
         response: reqwest::Response,
         config: &EndpointConfig,
-    ) -> Result<Response, (StatusCode, String)> {
+        timeout: Duration,
+    ) -> Result<Response, ProxyError> {
         let status = response.status();
         let mut response_headers = HeaderMap::new();
 
         // Forward response headers
         for header_name in &config.forward_response_headers {
-            if let Some(header_value) = response.headers().get(header_name) {
-                if let Ok(name) = HeaderName::from_bytes(header_name.as_bytes()) {
-                    response_headers.insert(name, header_value.clone());
-                }
+            if let Some(header_value) = response.headers().get(header_name)
+                && Self::forwards_response_header(header_name)
+                && let Ok(name) = HeaderName::from_bytes(header_name.as_bytes())
+            {
+                response_headers.insert(name, header_value.clone());
             }
         }
 
-        let html_text = response.text().await
-            .map_err(|e| {
-                error!("Failed to read HTML response: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read response".to_string())
-            })?;
+        let body_bytes = self.read_body_within(response, timeout).await?;
+        self.log_response_body("Response", config, &body_bytes);
+        let html_text = String::from_utf8_lossy(&body_bytes).into_owned();
 
         let mut html_response = Response::builder()
             .status(status)
@@ -302,30 +2210,1039 @@ impl ProxyService {
             .body(Body::from(html_text))
             .map_err(|e| {
                 error!("Failed to build HTML response: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to build response".to_string())
+                ProxyError::Internal("Failed to build response".to_string())
             })?;
 
         html_response.headers_mut().extend(response_headers);
 
         Ok(html_response)
     }
+}
 
-    fn parse_sse_line(line: &str) -> Option<String> {
-        let line = line.trim();
-        if line.is_empty() {
-            return None;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use serde_json::json;
+
+    use crate::proxy::idempotency::REPLAYED_HEADER;
+    use crate::test_support::{ADMIN_TOKEN, endpoint, init_admin_token, proxy_config, proxy_service, send, spawn_upstream};
+
+    /// Upstream answering every `POST` with the request body it received
+    async fn echo_upstream() -> String {
+        spawn_upstream(Router::new().route("/{*path}", post(|body: Bytes| async move {
+            ([("content-type", "application/json")], body)
+        })))
+        .await
+    }
+
+    /// Upstream answering `POST /drip` with SSE events `0`, `1`, ... each
+    /// sent after its delay in milliseconds
+    async fn drip_upstream(delays: &'static [u64]) -> String {
+        spawn_upstream(Router::new().route("/drip", post(move || async move {
+            let events = stream! {
+                for (n, delay) in delays.iter().enumerate() {
+                    tokio::time::sleep(Duration::from_millis(*delay)).await;
+                    yield Ok::<_, Infallible>(format!("data: {n}\n\n"));
+                }
+            };
+            ([("content-type", "text/event-stream")], Body::from_stream(events))
+        })))
+        .await
+    }
+
+    /// JSON body of the compressed fixtures below
+    const DECODED: &str = r#"{"choices":[{"text":"compressed"}]}"#;
+
+    /// `DECODED` compressed with gzip
+    const GZIPPED: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xab, 0x56, 0x4a, 0xce, 0xc8, 0xcf, 0x4c, 0x4e, 0x2d,
+        0x56, 0xb2, 0x8a, 0xae, 0x56, 0x2a, 0x49, 0xad, 0x28, 0x51, 0xb2, 0x52, 0x4a, 0xce, 0xcf, 0x2d, 0x28, 0x4a, 0x2d,
+        0x2e, 0x4e, 0x4d, 0x51, 0xaa, 0x8d, 0xad, 0x05, 0x00, 0xf4, 0x6a, 0x23, 0x60, 0x23, 0x00, 0x00, 0x00,
+    ];
+
+    /// `DECODED` compressed with deflate, in a zlib wrapper
+    const DEFLATED: &[u8] = &[
+        0x78, 0x9c, 0xab, 0x56, 0x4a, 0xce, 0xc8, 0xcf, 0x4c, 0x4e, 0x2d, 0x56, 0xb2, 0x8a, 0xae, 0x56, 0x2a, 0x49, 0xad,
+        0x28, 0x51, 0xb2, 0x52, 0x4a, 0xce, 0xcf, 0x2d, 0x28, 0x4a, 0x2d, 0x2e, 0x4e, 0x4d, 0x51, 0xaa, 0x8d, 0xad, 0x05,
+        0x00, 0xe0, 0x94, 0x0c, 0xc1,
+    ];
+
+    /// `DECODED` as a zstd frame holding one raw block
+    const ZSTD: &[u8] = &[
+        0x28, 0xb5, 0x2f, 0xfd, 0x20, 0x23, 0x19, 0x01, 0x00, 0x7b, 0x22, 0x63, 0x68, 0x6f, 0x69, 0x63, 0x65, 0x73, 0x22,
+        0x3a, 0x5b, 0x7b, 0x22, 0x74, 0x65, 0x78, 0x74, 0x22, 0x3a, 0x22, 0x63, 0x6f, 0x6d, 0x70, 0x72, 0x65, 0x73, 0x73,
+        0x65, 0x64, 0x22, 0x7d, 0x5d, 0x7d,
+    ];
+
+    /// Upstream answering `POST /{encoding}` with its fixture, sent with
+    /// that `content-encoding`
+    async fn encoded_upstream(fixtures: &'static [(&'static str, &'static [u8])]) -> String {
+        spawn_upstream(Router::new().route("/{encoding}", post(
+            move |axum::extract::Path(encoding): axum::extract::Path<String>| async move {
+                let (_, body) = fixtures.iter().find(|(name, _)| *name == encoding).unwrap();
+                ([("content-type", "application/json"), ("content-encoding", encoding.as_str())], *body).into_response()
+            },
+        )))
+        .await
+    }
+
+    fn json_request(path: &str, body: &Value, headers: &[(&str, &str)]) -> Request {
+        let mut req = Request::post(path).header("content-type", "application/json");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.body(Body::from(body.to_string())).unwrap()
+    }
+
+    /// Bodies the observe upstream answers with, by kind: JSON with unusual
+    /// whitespace and key order, SSE events and bytes that are not UTF-8
+    fn observed_body(kind: &str) -> (&'static str, Vec<u8>) {
+        match kind {
+            "json" => ("application/json", b"{ \"z\" :1,\n  \"a\":[ 1.50 , \"\\u00e9\" ] }".to_vec()),
+            "sse" => ("text/event-stream", b"event: ping\ndata: {\"n\": 1}\n\ndata: [DONE]\n\n".to_vec()),
+            _ => ("application/octet-stream", (0..=255u8).rev().chain([0, 0xff, 0x1f, 0x8b]).collect()),
+        }
+    }
+
+    #[tokio::test]
+    async fn observe_mode_passes_bodies_through_byte_for_byte() {
+        // The upstream reports the SHA-256 of the request body it received
+        let upstream = spawn_upstream(Router::new().route("/{kind}", post(
+            |axum::extract::Path(kind): axum::extract::Path<String>, body: Bytes| async move {
+                let (content_type, response) = observed_body(&kind);
+                let digest = hex::encode(Sha256::digest(&body));
+                ([("content-type", content_type), ("x-request-sha256", digest.as_str())], response).into_response()
+            },
+        )))
+        .await;
+        let endpoints = ["json", "sse", "stream"]
+            .into_iter()
+            .map(|kind| endpoint(json!({
+                "path": format!("/observe/{kind}"),
+                "target_url": format!("{upstream}/{kind}"),
+                "response_type": kind,
+                "mode": "observe",
+                "forward_response_headers": ["content-type", "x-request-sha256"],
+            })))
+            .collect();
+        let router = proxy_service(proxy_config(endpoints, json!({}))).create_router();
+
+        for kind in ["json", "sse", "stream"] {
+            let request_body = observed_body(kind).1.into_iter().rev().collect::<Vec<u8>>();
+            let req = Request::post(format!("/observe/{kind}"))
+                .header("content-type", "application/octet-stream")
+                .body(Body::from(request_body.clone()))
+                .unwrap();
+            let (status, headers, body) = send(&router, req).await;
+
+            assert_eq!(status, StatusCode::OK, "{kind}");
+            assert_eq!(headers["x-request-sha256"], hex::encode(Sha256::digest(&request_body)), "{kind} request");
+            assert_eq!(
+                hex::encode(Sha256::digest(&body)),
+                hex::encode(Sha256::digest(observed_body(kind).1)),
+                "{kind} response"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn idempotency_keys_are_scoped_to_the_client_and_body() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let upstream = spawn_upstream(Router::new().route("/count", post(move || {
+            let call = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Json(json!({ "call": call })) }
+        })))
+        .await;
+        let config = proxy_config(vec![endpoint(json!({ "target_url": format!("{upstream}/count") }))], json!({}));
+        let router = proxy_service(config).create_router();
+        let request = |body: Value, authorization: &str| {
+            json_request("/v1/test", &body, &[("idempotency-key", "k1"), ("authorization", authorization)])
+        };
+
+        let (status, _, first) = send(&router, request(json!({ "n": 1 }), "Bearer alice")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, headers, replayed) = send(&router, request(json!({ "n": 1 }), "Bearer alice")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[REPLAYED_HEADER], "true");
+        assert_eq!(replayed, first);
+
+        let (status, _, _) = send(&router, request(json!({ "n": 2 }), "Bearer alice")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, headers, other) = send(&router, request(json!({ "n": 1 }), "Bearer bob")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get(REPLAYED_HEADER).is_none());
+        assert_ne!(other, first);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn sse_events_keep_their_names_and_ids() {
+        const EVENTS: &str = "event: message_start\nid: 1\ndata: {\"type\":\"message_start\"}\n\n\
+            : ping\n\
+            event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\n\
+            data: \"delta\":{\"text\":\"Hi\"}}\n\n\
+            data: {\"legacy\":true}\n\n\
+            event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n\
+            data: never sent\n\n";
+        let upstream = spawn_upstream(Router::new().route("/stream", post(|| async {
+            ([("content-type", "text/event-stream")], EVENTS)
+        })))
+        .await;
+        let config = proxy_config(
+            vec![endpoint(json!({ "target_url": format!("{upstream}/stream"), "response_type": "sse" }))],
+            json!({}),
+        );
+        let router = proxy_service(config).create_router();
+
+        let (status, _, body) = send(&router, json_request("/v1/test", &json!({}), &[])).await;
+        assert_eq!(status, StatusCode::OK);
+        let mut parser = SseParser::default();
+        let events: Vec<_> = parser.feed(&body).into_iter().map(|event| (event.event, event.data)).collect();
+        assert_eq!(events.len(), 4, "{}", String::from_utf8_lossy(&body));
+        assert_eq!(events[0], (Some("message_start".to_string()), r#"{"type":"message_start"}"#.to_string()));
+        assert_eq!(
+            events[1],
+            (Some("content_block_delta".to_string()), "{\"type\":\"content_block_delta\",\n\"delta\":{\"text\":\"Hi\"}}".to_string())
+        );
+        assert_eq!(events[2], (None, r#"{"legacy":true}"#.to_string()));
+        // The provider's error ends the stream with its type kept
+        assert_eq!(events[3].0.as_deref(), Some("error"));
+        let error: Value = serde_json::from_str(&events[3].1).unwrap();
+        assert_eq!(error["error"]["type"], "overloaded_error");
+        assert!(String::from_utf8_lossy(&body).contains("id: 1\n"));
+    }
+
+    #[tokio::test]
+    async fn idempotent_responses_expire_after_the_ttl() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let upstream = spawn_upstream(Router::new().route("/count", post(move || {
+            let call = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Json(json!({ "call": call })) }
+        })))
+        .await;
+        let config = proxy_config(
+            vec![endpoint(json!({ "target_url": format!("{upstream}/count") }))],
+            json!({ "idempotency_ttl": 1 }),
+        );
+        let router = proxy_service(config).create_router();
+        let request = |body: Value| json_request("/v1/test", &body, &[("idempotency-key", "k1")]);
+
+        let (_, _, first) = send(&router, request(json!({ "n": 1 }))).await;
+        let (_, headers, replayed) = send(&router, request(json!({ "n": 1 }))).await;
+        assert_eq!(headers[REPLAYED_HEADER], "true");
+        assert_eq!(replayed, first);
+
+        // Once expired the key is free again, even for another body
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let (status, headers, body) = send(&router, request(json!({ "n": 2 }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get(REPLAYED_HEADER).is_none());
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!({ "call": 2 }));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rate_and_concurrency_limits_split_429_and_503() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::sync::{Semaphore, mpsc};
+
+        // The upstream holds every request until the test lets it through
+        let gate = Arc::new(Semaphore::new(0));
+        let received = Arc::new(AtomicUsize::new(0));
+        let upstream = {
+            let (gate, received) = (gate.clone(), received.clone());
+            spawn_upstream(Router::new().route("/held", post(move || async move {
+                received.fetch_add(1, Ordering::SeqCst);
+                gate.acquire().await.unwrap().forget();
+                Json(json!({ "ok": true }))
+            })))
+            .await
+        };
+        let config = proxy_config(
+            vec![endpoint(json!({
+                "target_url": format!("{upstream}/held"),
+                "max_concurrent": 2,
+                "on_full": "reject",
+                "rate_limit": { "requests_per_minute": 0.001, "burst_size": 6, "key": "authorization" },
+            }))],
+            json!({}),
+        );
+        let router = proxy_service(config).create_router();
+
+        // Fire `count` requests at once, reporting each status as it arrives
+        let fire = |count: usize, client: &'static str| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            for _ in 0..count {
+                let (router, tx) = (router.clone(), tx.clone());
+                tokio::spawn(async move {
+                    let req = json_request("/v1/test", &json!({}), &[("authorization", client)]);
+                    tx.send(send(&router, req).await.0).unwrap();
+                });
+            }
+            rx
+        };
+        let statuses = async |rx: &mut mpsc::UnboundedReceiver<StatusCode>, count: usize| {
+            let mut statuses = Vec::new();
+            for _ in 0..count {
+                let status = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+                statuses.push(status.as_u16());
+            }
+            statuses.sort();
+            statuses
+        };
+        let upstream_reached = async |count: usize| {
+            let reached = async {
+                while received.load(Ordering::SeqCst) < count {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(5), reached).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert_eq!(received.load(Ordering::SeqCst), count);
+        };
+
+        // 8 requests against a burst of 6 and 2 slots: 2 are rate limited,
+        // 2 reach the upstream and the other 4 find no free slot
+        let mut rx = fire(8, "Bearer a");
+        assert_eq!(statuses(&mut rx, 6).await, [429, 429, 503, 503, 503, 503]);
+        upstream_reached(2).await;
+        gate.add_permits(2);
+        assert_eq!(statuses(&mut rx, 2).await, [200, 200]);
+
+        // Both slots were released: another client gets two requests through
+        let mut rx = fire(3, "Bearer b");
+        assert_eq!(statuses(&mut rx, 1).await, [503]);
+        upstream_reached(4).await;
+        gate.add_permits(2);
+        assert_eq!(statuses(&mut rx, 2).await, [200, 200]);
+    }
+
+    #[tokio::test]
+    async fn cached_responses_are_served_per_query_and_caller() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use axum::routing::get;
+
+        // The upstream numbers the requests it answers
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        let upstream = spawn_upstream(Router::new().route("/models", get(move || async move {
+            axum::Json(json!({ "n": counter.fetch_add(1, Ordering::SeqCst) + 1 }))
+        })))
+        .await;
+        let config = proxy_config(
+            vec![endpoint(json!({ "method": "GET", "target_url": format!("{upstream}/models"), "cache_ttl_secs": 60 }))],
+            json!({}),
+        );
+        let router = proxy_service(config).create_router();
+        let get = |uri: &str, authorization: &str| {
+            Request::get(uri).header("authorization", authorization).body(Body::empty()).unwrap()
+        };
+
+        let cases = [
+            // (uri, Authorization, X-Cache, upstream response)
+            ("/v1/test", "Bearer a", "MISS", 1),
+            ("/v1/test", "Bearer a", "HIT", 1),
+            ("/v1/test?page=2", "Bearer a", "MISS", 2),
+            ("/v1/test", "Bearer b", "MISS", 3),
+            ("/v1/test?page=2", "Bearer a", "HIT", 2),
+            ("/v1/test", "Bearer b", "HIT", 3),
+        ];
+        for (uri, authorization, cache, n) in cases {
+            let (status, headers, body) = send(&router, get(uri, authorization)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers[CACHE_HEADER], cache, "{uri} {authorization}");
+            assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!({ "n": n }), "{uri} {authorization}");
+            assert_eq!(headers["content-type"], "application/json");
+        }
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn no_store_responses_are_not_cached() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use axum::routing::get;
+
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        let upstream = spawn_upstream(Router::new().route("/models", get(move || async move {
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            ([("cache-control", "private, no-store")], axum::Json(json!({ "n": n })))
+        })))
+        .await;
+        let config = proxy_config(
+            vec![endpoint(json!({ "method": "GET", "target_url": format!("{upstream}/models"), "cache_ttl_secs": 60 }))],
+            json!({}),
+        );
+        let router = proxy_service(config).create_router();
+
+        for n in 1..=2 {
+            let (status, headers, body) = send(&router, Request::get("/v1/test").body(Body::empty()).unwrap()).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers[CACHE_HEADER], "MISS");
+            assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!({ "n": n }));
+        }
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retried_failures_open_the_circuit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Upstream closing every connection before answering
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = format!("http://{}/flaky", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+        let config = proxy_config(
+            vec![endpoint(json!({
+                "target_url": upstream,
+                "method": "GET",
+                "retry": { "max_attempts": 5, "backoff_ms": 1 },
+                "circuit_breaker": { "failure_threshold": 2, "cooldown_secs": 60 },
+            }))],
+            json!({}),
+        );
+        let router = proxy_service(config).create_router();
+        let get = || Request::get("/v1/test").body(Body::empty()).unwrap();
+
+        let (status, _, _) = send(&router, get()).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(connections.load(Ordering::SeqCst), 2, "retries stop once the circuit opens");
+
+        let (status, headers, _) = send(&router, get()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(headers.contains_key(RETRY_AFTER));
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn host_circuits_open_for_every_endpoint_and_recover() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // The upstream fails with a 500 until it is healthy
+        let healthy = Arc::new(AtomicBool::new(false));
+        let state = healthy.clone();
+        let upstream = spawn_upstream(Router::new().route("/{*path}", post(move || async move {
+            match state.load(Ordering::SeqCst) {
+                true => (StatusCode::OK, axum::Json(json!({ "ok": true }))),
+                false => (StatusCode::INTERNAL_SERVER_ERROR, axum::Json(json!({ "ok": false }))),
+            }
+        })))
+        .await;
+        let endpoints = ["/v1/a", "/v1/b"]
+            .into_iter()
+            .map(|path| endpoint(json!({ "path": path, "target_url": format!("{upstream}{path}") })))
+            .collect();
+        let config = proxy_config(endpoints, json!({ "circuit_breaker": { "failure_threshold": 2, "cooldown_secs": 1 } }));
+        let router = proxy_service(config).create_router();
+        let post_to = |path| json_request(path, &json!({}), &[]);
+
+        for _ in 0..2 {
+            let (status, _, _) = send(&router, post_to("/v1/a")).await;
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        let (status, headers, body) = send(&router, post_to("/v1/b")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(headers[RETRY_AFTER], "1");
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["error"]["type"], "upstream_error");
+
+        healthy.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        for path in ["/v1/b", "/v1/a", "/v1/b"] {
+            let (status, _, _) = send(&router, post_to(path)).await;
+            assert_eq!(status, StatusCode::OK, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn an_open_primary_circuit_still_fails_over() {
+        let upstream = echo_upstream().await;
+        let config = proxy_config(
+            vec![endpoint(json!({
+                "target_url": "",
+                "targets": [{ "url": "http://127.0.0.1:9/refused" }, { "url": format!("{upstream}/echo") }],
+                "circuit_breaker": { "failure_threshold": 1, "cooldown_secs": 60 },
+            }))],
+            json!({}),
+        );
+        let service = proxy_service(config);
+        let router = service.create_router();
+
+        for _ in 0..2 {
+            let (status, _, body) = send(&router, json_request("/v1/test", &json!({ "model": "a" }), &[])).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!({ "model": "a" }));
+        }
+        let circuits = &service.circuits;
+        let breaker = CircuitBreakerConfig { failure_threshold: 1, cooldown_secs: 60, success_threshold: 1 };
+        assert!(circuits.allow("/v1/test http://127.0.0.1:9/refused", &breaker).is_err());
+        assert!(circuits.allow(&format!("/v1/test {upstream}/echo"), &breaker).is_ok());
+    }
+
+    #[tokio::test]
+    async fn body_patch_requires_an_admin_token() {
+        use axum::middleware;
+
+        use crate::auth::require_client_token;
+        use crate::proxy::config::InboundAuthConfig;
+
+        init_admin_token();
+        let upstream = echo_upstream().await;
+        let config = proxy_config(vec![endpoint(json!({ "target_url": format!("{upstream}/echo") }))], json!({}));
+        let inbound_auth = InboundAuthConfig {
+            tokens: vec!["client-token".to_string()],
+            clients: Vec::new(),
+            // `start` adds ADMIN_TOKEN to the configured admin tokens
+            admin_tokens: vec!["operator-token".to_string(), ADMIN_TOKEN.to_string()],
+            query_param: None,
+        };
+        let router = proxy_service(config)
+            .create_router()
+            .layer(middleware::from_fn_with_state(Arc::new(inbound_auth), require_client_token));
+
+        let body = json!({ "model": "a", "temperature": 0.7, "metadata": { "user": "u1" } });
+        let patch = r#"{"temperature": null, "metadata": {"tag": "x"}}"#;
+        let patched = json!({ "model": "a", "metadata": { "user": "u1", "tag": "x" } });
+        let admin_bearer = format!("Bearer {ADMIN_TOKEN}");
+        let cases = [
+            // (Authorization, status, body the upstream received)
+            (Some("Bearer operator-token"), StatusCode::OK, Some(&patched)),
+            (Some(admin_bearer.as_str()), StatusCode::OK, Some(&patched)),
+            (Some("Bearer client-token"), StatusCode::FORBIDDEN, None),
+            (Some("Bearer unknown-token"), StatusCode::UNAUTHORIZED, None),
+            (None, StatusCode::UNAUTHORIZED, None),
+        ];
+        for (authorization, status, upstream_body) in cases {
+            let mut headers = vec![(BODY_PATCH_HEADER, patch)];
+            headers.extend(authorization.map(|value| ("authorization", value)));
+            let (actual, _, received) = send(&router, json_request("/v1/test", &body, &headers)).await;
+            assert_eq!(actual, status, "{authorization:?}");
+            if let Some(upstream_body) = upstream_body {
+                assert_eq!(&serde_json::from_slice::<Value>(&received).unwrap(), upstream_body, "{authorization:?}");
+            }
         }
 
-        if let Some(data_content) = line.strip_prefix("data: ") {
-            if data_content == "[DONE]" {
-                Some("[DONE]".to_string())
+        // Without the header, client tokens are served as usual
+        let req = json_request("/v1/test", &body, &[("authorization", "Bearer client-token")]);
+        let (status, _, received) = send(&router, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Value>(&received).unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn body_patch_accepts_the_admin_token_without_inbound_auth() {
+        init_admin_token();
+        let upstream = echo_upstream().await;
+        let config = proxy_config(vec![endpoint(json!({ "target_url": format!("{upstream}/echo") }))], json!({}));
+        let router = proxy_service(config).create_router();
+        let bearer = format!("Bearer {ADMIN_TOKEN}");
+
+        let req = json_request(
+            "/v1/test",
+            &json!({ "model": "a" }),
+            &[(BODY_PATCH_HEADER, r#"{"model":"b"}"#), ("authorization", &bearer)],
+        );
+        let (status, _, body) = send(&router, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!({ "model": "b" }));
+
+        let req = json_request("/v1/test", &json!({ "model": "a" }), &[(BODY_PATCH_HEADER, r#"{"model":"b"}"#)]);
+        let (status, _, _) = send(&router, req).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn admin_credentials_never_reach_the_upstream() {
+        use axum::middleware;
+
+        use crate::auth::require_client_token;
+        use crate::proxy::config::InboundAuthConfig;
+
+        // The upstream answers with the Authorization header it received
+        let upstream = spawn_upstream(Router::new().route("/echo", post(|headers: HeaderMap| async move {
+            let authorization = headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()).map(str::to_string);
+            axum::Json(json!({ "authorization": authorization }))
+        })))
+        .await;
+        let config = proxy_config(
+            vec![endpoint(json!({
+                "target_url": format!("{upstream}/echo"),
+                "forward_request_headers": ["content-type", "authorization"],
+            }))],
+            json!({}),
+        );
+        let service = proxy_service(config);
+        let inbound_auth = InboundAuthConfig {
+            tokens: vec!["client-token".to_string()],
+            clients: Vec::new(),
+            admin_tokens: vec!["operator-token".to_string(), ADMIN_TOKEN.to_string()],
+            query_param: None,
+        };
+        let routers = [
+            service.create_router(),
+            service
+                .create_router()
+                .layer(middleware::from_fn_with_state(Arc::new(inbound_auth), require_client_token)),
+        ];
+
+        let admin_bearer = format!("Bearer {ADMIN_TOKEN}");
+        let patch = r#"{"model":"b"}"#;
+        for (i, router) in routers.iter().enumerate() {
+            let mut cases = vec![
+                // (request headers, Authorization the upstream received)
+                (vec![("authorization", admin_bearer.as_str())], None),
+                (vec![("authorization", admin_bearer.as_str()), (BODY_PATCH_HEADER, patch)], None),
+            ];
+            if i == 1 {
+                cases.push((vec![("authorization", "Bearer operator-token"), (BODY_PATCH_HEADER, patch)], None));
+                cases.push((vec![("authorization", "Bearer client-token")], Some("Bearer client-token")));
             } else {
-                Some(data_content.to_string())
+                cases.push((vec![("authorization", "Bearer upstream-key")], Some("Bearer upstream-key")));
+            }
+            for (headers, forwarded) in cases {
+                let (status, _, body) = send(router, json_request("/v1/test", &json!({ "model": "a" }), &headers)).await;
+                assert_eq!(status, StatusCode::OK, "{headers:?}");
+                let received: Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(received["authorization"].as_str(), forwarded, "{headers:?}");
+                assert!(!String::from_utf8_lossy(&body).contains(ADMIN_TOKEN));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn upstream_timeouts_answer_504_or_end_the_stream() {
+        let upstream = spawn_upstream(Router::new().route("/slow", post(|| async {
+            tokio::time::sleep(Duration::from_secs(3)).await;
+            Json(json!({ "late": true }))
+        })))
+        .await;
+        let stalled = drip_upstream(&[3000]).await;
+        let endpoints = vec![
+            endpoint(json!({ "path": "/v1/own", "target_url": format!("{upstream}/slow"), "timeout": 1 })),
+            endpoint(json!({ "path": "/v1/global", "target_url": format!("{upstream}/slow") })),
+            endpoint(json!({ "path": "/v1/sse", "target_url": format!("{stalled}/drip"), "response_type": "sse", "timeout": 1 })),
+        ];
+        let router = proxy_service(proxy_config(endpoints, json!({ "global_timeout": 1 }))).create_router();
+
+        for path in ["/v1/own", "/v1/global"] {
+            let started = Instant::now();
+            let (status, _, body) = send(&router, json_request(path, &json!({}), &[])).await;
+            assert_eq!(status, StatusCode::GATEWAY_TIMEOUT, "{path}");
+            assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["error"]["type"], "timeout_error", "{path}");
+            assert!(started.elapsed() < Duration::from_secs(2), "{path}");
+        }
+
+        // Streams have begun by the time the upstream stalls, so they end with an error event
+        let (status, _, body) = send(&router, json_request("/v1/sse", &json!({}), &[])).await;
+        assert_eq!(status, StatusCode::OK);
+        let events = SseParser::default().feed(&body);
+        assert_eq!(events.len(), 1, "{}", String::from_utf8_lossy(&body));
+        assert_eq!(events[0].event.as_deref(), Some("error"));
+        assert!(events[0].data.contains("Upstream sent no data within 1s"), "{}", events[0].data);
+    }
+
+    #[tokio::test]
+    async fn upstream_errors_keep_their_status_and_body() {
+        const ERROR: &str = r#"{"error":{"message":"Unknown parameter: 'temprature'","code":"unknown_parameter"}}"#;
+        let upstream = spawn_upstream(
+            Router::new()
+                .route("/json", post(|| async { (StatusCode::BAD_REQUEST, [("content-type", "application/json")], ERROR) }))
+                .route("/text", post(|| async { (StatusCode::IM_A_TEAPOT, "short and stout\n") }))
+                .route("/empty", post(|| async { StatusCode::SERVICE_UNAVAILABLE })),
+        )
+        .await;
+        let endpoints = ["json", "text", "empty"]
+            .into_iter()
+            .map(|kind| endpoint(json!({ "path": format!("/v1/{kind}"), "target_url": format!("{upstream}/{kind}") })))
+            .collect();
+        let router = proxy_service(proxy_config(endpoints, json!({}))).create_router();
+
+        let (status, headers, body) = send(&router, json_request("/v1/json", &json!({}), &[])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(headers[CONTENT_TYPE], "application/json");
+        assert_eq!(body, ERROR.as_bytes());
+
+        // Other bodies are wrapped in the proxy's own error shape
+        for (path, status, message) in [
+            ("/v1/text", StatusCode::IM_A_TEAPOT, "short and stout"),
+            ("/v1/empty", StatusCode::SERVICE_UNAVAILABLE, "Upstream returned 503 Service Unavailable"),
+        ] {
+            let (actual, _, body) = send(&router, json_request(path, &json!({}), &[])).await;
+            assert_eq!(actual, status, "{path}");
+            let error: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error["error"]["type"], "upstream_error", "{path}");
+            assert_eq!(error["error"]["message"], message, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn models_outside_the_allowlist_are_refused() {
+        let upstream = echo_upstream().await;
+        let config = proxy_config(
+            vec![endpoint(json!({ "target_url": format!("{upstream}/echo"), "allowed_models": ["gpt-4o", "claude-*"] }))],
+            json!({}),
+        );
+        let router = proxy_service(config).create_router();
+
+        for model in ["gpt-4o", "claude-sonnet-4"] {
+            let (status, _, body) = send(&router, json_request("/v1/test", &json!({ "model": model }), &[])).await;
+            assert_eq!(status, StatusCode::OK, "{model}");
+            assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["model"], model);
+        }
+        for body in [json!({ "model": "gpt-4o-mini" }), json!({ "prompt": "no model" })] {
+            let (status, _, response) = send(&router, json_request("/v1/test", &body, &[])).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+            let error: Value = serde_json::from_slice(&response).unwrap();
+            assert_eq!(error["error"]["type"], "permission_error");
+            assert!(error["error"]["message"].as_str().unwrap().contains("is not allowed"));
+        }
+    }
+
+    #[tokio::test]
+    async fn gzipped_responses_are_decoded_once() {
+        let upstream = encoded_upstream(&[("gzip", GZIPPED)]).await;
+        let endpoints = ["json", "stream"]
+            .into_iter()
+            .map(|kind| endpoint(json!({
+                "path": format!("/v1/{kind}"),
+                "target_url": format!("{upstream}/gzip"),
+                "response_type": kind,
+                "forward_request_headers": ["content-type", "accept-encoding"],
+                "forward_response_headers": ["content-type", "content-encoding", "content-length"],
+            })))
+            .collect();
+        let router = proxy_service(proxy_config(endpoints, json!({}))).create_router();
+
+        for kind in ["json", "stream"] {
+            let req = json_request(&format!("/v1/{kind}"), &json!({}), &[("accept-encoding", "gzip")]);
+            let (status, headers, body) = send(&router, req).await;
+            assert_eq!(status, StatusCode::OK, "{kind}");
+            assert!(headers.get(CONTENT_ENCODING).is_none(), "{kind}");
+            assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), serde_json::from_str::<Value>(DECODED).unwrap(), "{kind}");
+        }
+    }
+
+    #[tokio::test]
+    async fn the_request_timeout_ends_with_the_first_chunk() {
+        static SLOW_STREAM: [u64; 5] = [100, 400, 400, 400, 400];
+        let upstream = drip_upstream(&SLOW_STREAM).await;
+        let endpoints = vec![
+            endpoint(json!({ "path": "/v1/long", "target_url": format!("{upstream}/drip"), "response_type": "sse", "timeout": 1 })),
+            endpoint(json!({
+                "path": "/v1/capped",
+                "target_url": format!("{upstream}/drip"),
+                "response_type": "stream",
+                "timeout": 1,
+                "max_stream_secs": 1,
+            })),
+        ];
+        let router = proxy_service(proxy_config(endpoints, json!({}))).create_router();
+
+        // 1.7s of streaming outlasts the 1s timeout without being cut
+        let (status, _, body) = send(&router, json_request("/v1/long", &json!({}), &[])).await;
+        assert_eq!(status, StatusCode::OK);
+        let data: Vec<_> = SseParser::default().feed(&body).into_iter().map(|event| event.data).collect();
+        assert_eq!(data, ["0", "1", "2", "3", "4"]);
+
+        // max_stream_secs bounds the time after the first chunk
+        let (status, _, body) = send(&router, json_request("/v1/capped", &json!({}), &[])).await;
+        assert_eq!(status, StatusCode::OK);
+        let events = SseParser::default().feed(&body);
+        assert_eq!(events.iter().map(|event| event.data.as_str()).take(3).collect::<Vec<_>>(), ["0", "1", "2"]);
+        let last = events.last().unwrap();
+        assert_eq!(last.event.as_deref(), Some("error"));
+        assert!(last.data.contains("Stream exceeded the 1s limit"), "{}", last.data);
+    }
+
+    #[tokio::test]
+    async fn multipart_uploads_are_rebuilt_for_the_upstream() {
+        // The upstream describes the parts it received
+        let upstream = spawn_upstream(Router::new().route("/upload", post(|headers: HeaderMap, body: Body| async move {
+            let content_type = headers[CONTENT_TYPE].to_str().unwrap();
+            let mut fields = multer::Multipart::new(body.into_data_stream(), multer::parse_boundary(content_type).unwrap());
+            let mut parts = Vec::new();
+            while let Some(field) = fields.next_field().await.unwrap() {
+                let (name, file_name) = (field.name().map(str::to_string), field.file_name().map(str::to_string));
+                let mime = field.content_type().map(ToString::to_string);
+                let data = field.bytes().await.unwrap();
+                parts.push(json!({ "name": name, "file_name": file_name, "type": mime, "sha256": hex::encode(Sha256::digest(&data)) }));
+            }
+            Json(json!(parts))
+        })))
+        .await;
+        let config = proxy_config(
+            vec![endpoint(json!({
+                "target_url": format!("{upstream}/upload"),
+                "forward_as_multipart": true,
+                "allowed_models": ["whisper-1"],
+            }))],
+            json!({}),
+        );
+        let router = proxy_service(config).create_router();
+        let audio: Vec<u8> = (0..=255u8).collect();
+        let upload = |model: &str| {
+            let mut body = format!(
+                "--XyZ\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\n{model}\r\n\
+                 --XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"clip.wav\"\r\nContent-Type: audio/wav\r\n\r\n"
+            )
+            .into_bytes();
+            body.extend_from_slice(&audio);
+            body.extend_from_slice(b"\r\n--XyZ--\r\n");
+            Request::post("/v1/test")
+                .header(CONTENT_TYPE, "multipart/form-data; boundary=XyZ")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let (status, _, body) = send(&router, upload("whisper-1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!([
+                { "name": "model", "file_name": null, "type": null, "sha256": hex::encode(Sha256::digest(b"whisper-1")) },
+                { "name": "file", "file_name": "clip.wav", "type": "audio/wav", "sha256": hex::encode(Sha256::digest(&audio)) },
+            ])
+        );
+
+        // The model field is held against the allowlist like a JSON body's
+        let (status, _, _) = send(&router, upload("whisper-2")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _, _) = send(&router, json_request("/v1/test", &json!({ "model": "whisper-1" }), &[])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn oversized_conversions_follow_the_enforcement_mode() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let upstream = spawn_upstream(Router::new().route("/chat", post(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Json(json!({ "model": "tiny-1", "choices": [{ "index": 0, "message": { "role": "assistant", "content": "ok" } }] })) }
+        })))
+        .await;
+        // About 25 tokens against a 10 token window
+        let oversized = json!({ "model": "tiny-1", "prompt": "x".repeat(100) });
+
+        for (enforce, expected, upstream_calls) in [
+            ("reject", StatusCode::BAD_REQUEST, 0),
+            ("warn", StatusCode::OK, 1),
+            ("off", StatusCode::OK, 1),
+        ] {
+            calls.store(0, Ordering::SeqCst);
+            let config = proxy_config(
+                vec![endpoint(json!({ "target_url": format!("{upstream}/chat"), "conversion": "legacy_completions" }))],
+                json!({ "context_windows": { "enforce": enforce, "models": { "tiny-": 10 } } }),
+            );
+            let router = proxy_service(config).create_router();
+
+            let (status, _, body) = send(&router, json_request("/v1/test", &oversized, &[])).await;
+            assert_eq!(status, expected, "{enforce}");
+            assert_eq!(calls.load(Ordering::SeqCst), upstream_calls, "{enforce}");
+            if enforce == "reject" {
+                let error: Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(error["error"]["code"], "context_length_exceeded");
+                assert_eq!((error["error"]["estimated_tokens"].clone(), error["error"]["context_window"].clone()), (json!(25), json!(10)));
             }
-        } else if let Some(stripped) = line.strip_prefix("data:") {
-            Some(stripped.to_string())
-        } else {
-            Some(line.to_string())
         }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn options_requests_are_answered_204_with_cors_headers() {
+        const PATH: &str = "/api/provider/openai/v1/chat/completions";
+        let cors = json!({ "allowed_origins": ["https://app.example.com"], "allowed_headers": ["content-type"], "max_age_secs": 600 });
+        let endpoints = vec![
+            endpoint(json!({ "path": PATH, "cors": cors })),
+            endpoint(json!({ "path": "/v1/models", "method": "GET" })),
+        ];
+        let router = proxy_service(proxy_config(endpoints, json!({}))).create_router();
+
+        let req = Request::options(PATH)
+            .header("origin", "https://app.example.com")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "content-type")
+            .body(Body::empty())
+            .unwrap();
+        let (status, headers, _) = send(&router, req).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(headers["access-control-allow-origin"], "https://app.example.com");
+        assert!(headers["access-control-allow-methods"].to_str().unwrap().contains("POST"));
+        assert_eq!(headers["access-control-allow-headers"], "content-type");
+        assert_eq!(headers["access-control-max-age"], "600");
+
+        // Routes without a CORS policy list their methods
+        let (status, headers, _) = send(&router, Request::options("/v1/models").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(headers["allow"], "GET, OPTIONS");
+    }
+
+    #[tokio::test]
+    async fn requests_failing_conversion_land_in_the_dead_letter_log() {
+        let path = crate::test_support::temp_dir("dead-letter").join("dead_letter.jsonl");
+        let config = proxy_config(
+            vec![endpoint(json!({ "conversion": "legacy_completions" }))],
+            json!({ "dead_letter": { "path": path, "max_body_bytes": 8 } }),
+        );
+        let router = proxy_service(config).create_router();
+
+        let req = Request::post("/v1/test")
+            .header("content-type", "application/json")
+            .header("authorization", "Bearer sk-secret")
+            .header("x-request-id", "dead-1")
+            .body(Body::from("this is not a completion request"))
+            .unwrap();
+        let (status, _, _) = send(&router, req).await;
+        assert!(status.is_client_error(), "{status}");
+
+        // The entry is written in the background
+        let entry = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(log) = std::fs::read_to_string(&path)
+                    && let Some(line) = log.lines().next()
+                {
+                    break serde_json::from_str::<Value>(line).unwrap();
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("the refused request is logged");
+        assert_eq!(entry["request_id"], "dead-1");
+        assert_eq!((entry["endpoint"].as_str(), entry["method"].as_str()), (Some("/v1/test"), Some("POST")));
+        assert_eq!(entry["status"], status.as_u16());
+        assert_eq!(entry["headers"]["authorization"], crate::proxy::redact::REDACTED);
+        assert_eq!(entry["body"], "this is ");
+        assert_eq!((entry["body_bytes"].clone(), entry["body_truncated"].clone()), (json!(32), json!(true)));
+    }
+
+    #[tokio::test]
+    async fn requests_missing_a_required_header_are_refused() {
+        let upstream = spawn_upstream(Router::new().route("/messages", post(|headers: HeaderMap| async move {
+            Json(json!({ "anthropic-version": headers.get("anthropic-version").and_then(|v| v.to_str().ok()) }))
+        })))
+        .await;
+        let config = proxy_config(
+            vec![endpoint(json!({
+                "target_url": format!("{upstream}/messages"),
+                "require_headers": ["anthropic-version"],
+                "forward_request_headers": ["content-type", "anthropic-version"],
+            }))],
+            json!({}),
+        );
+        let router = proxy_service(config).create_router();
+        let body = json!({ "model": "claude-test" });
+
+        let (status, _, response) = send(&router, json_request("/v1/test", &body, &[("anthropic-version", "2023-06-01")])).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Value>(&response).unwrap(), json!({ "anthropic-version": "2023-06-01" }));
+
+        let (status, _, response) = send(&router, json_request("/v1/test", &body, &[])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error: Value = serde_json::from_slice(&response).unwrap();
+        assert_eq!(error["error"]["message"], "Missing required header anthropic-version");
+    }
+
+    #[tokio::test]
+    async fn shadow_exchanges_are_recorded_without_touching_the_primary() {
+        let upstream = spawn_upstream(
+            Router::new()
+                .route("/primary", post(|| async { Json(json!({ "text": "primary", "usage": { "prompt_tokens": 3 } })) }))
+                .route("/shadow", post(|| async {
+                    (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "text": "shadow", "usage": { "prompt_tokens": 4 } })))
+                })),
+        )
+        .await;
+        let dir = crate::test_support::temp_dir("shadow");
+        let shadowed = |path: &str, target: String, sample_rate: f64| {
+            let record_path = dir.join(format!("{}.jsonl", &path[1..]));
+            let shadow = json!({ "target_url": target, "sample_rate": sample_rate, "record_path": record_path });
+            endpoint(json!({ "path": path, "target_url": format!("{upstream}/primary"), "shadow": shadow }))
+        };
+        let endpoints = vec![
+            shadowed("/mirrored", format!("{upstream}/shadow"), 1.0),
+            shadowed("/unreachable", "http://127.0.0.1:9/".to_string(), 1.0),
+            shadowed("/unsampled", format!("{upstream}/shadow"), 0.0),
+        ];
+        let router = proxy_service(proxy_config(endpoints, json!({}))).create_router();
+
+        for path in ["/mirrored", "/unreachable", "/unsampled"] {
+            let (status, _, body) = send(&router, json_request(path, &json!({}), &[("x-request-id", path)])).await;
+            assert_eq!(status, StatusCode::OK, "{path}");
+            assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["text"], "primary", "{path}");
+        }
+
+        let record = |name: &str| {
+            let path = dir.join(name);
+            async move {
+                tokio::time::timeout(Duration::from_secs(5), async {
+                    loop {
+                        if let Ok(log) = std::fs::read_to_string(&path)
+                            && let Some(line) = log.lines().next()
+                        {
+                            break serde_json::from_str::<Value>(line).unwrap();
+                        }
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                    }
+                })
+                .await
+                .expect("the shadow exchange is recorded")
+            }
+        };
+        let mirrored = record("mirrored.jsonl").await;
+        assert_eq!((mirrored["request_id"].as_str(), mirrored["endpoint"].as_str()), (Some("/mirrored"), Some("/mirrored")));
+        assert_eq!((mirrored["primary"]["status"].clone(), mirrored["shadow"]["status"].clone()), (json!(200), json!(500)));
+        assert_eq!(mirrored["shadow"]["usage"], json!({ "prompt_tokens": 4 }));
+        assert!(mirrored["shadow"]["body_sha256"].is_string());
+        assert!(mirrored["shadow"].get("body").is_none(), "bodies are only recorded when asked for");
+
+        let unreachable = record("unreachable.jsonl").await;
+        assert!(unreachable["shadow"]["error"].is_string());
+        assert!(!dir.join("unsampled.jsonl").exists());
+    }
+
+    #[tokio::test]
+    async fn deflate_and_zstd_responses_are_decoded() {
+        let upstream = encoded_upstream(&[("deflate", DEFLATED), ("zstd", ZSTD), ("compress", DEFLATED)]).await;
+        let endpoints = ["deflate", "zstd", "compress"]
+            .into_iter()
+            .map(|encoding| endpoint(json!({
+                "path": format!("/v1/{encoding}"),
+                "target_url": format!("{upstream}/{encoding}"),
+                "forward_response_headers": ["content-type", "content-encoding"],
+            })))
+            .collect();
+        let router = proxy_service(proxy_config(endpoints, json!({}))).create_router();
+
+        for encoding in ["deflate", "zstd"] {
+            let (status, headers, body) = send(&router, json_request(&format!("/v1/{encoding}"), &json!({}), &[])).await;
+            assert_eq!(status, StatusCode::OK, "{encoding}");
+            assert!(headers.get(CONTENT_ENCODING).is_none(), "{encoding}");
+            assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), serde_json::from_str::<Value>(DECODED).unwrap(), "{encoding}");
+        }
+
+        // Encodings the proxy cannot decode are not passed off as JSON
+        let (status, _, body) = send(&router, json_request("/v1/compress", &json!({}), &[])).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert!(error["error"]["message"].as_str().unwrap().contains("unsupported content-encoding compress"));
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::http::HeaderName;
+use reqwest::{Client, Url};
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use tokio::sync::oneshot;
+use tracing::{debug, warn};
+
+use crate::metrics::ProxyMetrics;
+use super::config::ShadowConfig;
+use super::rate_limit::TokenBucket;
+use super::upstream_auth::Credential;
+
+/// What the client got from the primary upstream
+#[derive(Debug)]
+pub struct PrimaryOutcome {
+    pub status: u16,
+    pub content_type: Option<String>,
+    /// Time until the primary response headers arrived
+    pub latency_ms: u64,
+}
+
+/// The primary request a shadow copy is made of
+pub struct ShadowRequest {
+    pub request: reqwest::Request,
+    pub path: String,
+    pub request_id: String,
+    /// Header of the endpoint's own upstream credential, never sent to the shadow
+    pub primary_credential: Option<HeaderName>,
+    /// Credential of the shadow upstream
+    pub credential: Option<Credential>,
+}
+
+/// Sends sampled copies of requests to the endpoints' shadow upstreams.
+/// Shadow requests bypass rate limits, concurrency limits and the request
+/// metrics of the endpoint; they are counted in the `amp_shadow_*` metrics
+/// instead.
+pub struct ShadowTraffic {
+    client: Client,
+    metrics: Arc<ProxyMetrics>,
+    /// `max_qps` buckets by endpoint path
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+    /// Keeps record appends from interleaving
+    write: Arc<Mutex<()>>,
+}
+
+impl ShadowTraffic {
+    pub fn new(client: Client, metrics: Arc<ProxyMetrics>) -> Self {
+        Self { client, metrics, buckets: Mutex::new(HashMap::new()), write: Arc::new(Mutex::new(())) }
+    }
+
+    /// Whether a request to `path` is mirrored: sampled at `sample_rate`,
+    /// then within `max_qps`
+    fn admit(&self, path: &str, settings: &ShadowConfig) -> bool {
+        let draw = ulid::Ulid::new().random() as u64 as f64 / u64::MAX as f64;
+        if draw >= settings.sample_rate {
+            return false;
+        }
+        let Some(max_qps) = settings.max_qps else {
+            return true;
+        };
+        let burst = max_qps.ceil() as u32;
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .entry(path.to_string())
+            .and_modify(|bucket| {
+                if !bucket.has_limits(max_qps, burst) {
+                    *bucket = TokenBucket::new(max_qps, burst);
+                }
+            })
+            .or_insert_with(|| TokenBucket::new(max_qps, burst));
+        bucket.try_acquire()
+    }
+
+    /// Send a copy of the request to the shadow upstream once the primary
+    /// outcome arrives through the returned sender, then record the shadow
+    /// response. Nothing here affects the client: failures are only logged,
+    /// and a dropped sender (the primary failed) cancels the shadow request.
+    /// `None` when the request is not sampled.
+    pub fn spawn(&self, settings: &ShadowConfig, shadow: ShadowRequest) -> Option<oneshot::Sender<PrimaryOutcome>> {
+        let ShadowRequest { mut request, path, request_id, primary_credential, credential } = shadow;
+        if !self.admit(&path, settings) {
+            return None;
+        }
+        let url = match Url::parse(&settings.target_url) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("Invalid shadow target for {}: {}", path, e);
+                return None;
+            }
+        };
+        *request.url_mut() = url;
+        if let Some(name) = primary_credential {
+            request.headers_mut().remove(name);
+        }
+        if let Some((name, value)) = credential {
+            request.headers_mut().insert(name, value);
+        }
+
+        let (primary_tx, primary_rx) = oneshot::channel::<PrimaryOutcome>();
+        let client = self.client.clone();
+        let metrics = self.metrics.clone();
+        let write = self.write.clone();
+        let settings = settings.clone();
+
+        tokio::spawn(async move {
+            let Ok(primary) = primary_rx.await else {
+                debug!(path = %path, "Primary request failed, skipping shadow request");
+                return;
+            };
+
+            let started = Instant::now();
+            let mut shadow = json!({ "target": settings.target_url });
+            match client.execute(request).await {
+                Ok(response) => {
+                    let status = response.status();
+                    let content_type = response
+                        .headers()
+                        .get("content-type")
+                        .and_then(|ct| ct.to_str().ok())
+                        .map(str::to_string);
+                    let body = match response.bytes().await {
+                        Ok(body) => Ok(body),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    shadow["status"] = json!(status.as_u16());
+                    shadow["content_type"] = json!(content_type);
+                    shadow["latency_ms"] = json!(started.elapsed().as_millis() as u64);
+                    metrics.record_shadow_request(&path, Some(status));
+
+                    compare(&path, &primary, status.as_u16(), &content_type, body.as_deref().unwrap_or_default());
+                    match body {
+                        Ok(body) => {
+                            shadow["body_sha256"] = json!(hex::encode(Sha256::digest(&body)));
+                            shadow["body_bytes"] = json!(body.len());
+                            if settings.record_body {
+                                shadow["body"] = json!(String::from_utf8_lossy(&body));
+                            }
+                            if let Some(usage) = usage(&body) {
+                                let tokens = |fields: &[&str]| fields.iter().find_map(|field| usage.get(*field)?.as_u64());
+                                metrics.record_shadow_tokens(
+                                    &path,
+                                    tokens(&["prompt_tokens", "input_tokens"]).unwrap_or_default(),
+                                    tokens(&["completion_tokens", "output_tokens"]).unwrap_or_default(),
+                                );
+                                shadow["usage"] = Value::Object(usage);
+                            }
+                        }
+                        Err(e) => {
+                            warn!(path = %path, "Shadow response body failed: {}", e);
+                            shadow["error"] = json!(e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!(path = %path, "Shadow request failed: {}", e);
+                    metrics.record_shadow_request(&path, None);
+                    shadow["latency_ms"] = json!(started.elapsed().as_millis() as u64);
+                    shadow["error"] = json!(e.to_string());
+                }
+            }
+
+            let Some(record_path) = settings.record_path else {
+                return;
+            };
+            let record = json!({
+                "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                "request_id": request_id,
+                "endpoint": path,
+                "primary": {
+                    "status": primary.status,
+                    "content_type": primary.content_type,
+                    "latency_ms": primary.latency_ms,
+                },
+                "shadow": shadow,
+            });
+            let written = tokio::task::spawn_blocking(move || {
+                let _write = write.lock().unwrap();
+                append(&record_path, &record).map_err(|e| format!("{record_path}: {e}"))
+            })
+            .await;
+            match written {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Could not record shadow exchange to {}", e),
+                Err(e) => warn!("Could not record shadow exchange: {}", e),
+            }
+        });
+
+        Some(primary_tx)
+    }
+}
+
+/// Log whether the shadow response differs from the primary one in status or
+/// media type
+fn compare(path: &str, primary: &PrimaryOutcome, status: u16, content_type: &Option<String>, body: &[u8]) {
+    if primary.status != status || media_type(&primary.content_type) != media_type(content_type) {
+        warn!(
+            path = %path,
+            primary_status = primary.status,
+            shadow_status = status,
+            primary_content_type = ?primary.content_type,
+            shadow_content_type = ?content_type,
+            shadow_keys = ?top_level_keys(body),
+            "Shadow response differs from primary"
+        );
+    } else {
+        debug!(path = %path, status, "Shadow response matches primary");
+    }
+}
+
+fn append(path: &str, record: &Value) -> io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(&line)
+}
+
+/// Token usage reported in a response: the `usage` object of a JSON body,
+/// or the `usage` objects of a stream's events merged in order, so Anthropic
+/// streams get the input tokens of `message_start` and the output tokens of
+/// the final `message_delta`
+fn usage(body: &[u8]) -> Option<Map<String, Value>> {
+    if let Ok(Value::Object(mut body)) = serde_json::from_slice::<Value>(body) {
+        return match body.remove("usage") {
+            Some(Value::Object(usage)) => Some(usage),
+            _ => None,
+        };
+    }
+
+    let mut merged: Option<Map<String, Value>> = None;
+    for line in String::from_utf8_lossy(body).lines() {
+        let data = line.strip_prefix("data:").unwrap_or(line).trim();
+        let Ok(event) = serde_json::from_str::<Value>(data) else {
+            continue;
+        };
+        let usage = event.get("usage").or_else(|| event.get("message")?.get("usage"));
+        if let Some(Value::Object(usage)) = usage {
+            merged.get_or_insert_default().extend(usage.clone());
+        }
+    }
+    merged
+}
+
+/// Content type without parameters such as charset
+fn media_type(content_type: &Option<String>) -> Option<&str> {
+    content_type.as_deref().map(|ct| ct.split(';').next().unwrap_or(ct).trim())
+}
+
+fn top_level_keys(body: &[u8]) -> Option<Vec<String>> {
+    match serde_json::from_slice::<Value>(body).ok()? {
+        Value::Object(map) => Some(map.keys().cloned().collect()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(sample_rate: f64, max_qps: Option<f64>) -> ShadowConfig {
+        serde_json::from_value(json!({ "target_url": "http://127.0.0.1:9/", "sample_rate": sample_rate, "max_qps": max_qps }))
+            .unwrap()
+    }
+
+    #[test]
+    fn requests_are_sampled_then_limited_per_endpoint() {
+        let shadow = ShadowTraffic::new(Client::new(), Arc::new(ProxyMetrics::new()));
+        assert!((0..100).all(|_| !shadow.admit("/a", &settings(0.0, None))));
+        assert!((0..100).all(|_| shadow.admit("/a", &settings(1.0, None))));
+
+        let sampled = (0..2000).filter(|_| shadow.admit("/a", &settings(0.5, None))).count();
+        assert!((800..1200).contains(&sampled), "{sampled} of 2000");
+
+        // `max_qps` allows a burst of its size, per endpoint
+        let limited = settings(1.0, Some(3.0));
+        assert_eq!((0..10).filter(|_| shadow.admit("/a", &limited)).count(), 3);
+        assert!(shadow.admit("/b", &limited));
+    }
+
+    #[test]
+    fn usage_is_read_from_bodies_and_streams() {
+        let body = json!({ "choices": [], "usage": { "prompt_tokens": 5, "completion_tokens": 7 } }).to_string();
+        assert_eq!(Value::Object(usage(body.as_bytes()).unwrap()), json!({ "prompt_tokens": 5, "completion_tokens": 7 }));
+
+        let stream = concat!(
+            "event: message_start\n",
+            "data: {\"message\":{\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\n\n",
+            "data: {\"delta\":{\"text\":\"hi\"}}\n\n",
+            "event: message_delta\n",
+            "data: {\"usage\":{\"output_tokens\":30}}\n\n",
+        );
+        assert_eq!(Value::Object(usage(stream.as_bytes()).unwrap()), json!({ "input_tokens": 12, "output_tokens": 30 }));
+        assert!(usage(b"{\"choices\":[]}").is_none());
+    }
+}
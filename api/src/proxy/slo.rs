@@ -0,0 +1,338 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use super::config::{ProxyConfig, SloConfig};
+
+/// A completed request
+struct RequestSample {
+    at: Instant,
+    latency: Duration,
+    error: bool,
+}
+
+/// A finished stream and its throughput
+struct ThroughputSample {
+    at: Instant,
+    tokens_per_second: f64,
+}
+
+#[derive(Default)]
+struct Window {
+    requests: VecDeque<RequestSample>,
+    throughput: VecDeque<ThroughputSample>,
+    breached: bool,
+    last_alert: Option<Instant>,
+}
+
+/// Current SLO state of an endpoint, as shown by the admin endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct SloStatus {
+    /// `ok` or `breached`
+    pub slo: &'static str,
+    pub requests: usize,
+    pub latency_p95_ms: Option<u64>,
+    pub error_rate: Option<f64>,
+    pub tokens_per_second: Option<f64>,
+    /// Objectives missed in the current window
+    pub violations: Vec<String>,
+    /// The endpoint was removed by a configuration reload; it is listed until
+    /// its window holds no more requests
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub removed: bool,
+}
+
+/// Rolling-window SLO evaluation for one endpoint
+pub struct SloTracker {
+    path: String,
+    config: SloConfig,
+    window: Mutex<Window>,
+}
+
+impl SloTracker {
+    pub fn new(path: String, config: SloConfig) -> Self {
+        Self {
+            path,
+            config,
+            window: Mutex::new(Window::default()),
+        }
+    }
+
+    pub fn record_request(&self, latency: Duration, error: bool) {
+        self.window.lock().unwrap().requests.push_back(RequestSample {
+            at: Instant::now(),
+            latency,
+            error,
+        });
+    }
+
+    /// Record a finished stream; `tokens` is the number of streamed events
+    pub fn record_stream(&self, tokens: u64, streaming_time: Duration) {
+        let secs = streaming_time.as_secs_f64();
+        if tokens == 0 || secs <= 0.0 {
+            return;
+        }
+        self.window.lock().unwrap().throughput.push_back(ThroughputSample {
+            at: Instant::now(),
+            tokens_per_second: tokens as f64 / secs,
+        });
+    }
+
+    /// Drop samples older than the window and check the objectives. Breaches
+    /// are logged at most once per `alert_interval` while they last; recovery
+    /// is logged once.
+    pub fn evaluate(&self, now: Instant, alert_interval: Duration) -> SloStatus {
+        let mut window = self.window.lock().unwrap();
+        let window_len = Duration::from_secs(self.config.window_secs);
+        let expired = |at: Instant| now.saturating_duration_since(at) > window_len;
+        while window.requests.front().is_some_and(|s| expired(s.at)) {
+            window.requests.pop_front();
+        }
+        while window.throughput.front().is_some_and(|s| expired(s.at)) {
+            window.throughput.pop_front();
+        }
+
+        let status = self.status(&window);
+        let breached = !status.violations.is_empty();
+
+        if breached {
+            let alert_due = window
+                .last_alert
+                .is_none_or(|last| now.saturating_duration_since(last) >= alert_interval);
+            if alert_due {
+                warn!(path = %self.path, violations = ?status.violations, "SLO breached");
+                window.last_alert = Some(now);
+            }
+        } else if window.breached {
+            info!(path = %self.path, "SLO recovered");
+            window.last_alert = None;
+        }
+        window.breached = breached;
+
+        status
+    }
+
+    fn status(&self, window: &Window) -> SloStatus {
+        let latency_p95_ms = p95(window.requests.iter().map(|s| s.latency)).map(|p| p.as_millis() as u64);
+        let error_rate = (!window.requests.is_empty()).then(|| {
+            window.requests.iter().filter(|s| s.error).count() as f64 / window.requests.len() as f64
+        });
+        let tokens_per_second = (!window.throughput.is_empty()).then(|| {
+            window.throughput.iter().map(|s| s.tokens_per_second).sum::<f64>() / window.throughput.len() as f64
+        });
+
+        let mut violations = Vec::new();
+        if let (Some(target), Some(actual)) = (self.config.latency_p95_ms, latency_p95_ms)
+            && actual > target
+        {
+            violations.push(format!("latency p95 {actual}ms > {target}ms"));
+        }
+        if let (Some(ceiling), Some(actual)) = (self.config.max_error_rate, error_rate)
+            && actual > ceiling
+        {
+            violations.push(format!("error rate {actual:.3} > {ceiling}"));
+        }
+        if let (Some(floor), Some(actual)) = (self.config.min_tokens_per_second, tokens_per_second)
+            && actual < floor
+        {
+            violations.push(format!("throughput {actual:.1} tokens/s < {floor}"));
+        }
+
+        SloStatus {
+            slo: if violations.is_empty() { "ok" } else { "breached" },
+            requests: window.requests.len(),
+            latency_p95_ms,
+            error_rate,
+            tokens_per_second,
+            violations,
+            removed: false,
+        }
+    }
+}
+
+/// Nearest-rank 95th percentile
+fn p95(latencies: impl Iterator<Item = Duration>) -> Option<Duration> {
+    let mut latencies: Vec<Duration> = latencies.collect();
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort_unstable();
+    let rank = (latencies.len() as f64 * 0.95).ceil() as usize;
+    Some(latencies[rank.saturating_sub(1)])
+}
+
+/// SLO trackers of all endpoints that define objectives
+pub struct SloMonitor {
+    trackers: HashMap<String, Arc<SloTracker>>,
+    interval: Duration,
+    alert_interval: Duration,
+}
+
+impl SloMonitor {
+    pub fn new(config: &ProxyConfig) -> Self {
+        let trackers = config
+            .enabled_endpoints()
+            .into_iter()
+            .filter_map(|endpoint| {
+                let slo = endpoint.slo.clone()?;
+                Some((endpoint.path.clone(), Arc::new(SloTracker::new(endpoint.path.clone(), slo))))
+            })
+            .collect();
+
+        Self {
+            trackers,
+            interval: Duration::from_secs(config.slo_eval_interval),
+            alert_interval: Duration::from_secs(config.slo_alert_interval),
+        }
+    }
+
+    pub fn tracker(&self, path: &str) -> Option<Arc<SloTracker>> {
+        self.trackers.get(path).cloned()
+    }
+
+    /// Evaluate every endpoint now
+    pub fn evaluate(&self) -> HashMap<String, SloStatus> {
+        let now = Instant::now();
+        self.trackers
+            .iter()
+            .map(|(path, tracker)| (path.clone(), tracker.evaluate(now, self.alert_interval)))
+            .collect()
+    }
+
+    /// Evaluate every endpoint now against the configuration in effect.
+    /// Endpoints it no longer has are flagged `removed` while their window
+    /// still holds requests, and left out after that.
+    #[cfg(feature = "admin")]
+    pub fn evaluate_current(&self, config: &ProxyConfig) -> HashMap<String, SloStatus> {
+        let current: std::collections::HashSet<&str> =
+            config.enabled_endpoints().into_iter().map(|endpoint| endpoint.path.as_str()).collect();
+        let mut statuses = self.evaluate();
+        statuses.retain(|path, status| {
+            status.removed = !current.contains(path.as_str());
+            !status.removed || status.requests > 0
+        });
+        statuses
+    }
+
+    /// Evaluate every `slo_eval_interval` in the background, so breaches are
+    /// logged even when nobody polls the admin endpoints
+    pub fn spawn(self: &Arc<Self>) {
+        if self.trackers.is_empty() {
+            return;
+        }
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(monitor.interval);
+            loop {
+                ticker.tick().await;
+                monitor.evaluate();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(config: SloConfig) -> SloTracker {
+        SloTracker::new("/v1/test".to_string(), config)
+    }
+
+    fn slo(latency_p95_ms: Option<u64>, max_error_rate: Option<f64>, min_tokens_per_second: Option<f64>) -> SloConfig {
+        SloConfig { latency_p95_ms, min_tokens_per_second, max_error_rate, window_secs: 300 }
+    }
+
+    fn last_alert(tracker: &SloTracker) -> Option<Instant> {
+        tracker.window.lock().unwrap().last_alert
+    }
+
+    #[test]
+    fn p95_latency_breaches_and_recovers_with_the_window() {
+        let tracker = tracker(slo(Some(400), None, None));
+        let alert_interval = Duration::from_secs(60);
+        for _ in 0..19 {
+            tracker.record_request(Duration::from_millis(100), false);
+        }
+        tracker.record_request(Duration::from_millis(900), false);
+        let now = Instant::now();
+        let status = tracker.evaluate(now, alert_interval);
+        assert_eq!((status.slo, status.latency_p95_ms), ("ok", Some(100)));
+
+        for _ in 0..2 {
+            tracker.record_request(Duration::from_millis(900), false);
+        }
+        let status = tracker.evaluate(now, alert_interval);
+        assert_eq!((status.slo, status.latency_p95_ms), ("breached", Some(900)));
+        assert_eq!(status.violations, ["latency p95 900ms > 400ms"]);
+        assert_eq!(last_alert(&tracker), Some(now));
+
+        // Once the samples leave the window the endpoint recovers
+        let status = tracker.evaluate(now + Duration::from_secs(301), alert_interval);
+        assert_eq!((status.slo, status.requests), ("ok", 0));
+        assert_eq!(last_alert(&tracker), None);
+        assert!(!tracker.window.lock().unwrap().breached);
+    }
+
+    #[test]
+    fn breaches_are_logged_once_per_alert_interval() {
+        let tracker = tracker(slo(None, Some(0.1), None));
+        let alert_interval = Duration::from_secs(60);
+        tracker.record_request(Duration::from_millis(10), true);
+        let now = Instant::now();
+
+        tracker.evaluate(now, alert_interval);
+        assert_eq!(last_alert(&tracker), Some(now));
+        let status = tracker.evaluate(now + Duration::from_secs(30), alert_interval);
+        assert_eq!(status.slo, "breached");
+        assert_eq!(last_alert(&tracker), Some(now), "within the alert interval");
+        tracker.evaluate(now + Duration::from_secs(60), alert_interval);
+        assert_eq!(last_alert(&tracker), Some(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn error_rate_and_throughput_have_their_own_objectives() {
+        let tracker = tracker(slo(None, Some(0.25), Some(30.0)));
+        for error in [false, false, false, true] {
+            tracker.record_request(Duration::from_millis(10), error);
+        }
+        tracker.record_stream(50, Duration::from_secs(1));
+        tracker.record_stream(0, Duration::from_secs(1));
+        let status = tracker.evaluate(Instant::now(), Duration::from_secs(60));
+        assert_eq!((status.slo, status.error_rate, status.tokens_per_second), ("ok", Some(0.25), Some(50.0)));
+
+        tracker.record_request(Duration::from_millis(10), true);
+        tracker.record_stream(4, Duration::from_secs(2));
+        let status = tracker.evaluate(Instant::now(), Duration::from_secs(60));
+        assert_eq!(status.violations, ["error rate 0.400 > 0.25", "throughput 26.0 tokens/s < 30"]);
+    }
+
+    #[cfg(feature = "admin")]
+    #[test]
+    fn reloads_keep_windows_and_flag_removed_endpoints() {
+        use serde_json::json;
+
+        use crate::test_support::{endpoint, proxy_config};
+
+        let slo = json!({ "latency_p95_ms": 500 });
+        let config = |paths: &[&str], timeout: u64| {
+            let endpoints = paths
+                .iter()
+                .map(|path| endpoint(json!({ "path": path, "timeout": timeout, "slo": slo })))
+                .collect();
+            proxy_config(endpoints, json!({}))
+        };
+        let monitor = SloMonitor::new(&config(&["/a", "/b", "/c"], 30));
+        monitor.tracker("/a").unwrap().record_request(Duration::from_millis(100), false);
+        monitor.tracker("/b").unwrap().record_request(Duration::from_millis(100), false);
+
+        // `/a` is edited, `/b` and `/c` are removed
+        let statuses = monitor.evaluate_current(&config(&["/a"], 60));
+        assert_eq!((statuses["/a"].requests, statuses["/a"].removed), (1, false));
+        assert_eq!((statuses["/b"].requests, statuses["/b"].removed), (1, true));
+        assert!(!statuses.contains_key("/c"), "removed endpoints without requests are left out");
+    }
+}
@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use axum::response::sse::Event;
+use serde_json::Value;
+
+use crate::error::ProxyError;
+
+/// Fields of the event being read, dispatched at the next blank line
+#[derive(Default)]
+struct PendingEvent {
+    data: Vec<String>,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<u64>,
+}
+
+/// A complete event read from the upstream
+pub struct SseEvent {
+    pub data: String,
+    pub event: Option<String>,
+    id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl SseEvent {
+    /// Re-encode the event for the client
+    pub fn into_event(self) -> Event {
+        let mut event = Event::default().data(self.data);
+        if let Some(name) = self.event {
+            event = event.event(name);
+        }
+        if let Some(id) = self.id {
+            event = event.id(id);
+        }
+        if let Some(retry) = self.retry {
+            event = event.retry(Duration::from_millis(retry));
+        }
+        event
+    }
+
+    /// The error this event reports, see `provider_error`
+    pub fn provider_error(&self) -> Option<ProxyError> {
+        provider_error(self.event.as_deref(), &self.data)
+    }
+}
+
+/// Recognize an error a provider reports inside an already started stream:
+/// Anthropic's `error` event (`{"type": "error", "error": {"type": ...}}`)
+/// or an OpenAI-compatible `{"error": {...}}` chunk
+pub fn provider_error(event: Option<&str>, data: &str) -> Option<ProxyError> {
+    if !data.contains("error") {
+        return None;
+    }
+    let payload: Value = serde_json::from_str(data).ok()?;
+    let is_error = event == Some("error")
+        || payload.get("type").and_then(Value::as_str) == Some("error")
+        || payload.get("error").is_some_and(|error| !error.is_null());
+    if !is_error {
+        return None;
+    }
+
+    let error = payload.get("error").unwrap_or(&payload);
+    let error_type = error
+        .get("type")
+        .and_then(Value::as_str)
+        .filter(|kind| *kind != "error")
+        .map(str::to_string)
+        .or_else(|| match error.get("code") {
+            Some(Value::String(code)) => Some(code.clone()),
+            Some(Value::Number(code)) => Some(code.to_string()),
+            _ => None,
+        })
+        .unwrap_or_else(|| "upstream_error".to_string());
+    let message = match error {
+        Value::String(message) => message.clone(),
+        _ => error.get("message").and_then(Value::as_str).unwrap_or_default().to_string(),
+    };
+    Some(ProxyError::StreamError { error_type, message })
+}
+
+/// Incremental SSE parser: upstream bytes go in, complete events come out.
+/// Events end at a blank line and keep their `event`, `id` and `retry`
+/// fields; multiple `data` lines are joined with newlines. Comments and
+/// unknown fields are dropped.
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    pending: PendingEvent,
+}
+
+impl SseParser {
+    /// Consume a chunk, returning the events it completes
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Flush an event left open when the upstream closed without a final blank line
+    pub fn finish(mut self) -> Option<SseEvent> {
+        let rest = std::mem::take(&mut self.buffer);
+        let event = self.process_line(&rest);
+        event.or_else(|| self.dispatch())
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<SseEvent> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "data" => self.pending.data.push(value.to_string()),
+            "event" => self.pending.event = Some(value.to_string()),
+            "id" if !value.contains('\0') => self.pending.id = Some(value.to_string()),
+            "retry" => self.pending.retry = value.parse().ok(),
+            _ => {}
+        }
+        None
+    }
+
+    /// Build the pending event; events without data are not dispatched
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let pending = std::mem::take(&mut self.pending);
+        if pending.data.is_empty() {
+            return None;
+        }
+
+        Some(SseEvent {
+            data: pending.data.join("\n"),
+            event: pending.event,
+            id: pending.id,
+            retry: pending.retry,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::response::{IntoResponse, Sse};
+
+    use super::*;
+
+    /// Events parsed from `chunks` fed in turn, the rest flushed at the end
+    fn parse(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut parser = SseParser::default();
+        let mut events: Vec<SseEvent> = chunks.iter().flat_map(|chunk| parser.feed(chunk)).collect();
+        events.extend(parser.finish());
+        events
+    }
+
+    /// Data, event name, id and retry of an event
+    type Fields<'a> = (&'a str, Option<&'a str>, Option<&'a str>, Option<u64>);
+
+    fn fields(events: &[SseEvent]) -> Vec<Fields<'_>> {
+        events
+            .iter()
+            .map(|event| (event.data.as_str(), event.event.as_deref(), event.id.as_deref(), event.retry))
+            .collect()
+    }
+
+    #[test]
+    fn events_end_at_blank_lines() {
+        let stream = b": keep-alive\n\
+            event: message_start\nid: 1\nretry: 500\ndata: {\"a\":1}\n\n\
+            data: line one\ndata:line two\ndata\nunknown: x\n\n\
+            event: ping\n\n\
+            data: last";
+        let events = parse(&[stream]);
+        assert_eq!(
+            fields(&events),
+            [
+                ("{\"a\":1}", Some("message_start"), Some("1"), Some(500)),
+                ("line one\nline two\n", None, None, None),
+                ("last", None, None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn chunks_may_split_lines_anywhere() {
+        let stream: &[u8] = b"event: content_block_delta\r\ndata: {\"text\":\"h\xc3\xa9\"}\r\n\r\ndata: a\r\ndata: b\r\n\r\n";
+        let expected = [("{\"text\":\"hé\"}", Some("content_block_delta"), None, None), ("a\nb", None, None, None)];
+        for split in 0..=stream.len() {
+            let events = parse(&[&stream[..split], &stream[split..]]);
+            assert_eq!(fields(&events), expected, "split at {split}");
+        }
+        let bytes: Vec<&[u8]> = stream.chunks(1).collect();
+        assert_eq!(fields(&parse(&bytes)), expected);
+    }
+
+    #[tokio::test]
+    async fn events_survive_the_round_trip() {
+        let upstream = b"event: message_start\nid: 7\ndata: first\ndata: second\n\ndata: {\"done\":true}\n\n";
+        let events = parse(&[upstream]);
+        let events = tokio_stream::iter(events.into_iter().map(|event| Ok::<_, Infallible>(event.into_event())));
+        let body = axum::body::to_bytes(Sse::new(events).into_response().into_body(), usize::MAX).await.unwrap();
+
+        assert_eq!(
+            fields(&parse(&[&body])),
+            [("first\nsecond", Some("message_start"), Some("7"), None), ("{\"done\":true}", None, None, None)]
+        );
+    }
+
+    #[test]
+    fn provider_errors_keep_their_type() {
+        let anthropic = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        let cases = [
+            // (event name, data, error type and message)
+            (Some("error"), anthropic, Some(("overloaded_error", "Overloaded"))),
+            (None, anthropic, Some(("overloaded_error", "Overloaded"))),
+            (None, r#"{"error":{"message":"Rate limited","code":"rate_limit_exceeded"}}"#, Some(("rate_limit_exceeded", "Rate limited"))),
+            (None, r#"{"error":{"message":"Bad","code":500}}"#, Some(("500", "Bad"))),
+            (None, r#"{"error":"boom"}"#, Some(("upstream_error", "boom"))),
+            (Some("error"), r#"{"type":"error","message":"flat"}"#, Some(("upstream_error", "flat"))),
+            (None, r#"{"choices":[{"delta":{"content":"error"}}],"error":null}"#, None),
+            (Some("content_block_delta"), r#"{"delta":{"text":"an error"}}"#, None),
+            (Some("error"), "not json error", None),
+        ];
+        for (event, data, expected) in cases {
+            let error = provider_error(event, data);
+            let actual = error.as_ref().map(|error| (error.error_type(), error.message()));
+            assert_eq!(actual, expected, "{event:?} {data}");
+            if let Some(error) = error {
+                assert_eq!(error.status(), axum::http::StatusCode::BAD_GATEWAY);
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use tower::{Layer, Service};
+
+tokio::task_local! {
+    /// Phases of the upstream exchange the current task is waiting on
+    static PHASES: Arc<Mutex<UpstreamPhases>>;
+}
+
+/// Where the time before an upstream response went. `dns` and `connect` stay
+/// unset when the request reused a pooled connection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UpstreamPhases {
+    pub dns: Option<Duration>,
+    /// TCP connect and TLS handshake, after DNS
+    pub connect: Option<Duration>,
+    /// From the start of the request until the upstream response headers
+    pub headers: Option<Duration>,
+}
+
+impl UpstreamPhases {
+    /// Whether the request went out over an existing connection
+    pub fn reused(&self) -> bool {
+        self.headers.is_some() && self.connect.is_none()
+    }
+
+    /// Display of one phase for the access log: milliseconds, `reused` when
+    /// a pooled connection made the phase unnecessary, `-` when unknown
+    pub fn display(&self, phase: Option<Duration>) -> PhaseDisplay {
+        PhaseDisplay { phase, reused: self.reused() }
+    }
+}
+
+pub struct PhaseDisplay {
+    phase: Option<Duration>,
+    reused: bool,
+}
+
+impl fmt::Display for PhaseDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.phase {
+            Some(phase) => write!(f, "{:.1}ms", phase.as_secs_f64() * 1000.0),
+            None if self.reused => f.write_str("reused"),
+            None => f.write_str("-"),
+        }
+    }
+}
+
+/// Run `future` with connection phases recorded into `phases`. Connections
+/// the HTTP client opens in the background for other requests are not
+/// attributed to anyone.
+pub async fn record_phases<F: Future>(phases: Arc<Mutex<UpstreamPhases>>, future: F) -> F::Output {
+    PHASES.scope(phases, future).await
+}
+
+fn record(update: impl FnOnce(&mut UpstreamPhases)) {
+    let _ = PHASES.try_with(|phases| update(&mut phases.lock().unwrap()));
+}
+
+/// System resolver that records how long each lookup took
+pub struct TimedResolver;
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let started = Instant::now();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            record(|phases| phases.dns = Some(started.elapsed()));
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Connector layer recording the time to establish new connections, less
+/// the DNS lookup recorded by `TimedResolver`
+#[derive(Clone, Copy)]
+pub struct TimedConnectLayer;
+
+impl<S> Layer<S> for TimedConnectLayer {
+    type Service = TimedConnect<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TimedConnect { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct TimedConnect<S> {
+    inner: S,
+}
+
+impl<S, R> Service<R> for TimedConnect<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let connecting = self.inner.call(request);
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = connecting.await;
+            if result.is_ok() {
+                let elapsed = started.elapsed();
+                record(|phases| phases.connect = Some(elapsed.saturating_sub(phases.dns.unwrap_or_default())));
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn phases_are_recorded_for_the_scoped_task_only() {
+        let slow_connect = || {
+            TimedConnectLayer.layer(tower::service_fn(|_: ()| async {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                Ok::<_, Infallible>(())
+            }))
+        };
+
+        let phases = Arc::new(Mutex::new(UpstreamPhases::default()));
+        record_phases(phases.clone(), async {
+            let addrs = TimedResolver.resolve(Name::from_str("localhost").unwrap()).await.unwrap();
+            assert!(addrs.count() > 0);
+            slow_connect().call(()).await.unwrap();
+        })
+        .await;
+        let recorded = *phases.lock().unwrap();
+        assert!(recorded.dns.is_some());
+        assert!(recorded.connect.is_some_and(|connect| connect >= Duration::from_millis(25)));
+
+        // Connections opened outside a scope are attributed to no one
+        slow_connect().call(()).await.unwrap();
+        assert_eq!(phases.lock().unwrap().connect, recorded.connect);
+    }
+
+    #[test]
+    fn phases_display_as_milliseconds_reused_or_unknown() {
+        let fresh = UpstreamPhases {
+            dns: Some(Duration::from_micros(1250)),
+            connect: Some(Duration::from_millis(8)),
+            headers: Some(Duration::from_millis(120)),
+        };
+        assert!(!fresh.reused());
+        assert_eq!(fresh.display(fresh.dns).to_string(), "1.2ms");
+        assert_eq!(fresh.display(fresh.connect).to_string(), "8.0ms");
+
+        let pooled = UpstreamPhases { headers: Some(Duration::from_millis(40)), ..UpstreamPhases::default() };
+        assert!(pooled.reused());
+        assert_eq!(pooled.display(pooled.dns).to_string(), "reused");
+        assert_eq!(UpstreamPhases::default().display(None).to_string(), "-");
+    }
+}
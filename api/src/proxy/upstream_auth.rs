@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use axum::http::{HeaderName, HeaderValue, header::AUTHORIZATION};
+
+use crate::{get_amp_api_key, get_mistral_api_key};
+use super::config::{ProxyConfig, UpstreamAuthConfig};
+
+/// Header carrying an endpoint's upstream credential
+pub type Credential = (HeaderName, HeaderValue);
+
+/// Read the credentials of all enabled endpoints with an `auth` section from
+/// the environment, keyed by endpoint path. Fails on the first missing or
+/// unusable variable, so a misconfigured key is caught at startup.
+pub fn resolve_credentials(config: &ProxyConfig) -> Result<HashMap<String, Credential>, String> {
+    let mut credentials = HashMap::new();
+    for endpoint in config.enabled_endpoints() {
+        if let Some(auth) = &endpoint.auth
+            && let Some(credential) = credential(auth, &endpoint.path)?
+        {
+            credentials.insert(endpoint.path.clone(), credential);
+        }
+    }
+    Ok(credentials)
+}
+
+/// Like `resolve_credentials`, for the `auth` of the endpoints' shadow upstreams
+pub fn resolve_shadow_credentials(config: &ProxyConfig) -> Result<HashMap<String, Credential>, String> {
+    let mut credentials = HashMap::new();
+    for endpoint in config.enabled_endpoints() {
+        if let Some(auth) = endpoint.shadow.as_ref().and_then(|shadow| shadow.auth.as_ref())
+            && let Some(credential) = credential(auth, &endpoint.path).map_err(|e| format!("shadow of {e}"))?
+        {
+            credentials.insert(endpoint.path.clone(), credential);
+        }
+    }
+    Ok(credentials)
+}
+
+/// Credentials of the targets of endpoints with `targets`, by endpoint path,
+/// in the order the targets are listed: each target's own `auth`, else the
+/// endpoint's
+pub fn resolve_target_credentials(config: &ProxyConfig) -> Result<HashMap<String, Vec<Option<Credential>>>, String> {
+    let mut credentials = HashMap::new();
+    for endpoint in config.enabled_endpoints() {
+        let Some(targets) = &endpoint.targets else { continue };
+        let resolved = targets
+            .iter()
+            .map(|target| match target.auth.as_ref().or(endpoint.auth.as_ref()) {
+                Some(auth) => credential(auth, &endpoint.path),
+                None => Ok(None),
+            })
+            .collect::<Result<_, _>>()?;
+        credentials.insert(endpoint.path.clone(), resolved);
+    }
+    Ok(credentials)
+}
+
+fn credential(auth: &UpstreamAuthConfig, path: &str) -> Result<Option<Credential>, String> {
+    let (name, value) = match auth {
+        UpstreamAuthConfig::Passthrough => return Ok(None),
+        UpstreamAuthConfig::Bearer { env } => (AUTHORIZATION, format!("Bearer {}", env_value(env, path)?)),
+        UpstreamAuthConfig::Header { name, env } => {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("endpoint {path}: invalid auth header name {name}"))?;
+            (name, env_value(env, path)?)
+        }
+    };
+
+    let mut value = HeaderValue::from_str(&value)
+        .map_err(|_| format!("endpoint {}: {} is not a valid header value", path, auth.env().unwrap_or_default()))?;
+    value.set_sensitive(true);
+    Ok(Some((name, value)))
+}
+
+/// `AMP_API_KEY` keeps its built-in fallback when unset, and `MISTRAL_API_KEY`
+/// is the one read at startup
+fn env_value(env: &str, path: &str) -> Result<String, String> {
+    if env == "MISTRAL_API_KEY" {
+        return get_mistral_api_key()
+            .map(str::to_string)
+            .ok_or_else(|| format!("endpoint {path}: environment variable {env} is not set"));
+    }
+    match std::env::var(env) {
+        Ok(value) => Ok(value),
+        Err(_) if env == "AMP_API_KEY" => Ok(get_amp_api_key().to_string()),
+        Err(_) => Err(format!("endpoint {path}: environment variable {env} is not set")),
+    }
+}
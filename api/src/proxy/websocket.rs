@@ -0,0 +1,161 @@
+use std::pin::pin;
+use std::time::Duration;
+
+use axum::extract::ws::{self, CloseFrame, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{self, protocol::CloseFrame as UpstreamCloseFrame};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{info, warn};
+
+pub type UpstreamSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Close code sent to the client when the upstream connection breaks
+/// without a close frame
+const CLOSE_UPSTREAM_ERROR: u16 = 1011;
+
+/// How long the other direction may take to finish the close handshake once
+/// one side has closed
+const CLOSE_GRACE: Duration = Duration::from_secs(5);
+
+/// Relay frames between the client and the upstream until either side
+/// closes. Text and binary frames are forwarded as they are and close frames
+/// keep their code and reason; pings are answered by each socket itself.
+pub async fn relay(client: WebSocket, upstream: UpstreamSocket, path: &str) {
+    let (mut client_tx, mut client_rx) = client.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream.split();
+
+    let client_to_upstream = async {
+        while let Some(Ok(message)) = client_rx.next().await {
+            let Some(message) = to_upstream(message) else {
+                continue;
+            };
+            let closing = message.is_close();
+            if upstream_tx.send(message).await.is_err() || closing {
+                break;
+            }
+        }
+        let _ = upstream_tx.close().await;
+    };
+
+    let upstream_to_client = async {
+        loop {
+            match upstream_rx.next().await {
+                Some(Ok(message)) => {
+                    let Some(message) = to_client(message) else {
+                        continue;
+                    };
+                    if let ws::Message::Close(frame) = &message {
+                        info!(path = %path, code = ?frame.as_ref().map(|f| f.code), "Upstream closed the WebSocket");
+                    }
+                    let closing = matches!(message, ws::Message::Close(_));
+                    if client_tx.send(message).await.is_err() || closing {
+                        break;
+                    }
+                }
+                Some(Err(e)) => {
+                    warn!("Upstream WebSocket for {} failed: {}", path, e);
+                    let close = CloseFrame {
+                        code: CLOSE_UPSTREAM_ERROR,
+                        reason: "Upstream connection failed".into(),
+                    };
+                    let _ = client_tx.send(ws::Message::Close(Some(close))).await;
+                    break;
+                }
+                None => break,
+            }
+        }
+    };
+
+    let mut client_to_upstream = pin!(client_to_upstream);
+    let mut upstream_to_client = pin!(upstream_to_client);
+    let _ = tokio::select! {
+        _ = &mut client_to_upstream => tokio::time::timeout(CLOSE_GRACE, upstream_to_client).await,
+        _ = &mut upstream_to_client => tokio::time::timeout(CLOSE_GRACE, client_to_upstream).await,
+    };
+}
+
+fn to_upstream(message: ws::Message) -> Option<tungstenite::Message> {
+    match message {
+        ws::Message::Text(text) => Some(tungstenite::Message::text(text.as_str())),
+        ws::Message::Binary(data) => Some(tungstenite::Message::Binary(data)),
+        ws::Message::Close(frame) => Some(tungstenite::Message::Close(frame.map(|frame| UpstreamCloseFrame {
+            code: frame.code.into(),
+            reason: frame.reason.as_str().into(),
+        }))),
+        ws::Message::Ping(_) | ws::Message::Pong(_) => None,
+    }
+}
+
+fn to_client(message: tungstenite::Message) -> Option<ws::Message> {
+    match message {
+        tungstenite::Message::Text(text) => Some(ws::Message::Text(text.as_str().into())),
+        tungstenite::Message::Binary(data) => Some(ws::Message::Binary(data)),
+        tungstenite::Message::Close(frame) => Some(ws::Message::Close(frame.map(|frame| CloseFrame {
+            code: frame.code.into(),
+            reason: frame.reason.as_str().into(),
+        }))),
+        tungstenite::Message::Ping(_) | tungstenite::Message::Pong(_) | tungstenite::Message::Frame(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, extract::ws::WebSocketUpgrade, response::Response, routing::get};
+    use serde_json::json;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+    use super::*;
+    use crate::test_support::{endpoint, proxy_config, proxy_service, spawn_upstream};
+
+    /// Upstream echoing text and binary frames, closing with code 4000 when
+    /// asked to by a `close` text frame
+    async fn echo(upgrade: WebSocketUpgrade) -> Response {
+        upgrade.on_upgrade(|mut socket| async move {
+            while let Some(Ok(message)) = socket.recv().await {
+                let reply = match message {
+                    ws::Message::Text(text) if text.as_str() == "close" => {
+                        ws::Message::Close(Some(CloseFrame { code: 4000, reason: "bye".into() }))
+                    }
+                    ws::Message::Text(_) | ws::Message::Binary(_) => message,
+                    _ => continue,
+                };
+                if socket.send(reply).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn frames_and_close_codes_are_relayed() {
+        let upstream = spawn_upstream(Router::new().route("/echo", get(echo))).await;
+        let target_url = format!("{}/echo", upstream.replace("http://", "ws://"));
+        let config = proxy_config(
+            vec![endpoint(json!({ "path": "/v1/realtime", "method": "GET", "response_type": "websocket", "target_url": target_url }))],
+            json!({}),
+        );
+        let proxy = spawn_upstream(proxy_service(config).create_router()).await;
+
+        let url = format!("{}/v1/realtime", proxy.replace("http://", "ws://"));
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        socket.send(tungstenite::Message::text("hello")).await.unwrap();
+        assert_eq!(socket.next().await.unwrap().unwrap(), tungstenite::Message::text("hello"));
+        socket.send(tungstenite::Message::binary(vec![0u8, 159, 255])).await.unwrap();
+        assert_eq!(socket.next().await.unwrap().unwrap(), tungstenite::Message::binary(vec![0u8, 159, 255]));
+
+        socket.send(tungstenite::Message::text("close")).await.unwrap();
+        let tungstenite::Message::Close(Some(frame)) = socket.next().await.unwrap().unwrap() else {
+            panic!("expected a close frame");
+        };
+        assert_eq!((frame.code, frame.reason.as_str()), (CloseCode::from(4000), "bye"));
+    }
+
+    #[test]
+    fn websocket_endpoints_need_a_websocket_target() {
+        for (target_url, valid) in [("ws://127.0.0.1:9/", true), ("wss://api.example.com/v1", true), ("https://api.example.com/v1", false)] {
+            let endpoint = endpoint(json!({ "method": "GET", "response_type": "websocket", "target_url": target_url }));
+            assert_eq!(endpoint.validate().is_ok(), valid, "{target_url}");
+        }
+    }
+}
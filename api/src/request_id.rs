@@ -0,0 +1,25 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Request};
+use tower_http::request_id::{MakeRequestId, RequestId};
+use ulid::Ulid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Assigns a ULID to requests that arrive without an `x-request-id`
+#[derive(Clone, Copy, Default)]
+pub struct MakeRequestUlid;
+
+impl MakeRequestId for MakeRequestUlid {
+    fn make_request_id<B>(&mut self, _request: &Request<B>) -> Option<RequestId> {
+        HeaderValue::from_str(&Ulid::new().to_string()).ok().map(RequestId::new)
+    }
+}
+
+/// The request's id, set by `MakeRequestUlid` or the client; a fresh ULID
+/// when neither is present
+pub fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| Ulid::new().to_string())
+}
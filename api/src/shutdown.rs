@@ -0,0 +1,79 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use tokio::sync::watch;
+
+/// Coordinates draining on shutdown: counts in-flight proxied requests, and
+/// tells streams still running when the drain window is over to end
+pub struct Shutdown {
+    in_flight: AtomicU64,
+    draining: AtomicBool,
+    /// Requests that finished while draining, cut streams included
+    finished: AtomicU64,
+    cut_streams: AtomicU64,
+    cut: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            in_flight: AtomicU64::new(0),
+            draining: AtomicBool::new(false),
+            finished: AtomicU64::new(0),
+            cut_streams: AtomicU64::new(0),
+            cut: watch::Sender::new(false),
+        }
+    }
+
+    /// Count a request as in flight until the guard drops
+    pub fn track(self: &Arc<Self>) -> DrainGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        DrainGuard { shutdown: self.clone() }
+    }
+
+    /// Start draining, returning the number of requests in flight
+    pub fn begin_drain(&self) -> u64 {
+        self.draining.store(true, Ordering::Relaxed);
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// End the drain window: streams still running finish with a final event
+    pub fn cut(&self) {
+        self.cut.send_replace(true);
+    }
+
+    /// Requests that completed while draining, and those that were cut or
+    /// are still in flight
+    pub fn summary(&self) -> (u64, u64) {
+        let cut_streams = self.cut_streams.load(Ordering::Relaxed);
+        let drained = self.finished.load(Ordering::Relaxed).saturating_sub(cut_streams);
+        (drained, cut_streams + self.in_flight.load(Ordering::Relaxed))
+    }
+}
+
+/// In-flight marker of one request, see `Shutdown::track`
+pub struct DrainGuard {
+    shutdown: Arc<Shutdown>,
+}
+
+impl DrainGuard {
+    /// Resolves once the drain window is over and streams must end
+    pub async fn cut_off(&self) {
+        let mut cut = self.shutdown.cut.subscribe();
+        let _ = cut.wait_for(|cut| *cut).await;
+    }
+
+    /// Count this request's stream as cut by shutdown
+    pub fn record_cut(&self) {
+        self.shutdown.cut_streams.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for DrainGuard {
+    fn drop(&mut self) {
+        self.shutdown.in_flight.fetch_sub(1, Ordering::Relaxed);
+        if self.shutdown.draining.load(Ordering::Relaxed) {
+            self.shutdown.finished.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
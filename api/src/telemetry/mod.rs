@@ -1,18 +1,258 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use axum::{
     Json, Router,
-    routing::post,
+    body::Bytes,
+    extract::{FromRef, FromRequest, Query, Request, State},
+    http::{HeaderMap, StatusCode, header::{CONTENT_TYPE, USER_AGENT}},
+    response::{IntoResponse, Response},
+    routing::{get, post},
 };
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
 use serde_json::json;
+use tracing::{debug, error, info};
+
+mod sink;
+mod store;
+pub use sink::TelemetrySink;
+use store::TelemetryStore;
+
+use crate::error::{ProxyError, create_error_response};
+use crate::metrics::ProxyMetrics;
+use crate::request_id::request_id;
 
 type TelemetryEvent = Vec<HashMap<String, serde_json::Value>>;
 
-pub fn router() -> Router {
-    Router::new()
+/// Start and end of a time range, each optional
+type TimeRange = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+const DEFAULT_EVENT_LIMIT: usize = 100;
+const MAX_EVENT_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Event store and the sink writing to it, shared by the telemetry routes
+#[derive(Clone)]
+struct TelemetryState {
+    store: Arc<TelemetryStore>,
+    sink: Arc<TelemetrySink>,
+}
+
+impl FromRef<TelemetryState> for Arc<TelemetryStore> {
+    fn from_ref(state: &TelemetryState) -> Self {
+        state.store.clone()
+    }
+}
+
+impl FromRef<TelemetryState> for Arc<TelemetrySink> {
+    fn from_ref(state: &TelemetryState) -> Self {
+        state.sink.clone()
+    }
+}
+
+/// Telemetry routes, and the sink to flush at shutdown; fails when the
+/// telemetry directory cannot be created
+pub fn router(metrics: Arc<ProxyMetrics>) -> Result<(Router, Arc<TelemetrySink>), String> {
+    let store = Arc::new(TelemetryStore::from_env(metrics)?);
+    store.spawn_retention();
+    Ok(routes(store))
+}
+
+fn routes(store: Arc<TelemetryStore>) -> (Router, Arc<TelemetrySink>) {
+    let sink = Arc::new(TelemetrySink::spawn(store.clone()));
+    let router = Router::new()
         .route("/api/telemetry", post(telemetry))
+        .route("/api/telemetry/events", get(events))
+        .route("/api/telemetry/summary", get(summary))
+        .route("/api/errors", post(errors))
+        .with_state(TelemetryState { store, sink: sink.clone() });
+    (router, sink)
+}
+
+async fn telemetry(
+    State(sink): State<Arc<TelemetrySink>>,
+    LenientJson(request): LenientJson<TelemetryEvent>,
+) -> Json<serde_json::Value> {
+    let published = request.len();
+    let events = request
+        .into_iter()
+        .map(|event| serde_json::Value::Object(event.into_iter().collect()))
+        .collect();
+    sink.send(events);
+    Json(json!({ "message": "ok", "published": published }))
+}
+
+/// Stored events received in `[since, until)`, oldest first
+async fn events(
+    State(store): State<Arc<TelemetryStore>>,
+    headers: HeaderMap,
+    Query(query): Query<EventsQuery>,
+) -> Response {
+    let request_id = request_id(&headers);
+    let (since, until) = match time_range(&query) {
+        Ok(range) => range,
+        Err(error) => return create_error_response(error, &request_id),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_EVENT_LIMIT).clamp(1, MAX_EVENT_LIMIT);
+
+    match tokio::task::spawn_blocking(move || store.events(since, until, limit)).await {
+        Ok(Ok((events, truncated))) => Json(json!({ "events": events, "truncated": truncated })).into_response(),
+        Ok(Err(e)) => read_failed(e.to_string(), &request_id),
+        Err(e) => read_failed(e.to_string(), &request_id),
+    }
+}
+
+/// Counts of the stored events received in `[since, until)` by event name
+async fn summary(
+    State(store): State<Arc<TelemetryStore>>,
+    headers: HeaderMap,
+    Query(query): Query<EventsQuery>,
+) -> Response {
+    let request_id = request_id(&headers);
+    let (since, until) = match time_range(&query) {
+        Ok(range) => range,
+        Err(error) => return create_error_response(error, &request_id),
+    };
+
+    let dropped_events = store.dropped();
+    match tokio::task::spawn_blocking(move || store.summary(since, until)).await {
+        Ok(Ok((total, by_name))) => {
+            Json(json!({ "total": total, "by_name": by_name, "dropped_events": dropped_events })).into_response()
+        }
+        Ok(Err(e)) => read_failed(e.to_string(), &request_id),
+        Err(e) => read_failed(e.to_string(), &request_id),
+    }
 }
 
-async fn telemetry(Json(request): Json<TelemetryEvent>) -> Json<serde_json::Value> {
-    Json(json!({ "message": "ok", "published": request.len() }))
-}   
\ No newline at end of file
+fn read_failed(error: String, request_id: &str) -> Response {
+    error!("Could not read telemetry events: {}", error);
+    create_error_response(ProxyError::Internal("Could not read telemetry events".to_string()), request_id)
+}
+
+/// `since` and `until` as RFC 3339 times or `YYYY-MM-DD` dates, the latter
+/// meaning midnight UTC
+fn time_range(query: &EventsQuery) -> Result<TimeRange, ProxyError> {
+    let parse = |name: &str, value: &Option<String>| {
+        value
+            .as_deref()
+            .map(|value| {
+                DateTime::parse_from_rfc3339(value)
+                    .map(|time| time.with_timezone(&Utc))
+                    .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|day| day.and_time(Default::default()).and_utc()))
+                    .map_err(|_| {
+                        ProxyError::InvalidRequest(
+                            StatusCode::BAD_REQUEST,
+                            format!("{name} must be an RFC 3339 time or a YYYY-MM-DD date"),
+                        )
+                    })
+            })
+            .transpose()
+    };
+    Ok((parse("since", &query.since)?, parse("until", &query.until)?))
+}
+
+async fn errors(LenientJson(report): LenientJson<serde_json::Value>) -> Json<serde_json::Value> {
+    debug!("Client error report: {}", report);
+    Json(json!({ "ok": true }))
+}
+
+/// JSON body sent with any content type: older clients post JSON as
+/// `text/plain;charset=UTF-8`, which `Json` refuses with 415. The body keeps
+/// the default size limit; bodies that are not valid JSON get a 400 naming
+/// the parse error.
+struct LenientJson<T>(T);
+
+impl<S: Send + Sync, T: DeserializeOwned> FromRequest<S> for LenientJson<T> {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let request_id = request_id(req.headers());
+        let (content_type, user_agent) = {
+            let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+            (header(CONTENT_TYPE), header(USER_AGENT))
+        };
+        let path = req.uri().path().to_string();
+
+        let is_json = content_type.as_deref().is_some_and(|content_type| {
+            let mime = content_type.split(';').next().unwrap_or_default().trim();
+            mime.eq_ignore_ascii_case("application/json") || mime.to_ascii_lowercase().ends_with("+json")
+        });
+        if !is_json {
+            info!(
+                "Deprecated: client {} posted JSON to {} as {}; it should send application/json",
+                user_agent.as_deref().unwrap_or("(no user-agent)"),
+                path,
+                content_type.as_deref().unwrap_or("(no content type)")
+            );
+        }
+
+        let body = Bytes::from_request(req, state).await.map_err(IntoResponse::into_response)?;
+        serde_json::from_slice(&body).map(LenientJson).map_err(|e| {
+            let error = ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, format!("Invalid JSON body: {e}"));
+            create_error_response(error, &request_id)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    use crate::test_support::{send, temp_dir};
+
+    fn post(body: &str) -> Request<Body> {
+        Request::post("/api/telemetry").header(CONTENT_TYPE, "application/json").body(Body::from(body.to_string())).unwrap()
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn posted_events_are_read_back_from_the_sink() {
+        let metrics = Arc::new(ProxyMetrics::new());
+        let store = TelemetryStore::new(Some(temp_dir("telemetry")), None, metrics).unwrap();
+        let (router, sink) = routes(Arc::new(store));
+
+        let batch = r#"[{"event":"completion","model":"a"},{"event":"completion"},{"name":"startup"}]"#;
+        let (status, _, body) = send(&router, post(batch)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["published"], 3);
+        sink.flush().await;
+
+        let (status, _, body) = send(&router, get("/api/telemetry/events?limit=2")).await;
+        assert_eq!(status, StatusCode::OK);
+        let page: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(page["truncated"], true);
+        assert_eq!(page["events"][0]["event"], json!({"event": "completion", "model": "a"}));
+        assert!(page["events"][0]["received_at"].is_string());
+        assert_eq!(page["events"].as_array().unwrap().len(), 2);
+
+        let (_, _, body) = send(&router, get("/api/telemetry/summary")).await;
+        let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(summary["total"], 3);
+        assert_eq!(summary["by_name"], json!({"completion": 2, "startup": 1}));
+        assert_eq!(summary["dropped_events"], 0);
+    }
+
+    #[tokio::test]
+    async fn time_ranges_must_be_times_or_dates() {
+        let metrics = Arc::new(ProxyMetrics::new());
+        let (router, _) = routes(Arc::new(TelemetryStore::new(None, None, metrics).unwrap()));
+
+        let (status, _, _) = send(&router, get("/api/telemetry/events?since=2024-05-01&until=2024-05-02T00:00:00Z")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _, body) = send(&router, get("/api/telemetry/events?since=yesterday")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(String::from_utf8_lossy(&body).contains("since must be"));
+    }
+}
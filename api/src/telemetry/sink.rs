@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+use super::store::TelemetryStore;
+
+/// How often queued events are written to the store
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Batches queued before further ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Queued events written early, without waiting for the next flush
+const MAX_PENDING_EVENTS: usize = 1000;
+
+enum Message {
+    Events(DateTime<Utc>, Vec<Value>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Queue between the telemetry handler and the store: requests hand their
+/// events over without waiting on the disk, and a background task writes
+/// them to the store files every `FLUSH_INTERVAL`, or once
+/// `MAX_PENDING_EVENTS` are pending. Events that do not fit the queue, or
+/// cannot be written, are dropped and counted by the store.
+pub struct TelemetrySink {
+    store: Arc<TelemetryStore>,
+    /// Unset when the store keeps no events
+    queue: Option<mpsc::Sender<Message>>,
+}
+
+impl TelemetrySink {
+    /// Sink writing to `store`, with its writer task running
+    pub fn spawn(store: Arc<TelemetryStore>) -> Self {
+        if !store.enabled() {
+            return Self { store, queue: None };
+        }
+        let (queue, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(write_queued(store.clone(), receiver));
+        Self { store, queue: Some(queue) }
+    }
+
+    /// Queue a batch of events received now
+    pub fn send(&self, events: Vec<Value>) {
+        let Some(queue) = &self.queue else { return };
+        if events.is_empty() {
+            return;
+        }
+        let count = events.len() as u64;
+        if let Err(e) = queue.try_send(Message::Events(Utc::now(), events)) {
+            let reason = match e {
+                mpsc::error::TrySendError::Full(_) => "write queue is full",
+                mpsc::error::TrySendError::Closed(_) => "writer has stopped",
+            };
+            self.store.drop_events(count, reason);
+        }
+    }
+
+    /// Write the events queued so far, returning once they are stored
+    pub async fn flush(&self) {
+        let Some(queue) = &self.queue else { return };
+        let (done, written) = oneshot::channel();
+        if queue.send(Message::Flush(done)).await.is_ok() {
+            let _ = written.await;
+        }
+    }
+}
+
+async fn write_queued(store: Arc<TelemetryStore>, mut receiver: mpsc::Receiver<Message>) {
+    let mut pending = Vec::new();
+    let mut pending_events = 0;
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        let flushed = tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Events(received_at, events)) => {
+                    pending_events += events.len();
+                    pending.push((received_at, events));
+                    if pending_events < MAX_PENDING_EVENTS {
+                        continue;
+                    }
+                    None
+                }
+                Some(Message::Flush(done)) => Some(done),
+                None => break,
+            },
+            _ = interval.tick() => None,
+        };
+        write(&store, std::mem::take(&mut pending)).await;
+        pending_events = 0;
+        if let Some(done) = flushed {
+            let _ = done.send(());
+        }
+    }
+    write(&store, pending).await;
+}
+
+async fn write(store: &Arc<TelemetryStore>, batches: Vec<(DateTime<Utc>, Vec<Value>)>) {
+    if batches.is_empty() {
+        return;
+    }
+    let count = batches.iter().map(|(_, events)| events.len() as u64).sum();
+    let writer = store.clone();
+    let error = match tokio::task::spawn_blocking(move || writer.append(&batches)).await {
+        Ok(Ok(())) => return,
+        Ok(Err(e)) => e.to_string(),
+        Err(e) => e.to_string(),
+    };
+    store.drop_events(count, &error);
+}
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::{Value, json};
+use tracing::{error, info, warn};
+
+use crate::metrics::ProxyMetrics;
+
+/// Event fields naming an event, in order of preference
+const NAME_FIELDS: &[&str] = &["event", "name", "eventName", "type"];
+
+/// Received telemetry events, appended to one JSON lines file per UTC day
+/// (`events-YYYY-MM-DD.jsonl`) under `TELEMETRY_DIR`, each line holding the
+/// event and its `received_at` time, and written through a `TelemetrySink`.
+/// Without a directory events are discarded.
+pub struct TelemetryStore {
+    dir: Option<PathBuf>,
+    /// Days of events kept; all of them when unset
+    retention_days: Option<u64>,
+    /// Keeps appends and pruning from interleaving
+    write: Mutex<()>,
+    /// Events that could not be stored since startup
+    dropped: AtomicU64,
+    metrics: Arc<ProxyMetrics>,
+}
+
+impl TelemetryStore {
+    /// Store under `TELEMETRY_DIR`, created if missing, keeping
+    /// `TELEMETRY_RETENTION_DAYS` days of events
+    pub fn from_env(metrics: Arc<ProxyMetrics>) -> Result<Self, String> {
+        let retention_days = match std::env::var("TELEMETRY_RETENTION_DAYS") {
+            Ok(days) => match days.parse::<u64>() {
+                Ok(days) if days > 0 => Some(days),
+                _ => return Err(format!("TELEMETRY_RETENTION_DAYS must be a positive number of days, not {days:?}")),
+            },
+            Err(_) => None,
+        };
+        let dir = std::env::var_os("TELEMETRY_DIR").filter(|dir| !dir.is_empty()).map(PathBuf::from);
+        Self::new(dir, retention_days, metrics)
+    }
+
+    /// Store under `dir`, created if missing, keeping `retention_days` days
+    /// of events
+    pub fn new(dir: Option<PathBuf>, retention_days: Option<u64>, metrics: Arc<ProxyMetrics>) -> Result<Self, String> {
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir).map_err(|e| format!("Could not create telemetry directory {}: {}", dir.display(), e))?;
+            info!("Storing telemetry events in {}", dir.display());
+        }
+        Ok(Self { dir, retention_days, write: Mutex::new(()), dropped: AtomicU64::new(0), metrics })
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Whether events are stored at all
+    pub fn enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Log and count events that could not be stored
+    pub fn drop_events(&self, count: u64, reason: &str) {
+        error!("Dropping {} telemetry events: {}", count, reason);
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+        self.metrics.record_dropped_telemetry(count);
+    }
+
+    /// Append batches of events, each with the time it was received, to the
+    /// files of the days they were received on
+    pub fn append(&self, batches: &[(DateTime<Utc>, Vec<Value>)]) -> io::Result<()> {
+        let Some(dir) = &self.dir else { return Ok(()) };
+        let mut days: BTreeMap<NaiveDate, Vec<u8>> = BTreeMap::new();
+        for (received_at, events) in batches {
+            let lines = days.entry(received_at.date_naive()).or_default();
+            for event in events {
+                let line = json!({
+                    "received_at": received_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                    "event": event,
+                });
+                serde_json::to_writer(&mut *lines, &line)?;
+                lines.push(b'\n');
+            }
+        }
+
+        let _write = self.write.lock().unwrap();
+        for (day, lines) in days {
+            let path = dir.join(format!("events-{}.jsonl", day.format("%Y-%m-%d")));
+            OpenOptions::new().create(true).append(true).open(path)?.write_all(&lines)?;
+        }
+        Ok(())
+    }
+
+    /// Stored events received in `[since, until)`, oldest first, at most
+    /// `limit` of them; `true` when more matched
+    pub fn events(&self, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>, limit: usize) -> io::Result<(Vec<Value>, bool)> {
+        let mut events = Vec::new();
+        let mut truncated = false;
+        self.scan(since, until, |event| {
+            if events.len() == limit {
+                truncated = true;
+                return false;
+            }
+            events.push(event);
+            true
+        })?;
+        Ok((events, truncated))
+    }
+
+    /// Stored events received in `[since, until)`, counted by event name
+    pub fn summary(&self, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> io::Result<(u64, BTreeMap<String, u64>)> {
+        let mut total = 0;
+        let mut by_name = BTreeMap::new();
+        self.scan(since, until, |stored| {
+            total += 1;
+            let name = NAME_FIELDS
+                .iter()
+                .find_map(|field| stored.get("event")?.get(*field)?.as_str())
+                .unwrap_or("(unnamed)");
+            *by_name.entry(name.to_string()).or_insert(0) += 1;
+            true
+        })?;
+        Ok((total, by_name))
+    }
+
+    /// Feed stored events in the range to `visit`, oldest first, until it
+    /// returns `false`. Only the day files overlapping the range are read;
+    /// unreadable lines are skipped.
+    fn scan(
+        &self,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+        mut visit: impl FnMut(Value) -> bool,
+    ) -> io::Result<()> {
+        let Some(dir) = &self.dir else { return Ok(()) };
+        let mut days: Vec<(NaiveDate, PathBuf)> = self
+            .day_files(dir)?
+            .into_iter()
+            .filter(|(day, _)| since.is_none_or(|since| *day >= since.date_naive()))
+            .filter(|(day, _)| until.is_none_or(|until| *day <= until.date_naive()))
+            .collect();
+        days.sort();
+
+        for (_, path) in days {
+            let file = match fs::File::open(&path) {
+                Ok(file) => file,
+                // Pruned meanwhile
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for line in BufReader::new(file).lines() {
+                let Ok(stored) = serde_json::from_str::<Value>(&line?) else {
+                    continue;
+                };
+                let Some(received_at) = stored
+                    .get("received_at")
+                    .and_then(Value::as_str)
+                    .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+                    .map(|at| at.with_timezone(&Utc))
+                else {
+                    continue;
+                };
+                if since.is_some_and(|since| received_at < since) || until.is_some_and(|until| received_at >= until) {
+                    continue;
+                }
+                if !visit(stored) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn day_files(&self, dir: &PathBuf) -> io::Result<Vec<(NaiveDate, PathBuf)>> {
+        let mut days = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let day = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("events-")?.strip_suffix(".jsonl"))
+                .and_then(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok());
+            if let Some(day) = day {
+                days.push((day, path));
+            }
+        }
+        Ok(days)
+    }
+
+    /// Delete the day files older than the retention period
+    fn prune(&self) {
+        let (Some(dir), Some(retention_days)) = (&self.dir, self.retention_days) else {
+            return;
+        };
+        let oldest_kept = Utc::now().date_naive() - chrono::Days::new(retention_days);
+        let _write = self.write.lock().unwrap();
+        let days = match self.day_files(dir) {
+            Ok(days) => days,
+            Err(e) => {
+                warn!("Could not list telemetry directory {}: {}", dir.display(), e);
+                return;
+            }
+        };
+        for (day, path) in days {
+            if day < oldest_kept {
+                match fs::remove_file(&path) {
+                    Ok(()) => info!("Deleted telemetry events of {}", day),
+                    Err(e) => warn!("Could not delete {}: {}", path.display(), e),
+                }
+            }
+        }
+    }
+
+    /// Prune now and then once a day
+    pub fn spawn_retention(self: &Arc<Self>) {
+        if self.dir.is_none() || self.retention_days.is_none() {
+            return;
+        }
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(24 * 60 * 60));
+            loop {
+                interval.tick().await;
+                let store = store.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || store.prune()).await {
+                    error!("Telemetry pruning failed: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::SubsecRound;
+
+    use crate::test_support::temp_dir;
+
+    fn store(retention_days: Option<u64>) -> TelemetryStore {
+        TelemetryStore::new(Some(temp_dir("telemetry-store")), retention_days, Arc::new(ProxyMetrics::new())).unwrap()
+    }
+
+    /// The time `days` ago, to the millisecond events are stored with
+    fn days_ago(days: u64) -> DateTime<Utc> {
+        (Utc::now() - chrono::Days::new(days)).trunc_subsecs(3)
+    }
+
+    #[test]
+    fn events_are_kept_per_day_and_read_by_range() {
+        let store = store(None);
+        let old = days_ago(3);
+        let batches = [(old, vec![json!({"event": "old"})]), (Utc::now(), vec![json!({"type": "new"}), json!({})])];
+        store.append(&batches).unwrap();
+        assert_eq!(store.day_files(store.dir.as_ref().unwrap()).unwrap().len(), 2);
+
+        let (events, truncated) = store.events(None, None, 10).unwrap();
+        assert!(!truncated);
+        let names: Vec<_> = events.iter().map(|stored| stored["event"].clone()).collect();
+        assert_eq!(names, [json!({"event": "old"}), json!({"type": "new"}), json!({})]);
+
+        let (events, _) = store.events(Some(days_ago(1)), None, 10).unwrap();
+        assert_eq!(events.len(), 2);
+        let (events, _) = store.events(None, Some(old), 10).unwrap();
+        assert!(events.is_empty(), "until is exclusive");
+
+        let (total, by_name) = store.summary(None, None).unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(by_name, BTreeMap::from([("(unnamed)".to_string(), 1), ("new".to_string(), 1), ("old".to_string(), 1)]));
+    }
+
+    #[test]
+    fn retention_deletes_the_days_past_it() {
+        let store = store(Some(2));
+        let batches = [
+            (days_ago(5), vec![json!({"event": "expired"})]),
+            (days_ago(2), vec![json!({"event": "oldest kept"})]),
+            (Utc::now(), vec![json!({"event": "today"})]),
+        ];
+        store.append(&batches).unwrap();
+
+        store.prune();
+        let (_, by_name) = store.summary(None, None).unwrap();
+        assert_eq!(by_name.keys().collect::<Vec<_>>(), ["oldest kept", "today"]);
+    }
+
+    #[test]
+    fn events_are_dropped_without_a_directory() {
+        let store = TelemetryStore::new(None, Some(1), Arc::new(ProxyMetrics::new())).unwrap();
+        assert!(!store.enabled());
+        store.append(&[(Utc::now(), vec![json!({"event": "lost"})])]).unwrap();
+        assert_eq!(store.summary(None, None).unwrap().0, 0);
+
+        store.drop_events(4, "test");
+        assert_eq!(store.dropped(), 4);
+    }
+}
@@ -0,0 +1,82 @@
+//! Fixtures shared by the unit tests
+
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    http::{HeaderMap, Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+use crate::metrics::ProxyMetrics;
+use crate::proxy::config::EndpointConfig;
+use crate::proxy::{ProxyConfig, ProxyService};
+
+/// `ADMIN_TOKEN` of the test binary
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Set `ADMIN_TOKEN` for the whole test binary, as `start` does at startup
+pub fn init_admin_token() {
+    crate::ADMIN_TOKEN.get_or_init(|| Some(ADMIN_TOKEN.to_string()));
+}
+
+/// Endpoint from `fields`, on top of an enabled JSON `POST /v1/test` endpoint
+pub fn endpoint(fields: Value) -> EndpointConfig {
+    let mut endpoint = json!({
+        "path": "/v1/test",
+        "target_url": "http://127.0.0.1:9/",
+        "method": "POST",
+        "response_type": "json",
+        "custom_headers": {},
+        "forward_request_headers": ["content-type"],
+        "forward_response_headers": ["content-type"],
+        "enabled": true,
+    });
+    let fields = fields.as_object().cloned().expect("endpoint fields are an object");
+    endpoint.as_object_mut().unwrap().extend(fields);
+    serde_json::from_value(endpoint).expect("valid endpoint")
+}
+
+/// Proxy configuration serving `endpoints`, with the other sections from `fields`
+pub fn proxy_config(endpoints: Vec<EndpointConfig>, mut fields: Value) -> ProxyConfig {
+    fields["endpoints"] = json!([]);
+    let config: ProxyConfig = serde_json::from_value(fields).expect("valid proxy config");
+    ProxyConfig { endpoints, ..config }
+}
+
+/// Proxy service for `config`, with metrics of its own
+pub fn proxy_service(config: ProxyConfig) -> ProxyService {
+    init_admin_token();
+    ProxyService::new(config, Arc::new(ProxyMetrics::new())).expect("proxy service starts")
+}
+
+/// Serve `router` on an ephemeral local port, returning its base URL
+pub async fn spawn_upstream(router: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{addr}")
+}
+
+/// Send `req` through `router`, returning the status, headers and whole body
+pub async fn send(router: &Router, req: Request<Body>) -> (StatusCode, HeaderMap, Bytes) {
+    let response = router.clone().oneshot(req).await.unwrap();
+    let (parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+    (parts.status, parts.headers, body)
+}
+
+/// Empty directory of its own under the system temporary directory
+pub fn temp_dir(label: &str) -> std::path::PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("amp-server-{label}-{}-{n}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,197 @@
+use serde_json::Value;
+
+/// Token counts of one response. `input` holds the prompt tokens not served
+/// from the provider's cache and `cached` those that were, so each prompt
+/// token is counted once whatever the provider reports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenCounts {
+    pub input: u64,
+    pub cached: u64,
+    pub output: u64,
+}
+
+/// Collects the model and token usage reported in a response body, or in the
+/// events of a stream as they pass. Providers report usage differently:
+///
+/// - Chat Completions: `usage` with `prompt_tokens` (cached ones included,
+///   see `prompt_tokens_details.cached_tokens`) and `completion_tokens`,
+///   on the final chunk of streams
+/// - Responses API: `usage` with `input_tokens` and `output_tokens`, in the
+///   `response` of the final `response.completed` event of streams
+/// - Anthropic Messages: `usage` with `input_tokens` (cached ones excluded)
+///   and `cache_read_input_tokens`; streams report input in `message_start`
+///   and the running output count in `message_delta`
+/// - Gemini: `usageMetadata` with `promptTokenCount` and
+///   `candidatesTokenCount`, cumulative on every streamed chunk
+///
+/// Later reports replace earlier ones field by field.
+#[derive(Debug, Default)]
+pub struct UsageTracker {
+    model: Option<String>,
+    input: Option<u64>,
+    cached: Option<u64>,
+    output: Option<u64>,
+    /// Incomplete last line of the stream so far
+    partial: Vec<u8>,
+}
+
+impl UsageTracker {
+    /// Take a complete JSON response body
+    pub fn observe_body(&mut self, body: &[u8]) {
+        if let Ok(body) = serde_json::from_slice::<Value>(body) {
+            self.observe(&body);
+        }
+    }
+
+    /// Take the next bytes of an SSE or NDJSON stream. Only lines that
+    /// mention usage are parsed.
+    pub fn observe_stream(&mut self, bytes: &[u8]) {
+        self.partial.extend_from_slice(bytes);
+        while let Some(pos) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=pos).collect();
+            self.observe_line(&line);
+        }
+    }
+
+    /// Take a JSON response, stream chunk or stream event
+    pub fn observe(&mut self, value: &Value) {
+        // Responses API events carry the response, Anthropic's `message_start` the message
+        let value = value.get("response").filter(|response| response.is_object()).unwrap_or(value);
+        let message = value.get("message").filter(|message| message.is_object());
+        for source in std::iter::once(value).chain(message) {
+            if let Some(model) = source.get("model").or_else(|| source.get("modelVersion")).and_then(Value::as_str) {
+                self.model = Some(model.to_string());
+            }
+            if let Some(usage) = source.get("usage").or_else(|| source.get("usageMetadata")).filter(|usage| usage.is_object()) {
+                self.merge(usage);
+            }
+        }
+    }
+
+    /// The model and token counts seen, `None` when no usage was reported
+    pub fn finish(mut self) -> Option<(Option<String>, TokenCounts)> {
+        let partial = std::mem::take(&mut self.partial);
+        self.observe_line(&partial);
+        if self.input.is_none() && self.cached.is_none() && self.output.is_none() {
+            return None;
+        }
+        let counts = TokenCounts {
+            input: self.input.unwrap_or_default(),
+            cached: self.cached.unwrap_or_default(),
+            output: self.output.unwrap_or_default(),
+        };
+        Some((self.model, counts))
+    }
+
+    fn observe_line(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let data = line.trim();
+        let data = data.strip_prefix("data:").map_or(data, str::trim_start);
+        if data.starts_with('{')
+            && data.contains("\"usage")
+            && let Ok(event) = serde_json::from_str::<Value>(data)
+        {
+            self.observe(&event);
+        }
+    }
+
+    fn merge(&mut self, usage: &Value) {
+        let count = |path: &[&str]| path.iter().try_fold(usage, |value, key| value.get(key))?.as_u64();
+
+        if let Some(output) = count(&["completion_tokens"])
+            .or_else(|| count(&["output_tokens"]))
+            .or_else(|| count(&["candidatesTokenCount"]))
+        {
+            self.output = Some(output + count(&["thoughtsTokenCount"]).unwrap_or_default());
+        }
+
+        // Anthropic counts cache reads and writes apart from `input_tokens`
+        let cache_read = count(&["cache_read_input_tokens"]);
+        let cache_creation = count(&["cache_creation_input_tokens"]);
+        if cache_read.is_some() || cache_creation.is_some() {
+            if let Some(input) = count(&["input_tokens"]) {
+                self.input = Some(input + cache_creation.unwrap_or_default());
+            }
+            if cache_read.is_some() {
+                self.cached = cache_read;
+            }
+            return;
+        }
+
+        // Everyone else includes cached tokens in the prompt count
+        let prompt = count(&["prompt_tokens"])
+            .or_else(|| count(&["input_tokens"]))
+            .or_else(|| count(&["promptTokenCount"]));
+        let cached = count(&["prompt_tokens_details", "cached_tokens"])
+            .or_else(|| count(&["input_tokens_details", "cached_tokens"]))
+            .or_else(|| count(&["cachedContentTokenCount"]));
+        if let Some(prompt) = prompt {
+            self.input = Some(prompt.saturating_sub(cached.unwrap_or_default()));
+        }
+        if cached.is_some() {
+            self.cached = cached;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn counts(input: u64, cached: u64, output: u64) -> TokenCounts {
+        TokenCounts { input, cached, output }
+    }
+
+    fn stream(chunks: &[&str]) -> Option<(Option<String>, TokenCounts)> {
+        let mut tracker = UsageTracker::default();
+        for chunk in chunks {
+            tracker.observe_stream(chunk.as_bytes());
+        }
+        tracker.finish()
+    }
+
+    #[test]
+    fn chat_completions_count_cached_prompt_tokens_once() {
+        let mut tracker = UsageTracker::default();
+        let body = json!({
+            "model": "gpt-4o",
+            "usage": { "prompt_tokens": 100, "completion_tokens": 20, "prompt_tokens_details": { "cached_tokens": 40 } },
+        });
+        tracker.observe_body(body.to_string().as_bytes());
+        assert_eq!(tracker.finish(), Some((Some("gpt-4o".to_string()), counts(60, 40, 20))));
+
+        let mut tracker = UsageTracker::default();
+        tracker.observe_body(br#"{"choices":[]}"#);
+        assert_eq!(tracker.finish(), None);
+    }
+
+    #[test]
+    fn anthropic_streams_take_input_from_the_start_and_output_from_the_last_delta() {
+        let usage = stream(&[
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"model\":\"claude-x\",\"usage\":",
+            "{\"input_tokens\":10,\"cache_read_input_tokens\":5,\"cache_creation_input_tokens\":2,\"output_tokens\":1}}}\n\n",
+            "data: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\"hi\"}}\n\n",
+            "event: message_delta\ndata: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":30}}\n\n",
+        ]);
+        assert_eq!(usage, Some((Some("claude-x".to_string()), counts(12, 5, 30))));
+    }
+
+    #[test]
+    fn responses_and_gemini_streams_report_usage_at_the_end() {
+        let usage = stream(&[
+            "data: {\"type\":\"response.output_text.delta\",\"delta\":\"hi\"}\n\n",
+            "data: {\"type\":\"response.completed\",\"response\":{\"model\":\"o3\",\"usage\":",
+            "{\"input_tokens\":50,\"input_tokens_details\":{\"cached_tokens\":10},\"output_tokens\":7}}}\n\n",
+        ]);
+        assert_eq!(usage, Some((Some("o3".to_string()), counts(40, 10, 7))));
+
+        // Gemini counts are cumulative; the last line need not end the stream
+        let usage = stream(&[
+            "{\"modelVersion\":\"gemini-2.5\",\"usageMetadata\":{\"promptTokenCount\":8,\"candidatesTokenCount\":3}}\n",
+            "{\"usageMetadata\":{\"promptTokenCount\":8,\"candidatesTokenCount\":9,\"thoughtsTokenCount\":4}}",
+        ]);
+        assert_eq!(usage, Some((Some("gemini-2.5".to_string()), counts(8, 0, 13))));
+    }
+}
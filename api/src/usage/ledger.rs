@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use super::extract::{TokenCounts, UsageTracker};
+use crate::proxy::config::{ModelPrice, ProxyConfig};
+
+/// How often changed totals are written to the store file
+const PERSIST_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Model name of responses that reported usage without one
+const UNKNOWN_MODEL: &str = "unknown";
+
+/// Token totals of an endpoint and model over a day
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct UsageTotals {
+    /// Responses that reported usage
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub cached_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl UsageTotals {
+    pub fn add(&mut self, other: &UsageTotals) {
+        self.requests += other.requests;
+        self.input_tokens += other.input_tokens;
+        self.cached_tokens += other.cached_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+
+    /// Cost in US dollars at `price`
+    pub fn cost(&self, price: &ModelPrice) -> f64 {
+        let cached_price = price.cached_input_per_million.unwrap_or(price.input_per_million);
+        (self.input_tokens as f64 * price.input_per_million
+            + self.cached_tokens as f64 * cached_price
+            + self.output_tokens as f64 * price.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// Totals by endpoint path, then model
+pub type DayTotals = BTreeMap<String, BTreeMap<String, UsageTotals>>;
+
+type Days = BTreeMap<NaiveDate, DayTotals>;
+
+/// Token usage of proxied responses by UTC day, endpoint and model. Kept in
+/// memory, and also in the JSON file `usage.store_path` when configured,
+/// rewritten every few seconds while totals change.
+#[derive(Debug, Default)]
+pub struct UsageLedger {
+    days: Mutex<Days>,
+    path: Option<PathBuf>,
+    /// Totals changed since they were last written
+    dirty: AtomicBool,
+}
+
+impl UsageLedger {
+    /// Ledger of the `usage` section, loading the totals its store file
+    /// already holds
+    pub fn new(config: &ProxyConfig) -> Result<Self, String> {
+        let Some(path) = config.usage.as_ref().and_then(|usage| usage.store_path.as_ref()).map(PathBuf::from) else {
+            return Ok(Self::default());
+        };
+        let days: Days = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| format!("Invalid usage store {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Days::new(),
+            Err(e) => return Err(format!("Could not read usage store {}: {}", path.display(), e)),
+        };
+        info!("Usage store {} holds {} days", path.display(), days.len());
+        Ok(Self { days: Mutex::new(days), path: Some(path), dirty: AtomicBool::new(false) })
+    }
+
+    /// Recorder adding the usage of one response to `path`'s totals
+    pub fn recorder(self: &Arc<Self>, path: &str) -> UsageRecorder {
+        UsageRecorder { ledger: self.clone(), path: path.to_string(), tracker: UsageTracker::default() }
+    }
+
+    fn record(&self, path: &str, model: Option<String>, counts: TokenCounts) {
+        let today = Utc::now().date_naive();
+        let mut days = self.days.lock().unwrap();
+        let totals = days
+            .entry(today)
+            .or_default()
+            .entry(path.to_string())
+            .or_default()
+            .entry(model.unwrap_or_else(|| UNKNOWN_MODEL.to_string()))
+            .or_default();
+        totals.add(&UsageTotals {
+            requests: 1,
+            input_tokens: counts.input,
+            cached_tokens: counts.cached,
+            output_tokens: counts.output,
+        });
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Totals of one day
+    pub fn day(&self, day: NaiveDate) -> DayTotals {
+        self.days.lock().unwrap().get(&day).cloned().unwrap_or_default()
+    }
+
+    /// Totals of the days in `[since, until]`, with the first and last day
+    /// that had any
+    pub fn range(&self, since: Option<NaiveDate>, until: Option<NaiveDate>) -> (Option<(NaiveDate, NaiveDate)>, DayTotals) {
+        let days = self.days.lock().unwrap();
+        let mut first_last = None;
+        let mut totals = DayTotals::new();
+        for (day, day_totals) in days.iter() {
+            if since.is_some_and(|since| *day < since) || until.is_some_and(|until| *day > until) {
+                continue;
+            }
+            first_last = Some(first_last.map_or((*day, *day), |(first, _)| (first, *day)));
+            for (path, models) in day_totals {
+                for (model, model_totals) in models {
+                    totals.entry(path.clone()).or_default().entry(model.clone()).or_default().add(model_totals);
+                }
+            }
+        }
+        (first_last, totals)
+    }
+
+    /// Write the totals to the store file, if any, when they changed,
+    /// through a temporary file so a crash never leaves it half written
+    pub fn persist(&self) {
+        let Some(path) = &self.path else { return };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let contents = serde_json::to_vec(&*self.days.lock().unwrap());
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        let written = contents
+            .map_err(std::io::Error::other)
+            .and_then(|contents| fs::write(&temporary, contents))
+            .and_then(|()| fs::rename(&temporary, path));
+        if let Err(e) = written {
+            error!("Could not write usage store {}: {}", path.display(), e);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Persist changed totals every few seconds
+    pub fn spawn_persistence(self: &Arc<Self>) {
+        if self.path.is_none() {
+            return;
+        }
+        let ledger = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PERSIST_INTERVAL);
+            loop {
+                interval.tick().await;
+                let ledger = ledger.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || ledger.persist()).await {
+                    error!("Usage persistence failed: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// Usage reported in one response, added to the ledger when dropped, so a
+/// stream the client abandons still counts what the upstream reported
+pub struct UsageRecorder {
+    ledger: Arc<UsageLedger>,
+    path: String,
+    tracker: UsageTracker,
+}
+
+impl UsageRecorder {
+    pub fn tracker(&mut self) -> &mut UsageTracker {
+        &mut self.tracker
+    }
+}
+
+impl Drop for UsageRecorder {
+    fn drop(&mut self) {
+        if let Some((model, counts)) = std::mem::take(&mut self.tracker).finish() {
+            self.ledger.record(&self.path, model, counts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{proxy_config, temp_dir};
+
+    fn record(ledger: &Arc<UsageLedger>, path: &str, body: serde_json::Value) {
+        ledger.recorder(path).tracker().observe_body(body.to_string().as_bytes());
+    }
+
+    #[test]
+    fn totals_add_up_per_endpoint_and_model_and_survive_restarts() {
+        let store = temp_dir("usage").join("usage.json");
+        let config = proxy_config(Vec::new(), json!({ "usage": { "store_path": store } }));
+        let ledger = Arc::new(UsageLedger::new(&config).unwrap());
+        let usage = json!({ "prompt_tokens": 10, "completion_tokens": 5 });
+        record(&ledger, "/a", json!({ "model": "m-1", "usage": usage }));
+        record(&ledger, "/a", json!({ "model": "m-1", "usage": usage }));
+        record(&ledger, "/b", json!({ "usage": usage }));
+        record(&ledger, "/b", json!({ "model": "m-1" }));
+
+        let today = Utc::now().date_naive();
+        let day = ledger.day(today);
+        let a = day["/a"]["m-1"];
+        assert_eq!((a.requests, a.input_tokens, a.output_tokens), (2, 20, 10));
+        assert_eq!(day["/b"][UNKNOWN_MODEL].requests, 1, "responses without usage are not counted");
+        assert!(ledger.day(today.pred_opt().unwrap()).is_empty());
+        let (first_last, totals) = ledger.range(Some(today.succ_opt().unwrap()), None);
+        assert!(first_last.is_none() && totals.is_empty());
+        assert_eq!(ledger.range(None, Some(today)).0, Some((today, today)));
+
+        ledger.persist();
+        let restarted = UsageLedger::new(&config).unwrap();
+        assert_eq!(restarted.day(today)["/a"]["m-1"].requests, 2);
+    }
+
+    #[test]
+    fn cached_tokens_fall_back_to_the_input_price() {
+        let totals = UsageTotals { requests: 1, input_tokens: 1_000_000, cached_tokens: 2_000_000, output_tokens: 500_000 };
+        let price = ModelPrice { input_per_million: 2.0, output_per_million: 8.0, cached_input_per_million: Some(0.5) };
+        assert_eq!(totals.cost(&price), 7.0);
+        assert_eq!(totals.cost(&ModelPrice { cached_input_per_million: None, ..price }), 10.0);
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+mod extract;
+mod ledger;
+pub use ledger::{UsageLedger, UsageRecorder};
+use ledger::{DayTotals, UsageTotals};
+
+use crate::error::{ProxyError, create_error_response};
+use crate::proxy::config::UsageConfig;
+use crate::proxy::reload::LiveConfig;
+use crate::request_id::request_id;
+
+#[derive(Clone)]
+struct UsageState {
+    ledger: Arc<UsageLedger>,
+    live: Arc<LiveConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DayQuery {
+    date: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RangeQuery {
+    since: Option<String>,
+    until: Option<String>,
+}
+
+/// Usage routes, priced with the `usage` section of the current configuration
+pub fn router(ledger: Arc<UsageLedger>, live: Arc<LiveConfig>) -> Router {
+    ledger.spawn_persistence();
+    Router::new()
+        .route("/api/usage", get(usage))
+        .route("/api/usage/summary", get(summary))
+        .with_state(UsageState { ledger, live })
+}
+
+/// Totals of one UTC day, today by default
+async fn usage(State(state): State<UsageState>, headers: HeaderMap, Query(query): Query<DayQuery>) -> Response {
+    let day = match query.date.as_deref().map(|date| parse_date("date", date)).transpose() {
+        Ok(day) => day.unwrap_or_else(|| Utc::now().date_naive()),
+        Err(error) => return create_error_response(error, &request_id(&headers)),
+    };
+    let config = state.live.config();
+    let mut report = report(&state.ledger.day(day), config.usage.as_ref());
+    report.insert("date".to_string(), json!(day));
+    Json(report).into_response()
+}
+
+/// Totals of all stored days, or of those in `[since, until]`
+async fn summary(State(state): State<UsageState>, headers: HeaderMap, Query(query): Query<RangeQuery>) -> Response {
+    let parse = |name, value: &Option<String>| value.as_deref().map(|value| parse_date(name, value)).transpose();
+    let (since, until) = match (parse("since", &query.since), parse("until", &query.until)) {
+        (Ok(since), Ok(until)) => (since, until),
+        (Err(error), _) | (_, Err(error)) => return create_error_response(error, &request_id(&headers)),
+    };
+    let (first_last, totals) = state.ledger.range(since, until);
+    let config = state.live.config();
+    let mut report = report(&totals, config.usage.as_ref());
+    report.insert("since".to_string(), json!(first_last.map(|(first, _)| first)));
+    report.insert("until".to_string(), json!(first_last.map(|(_, last)| last)));
+    Json(report).into_response()
+}
+
+fn parse_date(name: &str, value: &str) -> Result<NaiveDate, ProxyError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| ProxyError::InvalidRequest(StatusCode::BAD_REQUEST, format!("{name} must be a YYYY-MM-DD date")))
+}
+
+/// Totals by model and by endpoint and model, each with its estimated cost
+/// (`null` for models without a price), and the estimated cost of all
+/// priced models
+fn report(totals: &DayTotals, config: Option<&UsageConfig>) -> Map<String, Value> {
+    let priced = |model: &str, totals: &UsageTotals| {
+        let cost = config.and_then(|config| config.price(model)).map(|price| totals.cost(price));
+        let mut entry = json!(totals);
+        entry["estimated_cost_usd"] = json!(cost);
+        (entry, cost)
+    };
+
+    let mut by_model: BTreeMap<&str, UsageTotals> = BTreeMap::new();
+    let mut endpoints = Map::new();
+    for (path, models) in totals {
+        let mut endpoint = Map::new();
+        for (model, model_totals) in models {
+            by_model.entry(model).or_default().add(model_totals);
+            endpoint.insert(model.clone(), priced(model, model_totals).0);
+        }
+        endpoints.insert(path.clone(), Value::Object(endpoint));
+    }
+
+    let mut models = Map::new();
+    let mut total_cost = 0.0;
+    for (model, model_totals) in by_model {
+        let (entry, cost) = priced(model, &model_totals);
+        total_cost += cost.unwrap_or_default();
+        models.insert(model.to_string(), entry);
+    }
+
+    let mut report = Map::new();
+    report.insert("models".to_string(), Value::Object(models));
+    report.insert("endpoints".to_string(), Value::Object(endpoints));
+    report.insert("estimated_cost_usd".to_string(), json!(total_cost));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::routing::post;
+
+    use super::*;
+    use crate::test_support::{endpoint, proxy_config, proxy_service, send, spawn_upstream};
+
+    #[tokio::test]
+    async fn proxied_usage_is_reported_by_day_and_priced() {
+        let upstream = spawn_upstream(
+            Router::new()
+                .route("/chat", post(|| async {
+                    Json(json!({
+                        "model": "gpt-4o-mini",
+                        "choices": [],
+                        "usage": { "prompt_tokens": 100, "completion_tokens": 20, "prompt_tokens_details": { "cached_tokens": 40 } },
+                    }))
+                }))
+                .route("/messages", post(|| async {
+                    let events = concat!(
+                        "event: message_start\ndata: {\"message\":{\"model\":\"claude-x\",\"usage\":{\"input_tokens\":7}}}\n\n",
+                        "event: message_delta\ndata: {\"usage\":{\"output_tokens\":3}}\n\n",
+                    );
+                    ([("content-type", "text/event-stream")], events)
+                })),
+        )
+        .await;
+        let endpoints = vec![
+            endpoint(json!({ "path": "/v1/chat", "target_url": format!("{upstream}/chat") })),
+            endpoint(json!({ "path": "/v1/messages", "target_url": format!("{upstream}/messages"), "response_type": "sse" })),
+        ];
+        let prices = json!({ "gpt-4o*": { "input_per_million": 2.5, "output_per_million": 10.0, "cached_input_per_million": 1.25 } });
+        let service = proxy_service(proxy_config(endpoints, json!({ "usage": { "prices": prices } })));
+        let proxy = service.create_router();
+        let usage = router(service.usage(), service.live_config());
+
+        for path in ["/v1/chat", "/v1/chat", "/v1/messages"] {
+            let req = Request::post(path).header("content-type", "application/json").body(Body::from("{}")).unwrap();
+            let (status, _, _) = send(&proxy, req).await;
+            assert_eq!(status, StatusCode::OK, "{path}");
+        }
+        let get = |uri: String| {
+            let usage = usage.clone();
+            async move {
+                let (status, _, body) = send(&usage, Request::get(uri).body(Body::empty()).unwrap()).await;
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+
+        let today = Utc::now().date_naive();
+        let (status, report) = get(format!("/api/usage?date={today}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["date"], json!(today));
+        assert_eq!(
+            report["models"]["gpt-4o-mini"],
+            json!({ "requests": 2, "input_tokens": 120, "cached_tokens": 80, "output_tokens": 40, "estimated_cost_usd": 0.0008 })
+        );
+        assert_eq!(report["endpoints"]["/v1/messages"]["claude-x"]["output_tokens"], 3);
+        assert_eq!(report["models"]["claude-x"]["estimated_cost_usd"], Value::Null);
+        assert_eq!(report["estimated_cost_usd"], 0.0008);
+
+        let (_, summary) = get("/api/usage/summary".to_string()).await;
+        assert_eq!((summary["since"].clone(), summary["until"].clone()), (json!(today), json!(today)));
+        assert_eq!(summary["models"], report["models"]);
+        let (_, yesterday) = get(format!("/api/usage?date={}", today.pred_opt().unwrap())).await;
+        assert_eq!(yesterday["models"], json!({}));
+
+        let (status, error) = get("/api/usage/summary?since=last-week".to_string()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"]["message"], "since must be a YYYY-MM-DD date");
+    }
+}
@@ -4,7 +4,9 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InternalRequest {
     pub method: String,
-    pub params: InternalParams,
+    /// Parameters of `method`, parsed once the method is known
+    #[serde(default)]
+    pub params: serde_json::Value,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -175,4 +177,41 @@ pub struct SystemPromptData {
     pub working_directory: String,
     #[serde(rename = "rootDirectoryListing")]
     pub root_directory_listing: String,
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+impl ThreadData {
+    /// Thread `id` at version `v` with one user message per text
+    pub fn fixture(id: &str, v: u32, texts: &[&str]) -> Self {
+        let messages: Vec<_> = texts
+            .iter()
+            .map(|text| serde_json::json!({ "role": "user", "content": [{ "type": "text", "text": text }] }))
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "v": v,
+            "id": id,
+            "created": 1_700_000_000_000u64,
+            "messages": messages,
+            "env": {
+                "initial": {
+                    "trees": [],
+                    "platform": {
+                        "os": "linux",
+                        "osVersion": "6",
+                        "cpuArchitecture": "x64",
+                        "webBrowser": false,
+                        "client": "test",
+                        "clientVersion": "1",
+                        "clientType": "cli",
+                        "config": { "settings": [], "environment": [] },
+                    },
+                    "interactive": true,
+                    "tags": [],
+                },
+                "systemPromptData": { "workspacePaths": [], "workingDirectory": "/", "rootDirectoryListing": "" },
+            },
+            "title": format!("Thread {id}"),
+        }))
+        .expect("valid thread")
+    }
+}
@@ -1,20 +1,47 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use axum::{
-    Json, Router,
+    Extension, Json, Router,
+    extract::{FromRef, Path, Query, State},
+    handler::Handler,
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 mod internal;
-use internal::InternalRequest;
+mod profile;
+mod store;
+mod sync;
+use internal::{InternalParams, InternalRequest, ThreadData};
+use profile::UserProfiles;
+use store::ThreadStore;
+use sync::{ThreadSharing, thread_action};
 use tracing::debug;
 
+use crate::auth::{ClientIdentity, admin_auth};
+use crate::proxy::config::DefaultUserConfig;
+
+/// Thread store key of callers without a configured client identity
+const DEFAULT_USER_ID: &str = "default";
+
+fn user_id(identity: &Option<Extension<ClientIdentity>>) -> &str {
+    identity.as_ref().map_or(DEFAULT_USER_ID, |Extension(ClientIdentity(user))| user.id.as_str())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct ThreadMeta {
     #[serde(rename = "id")]
     thread_id: Option<String>,
+    /// Sharing settings the client holds; unset counts as not shared
+    #[serde(default)]
+    private: Option<bool>,
+    #[serde(default)]
+    public: Option<bool>,
     #[serde(flatten)]
     extra: HashMap<String, serde_json::Value>,
 }
@@ -29,74 +56,444 @@ struct SyncThreadRequest {
     extra: HashMap<String, serde_json::Value>,
 }
 
-pub fn router() -> Router {
+/// Default and largest page size of `GET /api/threads`
+const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct ThreadListQuery {
+    /// 1-based
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+/// Owner of the thread an admin deletes
+#[derive(Debug, Deserialize)]
+struct ThreadOwnerQuery {
+    /// Client identity id; the caller's own threads when unset
+    user: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ThreadSummary {
+    id: String,
+    title: String,
+    created: u64,
+    message_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct ThreadList {
+    threads: Vec<ThreadSummary>,
+    total: usize,
+    page: usize,
+    per_page: usize,
+}
+
+/// Thread store and user profiles shared by the user routes
+#[derive(Clone)]
+struct UserState {
+    store: Arc<ThreadStore>,
+    profiles: Arc<UserProfiles>,
+}
+
+impl FromRef<UserState> for Arc<ThreadStore> {
+    fn from_ref(state: &UserState) -> Self {
+        state.store.clone()
+    }
+}
+
+impl FromRef<UserState> for Arc<UserProfiles> {
+    fn from_ref(state: &UserState) -> Self {
+        state.profiles.clone()
+    }
+}
+
+/// User and thread routes; fails when the thread store or the user profile
+/// file cannot be loaded
+pub fn router(default_user: &DefaultUserConfig) -> Result<Router, String> {
+    let state = UserState {
+        store: Arc::new(ThreadStore::from_env()?),
+        profiles: Arc::new(UserProfiles::new(default_user)?),
+    };
+    Ok(routes(state))
+}
+
+fn routes(state: UserState) -> Router {
     Router::new()
         .route("/api/user", get(get_user_info))
         .route("/api/connections", get(get_connections))
+        .route("/api/threads", get(list_threads))
         .route("/api/threads/sync", post(sync_thread))
+        .route("/api/threads/{id}", get(get_thread).delete(delete_thread.layer(middleware::from_fn(admin_auth))))
         .route("/api/internal", post(internal))
+        .with_state(state)
 }
 
-async fn get_user_info() -> Json<serde_json::Value> {
-    Json(json!(
-        {
-            "id": ulid::Ulid::new().to_string(),
-            "username": "USER_001",
-            "email": "user_001@any.com",
-            "firstName": "Any",
-            "lastName": "User",
-            "emailVerified": true,
-            "profilePictureUrl": "https://picsum.photos/200",
-            "lastSignInAt": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
-            "createdAt": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
-            "updatedAt": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
-            "siteAdmin": true
-        }
-    ))
+async fn get_user_info(
+    State(profiles): State<Arc<UserProfiles>>,
+    identity: Option<Extension<ClientIdentity>>,
+) -> Json<serde_json::Value> {
+    Json(profiles.get(identity.as_ref().map(|Extension(identity)| identity)))
 }
 
 async fn get_connections() -> Json<serde_json::Value> {
     Json(json!([]))
 }
 
-async fn sync_thread(Json(request): Json<SyncThreadRequest>) -> Json<serde_json::Value> {
-    let thread_id = if let Some(Some(thread_meta)) = request.thread_metas.first()
-        && let Some(thread_id) = &thread_meta.thread_id
-    {
-        thread_id
-    } else {
-        return Json(json!(
-            {
-                "threadActions": [],
-            }
-        ));
-    };
-    Json(json!(
-        {
-            "threadActions": [
-                {
-                    "id": thread_id,
-                    "action": "meta",
-                    "meta": {
-                        "private": false,
-                        "public": false,
-                    }
-                }
-            ],
+async fn sync_thread(
+    State(store): State<Arc<ThreadStore>>,
+    identity: Option<Extension<ClientIdentity>>,
+    Json(request): Json<SyncThreadRequest>,
+) -> Json<serde_json::Value> {
+    let user_id = user_id(&identity);
+    if request.thread_versions.len() != request.thread_metas.len() {
+        debug!(
+            "Thread sync with mismatched lengths: {} versions, {} metas",
+            request.thread_versions.len(),
+            request.thread_metas.len()
+        );
+    }
+
+    // Versions and metas are paired by index; a thread without a meta entry
+    // has no id and cannot be matched
+    let mut thread_actions = Vec::new();
+    for (index, meta) in request.thread_metas.iter().enumerate() {
+        let Some(meta) = meta else { continue };
+        let Some(thread_id) = &meta.thread_id else { continue };
+        let client_version = request
+            .thread_versions
+            .get(index)
+            .and_then(|version| version.parse::<u64>().ok());
+
+        let sharing = ThreadSharing { private: meta.private.unwrap_or(false), public: meta.public.unwrap_or(false) };
+        let stored = store.lookup(user_id, thread_id);
+        if let Some(action) = thread_action(thread_id, client_version, sharing, &stored) {
+            thread_actions.push(json!(action));
         }
-    ))
+    }
+
+    Json(json!({ "threadActions": thread_actions }))
 }
 
-async fn internal(Json(request): Json<InternalRequest>) -> Json<serde_json::Value> {
+/// Uploaded threads of the caller, newest first, a page at a time
+async fn list_threads(
+    State(store): State<Arc<ThreadStore>>,
+    identity: Option<Extension<ClientIdentity>>,
+    Query(query): Query<ThreadListQuery>,
+) -> Json<ThreadList> {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+
+    let threads = store.list(user_id(&identity));
+    let total = threads.len();
+    let threads = threads
+        .into_iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .map(|(id, record)| ThreadSummary {
+            id,
+            title: record.title,
+            created: record.created,
+            message_count: record.message_count,
+        })
+        .collect();
+
+    Json(ThreadList { threads, total, page, per_page })
+}
+
+/// A thread of the caller as last uploaded
+async fn get_thread(
+    State(store): State<Arc<ThreadStore>>,
+    identity: Option<Extension<ClientIdentity>>,
+    Path(id): Path<String>,
+) -> Result<Json<Arc<ThreadData>>, StatusCode> {
+    store
+        .get(user_id(&identity), &id)
+        .and_then(|record| record.thread)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Forget a thread of the user named by the `user` query parameter, so an
+/// admin can delete the threads of any client
+async fn delete_thread(
+    State(store): State<Arc<ThreadStore>>,
+    identity: Option<Extension<ClientIdentity>>,
+    Path(id): Path<String>,
+    Query(owner): Query<ThreadOwnerQuery>,
+) -> StatusCode {
+    let user_id = owner.user.as_deref().unwrap_or_else(|| user_id(&identity));
+    if store.remove(user_id, &id) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Internal methods: `uploadThread` stores the thread, `getUser` answers the
+/// same profile as `/api/user`; others are acknowledged
+async fn internal(
+    State(store): State<Arc<ThreadStore>>,
+    State(profiles): State<Arc<UserProfiles>>,
+    identity: Option<Extension<ClientIdentity>>,
+    Json(request): Json<InternalRequest>,
+) -> Response {
     match request.method.as_str() {
         "uploadThread" => {
-            let thread_data = &request.params.thread;
+            let params: InternalParams = match serde_json::from_value(request.params) {
+                Ok(params) => params,
+                Err(e) => {
+                    let error = json!({"ok": false, "error": format!("Invalid uploadThread params: {e}")});
+                    return (StatusCode::UNPROCESSABLE_ENTITY, Json(error)).into_response();
+                }
+            };
+            let thread_data = params.thread;
             debug!("Received thread upload request: ID={}, Title={}, Message count={}", thread_data.id, thread_data.title, thread_data.messages.len());
-            
-            Json(json!({"ok": true}))
+            store.record_upload(user_id(&identity), thread_data);
+
+            Json(json!({"ok": true})).into_response()
+        }
+        "getUser" => {
+            let user = profiles.get(identity.as_ref().map(|Extension(identity)| identity));
+            Json(json!({"ok": true, "result": user})).into_response()
         }
         _ => {
-            Json(json!({"ok": true}))
+            Json(json!({"ok": true})).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+
+    use crate::proxy::config::UserProfile;
+    use crate::test_support::{ADMIN_TOKEN, init_admin_token, send};
+
+    fn client(id: &str) -> ClientIdentity {
+        ClientIdentity(Arc::new(UserProfile {
+            id: id.to_string(),
+            username: id.to_string(),
+            email: format!("{id}@example.com"),
+            first_name: None,
+            last_name: None,
+        }))
+    }
+
+    fn user_routes() -> (Router, Arc<ThreadStore>) {
+        let store = Arc::new(ThreadStore::default());
+        let profiles = Arc::new(UserProfiles::new(&DefaultUserConfig::default()).unwrap());
+        (routes(UserState { store: store.clone(), profiles }), store)
+    }
+
+    fn request(method: &str, uri: &str, identity: Option<ClientIdentity>) -> Request<Body> {
+        let mut req = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        if let Some(identity) = identity {
+            req.extensions_mut().insert(identity);
+        }
+        req
+    }
+
+    fn json_request(method: &str, uri: &str, body: serde_json::Value) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn sync(router: &Router, body: serde_json::Value) -> serde_json::Value {
+        let (status, _, body) = send(router, json_request("POST", "/api/threads/sync", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["threadActions"].clone()
+    }
+
+    #[tokio::test]
+    async fn sync_answers_one_action_per_thread() {
+        let (router, store) = user_routes();
+        store.record_upload(DEFAULT_USER_ID, ThreadData::fixture("T-known", 2, &["a"]));
+        store.record_upload(DEFAULT_USER_ID, ThreadData::fixture("T-gone", 1, &["a"]));
+        store.remove(DEFAULT_USER_ID, "T-gone");
+        // Threads of other clients are unknown to the caller
+        store.record_upload("alice", ThreadData::fixture("T-alice", 1, &["a"]));
+
+        let cases = [
+            (json!({ "threadVersions": [], "threadMetas": [] }), json!([])),
+            (
+                json!({ "threadVersions": ["1", "4"], "threadMetas": [{ "id": "T-new" }, { "id": "T-alice" }] }),
+                json!([{ "id": "T-new", "action": "upload" }, { "id": "T-alice", "action": "upload" }]),
+            ),
+            (
+                json!({
+                    "threadVersions": ["2", "1", "1", "9"],
+                    "threadMetas": [{ "id": "T-known" }, null, { "id": "T-gone" }, { "id": "T-new" }],
+                }),
+                json!([{ "id": "T-gone", "action": "delete" }, { "id": "T-new", "action": "upload" }]),
+            ),
+            // Versions and metas of different lengths are paired as far as
+            // both go; a thread without a version is updated from scratch
+            (
+                json!({ "threadVersions": ["7"], "threadMetas": [{ "id": "T-new" }, { "id": "T-known" }] }),
+                json!([
+                    { "id": "T-new", "action": "upload" },
+                    { "id": "T-known", "action": "update", "diff": {
+                        "fromVersion": null,
+                        "toVersion": 2,
+                        "title": "Thread T-known",
+                        "fromIndex": 0,
+                        "messages": [{ "role": "user", "content": [{ "type": "text", "text": "a" }] }],
+                    } },
+                ]),
+            ),
+            (json!({ "threadVersions": ["1", "2"], "threadMetas": [] }), json!([])),
+        ];
+        for (request, actions) in cases {
+            assert_eq!(sync(&router, request.clone()).await, actions, "{request}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unchanged_threads_need_no_action() {
+        let (router, _) = user_routes();
+        let request = json!({ "threadVersions": ["3"], "threadMetas": [{ "id": "T-1" }] });
+        assert_eq!(sync(&router, request.clone()).await, json!([{ "id": "T-1", "action": "upload" }]));
+
+        let upload = json!({
+            "method": "uploadThread",
+            "params": { "thread": ThreadData::fixture("T-1", 3, &["hello"]), "createdOnServer": false },
+        });
+        let (status, _, _) = send(&router, json_request("POST", "/api/internal", upload)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(sync(&router, request).await, json!([]));
+        let shared = json!({ "threadVersions": ["3"], "threadMetas": [{ "id": "T-1", "public": true }] });
+        assert_eq!(
+            sync(&router, shared).await,
+            json!([{ "id": "T-1", "action": "meta", "meta": { "private": false, "public": false } }])
+        );
+    }
+
+    #[tokio::test]
+    async fn admins_delete_the_threads_of_other_clients() {
+        init_admin_token();
+        let (router, store) = user_routes();
+        store.record_upload("alice", ThreadData::fixture("T-1", 1, &["hello"]));
+        store.record_upload(DEFAULT_USER_ID, ThreadData::fixture("T-2", 1, &["hello"]));
+        let admin = |method, uri| {
+            let mut req = request(method, uri, None);
+            req.headers_mut().insert("authorization", format!("Bearer {ADMIN_TOKEN}").parse().unwrap());
+            req
+        };
+
+        // Without an owner the admin's own, default, threads are searched
+        let (status, _, _) = send(&router, admin("DELETE", "/api/threads/T-1")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _, _) = send(&router, request("GET", "/api/threads/T-1", Some(client("alice")))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _, _) = send(&router, admin("DELETE", "/api/threads/T-1?user=alice")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _, _) = send(&router, request("GET", "/api/threads/T-1", Some(client("alice")))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _, _) = send(&router, admin("DELETE", "/api/threads/T-1?user=alice")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _, _) = send(&router, admin("DELETE", "/api/threads/T-2")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        // Clients cannot delete threads, not even their own
+        store.record_upload("alice", ThreadData::fixture("T-3", 1, &["hello"]));
+        let (status, _, _) = send(&router, request("DELETE", "/api/threads/T-3?user=alice", Some(client("alice")))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(store.get("alice", "T-3").is_some());
+    }
+
+    #[tokio::test]
+    async fn threads_are_listed_newest_first_a_page_at_a_time() {
+        let (router, store) = user_routes();
+        for n in 1..=5u64 {
+            let texts: &[&str] = if n % 2 == 1 { &["hello", "again"] } else { &["hello"] };
+            let mut thread = ThreadData::fixture(&format!("T-{n}"), 1, texts);
+            thread.created = 1_700_000_000_000 + n;
+            store.record_upload(DEFAULT_USER_ID, thread);
         }
+        store.record_upload("alice", ThreadData::fixture("T-alice", 1, &["hello"]));
+        let list = |uri: &'static str| {
+            let router = router.clone();
+            async move {
+                let (status, _, body) = send(&router, request("GET", uri, None)).await;
+                assert_eq!(status, StatusCode::OK, "{uri}");
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+        let ids = |list: &serde_json::Value| -> Vec<String> {
+            list["threads"].as_array().unwrap().iter().map(|thread| thread["id"].as_str().unwrap().to_string()).collect()
+        };
+
+        let first = list("/api/threads?page=1&per_page=2").await;
+        assert_eq!((first["total"].clone(), first["page"].clone(), first["per_page"].clone()), (json!(5), json!(1), json!(2)));
+        assert_eq!(ids(&first), ["T-5", "T-4"]);
+        assert_eq!(
+            first["threads"][0],
+            json!({ "id": "T-5", "title": "Thread T-5", "created": 1_700_000_000_005u64, "message_count": 2 })
+        );
+        assert_eq!(ids(&list("/api/threads?page=3&per_page=2").await), ["T-1"]);
+        assert!(ids(&list("/api/threads?page=4&per_page=2").await).is_empty());
+
+        // Out-of-range parameters are brought back into range
+        let clamped = list("/api/threads?page=0&per_page=1000").await;
+        assert_eq!((clamped["page"].clone(), clamped["per_page"].clone()), (json!(1), json!(MAX_PER_PAGE)));
+        assert_eq!(ids(&clamped).len(), 5);
+
+        store.remove(DEFAULT_USER_ID, "T-5");
+        let after_delete = list("/api/threads").await;
+        assert_eq!((after_delete["total"].clone(), after_delete["per_page"].clone()), (json!(4), json!(DEFAULT_PER_PAGE)));
+        assert_eq!(ids(&after_delete), ["T-4", "T-3", "T-2", "T-1"]);
+    }
+
+    #[tokio::test]
+    async fn uploaded_threads_are_read_back_with_their_messages() {
+        let (router, _) = user_routes();
+        let thread = serde_json::to_value(ThreadData::fixture("T-1", 4, &["one", "two", "three"])).unwrap();
+        let upload = json!({ "method": "uploadThread", "params": { "thread": thread, "createdOnServer": false } });
+        let (status, _, body) = send(&router, json_request("POST", "/api/internal", upload)).await;
+        assert_eq!((status, serde_json::from_slice::<serde_json::Value>(&body).unwrap()), (StatusCode::OK, json!({ "ok": true })));
+
+        let (status, _, body) = send(&router, request("GET", "/api/threads/T-1", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let fetched: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(fetched, thread);
+        assert_eq!(fetched["messages"].as_array().unwrap().len(), 3);
+
+        // Threads are only read back by the client that uploaded them
+        let (status, _, _) = send(&router, request("GET", "/api/threads/T-1", Some(client("alice")))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let invalid = json!({ "method": "uploadThread", "params": { "thread": { "id": "T-2" } } });
+        let (status, _, _) = send(&router, json_request("POST", "/api/internal", invalid)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn the_user_and_get_user_answer_the_same_profile_every_time() {
+        let (router, _) = user_routes();
+        let user = || async {
+            let (status, _, body) = send(&router, request("GET", "/api/user", None)).await;
+            assert_eq!(status, StatusCode::OK);
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let first = user().await;
+        assert!(first["id"].as_str().is_some_and(|id| !id.is_empty()));
+        assert_eq!(user().await, first);
+
+        let get_user = json!({ "method": "getUser", "params": {} });
+        let (status, _, body) = send(&router, json_request("POST", "/api/internal", get_user)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), json!({ "ok": true, "result": first }));
+    }
+}
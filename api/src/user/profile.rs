@@ -0,0 +1,193 @@
+use std::env;
+
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::auth::ClientIdentity;
+use crate::proxy::config::DefaultUserConfig;
+use crate::proxy::interpolate::interpolate;
+
+/// Default user profile read from the JSON file `AMP_USER_PROFILE_PATH`, in
+/// the shape `/api/user` answers. Its fields take precedence over the `user`
+/// section and the `AMP_USER_*` variables; fields not listed here are added
+/// to the profile as given.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserProfileFile {
+    pub id: Option<String>,
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub email_verified: Option<bool>,
+    pub site_admin: Option<bool>,
+    /// Plan type, such as `"pro"`, or an object with the plan and its limits
+    pub plan: Option<Value>,
+    pub subscriptions: Option<Vec<Value>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl UserProfileFile {
+    /// Load a profile from a JSON file, expanding `${VAR}` references first
+    /// like `ProxyConfig::load_from_file`
+    pub fn load_from_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("Could not read user profile {path}: {e}"))?;
+        let content = interpolate(&content, &|name| env::var(name).ok()).map_err(|e| format!("User profile {path}: {e}"))?;
+        serde_json::from_str(&content).map_err(|e| format!("Invalid user profile {path}: {e}"))
+    }
+
+    /// The profile named by `AMP_USER_PROFILE_PATH`; empty when the variable
+    /// is unset or the file does not exist
+    fn from_env() -> Result<Self, String> {
+        let Some(path) = env::var("AMP_USER_PROFILE_PATH").ok().filter(|path| !path.is_empty()) else {
+            return Ok(Self::default());
+        };
+        if !std::path::Path::new(&path).exists() {
+            warn!("User profile {} does not exist, using the default user", path);
+            return Ok(Self::default());
+        }
+        let profile = Self::load_from_file(&path)?;
+        info!("Loaded the default user profile from {}", path);
+        Ok(profile)
+    }
+}
+
+/// Profiles answered by `/api/user` and the `getUser` internal method. The
+/// default user's id and every timestamp are fixed at startup, so clients
+/// comparing them across requests see the same user.
+pub struct UserProfiles {
+    default: Value,
+    /// Server start time, reported as the users' creation and sign-in time
+    started: String,
+}
+
+impl UserProfiles {
+    /// Fails when `AMP_USER_PROFILE_PATH` names a file that cannot be read or parsed
+    pub fn new(config: &DefaultUserConfig) -> Result<Self, String> {
+        let file = UserProfileFile::from_env()?;
+        let started = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let var = |name| env::var(name).ok().filter(|value: &String| !value.is_empty());
+
+        let configured_username = file.username.clone().or_else(|| var("AMP_USER_NAME")).or_else(|| config.username.clone());
+        let username = configured_username.clone().unwrap_or_else(|| "USER_001".to_string());
+        let email = file
+            .email
+            .clone()
+            .or_else(|| var("AMP_USER_EMAIL"))
+            .or_else(|| config.email.clone())
+            .unwrap_or_else(|| "user_001@any.com".to_string());
+        let (first_name, last_name) = match var("AMP_USER_DISPLAY_NAME") {
+            Some(display_name) => match display_name.split_once(' ') {
+                Some((first, last)) => (first.to_string(), last.trim().to_string()),
+                None => (display_name, String::new()),
+            },
+            None => (
+                config.first_name.clone().unwrap_or_else(|| "Any".to_string()),
+                config.last_name.clone().unwrap_or_else(|| "User".to_string()),
+            ),
+        };
+        let first_name = file.first_name.unwrap_or(first_name);
+        let last_name = file.last_name.unwrap_or(last_name);
+        let id = match (file.id.as_ref().or(config.id.as_ref()), &configured_username) {
+            (Some(id), _) => id.clone(),
+            (None, Some(username)) => derived_id(username),
+            (None, None) => ulid::Ulid::new().to_string(),
+        };
+
+        let mut default = profile(&id, &username, &email, &first_name, &last_name, &started);
+        if let Value::Object(fields) = &mut default {
+            fields.extend(config.extra.clone());
+            let overrides = [
+                ("emailVerified", file.email_verified.map(Value::Bool)),
+                ("siteAdmin", file.site_admin.map(Value::Bool)),
+                ("plan", file.plan),
+                ("subscriptions", file.subscriptions.map(Value::Array)),
+            ];
+            for (name, value) in overrides {
+                if let Some(value) = value {
+                    fields.insert(name.to_string(), value);
+                }
+            }
+            fields.extend(file.extra);
+        }
+        Ok(Self { default, started })
+    }
+
+    /// Profile of the caller: its configured client identity, or the default user
+    pub fn get(&self, identity: Option<&ClientIdentity>) -> Value {
+        match identity {
+            Some(ClientIdentity(user)) => profile(
+                &user.id,
+                &user.username,
+                &user.email,
+                user.first_name.as_deref().unwrap_or(&user.username),
+                user.last_name.as_deref().unwrap_or_default(),
+                &self.started,
+            ),
+            None => self.default.clone(),
+        }
+    }
+}
+
+/// ULID-shaped id hashed from a configured username, so the user keeps its
+/// id across restarts
+fn derived_id(username: &str) -> String {
+    let digest = Sha256::digest(username.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    ulid::Ulid::from_bytes(bytes).to_string()
+}
+
+fn profile(id: &str, username: &str, email: &str, first_name: &str, last_name: &str, started: &str) -> Value {
+    json!(
+        {
+            "id": id,
+            "username": username,
+            "email": email,
+            "firstName": first_name,
+            "lastName": last_name,
+            "emailVerified": true,
+            "profilePictureUrl": "https://picsum.photos/200",
+            "lastSignInAt": started,
+            "createdAt": started,
+            "updatedAt": started,
+            "siteAdmin": true
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(username: &str) -> DefaultUserConfig {
+        let mut extra = Map::new();
+        extra.insert("plan".to_string(), json!({ "type": "pro", "limit": 100 }));
+        DefaultUserConfig { username: Some(username.to_string()), extra, ..DefaultUserConfig::default() }
+    }
+
+    #[test]
+    fn configured_users_keep_their_id_across_restarts() {
+        let profile = UserProfiles::new(&user("alice")).unwrap().get(None);
+        let restarted = UserProfiles::new(&user("alice")).unwrap().get(None);
+        assert_eq!(profile["id"], restarted["id"]);
+        assert_ne!(profile["id"], UserProfiles::new(&user("bob")).unwrap().get(None)["id"]);
+        assert_eq!((profile["username"].as_str(), profile["plan"]["type"].as_str()), (Some("alice"), Some("pro")));
+
+        let pinned = DefaultUserConfig { id: Some("U-1".to_string()), ..user("alice") };
+        assert_eq!(UserProfiles::new(&pinned).unwrap().get(None)["id"], "U-1");
+    }
+
+    #[test]
+    fn timestamps_are_the_start_time() {
+        let profiles = UserProfiles::new(&DefaultUserConfig::default()).unwrap();
+        let profile = profiles.get(None);
+        assert_eq!(profile["createdAt"], profiles.started);
+        assert_eq!(profile["lastSignInAt"], profiles.started);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(profiles.get(None), profile);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+use super::internal::{ThreadData, ThreadMessage};
+
+/// Uploaded versions of a thread remembered for computing sync diffs
+const MAX_VERSION_SNAPSHOTS: usize = 32;
+
+/// Server-side state of a thread
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ThreadRecord {
+    pub version: u64,
+    pub private: bool,
+    pub public: bool,
+    pub title: String,
+    /// Creation time, in milliseconds since the epoch, as sent by the client
+    pub created: u64,
+    pub message_count: usize,
+    /// The thread as last uploaded
+    #[serde(default)]
+    pub thread: Option<Arc<ThreadData>>,
+    /// Messages of the most recently uploaded versions, oldest first
+    #[serde(default)]
+    pub versions: Vec<VersionSnapshot>,
+    /// Deleted on the server; kept so syncing clients learn of the deletion
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// Messages of an uploaded thread version, kept as a count and a digest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionSnapshot {
+    pub version: u64,
+    pub message_count: usize,
+    pub digest: String,
+}
+
+/// Whether a user's thread is stored
+pub enum ThreadLookup {
+    Stored(ThreadRecord),
+    Deleted,
+    Unknown,
+}
+
+/// Hex SHA-256 of the messages, each serialized in turn
+pub fn messages_digest(messages: &[ThreadMessage]) -> String {
+    let mut hasher = Sha256::new();
+    for message in messages {
+        if let Ok(message) = serde_json::to_vec(message) {
+            hasher.update(&message);
+        }
+        hasher.update(b"\n");
+    }
+    hex::encode(hasher.finalize())
+}
+
+type Threads = HashMap<String, HashMap<String, ThreadRecord>>;
+
+/// Thread state keyed by user id, then thread id, so users never see each
+/// other's threads. Kept in memory, and also in a JSON file when opened with
+/// a path.
+#[derive(Debug, Default)]
+pub struct ThreadStore {
+    threads: RwLock<Threads>,
+    path: Option<PathBuf>,
+}
+
+impl ThreadStore {
+    /// Store backed by the JSON file `THREAD_STORE_PATH` when it is set,
+    /// loading the threads it already holds; in memory only otherwise
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var_os("THREAD_STORE_PATH") {
+            Some(path) => Self::open(PathBuf::from(path)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Store backed by the JSON file at `path`, which need not exist yet
+    pub fn open(path: PathBuf) -> Result<Self, String> {
+        let threads: Threads = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| format!("Invalid thread store {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Threads::new(),
+            Err(e) => return Err(format!("Could not read thread store {}: {}", path.display(), e)),
+        };
+        info!(
+            "Thread store {} holds {} threads",
+            path.display(),
+            threads.values().map(HashMap::len).sum::<usize>()
+        );
+        Ok(Self { threads: RwLock::new(threads), path: Some(path) })
+    }
+
+    pub fn get(&self, user_id: &str, id: &str) -> Option<ThreadRecord> {
+        self.threads.read().unwrap().get(user_id)?.get(id).filter(|record| !record.deleted).cloned()
+    }
+
+    /// A thread of a user, telling deleted threads apart from unknown ones
+    pub fn lookup(&self, user_id: &str, id: &str) -> ThreadLookup {
+        match self.threads.read().unwrap().get(user_id).and_then(|threads| threads.get(id)) {
+            Some(record) if record.deleted => ThreadLookup::Deleted,
+            Some(record) => ThreadLookup::Stored(record.clone()),
+            None => ThreadLookup::Unknown,
+        }
+    }
+
+    /// Record an uploaded thread, keeping any existing sharing flags. A
+    /// deleted thread uploaded again is restored.
+    pub fn record_upload(&self, user_id: &str, thread: ThreadData) {
+        let mut threads = self.threads.write().unwrap();
+        let record = threads
+            .entry(user_id.to_string())
+            .or_default()
+            .entry(thread.id.clone())
+            .or_default();
+        record.version = u64::from(thread.v);
+        record.title = thread.title.clone();
+        record.created = thread.created;
+        record.message_count = thread.messages.len();
+        record.deleted = false;
+        record.versions.retain(|snapshot| snapshot.version != record.version);
+        record.versions.push(VersionSnapshot {
+            version: record.version,
+            message_count: thread.messages.len(),
+            digest: messages_digest(&thread.messages),
+        });
+        let excess = record.versions.len().saturating_sub(MAX_VERSION_SNAPSHOTS);
+        record.versions.drain(..excess);
+        record.thread = Some(Arc::new(thread));
+        self.persist(&threads);
+    }
+
+    /// All threads of a user with their ids, newest first
+    pub fn list(&self, user_id: &str) -> Vec<(String, ThreadRecord)> {
+        let threads = self.threads.read().unwrap();
+        let mut list: Vec<_> = threads
+            .get(user_id)
+            .map(|threads| {
+                threads
+                    .iter()
+                    .filter(|(_, record)| !record.deleted)
+                    .map(|(id, record)| (id.clone(), record.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        list.sort_by(|(a_id, a), (b_id, b)| b.created.cmp(&a.created).then_with(|| a_id.cmp(b_id)));
+        list
+    }
+
+    /// Forget a thread, keeping only the fact that it was deleted; `false`
+    /// when the user has no thread with that id
+    pub fn remove(&self, user_id: &str, id: &str) -> bool {
+        let mut threads = self.threads.write().unwrap();
+        let Some(record) = threads.get_mut(user_id).and_then(|threads| threads.get_mut(id)).filter(|record| !record.deleted)
+        else {
+            return false;
+        };
+        *record = ThreadRecord { version: record.version, deleted: true, ..ThreadRecord::default() };
+        self.persist(&threads);
+        true
+    }
+
+    /// Rewrite the backing file, if any, through a temporary file so a crash
+    /// never leaves it half written. Failures are logged and the in-memory
+    /// state kept.
+    fn persist(&self, threads: &Threads) {
+        let Some(path) = &self.path else { return };
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        let written = serde_json::to_vec(threads)
+            .map_err(std::io::Error::other)
+            .and_then(|contents| fs::write(&temporary, contents))
+            .and_then(|()| fs::rename(&temporary, path));
+        if let Err(e) = written {
+            error!("Could not write thread store {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn threads_survive_reopening_the_backing_file() {
+        let path = temp_dir("thread-store").join("threads.json");
+        let store = ThreadStore::open(path.clone()).unwrap();
+        store.record_upload("alice", ThreadData::fixture("T-1", 2, &["hello", "again"]));
+        store.record_upload("alice", ThreadData::fixture("T-2", 1, &["bye"]));
+        assert!(store.remove("alice", "T-2"));
+
+        let reopened = ThreadStore::open(path.clone()).unwrap();
+        let record = reopened.get("alice", "T-1").expect("the upload was persisted");
+        assert_eq!((record.version, record.message_count), (2, 2));
+        assert_eq!(record.thread.unwrap().messages.len(), 2);
+        assert!(matches!(reopened.lookup("alice", "T-2"), ThreadLookup::Deleted));
+        assert!(reopened.get("bob", "T-1").is_none());
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(ThreadStore::open(path).unwrap_err().starts_with("Invalid thread store"));
+    }
+}
@@ -0,0 +1,199 @@
+use serde::Serialize;
+
+use super::internal::ThreadMessage;
+use super::store::{ThreadLookup, ThreadRecord, messages_digest};
+
+/// What a client must do to bring one of its threads in line with the server
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum ThreadAction<'a> {
+    /// The server lacks the thread or has an older version; the client
+    /// uploads its own
+    Upload { id: &'a str },
+    /// The server has a newer version; the client applies the diff
+    Update { id: &'a str, diff: ThreadDiff<'a> },
+    /// The thread was deleted on the server
+    Delete { id: &'a str },
+    /// Both have the same version but different sharing settings; the
+    /// client takes the server's
+    Meta { id: &'a str, meta: ThreadSharing },
+}
+
+/// Messages taking a client from `from_version` to `to_version`: it keeps
+/// its first `from_index` messages and replaces the rest with `messages`.
+/// `from_index` is 0 when the client's version is unknown to the server or
+/// its messages were edited since.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadDiff<'a> {
+    pub from_version: Option<u64>,
+    pub to_version: u64,
+    pub title: &'a str,
+    pub from_index: usize,
+    pub messages: &'a [ThreadMessage],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ThreadSharing {
+    pub private: bool,
+    pub public: bool,
+}
+
+/// Action for a thread the client holds at `client_version` (`None` when it
+/// sent no valid version) with `client_sharing`, given what the server stores
+/// under its id; `None` when the client is up to date
+pub fn thread_action<'a>(
+    id: &'a str,
+    client_version: Option<u64>,
+    client_sharing: ThreadSharing,
+    stored: &'a ThreadLookup,
+) -> Option<ThreadAction<'a>> {
+    let record = match stored {
+        ThreadLookup::Stored(record) => record,
+        ThreadLookup::Deleted => return Some(ThreadAction::Delete { id }),
+        ThreadLookup::Unknown => return Some(ThreadAction::Upload { id }),
+    };
+    let Some(thread) = &record.thread else {
+        // Recorded before uploads were kept; only the client has the messages
+        return Some(ThreadAction::Upload { id });
+    };
+
+    let action = match client_version {
+        Some(version) if version > record.version => ThreadAction::Upload { id },
+        Some(version) if version == record.version => {
+            let sharing = ThreadSharing { private: record.private, public: record.public };
+            if sharing == client_sharing {
+                return None;
+            }
+            ThreadAction::Meta { id, meta: sharing }
+        }
+        _ => {
+            let from_index = shared_prefix(record, client_version, &thread.messages);
+            ThreadAction::Update {
+                id,
+                diff: ThreadDiff {
+                    from_version: client_version,
+                    to_version: record.version,
+                    title: &thread.title,
+                    from_index,
+                    messages: &thread.messages[from_index..],
+                },
+            }
+        }
+    };
+    Some(action)
+}
+
+/// Number of leading messages the client's version still shares with the
+/// stored one: all of its messages when they are unchanged, none otherwise
+fn shared_prefix(record: &ThreadRecord, client_version: Option<u64>, messages: &[ThreadMessage]) -> usize {
+    let Some(snapshot) = record.versions.iter().rev().find(|snapshot| Some(snapshot.version) == client_version) else {
+        return 0;
+    };
+    match messages.get(..snapshot.message_count) {
+        Some(prefix) if messages_digest(prefix) == snapshot.digest => snapshot.message_count,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::internal::ThreadData;
+    use crate::user::store::ThreadStore;
+
+    const NOT_SHARED: ThreadSharing = ThreadSharing { private: false, public: false };
+
+    fn action(client_version: Option<u64>, stored: &ThreadLookup) -> serde_json::Value {
+        serde_json::to_value(thread_action("T-1", client_version, NOT_SHARED, stored)).unwrap()
+    }
+
+    fn texts(texts: &[&str]) -> serde_json::Value {
+        texts.iter().map(|text| serde_json::json!({ "role": "user", "content": [{ "type": "text", "text": text }] })).collect()
+    }
+
+    #[test]
+    fn threads_the_server_lacks_or_has_older_are_uploaded() {
+        let upload = serde_json::json!({ "id": "T-1", "action": "upload" });
+        assert_eq!(action(Some(1), &ThreadLookup::Unknown), upload);
+        assert_eq!(action(None, &ThreadLookup::Unknown), upload);
+
+        let store = ThreadStore::default();
+        store.record_upload("u", ThreadData::fixture("T-1", 2, &["a"]));
+        assert_eq!(action(Some(3), &store.lookup("u", "T-1")), upload);
+
+        // Recorded before uploads were kept
+        let record = ThreadRecord { version: 5, ..ThreadRecord::default() };
+        assert_eq!(action(Some(1), &ThreadLookup::Stored(record)), upload);
+    }
+
+    #[test]
+    fn threads_deleted_on_the_server_are_deleted() {
+        let store = ThreadStore::default();
+        store.record_upload("u", ThreadData::fixture("T-1", 2, &["a"]));
+        store.remove("u", "T-1");
+        for version in [Some(1), Some(2), Some(3), None] {
+            assert_eq!(action(version, &store.lookup("u", "T-1")), serde_json::json!({ "id": "T-1", "action": "delete" }));
+        }
+    }
+
+    #[test]
+    fn newer_server_threads_are_sent_as_diffs() {
+        let store = ThreadStore::default();
+        store.record_upload("u", ThreadData::fixture("T-1", 1, &["a"]));
+        store.record_upload("u", ThreadData::fixture("T-1", 2, &["a", "b"]));
+        store.record_upload("u", ThreadData::fixture("T-1", 3, &["a", "b", "c"]));
+        let stored = store.lookup("u", "T-1");
+        let update = |from_version: Option<u64>, from_index: usize, messages: &[&str]| {
+            serde_json::json!({ "id": "T-1", "action": "update", "diff": {
+                "fromVersion": from_version,
+                "toVersion": 3,
+                "title": "Thread T-1",
+                "fromIndex": from_index,
+                "messages": texts(messages),
+            } })
+        };
+
+        assert_eq!(action(Some(1), &stored), update(Some(1), 1, &["b", "c"]));
+        assert_eq!(action(Some(2), &stored), update(Some(2), 2, &["c"]));
+        // Versions the server never saw, or none, start from scratch
+        assert_eq!(action(Some(0), &stored), update(Some(0), 0, &["a", "b", "c"]));
+        assert_eq!(action(None, &stored), update(None, 0, &["a", "b", "c"]));
+
+        // Messages edited since the client's version are all resent
+        store.record_upload("u", ThreadData::fixture("T-1", 4, &["edited", "b", "c"]));
+        let update = action(Some(2), &store.lookup("u", "T-1"));
+        assert_eq!(update["diff"]["fromIndex"], 0);
+        assert_eq!(update["diff"]["messages"], texts(&["edited", "b", "c"]));
+    }
+
+    #[test]
+    fn same_version_threads_with_other_sharing_get_the_server_settings() {
+        let store = ThreadStore::default();
+        store.record_upload("u", ThreadData::fixture("T-1", 2, &["a"]));
+        let ThreadLookup::Stored(record) = store.lookup("u", "T-1") else { panic!("T-1 is stored") };
+        let stored = ThreadLookup::Stored(ThreadRecord { public: true, ..record });
+        assert_eq!(
+            action(Some(2), &stored),
+            serde_json::json!({ "id": "T-1", "action": "meta", "meta": { "private": false, "public": true } })
+        );
+    }
+
+    #[test]
+    fn up_to_date_threads_need_an_action_only_when_their_sharing_differs() {
+        let store = ThreadStore::default();
+        store.record_upload("u", ThreadData::fixture("T-1", 2, &["a"]));
+        let stored = store.lookup("u", "T-1");
+        assert!(thread_action("T-1", Some(2), NOT_SHARED, &stored).is_none());
+
+        let ThreadLookup::Stored(mut record) = stored else { panic!("T-1 is stored") };
+        record.private = true;
+        let stored = ThreadLookup::Stored(record);
+        let sharing = ThreadSharing { private: true, public: false };
+        assert!(thread_action("T-1", Some(2), sharing, &stored).is_none());
+        assert!(matches!(
+            thread_action("T-1", Some(2), NOT_SHARED, &stored),
+            Some(ThreadAction::Meta { id: "T-1", meta }) if meta == sharing
+        ));
+    }
+}